@@ -0,0 +1,50 @@
+use core::{
+    time::Duration,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use alloc::{rc::Rc, sync::Arc};
+
+/// Point in time as seen by a [`Clock`]: time passed since the clock's own epoch.
+pub type Timestamp = Duration;
+
+/// Source of monotonic time for the cache.
+///
+/// The algorithm never reads OS time itself, so the embedder decides
+/// what "now" means (real monotonic time, a simulated clock in tests, ...).
+pub trait Clock {
+    fn now(&self) -> Timestamp;
+}
+
+impl<C: Clock + ?Sized> Clock for &C {
+    fn now(&self) -> Timestamp { (**self).now() }
+}
+
+impl<C: Clock + ?Sized> Clock for Rc<C> {
+    fn now(&self) -> Timestamp { (**self).now() }
+}
+
+impl<C: Clock + ?Sized> Clock for Arc<C> {
+    fn now(&self) -> Timestamp { (**self).now() }
+}
+
+/// Clock that only moves when told to. Useful for deterministic tests.
+#[derive(Default)]
+pub struct ManualClock {
+    nanos: AtomicU64,
+}
+
+impl ManualClock {
+    pub fn new(start: Timestamp) -> ManualClock {
+        ManualClock { nanos: AtomicU64::new(start.as_nanos() as u64) }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.nanos.fetch_add(by.as_nanos() as u64, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Timestamp {
+        Duration::from_nanos(self.nanos.load(Ordering::SeqCst))
+    }
+}
@@ -0,0 +1,52 @@
+use alloc::boxed::Box;
+
+use crate::{clock::Timestamp, Priority};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventKind {
+    Set,
+    /// removed by explicit delete
+    Deleted,
+    /// removed after its ttl passed
+    Expired,
+    /// displaced to make room for other items
+    Evicted,
+    /// eviction priority changed, the value is the same
+    Prioritized,
+}
+
+impl EventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::Set => "set",
+            EventKind::Deleted => "deleted",
+            EventKind::Expired => "expired",
+            EventKind::Evicted => "evicted",
+            EventKind::Prioritized => "prioritized",
+        }
+    }
+}
+
+/// Mutation of a store reported to its listener.
+#[derive(Debug)]
+pub struct Event<'a> {
+    pub kind: EventKind,
+    pub key: &'a str,
+    /// size of the value
+    pub size: usize,
+    /// value which is set or removed
+    pub data: &'a [u8],
+    /// there is no value but a marker of [`Memcached::set_negative`](crate::Memcached::set_negative)
+    pub negative: bool,
+    /// the value is a list, a set or a map, so `data` is empty
+    pub collection: bool,
+    /// deadline of the value
+    pub ttl: Option<Timestamp>,
+    /// when the value was set
+    pub stored_at: Timestamp,
+    pub priority: Priority,
+    pub at: Timestamp,
+}
+
+/// Called synchronously under the store lock, so it must be cheap, e.g. push into a channel.
+pub type Listener = Box<dyn Fn(&Event) + Send + Sync>;
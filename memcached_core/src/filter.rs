@@ -0,0 +1,52 @@
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use crate::hasher::KeyState;
+
+/// counters per expected key and hashes per key, about 1% of false positives at capacity
+const COUNTERS_PER_KEY: usize = 10;
+const HASHES: u64 = 7;
+
+/// Counting Bloom filter over keys of a store, see [`Options::key_filter`](crate::Options::key_filter).
+/// A key it doesn't contain is definitely missing. Counters are atomic, so lookups need
+/// no store lock, while the store updates them under its own. A counter which reached
+/// 255 is never decremented, keys mapped to it just always take the slow path.
+pub struct KeyFilter {
+    keys: KeyState,
+    counters: Vec<AtomicU8>,
+}
+
+impl KeyFilter {
+    pub(crate) fn new(capacity: usize, keys: KeyState) -> KeyFilter {
+        let counters = (0..capacity.max(1) * COUNTERS_PER_KEY).map(|_| AtomicU8::new(0)).collect();
+        KeyFilter { keys, counters }
+    }
+
+    /// false if the key is definitely not stored
+    pub fn may_contain(&self, key: &str) -> bool {
+        self.counters(self.keys.hash(key))
+            .all(|counter| counter.load(Ordering::Acquire) != 0)
+    }
+
+    pub(crate) fn add(&self, hash: u64) {
+        for counter in self.counters(hash) {
+            let _ = counter.fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| count.checked_add(1));
+        }
+    }
+
+    pub(crate) fn remove(&self, hash: u64) {
+        for counter in self.counters(hash) {
+            let _ = counter.fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| match count {
+                0 | u8::MAX => None,
+                count => Some(count - 1),
+            });
+        }
+    }
+
+    /// counters of a key by double hashing its store hash
+    fn counters(&self, hash: u64) -> impl Iterator<Item = &AtomicU8> + '_ {
+        let (first, step) = (hash & 0xffff_ffff, (hash >> 32) | 1);
+        let len = self.counters.len() as u64;
+        (0..HASHES).map(move |i| &self.counters[(first.wrapping_add(i.wrapping_mul(step)) % len) as usize])
+    }
+}
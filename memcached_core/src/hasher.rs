@@ -0,0 +1,119 @@
+use core::{
+    borrow::Borrow,
+    hash::{BuildHasher, Hash, Hasher},
+};
+use hashbrown::hash_map::{DefaultHashBuilder, HashMap, RawEntryMut};
+
+use crate::options::KeyHasher;
+
+type AHasher = <DefaultHashBuilder as BuildHasher>::Hasher;
+
+/// Seeded [`KeyHasher`] shared by the cache and leases,
+/// so a key hashed once can be looked up in both.
+#[derive(Clone)]
+pub(crate) enum KeyState {
+    AHash(DefaultHashBuilder),
+    #[cfg(feature = "std")]
+    SipHash(std::collections::hash_map::RandomState),
+}
+
+impl KeyState {
+    pub(crate) fn new(hasher: KeyHasher) -> KeyState {
+        match hasher {
+            KeyHasher::AHash => KeyState::AHash(DefaultHashBuilder::default()),
+            #[cfg(feature = "std")]
+            KeyHasher::SipHash => KeyState::SipHash(Default::default()),
+        }
+    }
+
+    pub(crate) fn hash(&self, key: &str) -> u64 {
+        self.hash_one(key)
+    }
+}
+
+impl BuildHasher for KeyState {
+    type Hasher = KeyStateHasher;
+
+    fn build_hasher(&self) -> KeyStateHasher {
+        match self {
+            KeyState::AHash(state) => KeyStateHasher::AHash(state.build_hasher()),
+            #[cfg(feature = "std")]
+            KeyState::SipHash(state) => KeyStateHasher::SipHash(state.build_hasher()),
+        }
+    }
+}
+
+pub(crate) enum KeyStateHasher {
+    AHash(AHasher),
+    #[cfg(feature = "std")]
+    SipHash(std::collections::hash_map::DefaultHasher),
+}
+
+impl Hasher for KeyStateHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        match self {
+            KeyStateHasher::AHash(hasher) => hasher.write(bytes),
+            #[cfg(feature = "std")]
+            KeyStateHasher::SipHash(hasher) => hasher.write(bytes),
+        }
+    }
+
+    fn write_u8(&mut self, i: u8) {
+        match self {
+            KeyStateHasher::AHash(hasher) => hasher.write_u8(i),
+            #[cfg(feature = "std")]
+            KeyStateHasher::SipHash(hasher) => hasher.write_u8(i),
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        match self {
+            KeyStateHasher::AHash(hasher) => hasher.finish(),
+            #[cfg(feature = "std")]
+            KeyStateHasher::SipHash(hasher) => hasher.finish(),
+        }
+    }
+}
+
+/// Lookups by a hash computed with [`KeyState::hash`] beforehand.
+pub(crate) trait Prehashed<K, V> {
+    fn find(&self, hash: u64, key: &str) -> Option<&V>;
+    fn find_mut(&mut self, hash: u64, key: &str) -> Option<(&K, &mut V)>;
+    fn take(&mut self, hash: u64, key: &str) -> Option<(K, V)>;
+    /// inserts or replaces the value, keeping the old key in the latter case
+    fn put(&mut self, hash: u64, key: K, value: V);
+}
+
+impl<K: Borrow<str> + Hash, V> Prehashed<K, V> for HashMap<K, V, KeyState> {
+    fn find(&self, hash: u64, key: &str) -> Option<&V> {
+        self.raw_entry().from_key_hashed_nocheck(hash, key).map(|(_, value)| value)
+    }
+
+    fn find_mut(&mut self, hash: u64, key: &str) -> Option<(&K, &mut V)> {
+        match self.raw_entry_mut().from_key_hashed_nocheck(hash, key) {
+            RawEntryMut::Occupied(entry) => {
+                let (key, value) = entry.into_key_value();
+                Some((&*key, value))
+            },
+            RawEntryMut::Vacant(_) => None,
+        }
+    }
+
+    fn take(&mut self, hash: u64, key: &str) -> Option<(K, V)> {
+        match self.raw_entry_mut().from_key_hashed_nocheck(hash, key) {
+            RawEntryMut::Occupied(entry) => Some(entry.remove_entry()),
+            RawEntryMut::Vacant(_) => None,
+        }
+    }
+
+    fn put(&mut self, hash: u64, key: K, value: V) {
+        match self.raw_entry_mut().from_key_hashed_nocheck(hash, Borrow::<str>::borrow(&key)) {
+            RawEntryMut::Occupied(mut entry) => {
+                entry.insert(value);
+            },
+            RawEntryMut::Vacant(entry) => {
+                entry.insert_hashed_nocheck(hash, key, value);
+            },
+        }
+    }
+}
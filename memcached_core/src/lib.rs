@@ -0,0 +1,2915 @@
+//! In-memory key-value store with ttl, size limit and LRU eviction,
+//! usable without the HTTP server.
+//!
+//! ```
+//! use core::time::Duration;
+//! use memcached_core::{Memcached, ManualClock};
+//!
+//! let mut mc = Memcached::new(1 << 20, ManualClock::default());
+//! assert!(mc.set("key".to_owned(), b"value".to_vec(), Some(Duration::from_secs(60))).is_ok());
+//! assert_eq!(mc.get("key"), Some(b"value".to_vec()));
+//! assert_eq!(mc.delete("key"), Some(b"value".to_vec()));
+//! ```
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod clock;
+pub mod events;
+mod filter;
+mod hasher;
+pub mod options;
+mod slab;
+pub mod tier;
+mod timer_wheel;
+mod tx;
+
+use core::{mem::size_of, time::Duration};
+use alloc::{
+    boxed::Box,
+    vec::Vec,
+    string::{String, ToString},
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    sync::Arc,
+};
+use hashbrown::HashMap;
+use log::debug;
+
+pub use crate::{
+    clock::{Clock, ManualClock, Timestamp},
+    events::{Event, EventKind, Listener},
+    filter::KeyFilter,
+    options::{Options, KeyError, KeyHasher, KeyRules, SlabConfig, TtlJitter, Watermarks},
+    tier::ColdTier,
+};
+use crate::{
+    hasher::{KeyState, Prehashed},
+    options::{exp_neg, unit},
+    slab::{element_len, Slabs, Value, COUNTER_LEN, ELEMENT_LEN},
+    timer_wheel::{TimerWheel, TimerHandle},
+    tx::Tx,
+};
+
+/// Key shared by the cache and the ttl and touch indexes.
+type Key = Arc<str>;
+
+struct Item {
+    touch: Timestamp,
+    ttl: Option<Timestamp>,
+    /// ttl restarted by [`Memcached::refresh`]
+    sliding: Option<Duration>,
+    /// changes on every set, for optimistic concurrency
+    version: u64,
+    /// when the value was set, age of [`Memcached::refresh_early`]
+    written: Timestamp,
+    /// never displaced to make room, so it is not in `keys_by_touch`
+    pinned: bool,
+    priority: Priority,
+    /// stale value was already handed out to a caller expected to set a fresh one
+    revalidating: bool,
+    /// known missing marker of [`Memcached::set_negative`] without data
+    negative: bool,
+    timer: Option<TimerHandle>,
+    data: Value,
+}
+
+/// Right to fill a missing key, see [`Memcached::lease`].
+struct Lease {
+    token: u64,
+    until: Timestamp,
+}
+
+/// What a garbage collection step did, see [`Memcached::sweep`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GcReport {
+    /// expired items removed
+    pub removed: usize,
+    /// bytes they took, counted against the limit
+    pub reclaimed: usize,
+    /// nothing expired is left
+    pub done: bool,
+}
+
+/// Limits amount of work done by a single [`Memcached::collect_garbage_step`]
+/// or [`Memcached::evict_step`] call, `None` means unlimited.
+/// A call handles at least one key, so even a zero budget makes progress.
+#[derive(Default, Clone, Copy)]
+pub struct GcBudget {
+    pub max_keys: Option<usize>,
+    pub max_duration: Option<Duration>,
+}
+
+impl GcBudget {
+    fn is_exhausted(&self, removed: usize, elapsed: Duration) -> bool {
+        removed != 0 && (
+            self.max_keys.is_some_and(|max| removed >= max)
+                || self.max_duration.is_some_and(|max| elapsed >= max)
+        )
+    }
+}
+
+/// Result of [`Memcached::get_stale`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum Freshness {
+    Fresh,
+    /// ttl has passed but the item is still within grace period,
+    /// `revalidate` is true only for the first caller to get it
+    Stale { revalidate: bool },
+}
+
+/// Upper bounds of ttl buckets of [`Stats::ttls`] and [`Distribution::remaining_ttl`].
+pub const TTL_BOUNDS: [Duration; 10] = [
+    Duration::from_secs(1), Duration::from_secs(10), Duration::from_secs(60), Duration::from_secs(5 * 60),
+    Duration::from_secs(15 * 60), Duration::from_secs(60 * 60), Duration::from_secs(6 * 60 * 60),
+    Duration::from_secs(24 * 60 * 60), Duration::from_secs(7 * 24 * 60 * 60), Duration::from_secs(30 * 24 * 60 * 60),
+];
+/// Upper bounds in bytes of value size buckets of [`Stats::sizes`] and [`Distribution::sizes`].
+pub const SIZE_BOUNDS: [usize; 10] = [64, 256, 1 << 10, 4 << 10, 16 << 10, 64 << 10, 256 << 10, 1 << 20, 4 << 20, 16 << 20];
+
+/// Counts per bucket of bounds such as [`TTL_BOUNDS`], the last bucket is above every bound.
+pub type Buckets = [u64; 11];
+
+/// Cumulative traffic counters of a store.
+#[derive(Default, Clone, Copy, Debug)]
+pub struct Stats {
+    /// bytes of values stored by `set`
+    pub written_bytes: u64,
+    /// bytes released by deletes, overwrites and expiry
+    pub freed_bytes: u64,
+    /// bytes displaced to make room for new values
+    pub evicted_bytes: u64,
+    /// ttls values were stored with per bucket of [`TTL_BOUNDS`], values which never expire are not counted
+    pub ttls: Buckets,
+    /// sum of ttls counted in `ttls`
+    pub ttl_sum: Duration,
+    /// sizes of values stored by `set` per bucket of [`SIZE_BOUNDS`]
+    pub sizes: Buckets,
+    /// sum of sizes counted in `sizes`
+    pub size_sum: u64,
+    /// when counting started
+    pub since: Timestamp,
+}
+
+/// Items of a store bucketed at a moment, see [`Memcached::distribution`].
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Distribution {
+    /// time left until expiry per bucket of [`TTL_BOUNDS`]
+    pub remaining_ttl: Buckets,
+    pub never_expiring: u64,
+    /// bytes of values per bucket of [`SIZE_BOUNDS`]
+    pub sizes: Buckets,
+}
+
+/// Everything needed to recreate an item in another store, see [`Memcached::dump`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dump {
+    pub data: Vec<u8>,
+    /// time left until expiry
+    pub ttl: Option<Duration>,
+    /// period of sliding ttl
+    pub sliding: Option<Duration>,
+    pub pinned: bool,
+    pub priority: Priority,
+    /// versions start from 1, so 0 makes restore issue a fresh one
+    pub version: u64,
+}
+
+/// Value of an item of any kind, see [`Memcached::snapshot`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Contents {
+    /// bytes are in [`Dump::data`]
+    Plain,
+    List(Vec<Vec<u8>>),
+    Set(Vec<Vec<u8>>),
+    Map(Vec<Field>),
+    Counter { value: i64, overflow: Overflow, refresh: Option<Duration> },
+    /// marker of [`Memcached::set_negative`]
+    Negative,
+}
+
+/// Eviction order of items: lower priority ones are displaced first, oldest first within a priority.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+/// End of a list, see [`Memcached::push`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum End {
+    Front,
+    Back,
+}
+
+/// Field of a map and its value, see [`Memcached::set_fields`].
+pub type Field = (Vec<u8>, Vec<u8>);
+
+/// Why a list, set or map operation left the item as it is.
+#[derive(Debug, PartialEq, Eq)]
+pub enum CollectionError {
+    /// key holds a value of another type
+    WrongType,
+    /// added values don't fit into memory limit or the key breaks [`Options::key_rules`]
+    NotStored,
+    /// nothing to add, collections are never empty
+    Empty,
+}
+
+/// Why [`Memcached::incr`] left the value as it is.
+#[derive(Debug, PartialEq, Eq)]
+pub enum IncrError {
+    /// value is not a decimal integer
+    NotANumber,
+    /// sum doesn't fit into i64
+    Overflow,
+    /// longer value doesn't fit into memory limit
+    NotStored,
+}
+
+/// What a counter does with a change which doesn't fit into i64, see [`CounterOptions`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Overflow {
+    /// the change fails and the counter keeps its value
+    #[default]
+    Error,
+    /// the counter stops at the bound it reached
+    Saturate,
+    /// the counter wraps around to the other bound
+    Wrap,
+}
+
+/// Semantics of a counter, fixed when it is created by [`Memcached::count`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CounterOptions {
+    /// value of a new counter before the change which creates it
+    pub initial: i64,
+    pub overflow: Overflow,
+    /// every change restarts the ttl the counter was created with,
+    /// otherwise it expires when it was first due like memcached `incr` does
+    pub refresh_ttl: bool,
+}
+
+/// Items of a slab class like memcached `stats items` and `stats slabs` report them,
+/// see [`Memcached::classes`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ClassStats {
+    /// chunk size of the class, `None` for values allocated on their own
+    pub chunk_size: Option<usize>,
+    pub items: usize,
+    /// bytes of values
+    pub bytes: usize,
+    pub pages: usize,
+    /// chunks of the pages, taken and free ones
+    pub chunks: usize,
+    /// items displaced to make room since start
+    pub evicted: u64,
+    /// time since the least recently used item was touched
+    pub oldest_age: Option<Duration>,
+}
+
+/// Estimated bytes an item takes, see [`Memcached::usage`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Usage {
+    /// key and the counts of its shared allocation
+    pub key: usize,
+    /// value as it is reserved: its slab chunk or allocation, collections with their element headers
+    pub value: usize,
+    /// slot of the hash table and entries of the ttl and eviction indexes
+    pub overhead: usize,
+}
+
+impl Usage {
+    pub fn total(&self) -> usize {
+        self.key + self.value + self.overhead
+    }
+}
+
+/// Why [`Memcached::count`] left the counter as it is.
+#[derive(Debug, PartialEq, Eq)]
+pub enum CounterError {
+    /// key holds a value of another type
+    WrongType,
+    /// change doesn't fit into i64 and the counter doesn't saturate or wrap
+    Overflow,
+    /// new counter doesn't fit into memory limit or the key breaks [`Options::key_rules`]
+    NotStored,
+}
+
+pub struct SetError(String, Vec<u8>);
+
+impl SetError {
+    pub fn into_kv(self) -> (String, Vec<u8>) { (self.0, self.1) }
+}
+
+pub struct Memcached<C: Clock> {
+    clock: C,
+    options: Options,
+    /// xorshift state for ttl jitter
+    rng: u64,
+    limit: usize,
+    /// bytes reserved for values: their sizes or sizes of their slab chunks
+    current_size: usize,
+    slabs: Slabs,
+    stats: Stats,
+    /// version of the latest set
+    last_version: u64,
+    cache: HashMap<Key, Item, KeyState>,
+    keys_by_ttl: TimerWheel,
+    /// evictable keys in eviction order
+    keys_by_touch: BTreeMap<(Priority, Timestamp), Vec<Key>>,
+    /// miss leases of missing keys
+    leases: HashMap<String, Lease, KeyState>,
+    last_lease: u64,
+    filter: Option<Arc<KeyFilter>>,
+    listener: Option<Listener>,
+    /// open transaction, see [`Memcached::begin`]
+    tx: Option<Tx>,
+    cold: Option<Box<dyn ColdTier>>,
+    /// bytes of values under each prefix, see [`Memcached::track_prefixes`]
+    tracked: Vec<(String, usize)>,
+}
+
+impl<C: Clock> Memcached<C> {
+    pub fn new(limit: usize, clock: C) -> Memcached<C> {
+        Memcached::with_options(limit, clock, Options::default())
+    }
+
+    pub fn with_options(limit: usize, clock: C, options: Options) -> Memcached<C> {
+        let stats = Stats { since: clock.now(), ..Stats::default() };
+        let keys = KeyState::new(options.hasher);
+        let slabs = options.slabs.map(Slabs::new).unwrap_or_default();
+        let filter = options.key_filter.map(|capacity| Arc::new(KeyFilter::new(capacity, keys.clone())));
+        Memcached {
+            slabs,
+            clock, options, limit, stats,
+            last_version: 0,
+            rng: 0x2545_f491_4f6c_dd1d,
+            current_size: 0,
+            cache: HashMap::with_hasher(keys.clone()),
+            keys_by_ttl: TimerWheel::default(),
+            keys_by_touch: BTreeMap::new(),
+            leases: HashMap::with_hasher(keys),
+            last_lease: 0,
+            filter,
+            listener: None,
+            tx: None,
+            cold: None,
+            tracked: Vec::new(),
+        }
+    }
+
+    pub fn clock(&self) -> &C {
+        &self.clock
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Changes memory limit, displacing oldest items if they don't fit anymore.
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        if self.slabs.reserved() > self.limit {
+            self.collect_garbage();
+        }
+        while self.slabs.reserved() > self.limit && self.remove_oldest() {
+            debug!("oldest key displaced: reserved {}", self.slabs.reserved());
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+
+    /// iterates over all stored keys including expired but not yet collected ones
+    pub fn keys(&self) -> impl Iterator<Item = &str> + '_ {
+        self.cache.keys().map(|key| &**key)
+    }
+
+    /// iterates over not expired items in no particular order, skipping negative markers and collections
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[u8])> + '_ {
+        let now = self.clock.now();
+        self.cache.iter()
+            .filter(move |(_, item)| !item.negative && item.data.is_plain() && item.ttl.is_none_or(|ttl| ttl >= now))
+            .map(move |(key, item)| (&**key, self.slabs.get(&item.data)))
+    }
+
+    /// bytes taken by values, with [`Options::slabs`] these are sizes of their chunks
+    pub fn size(&self) -> usize {
+        self.current_size
+    }
+
+    /// Starts accounting bytes of values under each of `prefixes`, a key counts towards
+    /// the first one it starts with. Walks every key unless the prefixes are the tracked ones.
+    pub fn track_prefixes(&mut self, prefixes: &[String]) {
+        if self.tracked.iter().map(|(prefix, _)| prefix).eq(prefixes) {
+            return
+        }
+
+        let mut tracked: Vec<(String, usize)> = prefixes.iter().map(|prefix| (prefix.clone(), 0)).collect();
+        for (key, item) in self.cache.iter() {
+            if let Some((_, bytes)) = tracked.iter_mut().find(|(prefix, _)| key.starts_with(prefix.as_str())) {
+                *bytes += self.slabs.size_of(&item.data);
+            }
+        }
+        self.tracked = tracked;
+    }
+
+    /// Tracked prefixes with bytes of values under them, counted like [`Memcached::size`].
+    pub fn prefix_bytes(&self) -> &[(String, usize)] {
+        &self.tracked
+    }
+
+    /// Accounts a change of the value size under the key to its tracked prefix.
+    fn account(&mut self, key: &str, added: usize, freed: usize) {
+        if let Some((_, bytes)) = self.tracked.iter_mut().find(|(prefix, _)| key.starts_with(prefix.as_str())) {
+            *bytes = *bytes + added - freed;
+        }
+    }
+
+    /// Bytes allocated for values, counted against the limit.
+    /// With [`Options::slabs`] it includes free chunks of slab pages.
+    pub fn reserved(&self) -> usize {
+        self.slabs.reserved()
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Breakdown of items by slab class, classes without pages, items or evictions are left out.
+    /// Without [`Options::slabs`] there is only the class of values allocated on their own.
+    /// Visits every item, so it takes time proportional to their number.
+    pub fn classes(&self) -> Vec<ClassStats> {
+        let now = self.clock.now();
+        let mut classes = self.slabs.class_stats();
+        for (_, item) in self.cache.iter() {
+            let class = &mut classes[self.slabs.class_index(&item.data)];
+            let age = now.saturating_sub(item.touch);
+            class.items += 1;
+            class.bytes += item.data.len();
+            class.oldest_age = Some(class.oldest_age.map_or(age, |oldest| oldest.max(age)));
+        }
+        classes.retain(|class| class.items > 0 || class.pages > 0 || class.evicted > 0);
+        classes
+    }
+
+    /// Remaining ttls and value sizes of not expired items.
+    /// Visits every item, so it takes time proportional to their number.
+    pub fn distribution(&self) -> Distribution {
+        let now = self.clock.now();
+        let mut distribution = Distribution::default();
+        let live = self.cache.iter().filter(|(_, item)| !item.negative && item.ttl.is_none_or(|ttl| ttl >= now));
+        for (_, item) in live {
+            match item.ttl {
+                Some(ttl) => distribution.remaining_ttl[bucket(&TTL_BOUNDS, &(ttl - now))] += 1,
+                None => distribution.never_expiring += 1,
+            }
+            distribution.sizes[bucket(&SIZE_BOUNDS, &item.data.len())] += 1;
+        }
+        distribution
+    }
+
+    /// Filter of stored keys, readable without borrowing the store.
+    pub fn key_filter(&self) -> Option<Arc<KeyFilter>> {
+        self.filter.clone()
+    }
+
+    /// Reports every following mutation to `listener`, replacing the previous one.
+    pub fn set_listener(&mut self, listener: Listener) {
+        self.listener = Some(listener);
+    }
+
+    /// Displaced items are moved to `cold` instead of being dropped,
+    /// they are brought back by [`Memcached::promote`].
+    pub fn set_cold_tier(&mut self, cold: Box<dyn ColdTier>) {
+        self.cold = Some(cold);
+    }
+
+    /// Deletes the item, also invalidating miss lease of the key.
+    /// Item spilled to cold tier is deleted there.
+    pub fn delete(&mut self, key: &str) -> Option<Vec<u8>> {
+        let hash = self.hash(key);
+        self.delete_hashed(hash, key)
+    }
+
+    fn delete_hashed(&mut self, hash: u64, key: &str) -> Option<Vec<u8>> {
+        self.leases.take(hash, key);
+        let deleted = self.discard(hash, key, EventKind::Deleted)
+            .map(|value| self.slabs.release(value));
+        match (deleted, &mut self.cold) {
+            (None, Some(cold)) => {
+                let now = self.clock.now();
+                cold.take(key)
+                    .filter(|(_, ttl)| ttl.is_none_or(|ttl| ttl >= now))
+                    .map(|(data, _)| data)
+            },
+            (deleted, _) => deleted,
+        }
+    }
+
+    /// Moves item missing in memory back from cold tier, keeping its deadline.
+    /// Value is returned even if memory can't take it back, but it is lost then.
+    pub fn promote(&mut self, key: &str) -> Option<Vec<u8>> {
+        let (data, ttl) = self.cold.as_mut()?.take(key)?;
+        let now = self.clock.now();
+        let ttl = match ttl {
+            Some(ttl) if ttl < now => return None,
+            ttl => ttl.map(|ttl| ttl - now),
+        };
+
+        let hash = self.hash(key);
+        let _ = self.insert(hash, key.to_owned(), data.clone(), ttl, false, false);
+        Some(data)
+    }
+
+    /// Grants a miss lease: the caller is expected to compute the value and set it,
+    /// while others are told to retry instead of computing it too.
+    /// Returns lease token or `None` if another lease is held for less than `ttl`.
+    /// Lease ends when the key is set or deleted.
+    pub fn lease(&mut self, key: &str, ttl: Duration) -> Option<u64> {
+        let hash = self.hash(key);
+        let now = self.clock.now();
+        if self.leases.find(hash, key).is_some_and(|lease| lease.until >= now) {
+            return None
+        }
+
+        self.last_lease += 1;
+        let token = self.last_lease;
+        self.leases.put(hash, key.to_owned(), Lease { token, until: now + ttl });
+        Some(token)
+    }
+
+    /// false if the key was set or deleted since the lease was granted,
+    /// or a new lease was granted after this one expired
+    pub fn holds_lease(&self, key: &str, token: u64) -> bool {
+        self.leases.find(self.hash(key), key).is_some_and(|lease| lease.token == token)
+    }
+
+    /// Gives up the lease, so others may compute the value, if it is still held.
+    pub fn release_lease(&mut self, key: &str, token: u64) {
+        if self.holds_lease(key, token) {
+            self.leases.take(self.hash(key), key);
+        }
+    }
+
+    /// Deletes the item accounting it in stats and reporting to listener.
+    /// Returned value is still to be released from slabs.
+    fn discard(&mut self, hash: u64, key: &str, kind: EventKind) -> Option<Value> {
+        let (key, item) = self.remove(hash, key)?;
+        let size = item.data.len();
+        match kind {
+            EventKind::Evicted => {
+                self.stats.evicted_bytes += size as u64;
+                self.slabs.count_eviction(&item.data);
+            },
+            _ => self.stats.freed_bytes += size as u64,
+        }
+        self.notify(kind, &key, &item);
+
+        Some(item.data)
+    }
+
+    /// Deletes the item without accounting it anywhere.
+    fn remove(&mut self, hash: u64, key: &str) -> Option<(Key, Item)> {
+        let (key_owned, item) = self.cache.take(hash, key)?;
+        if let Some(filter) = &self.filter {
+            filter.remove(hash);
+        }
+
+        if !item.pinned {
+            self.remove_from_touch(key, item.priority, item.touch);
+        }
+        if let Some(timer) = item.timer {
+            self.keys_by_ttl.remove(timer);
+        }
+        let size = self.slabs.size_of(&item.data);
+        self.current_size -= size;
+        self.account(key, 0, size);
+
+        Some((key_owned, item))
+    }
+
+    /// Hash of the key for every map of the store, computed once per call.
+    fn hash(&self, key: &str) -> u64 {
+        self.cache.hasher().hash(key)
+    }
+
+    /// not expired item, negative markers are not items
+    fn live(&self, hash: u64, key: &str) -> Option<&Item> {
+        let now = self.clock.now();
+        self.cache.find(hash, key)
+            .filter(|item| !item.negative && item.ttl.is_none_or(|ttl| ttl >= now))
+    }
+
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        self.get_with_version(key).map(|(data, _)| data)
+    }
+
+    /// not expired plain value, collections are read by their own operations only
+    fn value(&self, hash: u64, key: &str) -> Option<&Item> {
+        self.live(hash, key).filter(|item| item.data.is_plain())
+    }
+
+    pub fn get_with_version(&self, key: &str) -> Option<(Vec<u8>, u64)> {
+        self.value(self.hash(key), key)
+            .map(|item| (self.slabs.get(&item.data).to_vec(), item.version))
+    }
+
+    /// Version of a not expired item, every set of the key gives it a new one.
+    pub fn version(&self, key: &str) -> Option<u64> {
+        self.live(self.hash(key), key).map(|item| item.version)
+    }
+
+    /// Estimated memory taken by the item, including expired ones and negative markers
+    /// which are still stored. Allocator bookkeeping is not counted.
+    /// `None` if key is missing.
+    pub fn usage(&self, key: &str) -> Option<Usage> {
+        let item = self.cache.find(self.hash(key), key)?;
+        // hash table slot and its control byte
+        let mut overhead = size_of::<(Key, Item)>() + 1;
+        if !item.pinned {
+            overhead += size_of::<Key>();
+        }
+        if item.timer.is_some() {
+            overhead += timer_wheel::ENTRY_SIZE;
+        }
+        Some(Usage {
+            key: key.len() + 2 * size_of::<usize>(),
+            value: self.slabs.size_of(&item.data),
+            overhead,
+        })
+    }
+
+    /// Deadline of the item, `None` if it never expires or doesn't exist.
+    pub fn expires_at(&self, key: &str) -> Option<Timestamp> {
+        self.cache.find(self.hash(key), key)?.ttl
+    }
+
+    /// Like `get`, but also returns items expired less than [`Options::stale_grace`] ago.
+    pub fn get_stale(&mut self, key: &str) -> Option<(Vec<u8>, Freshness)> {
+        let hash = self.hash(key);
+        let now = self.clock.now();
+        let grace = self.options.stale_grace;
+        let (_, item) = self.cache.find_mut(hash, key)
+            .filter(|(_, item)| !item.negative && item.data.is_plain())?;
+
+        let freshness = match item.ttl {
+            Some(ttl) if ttl + grace < now => return None,
+            Some(ttl) if ttl < now => {
+                let revalidate = !item.revalidating;
+                item.revalidating = true;
+                Freshness::Stale { revalidate }
+            },
+            _ => Freshness::Fresh,
+        };
+
+        Some((self.slabs.get(&item.data).to_vec(), freshness))
+    }
+
+    /// Probabilistic early expiration (XFetch): whether this read should treat a not expired item
+    /// as missing and refresh it, so refreshes are spread ahead of the deadline. The chance grows
+    /// as the item nears its deadline and ages: with `beta` of [`Options::early_expiration`],
+    /// it is `exp(-remaining / (beta * age))`. Always false while a lease on the key is held,
+    /// so other readers keep getting the value while a refresh is under way, and for items
+    /// without ttl or with sliding one. `random` is the uniformly distributed draw, it is taken
+    /// by the caller so the check needs no exclusive access to the store.
+    pub fn refresh_early(&self, key: &str, random: u64) -> bool {
+        let beta = match self.options.early_expiration {
+            Some(beta) if beta > 0.0 => beta,
+            _ => return false,
+        };
+        let hash = self.hash(key);
+        let now = self.clock.now();
+        let (ttl, written) = match self.value(hash, key) {
+            Some(item) if item.sliding.is_none() => match item.ttl {
+                Some(ttl) => (ttl, item.written),
+                None => return false,
+            },
+            _ => return false,
+        };
+        if self.leases.find(hash, key).is_some_and(|lease| lease.until >= now) {
+            return false
+        }
+
+        let (remaining, age) = ((ttl - now).as_secs_f64(), (now - written).as_secs_f64());
+        if age == 0.0 {
+            return false
+        }
+        unit(random) < exp_neg(remaining / (beta * age))
+    }
+
+    /// true if reading the key should be followed by [`Memcached::refresh`]
+    pub fn is_sliding(&self, key: &str) -> bool {
+        self.cache.find(self.hash(key), key).is_some_and(|item| item.sliding.is_some())
+    }
+
+    /// Restarts ttl countdown of a sliding item, never past [`Options::max_ttl`] since it was written.
+    /// Returns false if key is missing, expired or doesn't have sliding ttl.
+    pub fn refresh(&mut self, key: &str) -> bool {
+        let hash = self.hash(key);
+        let now = self.clock.now();
+        let item = match self.cache.find_mut(hash, key) {
+            Some((_, item)) if item.ttl.is_none_or(|ttl| ttl >= now) => item,
+            _ => return false,
+        };
+
+        match (item.sliding, item.timer) {
+            (Some(sliding), Some(timer)) => {
+                let deadline = capped(self.options.max_ttl, item.written, now + sliding);
+                item.ttl = Some(deadline);
+                self.keys_by_ttl.reschedule(timer, deadline + self.options.stale_grace);
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// Replaces ttl of a not expired item, `None` makes it never expire, or expire in [`Options::max_ttl`] if it is set.
+    /// With [`Options::max_ttl`] the item doesn't outlive it since it was written.
+    /// Sliding items keep sliding with the new ttl. Returns false if key is missing or expired.
+    pub fn touch(&mut self, key: &str, ttl: Option<Duration>) -> bool {
+        let hash = self.hash(key);
+        self.touch_hashed(hash, key, ttl)
+    }
+
+    fn touch_hashed(&mut self, hash: u64, key: &str, ttl: Option<Duration>) -> bool {
+        if self.live(hash, key).is_none() {
+            return false
+        }
+
+        let now = self.clock.now();
+        // default ttl is for writes, touching without a ttl means never expiring
+        let ttl = match ttl {
+            Some(ttl) => self.lifetime(Some(ttl)),
+            None => self.options.max_ttl.map(|max| self.jittered(max)),
+        };
+        let (grace, max_ttl) = (self.options.stale_grace, self.options.max_ttl);
+        let (key, item) = self.cache.find_mut(hash, key).unwrap();
+
+        item.sliding = item.sliding.and(ttl);
+        item.ttl = ttl.map(|ttl| capped(max_ttl, item.written, now + ttl));
+        match (item.timer, item.ttl) {
+            (Some(timer), Some(deadline)) => self.keys_by_ttl.reschedule(timer, deadline + grace),
+            (None, Some(deadline)) => item.timer = Some(self.keys_by_ttl.insert(key.clone(), deadline + grace)),
+            (Some(timer), None) => {
+                self.keys_by_ttl.remove(timer);
+                item.timer = None;
+            },
+            (None, None) => {},
+        }
+
+        true
+    }
+
+    /// Exempts a not expired item from eviction, it still expires and can be deleted.
+    /// Pin is dropped when the key is set again. Returns false if key is missing or expired.
+    pub fn pin(&mut self, key: &str) -> bool {
+        let hash = self.hash(key);
+        self.pin_hashed(hash, key)
+    }
+
+    fn pin_hashed(&mut self, hash: u64, key: &str) -> bool {
+        if self.live(hash, key).is_none() {
+            return false
+        }
+        let (_, item) = self.cache.find_mut(hash, key).unwrap();
+        if !item.pinned {
+            item.pinned = true;
+            let (priority, touch) = (item.priority, item.touch);
+            self.remove_from_touch(key, priority, touch);
+        }
+        true
+    }
+
+    /// Makes item evictable again. Returns false if key is missing or expired.
+    pub fn unpin(&mut self, key: &str) -> bool {
+        let hash = self.hash(key);
+        if self.live(hash, key).is_none() {
+            return false
+        }
+        let (key, item) = self.cache.find_mut(hash, key).unwrap();
+        if item.pinned {
+            item.pinned = false;
+            let (key, priority, touch) = (key.clone(), item.priority, item.touch);
+            self.add_to_touch(key, priority, touch);
+        }
+        true
+    }
+
+    pub fn is_pinned(&self, key: &str) -> bool {
+        self.cache.find(self.hash(key), key).is_some_and(|item| item.pinned)
+    }
+
+    /// Changes eviction priority of a not expired item, it is [`Priority::Normal`] once the key is set again.
+    /// Returns false if key is missing or expired.
+    pub fn set_priority(&mut self, key: &str, priority: Priority) -> bool {
+        let hash = self.hash(key);
+        self.set_priority_hashed(hash, key, priority)
+    }
+
+    fn set_priority_hashed(&mut self, hash: u64, key: &str, priority: Priority) -> bool {
+        if self.live(hash, key).is_none() {
+            return false
+        }
+        let (key, item) = self.cache.find_mut(hash, key).unwrap();
+        let previous = core::mem::replace(&mut item.priority, priority);
+        if previous == priority {
+            return true
+        }
+        let (key, touch, pinned) = (key.clone(), item.touch, item.pinned);
+        if !pinned {
+            self.remove_from_touch(&key, previous, touch);
+            self.add_to_touch(key.clone(), priority, touch);
+        }
+        let item = self.cache.find(hash, &key).unwrap();
+        let event = event(&self.slabs, self.clock.now(), EventKind::Prioritized, &key, item);
+        report(&self.listener, &mut self.tx, &event);
+        true
+    }
+
+    pub fn priority(&self, key: &str) -> Option<Priority> {
+        self.live(self.hash(key), key).map(|item| item.priority)
+    }
+
+    /// Get and touch: returns the value and replaces its ttl in one go.
+    pub fn gat(&mut self, key: &str, ttl: Option<Duration>) -> Option<Vec<u8>> {
+        let hash = self.hash(key);
+        let data = self.slabs.get(&self.value(hash, key)?.data).to_vec();
+        self.touch_hashed(hash, key, ttl);
+        Some(data)
+    }
+
+    /// Pushes `values` one by one to an end of the list, so pushing `[a, b]` to the front puts `b` first.
+    /// Missing or expired key becomes a new list expiring in `ttl`, an existing list keeps its ttl.
+    /// Lists share ttl and eviction with plain values, but are not dumped, spilled to cold tier
+    /// or returned by `get`. Returns length of the list.
+    pub fn push(&mut self, key: &str, end: End, values: Vec<Vec<u8>>, ttl: Option<Duration>) -> Result<usize, CollectionError> {
+        if values.is_empty() {
+            return Err(CollectionError::Empty)
+        }
+        let hash = self.hash(key);
+        let added = values.iter().map(|data| element_len(data)).sum();
+        let stored = match self.collection(hash, key, Value::is_list)? {
+            Some(stored) => stored,
+            None => {
+                let len = values.len();
+                let list = |slabs: &mut Slabs| slabs.store_list(values);
+                return self.create(hash, key, added, ttl, list).map(|_| len)
+            },
+        };
+
+        self.grow(hash, key, stored, added)?;
+        let (_, item) = self.cache.find_mut(hash, key).unwrap();
+        let slabs = &mut self.slabs;
+        values.into_iter().for_each(|data| slabs.push(&mut item.data, end, data));
+        let len = item.data.list().map_or(0, VecDeque::len);
+        self.changed(hash, key, added, 0);
+        Ok(len)
+    }
+
+    /// Takes up to `count` values from an end of the list, an emptied list is deleted.
+    /// Returns `None` if key is missing or expired.
+    pub fn pop(&mut self, key: &str, end: End, count: usize) -> Result<Option<Vec<Vec<u8>>>, CollectionError> {
+        let hash = self.hash(key);
+        if self.collection(hash, key, Value::is_list)?.is_none() {
+            return Ok(None)
+        }
+
+        let (_, item) = self.cache.find_mut(hash, key).unwrap();
+        let slabs = &mut self.slabs;
+        let popped: Vec<Vec<u8>> = (0..count).map_while(|_| slabs.pop(&mut item.data, end)).collect();
+        self.changed(hash, key, 0, popped.iter().map(|data| element_len(data)).sum());
+        Ok(Some(popped))
+    }
+
+    /// Values of the list from `start` to `stop` inclusive, negative indexes count from the back
+    /// like in `LRANGE` of Redis. Returns `None` if key is missing or expired.
+    pub fn range(&self, key: &str, start: i64, stop: i64) -> Result<Option<Vec<Vec<u8>>>, CollectionError> {
+        let items = match self.live(self.hash(key), key) {
+            Some(item) => item.data.list().ok_or(CollectionError::WrongType)?,
+            None => return Ok(None),
+        };
+        let len = items.len() as i64;
+        let index = |at: i64| if at < 0 { len + at } else { at };
+        let (start, stop) = (index(start).max(0), index(stop).min(len - 1));
+        if start > stop {
+            return Ok(Some(Vec::new()))
+        }
+        Ok(Some(items.range(start as usize..=stop as usize).cloned().collect()))
+    }
+
+    /// Adds members to the set, missing or expired key becomes a new set expiring in `ttl`,
+    /// an existing set keeps its ttl. Sets share ttl and eviction with plain values like lists do.
+    /// Returns how many members were not in the set yet.
+    pub fn add_members(&mut self, key: &str, members: Vec<Vec<u8>>, ttl: Option<Duration>) -> Result<usize, CollectionError> {
+        if members.is_empty() {
+            return Err(CollectionError::Empty)
+        }
+        let hash = self.hash(key);
+        let stored = self.collection(hash, key, Value::is_set)?;
+        let mut new: BTreeSet<Vec<u8>> = members.into_iter().collect();
+        if let Some(set) = self.live(hash, key).and_then(|item| item.data.set()) {
+            new.retain(|member| !set.contains(member));
+        }
+        let (count, added) = (new.len(), new.iter().map(|member| element_len(member)).sum());
+
+        let stored = match stored {
+            Some(stored) => stored,
+            None => return self.create(hash, key, added, ttl, |slabs| slabs.store_set(new)).map(|_| count),
+        };
+        self.grow(hash, key, stored, added)?;
+        let (_, item) = self.cache.find_mut(hash, key).unwrap();
+        let slabs = &mut self.slabs;
+        new.into_iter().for_each(|member| {
+            slabs.insert_member(&mut item.data, member);
+        });
+        self.changed(hash, key, added, 0);
+        Ok(count)
+    }
+
+    /// Removes members of the set, an emptied set is deleted.
+    /// Returns how many of them were in the set, `None` if key is missing or expired.
+    pub fn remove_members(&mut self, key: &str, members: &[Vec<u8>]) -> Result<Option<usize>, CollectionError> {
+        let hash = self.hash(key);
+        if self.collection(hash, key, Value::is_set)?.is_none() {
+            return Ok(None)
+        }
+
+        let (_, item) = self.cache.find_mut(hash, key).unwrap();
+        let slabs = &mut self.slabs;
+        let removed: Vec<&Vec<u8>> = members.iter()
+            .filter(|member| slabs.remove_member(&mut item.data, member))
+            .collect();
+        self.changed(hash, key, 0, removed.iter().map(|member| element_len(member)).sum());
+        Ok(Some(removed.len()))
+    }
+
+    /// false if the set doesn't have the member or the key is missing or expired
+    pub fn is_member(&self, key: &str, member: &[u8]) -> Result<bool, CollectionError> {
+        match self.live(self.hash(key), key) {
+            Some(item) => Ok(item.data.set().ok_or(CollectionError::WrongType)?.contains(member)),
+            None => Ok(false),
+        }
+    }
+
+    /// Members of the set in ascending order, `None` if key is missing or expired.
+    pub fn members(&self, key: &str) -> Result<Option<Vec<Vec<u8>>>, CollectionError> {
+        match self.live(self.hash(key), key) {
+            Some(item) => Ok(Some(item.data.set().ok_or(CollectionError::WrongType)?.iter().cloned().collect())),
+            None => Ok(None),
+        }
+    }
+
+    /// Sets fields of the map, missing or expired key becomes a new map expiring in `ttl`,
+    /// an existing map keeps its ttl. Maps share ttl and eviction with plain values like lists do.
+    /// Returns how many fields are new to the map.
+    pub fn set_fields(&mut self, key: &str, fields: Vec<Field>, ttl: Option<Duration>) -> Result<usize, CollectionError> {
+        let hash = self.hash(key);
+        let fields: BTreeMap<Vec<u8>, Vec<u8>> = fields.into_iter().collect();
+        if fields.is_empty() {
+            return Err(CollectionError::Empty)
+        }
+        let entry_len = |(field, value): (&Vec<u8>, &Vec<u8>)| element_len(field) + element_len(value);
+        let stored = match self.collection(hash, key, Value::is_map)? {
+            Some(stored) => stored,
+            None => {
+                let (count, len) = (fields.len(), fields.iter().map(entry_len).sum());
+                return self.create(hash, key, len, ttl, |slabs| slabs.store_map(fields)).map(|_| count)
+            },
+        };
+
+        // replaced values are freed only once the new ones are stored
+        let added = fields.iter().map(entry_len).sum();
+        self.grow(hash, key, stored, added)?;
+        let (_, item) = self.cache.find_mut(hash, key).unwrap();
+        let (mut count, mut added, mut freed) = (0, 0, 0);
+        for (field, value) in fields {
+            let field_len = element_len(&field) + ELEMENT_LEN;
+            added += value.len();
+            match self.slabs.insert_field(&mut item.data, field, value) {
+                Some(previous) => freed += previous.len(),
+                None => {
+                    count += 1;
+                    added += field_len;
+                },
+            }
+        }
+        self.changed(hash, key, added, freed);
+        Ok(count)
+    }
+
+    /// `None` if the map doesn't have the field or the key is missing or expired
+    pub fn get_field(&self, key: &str, field: &[u8]) -> Result<Option<Vec<u8>>, CollectionError> {
+        match self.live(self.hash(key), key) {
+            Some(item) => Ok(item.data.map().ok_or(CollectionError::WrongType)?.get(field).cloned()),
+            None => Ok(None),
+        }
+    }
+
+    /// Removes fields of the map, an emptied map is deleted.
+    /// Returns how many of them were in the map, `None` if key is missing or expired.
+    pub fn delete_fields(&mut self, key: &str, fields: &[Vec<u8>]) -> Result<Option<usize>, CollectionError> {
+        let hash = self.hash(key);
+        if self.collection(hash, key, Value::is_map)?.is_none() {
+            return Ok(None)
+        }
+
+        let (_, item) = self.cache.find_mut(hash, key).unwrap();
+        let (mut count, mut freed) = (0, 0);
+        for field in fields {
+            if let Some(value) = self.slabs.remove_field(&mut item.data, field) {
+                count += 1;
+                freed += element_len(field) + element_len(&value);
+            }
+        }
+        self.changed(hash, key, 0, freed);
+        Ok(Some(count))
+    }
+
+    /// Fields of the map in ascending order, `None` if key is missing or expired.
+    pub fn fields(&self, key: &str) -> Result<Option<Vec<Field>>, CollectionError> {
+        match self.live(self.hash(key), key) {
+            Some(item) => {
+                let fields = item.data.map().ok_or(CollectionError::WrongType)?;
+                Ok(Some(fields.iter().map(|(field, value)| (field.clone(), value.clone())).collect()))
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Adds `delta` to the counter. Missing or expired key becomes a new counter with `options`,
+    /// starting at their initial value and expiring in `ttl`, an existing one keeps the options
+    /// it was created with. Counters share ttl and eviction with plain values, but are not dumped,
+    /// spilled to cold tier or returned by `get`. Returns the new value.
+    pub fn count(&mut self, key: &str, delta: i64, ttl: Option<Duration>, options: CounterOptions) -> Result<i64, CounterError> {
+        let hash = self.hash(key);
+        match self.live(hash, key) {
+            Some(item) if !item.data.is_counter() => return Err(CounterError::WrongType),
+            Some(_) => (),
+            None => {
+                let value = add(options.initial, delta, options.overflow)?;
+                let refresh = ttl.filter(|_| options.refresh_ttl);
+                let counter = |slabs: &mut Slabs| slabs.store_counter(value, options.overflow, refresh);
+                return self.create(hash, key, COUNTER_LEN, ttl, counter)
+                    .map(|_| value)
+                    .map_err(|_| CounterError::NotStored)
+            },
+        }
+
+        self.touch_now(hash, key);
+        let (_, item) = self.cache.find_mut(hash, key).unwrap();
+        let (value, overflow, refresh) = item.data.counter_mut().unwrap();
+        *value = add(*value, delta, overflow)?;
+        let value = *value;
+        if let Some(ttl) = refresh {
+            self.touch_hashed(hash, key, Some(ttl));
+        }
+        self.changed(hash, key, 0, 0);
+        Ok(value)
+    }
+
+    /// Value of the counter, `None` if key is missing or expired.
+    pub fn counter(&self, key: &str) -> Result<Option<i64>, CounterError> {
+        match self.live(self.hash(key), key) {
+            Some(item) => item.data.counter().map(Some).ok_or(CounterError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// Size of a not expired collection of the kind, `None` if key is missing or expired.
+    fn collection(&self, hash: u64, key: &str, kind: fn(&Value) -> bool) -> Result<Option<usize>, CollectionError> {
+        match self.live(hash, key) {
+            Some(item) if !kind(&item.data) => Err(CollectionError::WrongType),
+            item => Ok(item.map(|item| item.data.len())),
+        }
+    }
+
+    /// Stores a new collection of `len` bytes built by `value`.
+    fn create(
+        &mut self, hash: u64, key: &str, len: usize, ttl: Option<Duration>, value: impl FnOnce(&mut Slabs) -> Value,
+    ) -> Result<(), CollectionError> {
+        let new = self.cache.find(hash, key).is_none();
+        if self.options.key_rules.check(key).is_err() || !self.make_room(len, false, new) {
+            return Err(CollectionError::NotStored)
+        }
+        let ttl = self.lifetime(ttl);
+        let value = value(&mut self.slabs);
+        self.put(hash, key.to_owned(), value, ttl, false, false);
+        Ok(())
+    }
+
+    /// Makes room for `added` bytes of a collection which already has `stored` ones.
+    fn grow(&mut self, hash: u64, key: &str, stored: usize, added: usize) -> Result<(), CollectionError> {
+        // the collection is the newest write, so it is the last one to be displaced making room
+        self.touch_now(hash, key);
+        match stored + added <= self.limit && self.make_room(added, false, false) && self.live(hash, key).is_some() {
+            true => Ok(()),
+            false => Err(CollectionError::NotStored),
+        }
+    }
+
+    /// Accounts bytes added to and freed from a collection, which gets a new version.
+    /// An emptied collection is deleted.
+    fn changed(&mut self, hash: u64, key: &str, added: usize, freed: usize) {
+        self.current_size = self.current_size + added - freed;
+        self.account(key, added, freed);
+        self.stats.written_bytes += added as u64;
+        self.stats.freed_bytes += freed as u64;
+        self.last_version += 1;
+        let (key, item) = self.cache.find_mut(hash, key).unwrap();
+        item.version = self.last_version;
+
+        let (key, drained) = (key.clone(), item.data.is_drained());
+        match drained {
+            true => {
+                if let Some(value) = self.discard(hash, &key, EventKind::Deleted) {
+                    self.slabs.free(value);
+                }
+            },
+            false => {
+                let item = self.cache.find(hash, &key).unwrap();
+                let event = event(&self.slabs, self.clock.now(), EventKind::Set, &key, item);
+                report(&self.listener, &mut self.tx, &event);
+            },
+        }
+    }
+
+    /// Moves an evictable item to the newest end of LRU order.
+    fn touch_now(&mut self, hash: u64, key: &str) {
+        let now = self.clock.now();
+        let (key, item) = match self.cache.find_mut(hash, key) {
+            Some((key, item)) if !item.pinned => (key.clone(), item),
+            _ => return,
+        };
+        let (touch, priority) = (core::mem::replace(&mut item.touch, now), item.priority);
+        self.remove_from_touch(&key, priority, touch);
+        self.add_to_touch(key, priority, now);
+    }
+
+    /// Adds `delta` to a decimal integer value like memcached `incr` and `decr`,
+    /// keeping its ttl, sliding period and pin. The sum gets a new version.
+    /// Returns the sum, `None` if key is missing or expired.
+    pub fn incr(&mut self, key: &str, delta: i64) -> Result<Option<i64>, IncrError> {
+        let dump = match self.dump(key) {
+            Some(dump) => dump,
+            None => return Ok(None),
+        };
+        let sum = core::str::from_utf8(&dump.data).ok()
+            .and_then(|data| data.parse::<i64>().ok())
+            .ok_or(IncrError::NotANumber)?
+            .checked_add(delta)
+            .ok_or(IncrError::Overflow)?;
+
+        self.rewrite(key, sum.to_string().into_bytes(), &dump).map_err(|_| IncrError::NotStored)?;
+        Ok(Some(sum))
+    }
+
+    /// Replaces a value keeping its ttl, sliding period, pin and priority, the new value gets a new version.
+    /// Returns false if key is missing or expired.
+    pub fn replace(&mut self, key: &str, data: Vec<u8>) -> Result<bool, SetError> {
+        match self.dump(key) {
+            Some(dump) => self.rewrite(key, data, &dump).map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    fn rewrite(&mut self, key: &str, data: Vec<u8>, dump: &Dump) -> Result<(), SetError> {
+        let hash = self.hash(key);
+        self.insert(hash, key.to_owned(), data, dump.ttl, false, false)?;
+        let (_, item) = self.cache.find_mut(hash, key).unwrap();
+        item.sliding = dump.sliding;
+        self.set_priority_hashed(hash, key, dump.priority);
+        if dump.pinned {
+            self.pin_hashed(hash, key);
+        }
+        Ok(())
+    }
+
+    /// true if key is present but its ttl and grace period have passed,
+    /// so neither `get` nor `get_stale` return it
+    pub fn is_expired(&self, key: &str) -> bool {
+        self.is_expired_hashed(self.hash(key), key)
+    }
+
+    fn is_expired_hashed(&self, hash: u64, key: &str) -> bool {
+        let now = self.clock.now();
+        let grace = self.options.stale_grace;
+        self.cache.find(hash, key)
+            .and_then(|item| item.ttl)
+            .is_some_and(|ttl| ttl + grace < now)
+    }
+
+    /// Frees expired item without waiting for gc.
+    /// Returns false if key is missing or not expired (e.g. it was set again meanwhile).
+    pub fn remove_expired(&mut self, key: &str) -> bool {
+        let hash = self.hash(key);
+        self.is_expired_hashed(hash, key)
+            && self.discard(hash, key, EventKind::Expired).map(|value| self.slabs.free(value)).is_some()
+    }
+
+    /// Stores the item, its ttl is sliding if [`Options::sliding_ttl`] is set.
+    pub fn set(&mut self, key: String, data: Vec<u8>, ttl: Option<Duration>) -> Result<(), SetError> {
+        let sliding = self.options.sliding_ttl;
+        self.set_with(key, data, ttl, sliding)
+    }
+
+    /// Stores the item, sliding ttl is restarted on every [`Memcached::refresh`].
+    pub fn set_with(&mut self, key: String, data: Vec<u8>, ttl: Option<Duration>, sliding: bool) -> Result<(), SetError> {
+        let hash = self.hash(&key);
+        let ttl = self.lifetime(ttl);
+        self.insert(hash, key, data, ttl, sliding, false)
+    }
+
+    /// Stores a marker telling the key is known to be missing, e.g. in the database behind the cache,
+    /// so it is not looked up there again until `ttl` passes. The marker is not a value:
+    /// `get` doesn't return it, [`Memcached::is_negative`] tells it apart from a miss.
+    pub fn set_negative(&mut self, key: String, ttl: Duration) -> Result<(), SetError> {
+        let hash = self.hash(&key);
+        let ttl = self.lifetime(Some(ttl));
+        self.insert(hash, key, Vec::new(), ttl, false, true)
+    }
+
+    /// true if a not expired marker of [`Memcached::set_negative`] is stored for the key
+    pub fn is_negative(&self, key: &str) -> bool {
+        let now = self.clock.now();
+        self.cache.find(self.hash(key), key)
+            .is_some_and(|item| item.negative && item.ttl.is_none_or(|ttl| ttl >= now))
+    }
+
+    /// Takes the lock if key is missing or expired, storing `owner` as its value.
+    /// Lock is pinned, so it is held until released or its ttl passes, ttl is capped at
+    /// [`Options::max_ttl`] but not jittered.
+    /// Returns fencing token, which is greater than any token issued by the store before,
+    /// or `None` if the lock is held.
+    pub fn acquire_lock(&mut self, key: String, owner: Vec<u8>, ttl: Duration) -> Result<Option<u64>, SetError> {
+        let hash = self.hash(&key);
+        if self.live(hash, &key).is_some() {
+            return Ok(None)
+        }
+
+        let ttl = self.bounded(Some(ttl));
+        self.insert(hash, key.clone(), owner, ttl, false, false)?;
+        self.pin_hashed(hash, &key);
+        Ok(self.live(hash, &key).map(|item| item.version))
+    }
+
+    /// Releases the lock if it is still held with `token`.
+    pub fn release_lock(&mut self, key: &str, token: u64) -> bool {
+        let hash = self.hash(key);
+        self.live(hash, key).is_some_and(|item| item.version == token)
+            && self.delete_hashed(hash, key).is_some()
+    }
+
+    /// Exports a not expired item with its remaining ttl, flags, priority and version.
+    /// Lists, sets and maps are not exported.
+    pub fn dump(&self, key: &str) -> Option<Dump> {
+        let now = self.clock.now();
+        let item = self.value(self.hash(key), key)?;
+
+        Some(Dump {
+            data: self.slabs.get(&item.data).to_vec(),
+            ttl: item.ttl.map(|ttl| ttl - now),
+            sliding: item.sliding,
+            pinned: item.pinned,
+            priority: item.priority,
+            version: item.version,
+        })
+    }
+
+    /// Recreates a dumped item keeping its version unless it is 0, ttl is bounded by
+    /// [`Options::default_ttl`] and [`Options::max_ttl`] but not jittered.
+    /// Versions issued afterwards are greater than the restored one.
+    pub fn restore(&mut self, key: String, dump: Dump) -> Result<(), SetError> {
+        let hash = self.hash(&key);
+        let ttl = self.bounded(dump.ttl);
+        self.insert(hash, key.clone(), dump.data, ttl, false, false)?;
+        self.restored(hash, &key, dump.sliding, dump.version, dump.priority, dump.pinned);
+        Ok(())
+    }
+
+    /// Like [`Memcached::dump`], but of any kind of not expired item, negative markers included.
+    /// Data of the dump is empty unless contents are [`Contents::Plain`].
+    pub fn snapshot(&self, key: &str) -> Option<(Dump, Contents)> {
+        let now = self.clock.now();
+        let item = self.cache.find(self.hash(key), key)
+            .filter(|item| item.ttl.is_none_or(|ttl| ttl >= now))?;
+        let contents = match &item.data {
+            _ if item.negative => Contents::Negative,
+            Value::Heap(_) | Value::Slab { .. } => Contents::Plain,
+            Value::List { items, .. } => Contents::List(items.iter().cloned().collect()),
+            Value::Set { members, .. } => Contents::Set(members.iter().cloned().collect()),
+            Value::Map { fields, .. } => {
+                Contents::Map(fields.iter().map(|(field, value)| (field.clone(), value.clone())).collect())
+            },
+            Value::Counter { value, overflow, refresh } => {
+                Contents::Counter { value: *value, overflow: *overflow, refresh: *refresh }
+            },
+        };
+
+        let dump = Dump {
+            data: self.slabs.get(&item.data).to_vec(),
+            ttl: item.ttl.map(|ttl| ttl - now),
+            sliding: item.sliding,
+            pinned: item.pinned,
+            priority: item.priority,
+            version: item.version,
+        };
+        Some((dump, contents))
+    }
+
+    /// Recreates an item of [`Memcached::snapshot`] like [`Memcached::restore`] does.
+    /// Empty collections are not stored.
+    pub fn restore_snapshot(&mut self, key: String, dump: Dump, contents: Contents) -> Result<(), SetError> {
+        let hash = self.hash(&key);
+        let ttl = self.bounded(dump.ttl);
+        let (len, value) = match contents {
+            Contents::Plain => return self.restore(key, dump),
+            Contents::Negative => {
+                self.insert(hash, key.clone(), Vec::new(), ttl, false, true)?;
+                self.restored(hash, &key, None, dump.version, dump.priority, dump.pinned);
+                return Ok(())
+            },
+            Contents::List(items) => {
+                let len = items.iter().map(|data| element_len(data)).sum();
+                (len, Value::List { items: items.into(), len })
+            },
+            // collected before being measured, so repeated members and fields count once
+            Contents::Set(members) => {
+                let members: BTreeSet<Vec<u8>> = members.into_iter().collect();
+                let len = members.iter().map(|member| element_len(member)).sum();
+                (len, Value::Set { members, len })
+            },
+            Contents::Map(fields) => {
+                let fields: BTreeMap<Vec<u8>, Vec<u8>> = fields.into_iter().collect();
+                let len = fields.iter().map(|(field, value)| element_len(field) + element_len(value)).sum();
+                (len, Value::Map { fields, len })
+            },
+            Contents::Counter { value, overflow, refresh } => (COUNTER_LEN, Value::Counter { value, overflow, refresh }),
+        };
+
+        let new = self.cache.find(hash, &key).is_none();
+        if value.is_drained() || self.options.key_rules.check(&key).is_err() || !self.make_room(len, false, new) {
+            return Err(SetError(key, dump.data))
+        }
+        let value = self.slabs.attach(value);
+        self.put(hash, key.clone(), value, ttl, false, false);
+        self.restored(hash, &key, dump.sliding, dump.version, dump.priority, dump.pinned);
+        Ok(())
+    }
+
+    /// Gives a just restored item its sliding ttl, version, priority and pin.
+    fn restored(&mut self, hash: u64, key: &str, sliding: Option<Duration>, version: u64, priority: Priority, pinned: bool) {
+        let sliding = sliding.map(|sliding| self.options.max_ttl.map_or(sliding, |max| sliding.min(max)));
+        let (_, item) = self.cache.find_mut(hash, key).unwrap();
+        item.sliding = sliding;
+        if version != 0 {
+            item.version = version;
+            self.last_version = self.last_version.max(version);
+        }
+        self.set_priority_hashed(hash, key, priority);
+        if pinned {
+            self.pin_hashed(hash, key);
+        }
+    }
+
+    /// `hash` is the hash of `key` by [`Memcached::hash`]
+    fn insert(
+        &mut self, hash: u64, key: String, data: Vec<u8>, ttl: Option<Duration>, sliding: bool, negative: bool,
+    ) -> Result<(), SetError> {
+        let new = self.cache.find(hash, &key).is_none();
+        if self.options.key_rules.check(&key).is_err() || !self.make_room(self.slabs.size_for(data.len()), true, new) {
+            return Err(SetError(key, data))
+        }
+        let data = self.slabs.store(data);
+        self.put(hash, key, data, ttl, sliding, negative);
+        Ok(())
+    }
+
+    /// Collects garbage and displaces oldest items until `size` more bytes fit,
+    /// and one more item if the key is `new` to the store. Returns false if they don't fit even then.
+    fn make_room(&mut self, size: usize, plain: bool, new: bool) -> bool {
+        // value can't fit even into an empty cache, so evicting anything would be pointless
+        if size.max(self.slabs.growth(size, plain)) > self.limit || (new && self.options.max_items == Some(0)) {
+            return false
+        }
+
+        // whole slab pages count against the limit, a free chunk of the class needs no new one
+        let not_enough_space = |mc: &Self| {
+            (mc.slabs.reserved() + mc.slabs.growth(size, plain)) > mc.limit
+                || (new && mc.options.max_items.is_some_and(|max| mc.cache.len() >= max))
+        };
+
+        if not_enough_space(self) {
+            self.collect_garbage()
+        }
+
+        while not_enough_space(self) && self.remove_oldest() {
+            debug!("oldest key displaced: reserved {}", self.slabs.reserved());
+        }
+
+        !not_enough_space(self)
+    }
+
+    /// Stores an item replacing the previous one, room for it must be made already.
+    fn put(&mut self, hash: u64, key: String, data: Value, ttl: Option<Duration>, sliding: bool, negative: bool) {
+        self.leases.take(hash, &key);
+        if let Some(cold) = &mut self.cold {
+            cold.forget(&key);
+        }
+
+        // overwrite is reported as set only
+        if let Some((_, item)) = self.remove(hash, &key) {
+            self.stats.freed_bytes += item.data.len() as u64;
+            self.slabs.free(item.data);
+        }
+
+        let touch = self.clock.now();
+        if let (Some(ttl), false) = (ttl, negative) {
+            self.stats.ttls[bucket(&TTL_BOUNDS, &ttl)] += 1;
+            self.stats.ttl_sum += ttl;
+        }
+        if !negative {
+            self.stats.sizes[bucket(&SIZE_BOUNDS, &data.len())] += 1;
+            self.stats.size_sum += data.len() as u64;
+        }
+        let sliding = ttl.filter(|_| sliding);
+        let ttl = ttl.map(|ttl| touch + ttl);
+
+        let key = Key::from(key);
+        self.add_to_touch(key.clone(), Priority::Normal, touch);
+
+        let grace = self.options.stale_grace;
+        let timer = ttl.map(|ttl| self.keys_by_ttl.insert(key.clone(), ttl + grace));
+
+        let size = self.slabs.size_of(&data);
+        self.current_size += size;
+        self.account(&key, size, 0);
+        self.stats.written_bytes += data.len() as u64;
+        self.last_version += 1;
+        let version = self.last_version;
+
+        let item = Item {
+            touch, ttl, sliding, version, negative, timer, data,
+            written: touch,
+            pinned: false,
+            priority: Priority::Normal,
+            revalidating: false,
+        };
+        self.notify(EventKind::Set, &key, &item);
+        self.cache.put(hash, key, item);
+        if let Some(filter) = &self.filter {
+            filter.add(hash);
+        }
+    }
+
+    /// Stores the item and returns the previous value if it was not expired.
+    /// On error the previous value stays in place.
+    pub fn getset(&mut self, key: String, data: Vec<u8>, ttl: Option<Duration>) -> Result<Option<Vec<u8>>, SetError> {
+        let sliding = self.options.sliding_ttl;
+        self.getset_with(key, data, ttl, sliding)
+    }
+
+    pub fn getset_with(&mut self, key: String, data: Vec<u8>, ttl: Option<Duration>, sliding: bool) -> Result<Option<Vec<u8>>, SetError> {
+        let hash = self.hash(&key);
+        let previous = self.value(hash, &key).map(|item| self.slabs.get(&item.data).to_vec());
+        let ttl = self.lifetime(ttl);
+        self.insert(hash, key, data, ttl, sliding, false)?;
+        Ok(previous)
+    }
+
+    pub fn collect_garbage(&mut self) {
+        self.collect_garbage_step(&GcBudget::default());
+    }
+
+    /// Collects expired items until budget is exhausted.
+    /// Returns true if nothing expired is left, otherwise next call resumes from the oldest leftover.
+    pub fn collect_garbage_step(&mut self, budget: &GcBudget) -> bool {
+        self.sweep(budget).done
+    }
+
+    /// [`Memcached::collect_garbage_step`] reporting what it collected.
+    pub fn sweep(&mut self, budget: &GcBudget) -> GcReport {
+        let now = self.clock.now();
+        let size_before = self.current_size;
+        let mut removed = 0;
+        self.leases.retain(|_, lease| lease.until >= now);
+
+        let done = loop {
+            if budget.is_exhausted(removed, self.clock.now() - now) {
+                break false
+            }
+
+            let key = match self.keys_by_ttl.next_expired(now) {
+                Some(key) => key,
+                None => break true,
+            };
+            let hash = self.hash(&key);
+            if let Some(value) = self.discard(hash, &key, EventKind::Expired) {
+                self.slabs.free(value);
+            }
+            removed += 1;
+        };
+
+        let memory_retrieved = size_before - self.current_size;
+        if memory_retrieved != 0 {
+            debug!("gc retrieved {}B in {:?}", memory_retrieved, self.clock.now() - now);
+        }
+
+        GcReport { removed, reclaimed: memory_retrieved, done }
+    }
+
+    /// true if usage crossed [`Options::watermarks`] high mark, so [`Memcached::evict_step`] should run
+    pub fn is_above_high_watermark(&self) -> bool {
+        self.options.watermarks
+            .is_some_and(|marks| self.slabs.reserved() as f64 > self.limit as f64 * marks.high)
+    }
+
+    /// Displaces oldest items until usage is down to low watermark or budget is exhausted.
+    /// Returns true if usage is at low watermark (or watermarks are not set).
+    pub fn evict_step(&mut self, budget: &GcBudget) -> bool {
+        let target = match self.options.watermarks {
+            Some(marks) => (self.limit as f64 * marks.low) as usize,
+            None => return true,
+        };
+        let now = self.clock.now();
+        let mut removed = 0;
+
+        while self.slabs.reserved() > target {
+            if budget.is_exhausted(removed, self.clock.now() - now) {
+                return false
+            }
+            if !self.remove_oldest() {
+                break
+            }
+            removed += 1;
+        }
+
+        if removed != 0 {
+            debug!("{} oldest keys evicted down to low watermark in {:?}", removed, self.clock.now() - now);
+        }
+        true
+    }
+}
+
+impl<C: Clock> Memcached<C> {
+    /// ttl a written item gets: the given one or [`Options::default_ttl`], capped at [`Options::max_ttl`]
+    fn bounded(&self, ttl: Option<Duration>) -> Option<Duration> {
+        let ttl = ttl.or(self.options.default_ttl);
+        match (ttl, self.options.max_ttl) {
+            (Some(ttl), Some(max)) => Some(ttl.min(max)),
+            (ttl, max) => ttl.or(max),
+        }
+    }
+
+    /// [`Memcached::bounded`] ttl with jitter
+    fn lifetime(&mut self, ttl: Option<Duration>) -> Option<Duration> {
+        self.bounded(ttl).map(|ttl| self.jittered(ttl))
+    }
+
+    fn jittered(&mut self, ttl: Duration) -> Duration {
+        match self.options.ttl_jitter {
+            Some(jitter) => {
+                let random = self.next_random();
+                jitter.apply(ttl, random)
+            },
+            None => ttl,
+        }
+    }
+
+    fn next_random(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+
+    /// Reports the mutation to listener, or holds it back while a transaction is open.
+    fn notify(&mut self, kind: EventKind, key: &str, item: &Item) {
+        let event = event(&self.slabs, self.clock.now(), kind, key, item);
+        report(&self.listener, &mut self.tx, &event);
+    }
+
+    fn add_to_touch(&mut self, key: Key, priority: Priority, touch: Timestamp) {
+        let mut new_keys_by_touch = self.keys_by_touch
+            .remove(&(priority, touch)).unwrap_or_else(|| Vec::with_capacity(1));
+        new_keys_by_touch.push(key);
+        self.keys_by_touch.insert((priority, touch), new_keys_by_touch);
+    }
+
+    fn remove_from_touch(&mut self, key: &str, priority: Priority, touch: Timestamp) {
+        let mut keys = self.keys_by_touch.remove(&(priority, touch)).unwrap();
+        keys.retain(|k| &**k != key);
+        if !keys.is_empty() {
+            self.keys_by_touch.insert((priority, touch), keys);
+        }
+    }
+
+    fn remove_oldest(&mut self) -> bool {
+        let key = match self.keys_by_touch.iter().next() {
+            Some((_, keys)) => keys.first().cloned()
+                .expect("empty vec in keys_by_touch (impossibre)"),
+            None => return false,
+        };
+
+        let hash = self.hash(&key);
+        if let (Some(cold), Some(item)) = (&mut self.cold, self.cache.find(hash, &key)) {
+            if !item.negative && item.data.is_plain() {
+                cold.spill(&key, self.slabs.get(&item.data), item.ttl);
+            }
+        }
+        match self.discard(hash, &key, EventKind::Evicted) {
+            Some(value) => {
+                self.slabs.free(value);
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+/// index of the first bucket which bound is not below `value`
+/// `deadline` moved back to `max_ttl` after `written`, if it is set
+fn capped(max_ttl: Option<Duration>, written: Timestamp, deadline: Timestamp) -> Timestamp {
+    max_ttl.map_or(deadline, |max| deadline.min(written + max))
+}
+
+fn bucket<T: PartialOrd>(bounds: &[T], value: &T) -> usize {
+    bounds.partition_point(|bound| bound < value)
+}
+
+/// `value` changed by `delta` as the overflow policy of a counter tells
+fn add(value: i64, delta: i64, overflow: Overflow) -> Result<i64, CounterError> {
+    match overflow {
+        Overflow::Error => value.checked_add(delta).ok_or(CounterError::Overflow),
+        Overflow::Saturate => Ok(value.saturating_add(delta)),
+        Overflow::Wrap => Ok(value.wrapping_add(delta)),
+    }
+}
+
+/// mutation of the item as listener sees it
+fn event<'a>(slabs: &'a Slabs, at: Timestamp, kind: EventKind, key: &'a str, item: &'a Item) -> Event<'a> {
+    Event {
+        kind, key, at,
+        size: item.data.len(),
+        data: slabs.get(&item.data),
+        negative: item.negative,
+        collection: !item.data.is_plain(),
+        ttl: item.ttl,
+        stored_at: item.touch,
+        priority: item.priority,
+    }
+}
+
+/// passes the event to listener unless a transaction holds it back
+fn report(listener: &Option<Listener>, tx: &mut Option<Tx>, event: &Event) {
+    if Tx::hold(tx, event) {
+        return
+    }
+    if let Some(listener) = listener {
+        listener(event);
+    }
+}
+
+#[cfg(test)]
+mod public_tests {
+    use super::*;
+    use alloc::rc::Rc;
+
+    fn new_mc(limit: usize) -> (Memcached<Rc<ManualClock>>, Rc<ManualClock>) {
+        let clock = Rc::new(ManualClock::default());
+        (Memcached::new(limit, clock.clone()), clock)
+    }
+
+    #[test]
+    fn set_get_ok() {
+        let (mut mc, _) = new_mc(300);
+        let _ = mc.set("a".to_owned(), "a".as_bytes().to_owned(), None);
+        assert_eq!(mc.get("a"), Some("a".into()));
+    }
+
+    #[test]
+    fn dump_restore() {
+        let (mut mc, clock) = new_mc(300);
+        for _ in 0..3 {
+            let _ = mc.set_with("a".to_owned(), "a".as_bytes().to_owned(), Some(Duration::from_millis(100)), true);
+        }
+        mc.pin("a");
+        clock.advance(Duration::from_millis(40));
+
+        let dump = mc.dump("a").unwrap();
+        assert_eq!(dump.ttl, Some(Duration::from_millis(60)));
+        assert_eq!(dump.sliding, Some(Duration::from_millis(100)));
+        assert!(dump.pinned);
+
+        let (mut other, _) = new_mc(300);
+        assert!(other.restore("a".to_owned(), dump.clone()).is_ok());
+        assert_eq!(other.dump("a"), Some(dump.clone()));
+
+        let _ = other.set("b".to_owned(), "b".as_bytes().to_owned(), None);
+        assert!(other.version("b") > Some(dump.version));
+
+        assert!(other.restore("c".to_owned(), Dump { version: 0, ..dump.clone() }).is_ok());
+        assert!(other.version("c") > other.version("b"));
+
+        clock.advance(Duration::from_millis(100));
+        assert_eq!(mc.dump("a"), None);
+    }
+
+    #[test]
+    fn iter_skips_expired() {
+        let (mut mc, clock) = new_mc(300);
+        let _ = mc.set("a".to_owned(), "1".as_bytes().to_owned(), Some(Duration::from_millis(100)));
+        let _ = mc.set("b".to_owned(), "2".as_bytes().to_owned(), None);
+
+        let mut items: Vec<_> = mc.iter().collect();
+        items.sort();
+        assert_eq!(items, vec![("a", "1".as_bytes()), ("b", "2".as_bytes())]);
+
+        clock.advance(Duration::from_millis(200));
+
+        assert_eq!(mc.iter().collect::<Vec<_>>(), vec![("b", "2".as_bytes())]);
+        assert_eq!(mc.keys().count(), 2);
+    }
+
+    #[test]
+    fn get_none() {
+        let (mc, _) = new_mc(300);
+        assert_eq!(mc.get("b"), None);
+    }
+
+    #[test]
+    fn displace_oldest() {
+        let (mut mc, clock) = new_mc(3);
+        for key in &["a", "b", "c", "d"] {
+            let _ = mc.set(key.to_string(), "a".as_bytes().to_owned(), None);
+            clock.advance(Duration::from_millis(1));
+        }
+
+        assert_eq!(mc.get("a"), None);
+        assert_eq!(mc.get("b"), Some("a".into()));
+        assert_eq!(mc.get("c"), Some("a".into()));
+        assert_eq!(mc.get("d"), Some("a".into()));
+    }
+
+    #[test]
+    fn displace_oldest_same_tick() {
+        let (mut mc, _) = new_mc(3);
+        let _ = mc.set("a".to_owned(), "a".as_bytes().to_owned(), None);
+        let _ = mc.set("b".to_owned(), "a".as_bytes().to_owned(), None);
+        let _ = mc.set("c".to_owned(), "a".as_bytes().to_owned(), None);
+        let _ = mc.set("d".to_owned(), "a".as_bytes().to_owned(), None);
+
+        assert_eq!(mc.get("a"), None);
+        assert_eq!(mc.get("d"), Some("a".into()));
+    }
+
+    #[test]
+    fn expire() {
+        let (mut mc, clock) = new_mc(300);
+        let _ = mc.set("a".to_owned(), "a".as_bytes().to_owned(), Some(Duration::from_millis(100)));
+        assert_eq!(mc.get("a"), Some("a".into()));
+
+        clock.advance(Duration::from_millis(100));
+        assert_eq!(mc.get("a"), Some("a".into()));
+
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(mc.get("a"), None);
+    }
+
+    #[test]
+    fn siphash_keys() {
+        let options = Options { hasher: KeyHasher::SipHash, ..Options::default() };
+        let mut mc = Memcached::with_options(300, ManualClock::default(), options);
+        let _ = mc.set("a".to_owned(), "a".as_bytes().to_owned(), None);
+        let token = mc.lease("b", Duration::from_secs(1)).unwrap();
+        assert!(mc.holds_lease("b", token));
+        assert_eq!(mc.get("a"), Some("a".into()));
+
+        let _ = mc.set("b".to_owned(), "b".as_bytes().to_owned(), None);
+        assert!(!mc.holds_lease("b", token));
+        assert_eq!(mc.delete("a"), Some("a".into()));
+        assert_eq!(mc.keys().collect::<Vec<_>>(), vec!["b"]);
+    }
+
+    #[test]
+    fn slabs() {
+        let slabs = SlabConfig { page_size: 1024, min_chunk: 64, growth_factor: 2.0 };
+        let options = Options { slabs: Some(slabs), ..Options::default() };
+        let mut mc = Memcached::with_options(4096, ManualClock::default(), options);
+
+        let _ = mc.set("a".to_owned(), vec![1; 10], None);
+        let _ = mc.set("b".to_owned(), vec![2; 100], None);
+        let _ = mc.set("c".to_owned(), vec![3; 2000], None);
+        assert_eq!(mc.get("a"), Some(vec![1; 10]));
+        assert_eq!(mc.get("b"), Some(vec![2; 100]));
+        assert_eq!(mc.get("c"), Some(vec![3; 2000]));
+        // chunks of 64 and 128 bytes, the value larger than a page is stored as is
+        assert_eq!(mc.size(), 64 + 128 + 2000);
+        assert_eq!(mc.reserved(), 1024 + 1024 + 2000);
+
+        let _ = mc.set("d".to_owned(), vec![4; 20], None);
+        assert_eq!(mc.reserved(), 1024 + 1024 + 2000);
+
+        assert_eq!(mc.delete("a"), Some(vec![1; 10]));
+        assert_eq!(mc.delete("b"), Some(vec![2; 100]));
+        assert_eq!(mc.delete("c"), Some(vec![3; 2000]));
+        assert_eq!(mc.size(), 64);
+        assert_eq!(mc.reserved(), 1024);
+        assert_eq!(mc.get("d"), Some(vec![4; 20]));
+
+        // four chunks of 1024 bytes fill the limit
+        for key in ["e", "f", "g", "h"].iter() {
+            let _ = mc.set(key.to_string(), vec![5; 600], None);
+        }
+        assert_eq!(mc.get("d"), None);
+        assert_eq!(mc.size(), 4096);
+    }
+
+    #[test]
+    fn key_filter() {
+        let options = Options { key_filter: Some(100), ..Options::default() };
+        let (clock, ttl) = (Rc::new(ManualClock::default()), Duration::from_millis(100));
+        let mut mc = Memcached::with_options(300, clock.clone(), options);
+        let filter = mc.key_filter().unwrap();
+
+        let _ = mc.set("a".to_owned(), "a".as_bytes().to_owned(), Some(ttl));
+        let _ = mc.set("a".to_owned(), "b".as_bytes().to_owned(), Some(ttl));
+        let _ = mc.set("b".to_owned(), "b".as_bytes().to_owned(), None);
+        assert!(filter.may_contain("a"));
+        assert!(filter.may_contain("b"));
+        let misses = (0..100).filter(|i| !filter.may_contain(&format!("missing {}", i))).count();
+        assert!(misses > 90, "{} definite misses", misses);
+
+        mc.delete("b");
+        assert!(!filter.may_contain("b"));
+        clock.advance(ttl * 2);
+        mc.collect_garbage();
+        assert!(!filter.may_contain("a"));
+    }
+
+    #[test]
+    fn negative() {
+        let (mut mc, clock) = new_mc(300);
+        let token = mc.lease("a", Duration::from_secs(1)).unwrap();
+        assert!(mc.set_negative("a".to_owned(), Duration::from_millis(100)).is_ok());
+        assert!(!mc.holds_lease("a", token));
+        assert!(mc.is_negative("a"));
+        assert_eq!(mc.get("a"), None);
+        assert_eq!(mc.dump("a"), None);
+        assert_eq!(mc.iter().count(), 0);
+        assert!(!mc.pin("a"));
+
+        clock.advance(Duration::from_millis(101));
+        assert!(!mc.is_negative("a"));
+
+        assert!(mc.set_negative("b".to_owned(), Duration::from_millis(100)).is_ok());
+        let _ = mc.set("b".to_owned(), "b".as_bytes().to_owned(), None);
+        assert!(!mc.is_negative("b"));
+        assert_eq!(mc.get("b"), Some("b".into()));
+    }
+
+    #[test]
+    fn ttl_jitter() {
+        let clock = Rc::new(ManualClock::default());
+        let options = Options { ttl_jitter: Some(TtlJitter::Ratio(0.5)), ..Options::default() };
+        let mut mc = Memcached::with_options(300, clock.clone(), options);
+        let keys: Vec<String> = (0..100).map(|i| i.to_string()).collect();
+        for key in &keys {
+            let _ = mc.set(key.clone(), "a".as_bytes().to_owned(), Some(Duration::from_millis(100)));
+        }
+        let alive = |mc: &Memcached<_>| keys.iter().filter(|key| mc.get(key).is_some()).count();
+
+        clock.advance(Duration::from_millis(49));
+        assert_eq!(alive(&mc), 100);
+
+        clock.advance(Duration::from_millis(26));
+        let half_way = alive(&mc);
+        assert!(half_way > 20 && half_way < 80, "{} keys alive", half_way);
+
+        clock.advance(Duration::from_millis(26));
+        assert_eq!(alive(&mc), 0);
+    }
+
+    #[test]
+    fn ttl_jitter_absolute() {
+        let jitter = TtlJitter::Absolute(Duration::from_secs(10));
+        assert_eq!(jitter.apply(Duration::from_secs(20), 0), Duration::from_secs(20));
+        assert_eq!(jitter.apply(Duration::from_secs(20), 10_000_000_000), Duration::from_secs(10));
+        assert_eq!(jitter.apply(Duration::from_secs(5), 9_000_000_000), Duration::from_secs(0));
+        assert!(jitter.apply(Duration::from_secs(20), u64::MAX) >= Duration::from_secs(10));
+    }
+
+    #[test]
+    fn sliding_ttl() {
+        let (mut mc, clock) = new_mc(300);
+        let _ = mc.set_with("a".to_owned(), "a".as_bytes().to_owned(), Some(Duration::from_millis(100)), true);
+        let _ = mc.set_with("b".to_owned(), "b".as_bytes().to_owned(), Some(Duration::from_millis(100)), false);
+        assert!(mc.is_sliding("a"));
+        assert!(!mc.is_sliding("b"));
+
+        clock.advance(Duration::from_millis(80));
+        assert!(mc.refresh("a"));
+        assert!(!mc.refresh("b"));
+
+        clock.advance(Duration::from_millis(80));
+        mc.collect_garbage();
+        assert_eq!(mc.get("a"), Some("a".as_bytes().to_owned()));
+        assert_eq!(mc.get("b"), None);
+
+        clock.advance(Duration::from_millis(30));
+        assert!(!mc.refresh("a"));
+        mc.collect_garbage();
+        assert_eq!(mc.len(), 0);
+    }
+
+    #[test]
+    fn stale_grace() {
+        let clock = Rc::new(ManualClock::default());
+        let options = Options { stale_grace: Duration::from_millis(50), ..Options::default() };
+        let mut mc = Memcached::with_options(300, clock.clone(), options);
+        let data = "a".as_bytes().to_owned();
+        let _ = mc.set("a".to_owned(), data.clone(), Some(Duration::from_millis(100)));
+
+        assert_eq!(mc.get_stale("a"), Some((data.clone(), Freshness::Fresh)));
+
+        clock.advance(Duration::from_millis(120));
+        mc.collect_garbage();
+        assert_eq!(mc.get("a"), None);
+        assert!(!mc.is_expired("a"));
+        assert_eq!(mc.get_stale("a"), Some((data.clone(), Freshness::Stale { revalidate: true })));
+        assert_eq!(mc.get_stale("a"), Some((data, Freshness::Stale { revalidate: false })));
+
+        clock.advance(Duration::from_millis(40));
+        assert_eq!(mc.get_stale("a"), None);
+        mc.collect_garbage();
+        assert_eq!(mc.len(), 0);
+    }
+
+    #[test]
+    fn getset() {
+        let (mut mc, clock) = new_mc(3);
+
+        assert_eq!(mc.getset("a".to_owned(), "a".as_bytes().to_owned(), Some(Duration::from_millis(10))).ok(), Some(None));
+        assert_eq!(mc.getset("a".to_owned(), "b".as_bytes().to_owned(), None).ok(), Some(Some("a".as_bytes().to_owned())));
+        assert!(mc.getset("a".to_owned(), "cccc".as_bytes().to_owned(), None).is_err());
+        assert_eq!(mc.get("a"), Some("b".as_bytes().to_owned()));
+
+        let _ = mc.set("b".to_owned(), "b".as_bytes().to_owned(), Some(Duration::from_millis(10)));
+        clock.advance(Duration::from_millis(20));
+        assert_eq!(mc.getset("b".to_owned(), "c".as_bytes().to_owned(), None).ok(), Some(None));
+    }
+
+    #[test]
+    fn gat() {
+        let (mut mc, clock) = new_mc(300);
+        let _ = mc.set("a".to_owned(), "a".as_bytes().to_owned(), Some(Duration::from_millis(10)));
+        let _ = mc.set("b".to_owned(), "b".as_bytes().to_owned(), None);
+
+        assert_eq!(mc.gat("a", None), Some("a".as_bytes().to_owned()));
+        assert_eq!(mc.gat("b", Some(Duration::from_millis(10))), Some("b".as_bytes().to_owned()));
+        assert_eq!(mc.gat("c", None), None);
+
+        clock.advance(Duration::from_millis(20));
+        mc.collect_garbage();
+        assert_eq!(mc.get("a"), Some("a".as_bytes().to_owned()));
+        assert_eq!(mc.get("b"), None);
+        assert!(!mc.touch("b", None));
+        assert_eq!(mc.keys_by_ttl.len(), 0);
+    }
+
+    #[test]
+    fn incr() {
+        let (mut mc, clock) = new_mc(300);
+        let _ = mc.set("a".to_owned(), "9".as_bytes().to_owned(), Some(Duration::from_millis(10)));
+        let _ = mc.set("b".to_owned(), "b".as_bytes().to_owned(), None);
+        let _ = mc.set("max".to_owned(), i64::MAX.to_string().into_bytes(), None);
+        mc.pin("a");
+        let version = mc.version("a");
+
+        assert_eq!(mc.incr("a", 1), Ok(Some(10)));
+        assert_eq!(mc.get("a"), Some("10".as_bytes().to_owned()));
+        assert_ne!(mc.version("a"), version);
+        assert!(mc.is_pinned("a"));
+        assert_eq!(mc.incr("a", -15), Ok(Some(-5)));
+        assert_eq!(mc.incr("b", 1), Err(IncrError::NotANumber));
+        assert_eq!(mc.incr("max", 1), Err(IncrError::Overflow));
+        assert_eq!(mc.get("max"), Some(i64::MAX.to_string().into_bytes()));
+        assert_eq!(mc.incr("c", 1), Ok(None));
+
+        clock.advance(Duration::from_millis(20));
+        assert_eq!(mc.incr("a", 1), Ok(None));
+    }
+
+    #[test]
+    fn replace() {
+        let (mut mc, clock) = new_mc(300);
+        let _ = mc.set("a".to_owned(), "a".as_bytes().to_owned(), Some(Duration::from_millis(10)));
+        let version = mc.version("a");
+
+        assert_eq!(mc.replace("a", "b".as_bytes().to_owned()).ok(), Some(true));
+        assert_eq!(mc.get("a"), Some("b".as_bytes().to_owned()));
+        assert_ne!(mc.version("a"), version);
+        assert_eq!(mc.replace("c", "c".as_bytes().to_owned()).ok(), Some(false));
+        assert_eq!(mc.get("c"), None);
+
+        clock.advance(Duration::from_millis(20));
+        assert_eq!(mc.replace("a", "c".as_bytes().to_owned()).ok(), Some(false));
+    }
+
+    #[test]
+    fn lists() {
+        let (mut mc, clock) = new_mc(300);
+        let values = |values: &[&str]| values.iter().map(|value| value.as_bytes().to_vec()).collect::<Vec<_>>();
+
+        assert_eq!(mc.push("l", End::Back, values(&["b", "c"]), Some(Duration::from_millis(10))), Ok(2));
+        assert_eq!(mc.push("l", End::Front, values(&["a", "z"]), None), Ok(4));
+        assert_eq!(mc.range("l", 0, -1), Ok(Some(values(&["z", "a", "b", "c"]))));
+        assert_eq!(mc.range("l", -2, 10), Ok(Some(values(&["b", "c"]))));
+        assert_eq!(mc.range("l", 3, 1), Ok(Some(Vec::new())));
+        assert_eq!(mc.range("missing", 0, -1), Ok(None));
+        assert_eq!(mc.get("l"), None);
+
+        assert_eq!(mc.pop("l", End::Front, 1), Ok(Some(values(&["z"]))));
+        assert_eq!(mc.pop("l", End::Back, 2), Ok(Some(values(&["c", "b"]))));
+        assert_eq!(mc.pop("missing", End::Back, 1), Ok(None));
+
+        let _ = mc.set("plain".to_owned(), "a".as_bytes().to_owned(), None);
+        assert_eq!(mc.push("plain", End::Back, values(&["a"]), None), Err(CollectionError::WrongType));
+        assert_eq!(mc.pop("plain", End::Back, 1), Err(CollectionError::WrongType));
+        assert_eq!(mc.range("plain", 0, -1), Err(CollectionError::WrongType));
+
+        // pushing keeps the ttl of the list
+        clock.advance(Duration::from_millis(20));
+        assert_eq!(mc.range("l", 0, -1), Ok(None));
+        mc.collect_garbage();
+        assert_eq!(mc.len(), 1);
+
+        assert_eq!(mc.push("empty", End::Back, Vec::new(), None), Err(CollectionError::Empty));
+
+        // popping the last value deletes the list
+        assert_eq!(mc.push("l", End::Back, values(&["a"]), None), Ok(1));
+        assert_eq!(mc.pop("l", End::Back, 5), Ok(Some(values(&["a"]))));
+        assert_eq!(mc.range("l", 0, -1), Ok(None));
+    }
+
+    #[test]
+    fn lists_are_evicted() {
+        // every element is accounted with the header of its allocation
+        let element = 4 + size_of::<Vec<u8>>();
+        let (mut mc, clock) = new_mc(2 * element + 3);
+        assert_eq!(mc.push("l", End::Back, vec![b"aaaa".to_vec()], None), Ok(1));
+        clock.advance(Duration::from_millis(1));
+        let _ = mc.set("a".to_owned(), "aaaa".as_bytes().to_owned(), None);
+        clock.advance(Duration::from_millis(1));
+
+        // the pushed list is the newest write, so the plain value makes room
+        assert_eq!(mc.push("l", End::Back, vec![b"bbbb".to_vec()], None), Ok(2));
+        assert_eq!(mc.get("a"), None);
+        assert_eq!(mc.push("l", End::Back, vec![b"cccc".to_vec()], None), Err(CollectionError::NotStored));
+        assert_eq!(mc.range("l", 0, -1), Ok(Some(vec![b"aaaa".to_vec(), b"bbbb".to_vec()])));
+        assert_eq!(mc.size(), 2 * element);
+
+        assert_eq!(mc.pop("l", End::Front, 1), Ok(Some(vec![b"aaaa".to_vec()])));
+        assert_eq!(mc.size(), element);
+        let _ = mc.set("a".to_owned(), "a".repeat(element + 4).into_bytes(), None);
+        assert_eq!(mc.range("l", 0, -1), Ok(None));
+    }
+
+    #[test]
+    fn sets() {
+        let (mut mc, clock) = new_mc(300);
+        let members = |members: &[&str]| members.iter().map(|member| member.as_bytes().to_vec()).collect::<Vec<_>>();
+
+        assert_eq!(mc.add_members("s", members(&["b", "a", "b"]), Some(Duration::from_millis(10))), Ok(2));
+        assert_eq!(mc.add_members("s", members(&["a", "c"]), None), Ok(1));
+        assert_eq!(mc.members("s"), Ok(Some(members(&["a", "b", "c"]))));
+        let header = size_of::<Vec<u8>>();
+        assert_eq!(mc.size(), 3 + 3 * header);
+        assert_eq!(mc.is_member("s", b"a"), Ok(true));
+        assert_eq!(mc.is_member("s", b"z"), Ok(false));
+        assert_eq!(mc.is_member("missing", b"a"), Ok(false));
+        assert_eq!(mc.get("s"), None);
+
+        assert_eq!(mc.remove_members("s", &members(&["a", "z"])), Ok(Some(1)));
+        assert_eq!(mc.size(), 2 + 2 * header);
+        assert_eq!(mc.add_members("empty", Vec::new(), None), Err(CollectionError::Empty));
+        assert_eq!(mc.remove_members("missing", &members(&["a"])), Ok(None));
+
+        let _ = mc.push("l", End::Back, members(&["a"]), None);
+        assert_eq!(mc.add_members("l", members(&["a"]), None), Err(CollectionError::WrongType));
+        assert_eq!(mc.is_member("l", b"a"), Err(CollectionError::WrongType));
+        assert_eq!(mc.push("s", End::Back, members(&["a"]), None), Err(CollectionError::WrongType));
+
+        // removing the last member deletes the set
+        assert_eq!(mc.remove_members("s", &members(&["b", "c"])), Ok(Some(2)));
+        assert_eq!(mc.members("s"), Ok(None));
+
+        assert_eq!(mc.add_members("s", members(&["a"]), Some(Duration::from_millis(10))), Ok(1));
+        clock.advance(Duration::from_millis(20));
+        assert_eq!(mc.members("s"), Ok(None));
+    }
+
+    #[test]
+    fn maps() {
+        let (mut mc, clock) = new_mc(300);
+        let field = |field: &str, value: &str| (field.as_bytes().to_vec(), value.as_bytes().to_vec());
+
+        // a field and its value are allocated on their own
+        let header = 2 * size_of::<Vec<u8>>();
+        assert_eq!(mc.set_fields("m", vec![field("a", "1"), field("b", "22")], Some(Duration::from_millis(10))), Ok(2));
+        assert_eq!(mc.size(), 5 + 2 * header);
+        assert_eq!(mc.set_fields("m", vec![field("b", "3"), field("c", "4")], None), Ok(1));
+        assert_eq!(mc.size(), 6 + 3 * header);
+        assert_eq!(mc.set_fields("m", Vec::new(), None), Err(CollectionError::Empty));
+        assert_eq!(mc.get_field("m", b"b"), Ok(Some(b"3".to_vec())));
+        assert_eq!(mc.get_field("m", b"z"), Ok(None));
+        assert_eq!(mc.get_field("missing", b"a"), Ok(None));
+        assert_eq!(mc.fields("m"), Ok(Some(vec![field("a", "1"), field("b", "3"), field("c", "4")])));
+        assert_eq!(mc.get("m"), None);
+
+        assert_eq!(mc.delete_fields("m", &[b"a".to_vec(), b"z".to_vec()]), Ok(Some(1)));
+        assert_eq!(mc.size(), 4 + 2 * header);
+        let _ = mc.add_members("s", vec![b"a".to_vec()], None);
+        assert_eq!(mc.set_fields("s", vec![field("a", "1")], None), Err(CollectionError::WrongType));
+        assert_eq!(mc.get_field("s", b"a"), Err(CollectionError::WrongType));
+
+        // removing the last field deletes the map
+        assert_eq!(mc.delete_fields("m", &[b"b".to_vec(), b"c".to_vec()]), Ok(Some(2)));
+        assert_eq!(mc.fields("m"), Ok(None));
+
+        assert_eq!(mc.set_fields("m", vec![field("a", "1")], Some(Duration::from_millis(10))), Ok(1));
+        clock.advance(Duration::from_millis(20));
+        assert_eq!(mc.fields("m"), Ok(None));
+    }
+
+    #[test]
+    fn classes() {
+        let clock = Rc::new(ManualClock::default());
+        let slabs = SlabConfig { page_size: 256, min_chunk: 16, growth_factor: 2.0 };
+        let options = Options { slabs: Some(slabs), ..Options::default() };
+        let mut mc = Memcached::with_options(900, clock.clone(), options);
+        let _ = mc.set("a".to_owned(), vec![b'a'; 10], None);
+        clock.advance(Duration::from_millis(5));
+        let _ = mc.set("b".to_owned(), vec![b'b'; 12], None);
+        let _ = mc.set("c".to_owned(), vec![b'c'; 30], None);
+        let _ = mc.set("heap".to_owned(), vec![b'h'; 300], None);
+
+        let classes = mc.classes();
+        assert_eq!(classes.len(), 3);
+        let small = ClassStats {
+            chunk_size: Some(16), items: 2, bytes: 22, pages: 1, chunks: 16, evicted: 0,
+            oldest_age: Some(Duration::from_millis(5)),
+        };
+        assert_eq!(classes[0], small);
+        assert_eq!((classes[1].chunk_size, classes[1].items, classes[1].bytes), (Some(32), 1, 30));
+        assert_eq!((classes[2].chunk_size, classes[2].items, classes[2].pages), (None, 1, 0));
+
+        // whole pages count against the limit: free chunks of a page are taken without evictions,
+        // the next page is made room for by the oldest values, which are small ones
+        for key in ["d", "e", "f", "g", "h", "i", "j"].iter() {
+            assert!(mc.set(key.to_string(), vec![b'x'; 30], None).is_ok());
+        }
+        assert_eq!(mc.len(), 11);
+        assert!(mc.set("k".to_owned(), vec![b'x'; 30], None).is_ok());
+        let classes = mc.classes();
+        assert_eq!((classes[0].chunk_size, classes[0].items, classes[0].evicted), (Some(16), 0, 2));
+        assert_eq!((classes[1].items, classes[1].evicted), (9, 0));
+        assert_eq!(mc.reserved(), 2 * 256 + 300);
+    }
+
+    #[test]
+    fn distribution() {
+        let (mut mc, clock) = new_mc(3000);
+        let _ = mc.set("a".to_owned(), b"a".to_vec(), Some(Duration::from_secs(30)));
+        let _ = mc.set("b".to_owned(), vec![b'b'; 100], Some(Duration::from_secs(120)));
+        let _ = mc.set("c".to_owned(), b"c".to_vec(), None);
+        let _ = mc.set("expired".to_owned(), b"e".to_vec(), Some(Duration::from_secs(1)));
+        let _ = mc.set_negative("missing".to_owned(), Duration::from_secs(30));
+
+        let stats = mc.stats();
+        assert_eq!((stats.ttls[0], stats.ttls[2], stats.ttls[3]), (1, 1, 1));
+        assert_eq!(stats.ttls.iter().sum::<u64>(), 3);
+        assert_eq!(stats.ttl_sum, Duration::from_secs(151));
+        assert_eq!((stats.sizes[0], stats.sizes[1], stats.size_sum), (3, 1, 103));
+
+        clock.advance(Duration::from_secs(61));
+        let distribution = mc.distribution();
+        assert_eq!(distribution.never_expiring, 1);
+        // b has 59s left, a and the expired one are gone
+        assert_eq!(distribution.remaining_ttl[2], 1);
+        assert_eq!(distribution.remaining_ttl.iter().sum::<u64>(), 1);
+        assert_eq!((distribution.sizes[0], distribution.sizes[1]), (1, 1));
+    }
+
+    #[test]
+    fn usage() {
+        let (mut mc, _) = new_mc(300);
+        let _ = mc.set("a".to_owned(), b"aaa".to_vec(), None);
+        let _ = mc.set("expiring".to_owned(), b"aaa".to_vec(), Some(Duration::from_secs(1)));
+
+        let usage = mc.usage("a").unwrap();
+        assert_eq!(usage.key, 1 + 2 * size_of::<usize>());
+        assert_eq!(usage.value, mc.slabs.size_for(3));
+        assert_eq!(usage.total(), usage.key + usage.value + usage.overhead);
+        let expiring = mc.usage("expiring").unwrap();
+        assert_eq!(expiring.overhead, usage.overhead + timer_wheel::ENTRY_SIZE);
+        mc.pin("a");
+        assert_eq!(mc.usage("a").unwrap().overhead, usage.overhead - size_of::<Key>());
+
+        let _ = mc.add_members("s", vec![b"ab".to_vec(), b"c".to_vec()], None);
+        assert_eq!(mc.usage("s").unwrap().value, 3 + 2 * size_of::<Vec<u8>>());
+        assert_eq!(mc.usage("missing"), None);
+    }
+
+    #[test]
+    fn counters() {
+        let (mut mc, clock) = new_mc(300);
+        let ttl = Some(Duration::from_millis(10));
+        let options = |initial, overflow, refresh_ttl| CounterOptions { initial, overflow, refresh_ttl };
+
+        assert_eq!(mc.count("c", 1, ttl, options(10, Overflow::Error, false)), Ok(11));
+        assert_eq!(mc.size(), COUNTER_LEN);
+        let version = mc.version("c");
+        // an existing counter keeps the options it was created with
+        assert_eq!(mc.count("c", -2, None, options(0, Overflow::Wrap, true)), Ok(9));
+        assert_ne!(mc.version("c"), version);
+        assert_eq!(mc.counter("c"), Ok(Some(9)));
+        assert_eq!(mc.get("c"), None);
+        assert_eq!(mc.counter("missing"), Ok(None));
+
+        assert_eq!(mc.count("max", i64::MAX, None, options(0, Overflow::Error, false)), Ok(i64::MAX));
+        assert_eq!(mc.count("max", 1, None, options(0, Overflow::Error, false)), Err(CounterError::Overflow));
+        assert_eq!(mc.counter("max"), Ok(Some(i64::MAX)));
+        assert_eq!(mc.count("sat", i64::MIN, None, options(-1, Overflow::Saturate, false)), Ok(i64::MIN));
+        assert_eq!(mc.count("wrap", 1, None, options(i64::MAX, Overflow::Wrap, false)), Ok(i64::MIN));
+        assert_eq!(mc.count("new", i64::MAX, None, options(1, Overflow::Error, false)), Err(CounterError::Overflow));
+        assert_eq!(mc.counter("new"), Ok(None));
+
+        let _ = mc.set("plain".to_owned(), b"1".to_vec(), None);
+        assert_eq!(mc.count("plain", 1, None, CounterOptions::default()), Err(CounterError::WrongType));
+        assert_eq!(mc.counter("plain"), Err(CounterError::WrongType));
+
+        // changes of a refreshed counter restart its ttl, others keep the first deadline
+        assert_eq!(mc.count("r", 1, ttl, options(0, Overflow::Error, true)), Ok(1));
+        clock.advance(Duration::from_millis(8));
+        assert_eq!(mc.count("c", 1, None, CounterOptions::default()), Ok(10));
+        assert_eq!(mc.count("r", 1, None, CounterOptions::default()), Ok(2));
+        clock.advance(Duration::from_millis(8));
+        assert_eq!(mc.counter("c"), Ok(None));
+        assert_eq!(mc.counter("r"), Ok(Some(2)));
+        assert_eq!(mc.count("c", 1, None, CounterOptions::default()), Ok(1));
+    }
+
+    #[test]
+    fn stats() {
+        let (mut mc, clock) = new_mc(3);
+        let _ = mc.set("a".to_owned(), "aa".as_bytes().to_owned(), Some(Duration::from_millis(10)));
+        let _ = mc.set("b".to_owned(), "b".as_bytes().to_owned(), None);
+        let _ = mc.set("c".to_owned(), "c".as_bytes().to_owned(), None);
+        mc.delete("b");
+        clock.advance(Duration::from_millis(20));
+        mc.collect_garbage();
+
+        let stats = mc.stats();
+        assert_eq!(stats.written_bytes, 4);
+        assert_eq!(stats.freed_bytes, 1);
+        assert_eq!(stats.evicted_bytes, 2);
+        assert_eq!(mc.size(), 1);
+    }
+
+    #[test]
+    fn versions() {
+        let (mut mc, clock) = new_mc(300);
+        let _ = mc.set("a".to_owned(), "a".as_bytes().to_owned(), Some(Duration::from_millis(10)));
+        let first = mc.version("a").unwrap();
+        assert_eq!(mc.get_with_version("a"), Some(("a".as_bytes().to_owned(), first)));
+
+        let _ = mc.set("a".to_owned(), "b".as_bytes().to_owned(), Some(Duration::from_millis(10)));
+        let second = mc.version("a").unwrap();
+        assert_ne!(first, second);
+        mc.touch("a", None);
+        assert_eq!(mc.version("a"), Some(second));
+
+        let _ = mc.set("b".to_owned(), "b".as_bytes().to_owned(), Some(Duration::from_millis(10)));
+        clock.advance(Duration::from_millis(20));
+        assert_eq!(mc.version("b"), None);
+        assert_eq!(mc.version("c"), None);
+    }
+
+    #[test]
+    fn watermarks() {
+        let clock = Rc::new(ManualClock::default());
+        let options = Options { watermarks: Some(Watermarks { low: 0.5, high: 0.8 }), ..Options::default() };
+        let mut mc = Memcached::with_options(10, clock.clone(), options);
+        for key in &["a", "b", "c", "d", "e", "f", "g", "h"] {
+            clock.advance(Duration::from_millis(1));
+            let _ = mc.set(key.to_string(), "x".as_bytes().to_owned(), None);
+        }
+        assert!(!mc.is_above_high_watermark());
+
+        let _ = mc.set("i".to_owned(), "x".as_bytes().to_owned(), None);
+        assert!(mc.is_above_high_watermark());
+
+        assert!(!mc.evict_step(&GcBudget { max_keys: Some(2), max_duration: None }));
+        assert!(mc.evict_step(&GcBudget::default()));
+        assert_eq!(mc.size(), 5);
+        assert_eq!(mc.get("d"), None);
+        assert!(mc.get("e").is_some());
+    }
+
+    #[test]
+    fn max_items() {
+        let clock = Rc::new(ManualClock::default());
+        let options = Options { max_items: Some(2), ..Options::default() };
+        let mut mc = Memcached::with_options(1 << 10, clock.clone(), options);
+        for key in &["a", "b"] {
+            clock.advance(Duration::from_millis(1));
+            let _ = mc.set(key.to_string(), "x".as_bytes().to_owned(), None);
+        }
+        // overwrites don't take another item
+        assert!(mc.set("a".to_owned(), "y".as_bytes().to_owned(), None).is_ok());
+        assert_eq!(mc.len(), 2);
+
+        assert!(mc.set("c".to_owned(), "x".as_bytes().to_owned(), None).is_ok());
+        assert_eq!(mc.len(), 2);
+        assert_eq!(mc.get("b"), None);
+        assert!(mc.get("a").is_some());
+    }
+
+    #[test]
+    fn early_expiration() {
+        let clock = Rc::new(ManualClock::default());
+        let options = Options { early_expiration: Some(1.0), ..Options::default() };
+        let mut mc = Memcached::with_options(1 << 10, clock.clone(), options);
+        let _ = mc.set("a".to_owned(), "x".as_bytes().to_owned(), Some(Duration::from_secs(10)));
+        let _ = mc.set("forever".to_owned(), "x".as_bytes().to_owned(), None);
+        // Weyl sequence covers [0, 1] evenly
+        let draws = |mc: &Memcached<_>, key| {
+            (0..1000u64).filter(|i| mc.refresh_early(key, i.wrapping_mul(0x9e37_79b9_7f4a_7c15))).count()
+        };
+
+        // just written
+        assert_eq!(draws(&mc, "a"), 0);
+        clock.advance(Duration::from_secs(1));
+        assert!(draws(&mc, "a") < 20);
+        clock.advance(Duration::from_millis(8900));
+        assert!(draws(&mc, "a") > 950);
+        assert_eq!(draws(&mc, "forever"), 0);
+        assert_eq!(draws(&mc, "missing"), 0);
+
+        // the lease holder refreshes, others keep reading
+        assert!(mc.lease("a", Duration::from_secs(1)).is_some());
+        assert_eq!(draws(&mc, "a"), 0);
+
+        let mut mc = Memcached::new(1 << 10, clock.clone());
+        let _ = mc.set("a".to_owned(), "x".as_bytes().to_owned(), Some(Duration::from_secs(1)));
+        clock.advance(Duration::from_millis(999));
+        assert_eq!(draws(&mc, "a"), 0);
+    }
+
+    #[test]
+    fn eviction_priority() {
+        let clock = Rc::new(ManualClock::default());
+        let options = Options { max_items: Some(3), ..Options::default() };
+        let mut mc = Memcached::with_options(1 << 10, clock.clone(), options);
+        for key in &["high", "normal", "low"] {
+            clock.advance(Duration::from_millis(1));
+            let _ = mc.set(key.to_string(), "x".as_bytes().to_owned(), None);
+        }
+        assert!(mc.set_priority("high", Priority::High));
+        assert!(mc.set_priority("low", Priority::Low));
+        assert!(!mc.set_priority("missing", Priority::Low));
+        assert_eq!(mc.priority("normal"), Some(Priority::Normal));
+
+        // the newest item goes first as it is the only low one
+        clock.advance(Duration::from_millis(1));
+        assert!(mc.set("a".to_owned(), "x".as_bytes().to_owned(), None).is_ok());
+        assert_eq!(mc.get("low"), None);
+        // touched normal ones go before the oldest high one
+        clock.advance(Duration::from_millis(1));
+        assert!(mc.set("b".to_owned(), "x".as_bytes().to_owned(), None).is_ok());
+        assert_eq!(mc.get("normal"), None);
+        assert!(mc.get("high").is_some());
+
+        // priority survives dump and restore, not a plain set
+        let dump = mc.dump("high").unwrap();
+        assert_eq!(dump.priority, Priority::High);
+        assert!(mc.restore("c".to_owned(), dump).is_ok());
+        assert_eq!(mc.priority("c"), Some(Priority::High));
+        let _ = mc.set("high".to_owned(), "y".as_bytes().to_owned(), None);
+        assert_eq!(mc.priority("high"), Some(Priority::Normal));
+    }
+
+    #[test]
+    fn key_rules() {
+        let rules = KeyRules { max_len: Some(8), allowed_chars: Some(":_".to_owned()), reject_whitespace: true };
+        assert_eq!(rules.check("user:1_a"), Ok(()));
+        assert_eq!(rules.check("user:1234"), Err(KeyError::TooLong { max: 8 }));
+        assert_eq!(rules.check("a b"), Err(KeyError::Whitespace));
+        assert_eq!(rules.check("a\nb"), Err(KeyError::Whitespace));
+        assert_eq!(rules.check("a/b"), Err(KeyError::Disallowed('/')));
+        assert!(!KeyRules::default().is_restrictive());
+
+        let options = Options { key_rules: rules, ..Options::default() };
+        let mut mc = Memcached::with_options(1 << 10, ManualClock::default(), options);
+        assert!(mc.set("a b".to_owned(), "x".as_bytes().to_owned(), None).is_err());
+        assert!(mc.push("a/b", End::Back, vec!["x".as_bytes().to_owned()], None).is_err());
+        assert!(mc.set("a".to_owned(), "x".as_bytes().to_owned(), None).is_ok());
+        assert_eq!(mc.len(), 1);
+    }
+
+    #[test]
+    fn default_and_max_ttl() {
+        let clock = Rc::new(ManualClock::default());
+        let options = Options {
+            default_ttl: Some(Duration::from_secs(10)),
+            max_ttl: Some(Duration::from_secs(60)),
+            ..Options::default()
+        };
+        let mut mc = Memcached::with_options(1 << 10, clock.clone(), options);
+        let _ = mc.set("default".to_owned(), "x".as_bytes().to_owned(), None);
+        let _ = mc.set("capped".to_owned(), "x".as_bytes().to_owned(), Some(Duration::from_secs(3600)));
+        let _ = mc.set("short".to_owned(), "x".as_bytes().to_owned(), Some(Duration::from_secs(1)));
+        assert_eq!(mc.expires_at("default"), Some(clock.now() + Duration::from_secs(10)));
+        assert_eq!(mc.expires_at("capped"), Some(clock.now() + Duration::from_secs(60)));
+        assert_eq!(mc.expires_at("short"), Some(clock.now() + Duration::from_secs(1)));
+
+        assert!(mc.touch("default", None));
+        assert_eq!(mc.expires_at("default"), Some(clock.now() + Duration::from_secs(60)));
+
+        // sliding refreshes and touches don't extend past max_ttl since the write
+        let written = clock.now();
+        assert!(mc.set_with("sliding".to_owned(), b"x".to_vec(), Some(Duration::from_secs(30)), true).is_ok());
+        clock.advance(Duration::from_secs(25));
+        assert!(mc.refresh("sliding"));
+        clock.advance(Duration::from_secs(25));
+        assert!(mc.refresh("sliding"));
+        assert_eq!(mc.expires_at("sliding"), Some(written + Duration::from_secs(60)));
+        assert!(mc.touch("sliding", Some(Duration::from_secs(30))));
+        assert_eq!(mc.expires_at("sliding"), Some(written + Duration::from_secs(60)));
+        clock.advance(Duration::from_secs(11));
+        assert_eq!(mc.get("sliding"), None);
+    }
+
+    #[test]
+    fn miss_leases() {
+        let (mut mc, clock) = new_mc(4);
+        let ttl = Duration::from_millis(10);
+        let token = mc.lease("a", ttl).unwrap();
+        assert_eq!(mc.lease("a", ttl), None);
+        assert!(mc.holds_lease("a", token));
+
+        let _ = mc.set("a".to_owned(), b"a".to_vec(), None);
+        assert!(!mc.holds_lease("a", token));
+        mc.delete("a");
+
+        let token = mc.lease("a", ttl).unwrap();
+        mc.delete("a");
+        assert!(!mc.holds_lease("a", token));
+
+        let token = mc.lease("a", ttl).unwrap();
+        mc.release_lease("a", token);
+        assert!(!mc.holds_lease("a", token));
+        let token = mc.lease("a", ttl).unwrap();
+        mc.release_lease("a", token - 1);
+        assert!(mc.holds_lease("a", token));
+        mc.delete("a");
+
+        let token = mc.lease("a", ttl).unwrap();
+        clock.advance(Duration::from_millis(11));
+        let next = mc.lease("a", ttl).unwrap();
+        assert!(next > token);
+        assert!(!mc.holds_lease("a", token));
+
+        clock.advance(Duration::from_millis(11));
+        mc.collect_garbage();
+        assert!(!mc.holds_lease("a", next));
+    }
+
+    #[derive(Default)]
+    struct MemoryTier(BTreeMap<String, (Vec<u8>, Option<Timestamp>)>);
+
+    impl ColdTier for MemoryTier {
+        fn spill(&mut self, key: &str, data: &[u8], ttl: Option<Timestamp>) {
+            self.0.insert(key.to_owned(), (data.to_vec(), ttl));
+        }
+
+        fn take(&mut self, key: &str) -> Option<(Vec<u8>, Option<Timestamp>)> {
+            self.0.remove(key)
+        }
+
+        fn forget(&mut self, key: &str) {
+            self.0.remove(key);
+        }
+    }
+
+    #[test]
+    fn cold_tier() {
+        let (mut mc, clock) = new_mc(2);
+        mc.set_cold_tier(Box::new(MemoryTier::default()));
+        let _ = mc.set("a".to_owned(), b"a".to_vec(), Some(Duration::from_millis(10)));
+        clock.advance(Duration::from_millis(1));
+        let _ = mc.set("b".to_owned(), b"b".to_vec(), None);
+        clock.advance(Duration::from_millis(1));
+        let _ = mc.set("c".to_owned(), b"c".to_vec(), None);
+        assert_eq!(mc.get("a"), None);
+
+        // promoted "a" displaces "b" in turn
+        assert_eq!(mc.promote("a"), Some(b"a".to_vec()));
+        assert_eq!(mc.get("a"), Some(b"a".to_vec()));
+        assert_eq!(mc.get("b"), None);
+        assert_eq!(mc.promote("a"), None);
+
+        // set replaces the spilled value
+        let _ = mc.set("b".to_owned(), b"B".to_vec(), None);
+        assert_eq!(mc.promote("b"), None);
+        assert_eq!(mc.get("b"), Some(b"B".to_vec()));
+
+        // "c" is spilled now and deleted there
+        assert_eq!(mc.delete("c"), Some(b"c".to_vec()));
+        assert_eq!(mc.promote("c"), None);
+
+        // deadline is kept in cold tier
+        let _ = mc.set("d".to_owned(), b"d".to_vec(), None);
+        clock.advance(Duration::from_millis(10));
+        assert_eq!(mc.promote("a"), None);
+    }
+
+    #[test]
+    fn locks() {
+        let (mut mc, clock) = new_mc(4);
+        let ttl = Duration::from_millis(10);
+        let token = mc.acquire_lock("lock".to_owned(), b"w1".to_vec(), ttl).ok().unwrap().unwrap();
+        assert!(mc.is_pinned("lock"));
+        assert_eq!(mc.acquire_lock("lock".to_owned(), b"w2".to_vec(), ttl).ok().unwrap(), None);
+
+        assert!(!mc.release_lock("lock", token + 1));
+        assert!(mc.release_lock("lock", token));
+        assert!(!mc.release_lock("lock", token));
+
+        let second = mc.acquire_lock("lock".to_owned(), b"w2".to_vec(), ttl).ok().unwrap().unwrap();
+        assert!(second > token);
+
+        clock.advance(Duration::from_millis(11));
+        let third = mc.acquire_lock("lock".to_owned(), b"w3".to_vec(), ttl).ok().unwrap().unwrap();
+        assert!(third > second);
+        assert!(!mc.release_lock("lock", second));
+        assert_eq!(mc.get("lock"), Some(b"w3".to_vec()));
+    }
+
+    #[test]
+    fn pinned_are_not_displaced() {
+        let (mut mc, clock) = new_mc(2);
+        let _ = mc.set("a".to_owned(), "a".as_bytes().to_owned(), Some(Duration::from_millis(10)));
+        assert!(mc.pin("a"));
+        assert!(!mc.pin("missing"));
+
+        clock.advance(Duration::from_millis(1));
+        let _ = mc.set("b".to_owned(), "b".as_bytes().to_owned(), None);
+        clock.advance(Duration::from_millis(1));
+        let _ = mc.set("c".to_owned(), "c".as_bytes().to_owned(), None);
+        assert!(mc.get("a").is_some());
+        assert_eq!(mc.get("b"), None);
+
+        assert!(mc.pin("c"));
+        assert!(mc.set("d".to_owned(), "d".as_bytes().to_owned(), None).is_err());
+
+        assert!(mc.unpin("c"));
+        assert!(mc.set("d".to_owned(), "d".as_bytes().to_owned(), None).is_ok());
+        assert_eq!(mc.get("c"), None);
+
+        clock.advance(Duration::from_millis(20));
+        mc.collect_garbage();
+        assert_eq!(mc.get("a"), None);
+        assert_eq!(mc.len(), 1);
+    }
+
+    #[test]
+    fn events() {
+        use alloc::sync::Arc;
+        use std::sync::Mutex;
+
+        let (mut mc, clock) = new_mc(3);
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        mc.set_listener(Box::new(move |event: &Event| {
+            sink.lock().unwrap().push((event.kind, event.key.to_owned(), event.size));
+        }));
+
+        let _ = mc.set("a".to_owned(), "a".as_bytes().to_owned(), Some(Duration::from_millis(10)));
+        let _ = mc.set("a".to_owned(), "aa".as_bytes().to_owned(), Some(Duration::from_millis(10)));
+        clock.advance(Duration::from_millis(20));
+        mc.collect_garbage();
+        let _ = mc.set("b".to_owned(), "b".as_bytes().to_owned(), None);
+        clock.advance(Duration::from_millis(1));
+        let _ = mc.set("c".to_owned(), "ccc".as_bytes().to_owned(), None);
+        mc.set_priority("c", Priority::High);
+        mc.set_priority("c", Priority::High);
+        mc.delete("c");
+
+        assert_eq!(*events.lock().unwrap(), vec![
+            (EventKind::Set, "a".to_owned(), 1),
+            (EventKind::Set, "a".to_owned(), 2),
+            (EventKind::Expired, "a".to_owned(), 2),
+            (EventKind::Set, "b".to_owned(), 1),
+            (EventKind::Evicted, "b".to_owned(), 1),
+            (EventKind::Set, "c".to_owned(), 3),
+            (EventKind::Prioritized, "c".to_owned(), 3),
+            (EventKind::Deleted, "c".to_owned(), 3),
+        ]);
+    }
+
+    #[test]
+    fn transaction_rollback() {
+        use alloc::sync::Arc;
+        use std::sync::Mutex;
+
+        let (mut mc, _) = new_mc(300);
+        let values = |values: &[&str]| values.iter().map(|value| value.as_bytes().to_vec()).collect::<Vec<_>>();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        mc.set_listener(Box::new(move |event: &Event| sink.lock().unwrap().push((event.kind, event.key.to_owned()))));
+        assert_eq!(mc.push("l", End::Back, values(&["a", "b"]), None), Ok(2));
+        assert!(mc.set_negative("n".to_owned(), Duration::from_secs(1)).is_ok());
+        events.lock().unwrap().clear();
+
+        mc.begin();
+        for key in &["l", "n", "new"] {
+            mc.save(key);
+            assert!(mc.set(key.to_string(), "x".as_bytes().to_owned(), None).is_ok());
+        }
+        assert!(mc.rollback());
+
+        assert_eq!(mc.range("l", 0, -1), Ok(Some(values(&["a", "b"]))));
+        assert!(mc.is_negative("n"));
+        assert_eq!(mc.get("new"), None);
+        assert_eq!(mc.len(), 2);
+        assert!(events.lock().unwrap().is_empty());
+
+        mc.begin();
+        mc.save("n");
+        mc.delete("n");
+        assert!(events.lock().unwrap().is_empty());
+        mc.commit();
+        assert_eq!(*events.lock().unwrap(), vec![(EventKind::Deleted, "n".to_owned())]);
+    }
+
+    #[test]
+    fn tracked_prefixes() {
+        let (mut mc, _) = new_mc(1 << 10);
+        assert!(mc.set("a:1".to_owned(), b"aa".to_vec(), None).is_ok());
+        assert!(mc.set("b:1".to_owned(), b"b".to_vec(), None).is_ok());
+        let prefixes = ["a:".to_owned(), "b:".to_owned()];
+        mc.track_prefixes(&prefixes);
+        assert_eq!(mc.prefix_bytes(), &[("a:".to_owned(), 2), ("b:".to_owned(), 1)]);
+
+        assert!(mc.set("a:1".to_owned(), b"a".to_vec(), None).is_ok());
+        assert_eq!(mc.push("b:list", End::Back, vec![b"xy".to_vec()], None), Ok(1));
+        let list = mc.size() - 2;
+        mc.delete("b:1");
+        assert!(mc.set("c".to_owned(), b"c".to_vec(), None).is_ok());
+        assert_eq!(mc.prefix_bytes(), &[("a:".to_owned(), 1), ("b:".to_owned(), list)]);
+
+        assert_eq!(mc.pop("b:list", End::Back, 1), Ok(Some(vec![b"xy".to_vec()])));
+        // unchanged prefixes are kept as counted
+        mc.track_prefixes(&prefixes);
+        assert_eq!(mc.prefix_bytes(), &[("a:".to_owned(), 1), ("b:".to_owned(), 0)]);
+    }
+
+    #[test]
+    fn snapshots() {
+        let (mut mc, _) = new_mc(1 << 10);
+        let values = |values: &[&str]| values.iter().map(|value| value.as_bytes().to_vec()).collect::<Vec<_>>();
+        assert!(mc.set("plain".to_owned(), b"a".to_vec(), None).is_ok());
+        assert_eq!(mc.push("list", End::Back, values(&["a", "b"]), Some(Duration::from_secs(10))), Ok(2));
+        assert_eq!(mc.add_members("set", values(&["a"]), None), Ok(1));
+        assert_eq!(mc.set_fields("map", vec![(b"f".to_vec(), b"v".to_vec())], None), Ok(1));
+        assert_eq!(mc.count("counter", 5, None, CounterOptions::default()), Ok(5));
+        assert!(mc.set_negative("negative".to_owned(), Duration::from_secs(10)).is_ok());
+        assert!(mc.pin("list"));
+
+        let (mut copy, _) = new_mc(1 << 10);
+        for key in &["plain", "list", "set", "map", "counter", "negative"] {
+            let (dump, contents) = mc.snapshot(key).unwrap();
+            assert!(copy.restore_snapshot(key.to_string(), dump, contents).is_ok(), "{}", key);
+        }
+        assert_eq!(copy.get("plain"), Some(b"a".to_vec()));
+        assert_eq!(copy.range("list", 0, -1), Ok(Some(values(&["a", "b"]))));
+        assert!(copy.is_pinned("list"));
+        assert_eq!(copy.expires_at("list"), mc.expires_at("list"));
+        assert_eq!(copy.members("set"), Ok(Some(values(&["a"]))));
+        assert_eq!(copy.get_field("map", b"f"), Ok(Some(b"v".to_vec())));
+        assert_eq!(copy.count("counter", 1, None, CounterOptions::default()), Ok(6));
+        assert!(copy.is_negative("negative"));
+        assert_eq!(copy.size(), mc.size());
+        assert_eq!(mc.snapshot("missing"), None);
+
+        let empty = Dump { data: Vec::new(), ttl: None, sliding: None, pinned: false, priority: Priority::Normal, version: 0 };
+        assert!(copy.restore_snapshot("empty".to_owned(), empty, Contents::List(Vec::new())).is_err());
+    }
+
+    #[test]
+    fn overflow() {
+        let (mut mc, _) = new_mc(1);
+        let _ = mc.set("a".to_owned(), "aa".as_bytes().to_owned(), None);
+        assert_eq!(mc.get("a"), None);
+    }
+
+    #[test]
+    fn shrink_limit() {
+        let (mut mc, clock) = new_mc(3);
+        for key in &["a", "b", "c"] {
+            let _ = mc.set(key.to_string(), "a".as_bytes().to_owned(), None);
+            clock.advance(Duration::from_millis(1));
+        }
+
+        mc.set_limit(2);
+        assert_eq!(mc.get("a"), None);
+        assert_eq!(mc.get("b"), Some("a".into()));
+        assert_eq!(mc.get("c"), Some("a".into()));
+        assert_eq!(mc.limit(), 2);
+    }
+
+    #[test]
+    fn overflow_keeps_existing() {
+        let (mut mc, _) = new_mc(3);
+        let _ = mc.set("a".to_owned(), "a".as_bytes().to_owned(), None);
+        let _ = mc.set("b".to_owned(), "b".as_bytes().to_owned(), None);
+
+        let (key, data) = mc.set("c".to_owned(), "cccc".as_bytes().to_owned(), None)
+            .expect_err("oversized set must fail").into_kv();
+        assert_eq!(key, "c");
+        assert_eq!(data, "cccc".as_bytes());
+
+        assert_eq!(mc.get("a"), Some("a".into()));
+        assert_eq!(mc.get("b"), Some("b".into()));
+        assert_eq!(mc.get("c"), None);
+    }
+}
+
+
+#[cfg(test)]
+mod inner_tests {
+    use super::*;
+    use alloc::rc::Rc;
+
+    fn new_mc(limit: usize) -> (Memcached<Rc<ManualClock>>, Rc<ManualClock>) {
+        let clock = Rc::new(ManualClock::default());
+        (Memcached::new(limit, clock.clone()), clock)
+    }
+
+    #[test]
+    fn exp_neg_approximation() {
+        for &(x, expected) in &[(0.0, 1.0), (0.01, 0.990_049_834), (1.0, 0.367_879_441), (20.0, 2.061_153_622e-9)] {
+            assert!((exp_neg(x) - expected).abs() <= expected * 1e-4, "{}", x);
+        }
+        assert_eq!(exp_neg(f64::INFINITY), 0.0);
+    }
+
+    /// test validates that pointers to keys are equal in cache, keys_by_touch and keys_by_ttl
+    #[test]
+    fn valid_pointers() {
+        let (mut mc, _) = new_mc(300);
+        let _ = mc.set("a".to_owned(), "a".as_bytes().to_owned(), Some(Duration::from_secs(300)));
+
+        let (key, v) = mc.cache.get_key_value("a").unwrap();
+        let key_ttl = mc.keys_by_ttl.key(v.timer.unwrap());
+        let key_touch = &mc.keys_by_touch[&(Priority::Normal, v.touch)][0];
+        assert_eq!(key.as_ptr(), key_ttl.as_ptr());
+        assert_eq!(key.as_ptr(), key_touch.as_ptr());
+    }
+
+    #[test]
+    fn expire_without_gc() {
+        let (mut mc, clock) = new_mc(300);
+        let _ = mc.set("a".to_owned(), "a".as_bytes().to_owned(), Some(Duration::from_millis(100)));
+        assert_eq!(mc.get("a"), Some("a".into()));
+
+        clock.advance(Duration::from_millis(200));
+
+        assert_eq!(mc.get("a"), None);
+        assert_eq!(mc.current_size, 1);
+        assert_eq!(mc.cache.len(), 1);
+        assert_eq!(mc.keys_by_ttl.len(), 1);
+        assert_eq!(mc.keys_by_touch.len(), 1);
+    }
+
+    #[test]
+    fn lazy_expire() {
+        let (mut mc, clock) = new_mc(300);
+        let _ = mc.set("a".to_owned(), "a".as_bytes().to_owned(), Some(Duration::from_millis(100)));
+        let _ = mc.set("b".to_owned(), "b".as_bytes().to_owned(), Some(Duration::from_millis(300)));
+        assert!(!mc.remove_expired("a"));
+
+        clock.advance(Duration::from_millis(200));
+
+        assert!(mc.is_expired("a"));
+        assert!(!mc.is_expired("b"));
+        assert!(!mc.is_expired("c"));
+
+        assert!(mc.remove_expired("a"));
+        assert!(!mc.remove_expired("b"));
+        assert_eq!(mc.current_size, 1);
+        assert_eq!(mc.cache.len(), 1);
+        assert_eq!(mc.keys_by_ttl.len(), 1);
+    }
+
+    #[test]
+    fn expire_with_gc() {
+        let (mut mc, clock) = new_mc(300);
+        let _ = mc.set("a".to_owned(), "a".as_bytes().to_owned(), Some(Duration::from_millis(100)));
+        assert_eq!(mc.get("a"), Some("a".into()));
+
+        clock.advance(Duration::from_millis(200));
+        mc.collect_garbage();
+
+        assert_eq!(mc.get("a"), None);
+        assert_eq!(mc.current_size, 0);
+        assert_eq!(mc.cache.len(), 0);
+        assert_eq!(mc.keys_by_ttl.len(), 0);
+        assert_eq!(mc.keys_by_touch.len(), 0);
+    }
+
+    #[test]
+    fn gc_step_resumes() {
+        let (mut mc, clock) = new_mc(300);
+        for key in &["a", "b", "c", "d", "e"] {
+            let _ = mc.set(key.to_string(), "a".as_bytes().to_owned(), Some(Duration::from_millis(100)));
+            clock.advance(Duration::from_millis(1));
+        }
+        clock.advance(Duration::from_millis(200));
+
+        let budget = GcBudget { max_keys: Some(2), max_duration: None };
+        assert!(!mc.collect_garbage_step(&budget));
+        assert_eq!(mc.cache.len(), 3);
+        assert!(!mc.cache.contains_key("a"));
+        assert!(!mc.cache.contains_key("b"));
+
+        assert!(!mc.collect_garbage_step(&budget));
+        assert!(mc.collect_garbage_step(&budget));
+        assert_eq!(mc.current_size, 0);
+        assert_eq!(mc.keys_by_ttl.len(), 0);
+        assert_eq!(mc.keys_by_touch.len(), 0);
+    }
+
+    #[test]
+    fn zero_gc_budget_makes_progress() {
+        let (mut mc, clock) = new_mc(300);
+        for key in &["a", "b"] {
+            let _ = mc.set(key.to_string(), "a".as_bytes().to_owned(), Some(Duration::from_millis(100)));
+        }
+        clock.advance(Duration::from_millis(200));
+
+        let budget = GcBudget { max_keys: Some(0), max_duration: Some(Duration::from_secs(0)) };
+        assert_eq!(mc.sweep(&budget).removed, 1);
+        assert_eq!(mc.sweep(&budget).removed, 1);
+        assert!(mc.sweep(&budget).done);
+        assert_eq!(mc.cache.len(), 0);
+    }
+
+    #[test]
+    fn sweep_reports() {
+        let (mut mc, clock) = new_mc(300);
+        for key in &["a", "b", "c"] {
+            let _ = mc.set(key.to_string(), "a".as_bytes().to_owned(), Some(Duration::from_millis(100)));
+        }
+        let _ = mc.set("d".to_string(), "a".as_bytes().to_owned(), None);
+        let size = mc.current_size;
+        clock.advance(Duration::from_millis(200));
+
+        let report = mc.sweep(&GcBudget { max_keys: Some(2), max_duration: None });
+        assert_eq!(report.removed, 2);
+        assert!(!report.done);
+        let rest = mc.sweep(&GcBudget::default());
+        assert_eq!(rest, GcReport { removed: 1, reclaimed: size - mc.current_size - report.reclaimed, done: true });
+        assert!(report.reclaimed > 0);
+        assert_eq!(mc.cache.len(), 1);
+    }
+
+    #[test]
+    fn gc_keeps_not_expired() {
+        let (mut mc, clock) = new_mc(300);
+        let _ = mc.set("a".to_owned(), "a".as_bytes().to_owned(), Some(Duration::from_millis(100)));
+        let _ = mc.set("b".to_owned(), "b".as_bytes().to_owned(), Some(Duration::from_millis(300)));
+        let _ = mc.set("c".to_owned(), "c".as_bytes().to_owned(), None);
+
+        clock.advance(Duration::from_millis(200));
+        mc.collect_garbage();
+
+        assert_eq!(mc.current_size, 2);
+        assert_eq!(mc.cache.len(), 2);
+        assert_eq!(mc.keys_by_ttl.len(), 1);
+        assert_eq!(mc.keys_by_touch.len(), 1);
+    }
+}
@@ -0,0 +1,170 @@
+use core::{fmt, time::Duration};
+use alloc::string::String;
+
+/// Random shortening of item ttl, so items written together don't expire together.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TtlJitter {
+    /// up to this duration
+    Absolute(Duration),
+    /// up to this fraction of ttl, between 0 and 1
+    Ratio(f64),
+}
+
+impl TtlJitter {
+    /// `random` is uniformly distributed over u64
+    pub(crate) fn apply(&self, ttl: Duration, random: u64) -> Duration {
+        let jitter = match *self {
+            TtlJitter::Absolute(max) => match max.as_nanos() as u64 {
+                0 => Duration::from_secs(0),
+                max => Duration::from_nanos(random % (max + 1)),
+            },
+            TtlJitter::Ratio(ratio) => ttl.mul_f64(ratio * unit(random)),
+        };
+
+        ttl - jitter.min(ttl)
+    }
+}
+
+/// Fractions of memory limit: once usage is above `high`,
+/// background eviction displaces oldest items down to `low`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Watermarks {
+    pub low: f64,
+    pub high: f64,
+}
+
+/// Constraints on keys of stored items, any key is taken by default.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeyRules {
+    /// in bytes
+    pub max_len: Option<usize>,
+    /// characters allowed besides ascii letters and digits, any character is allowed if not set
+    pub allowed_chars: Option<String>,
+    /// rejects whitespace and control characters, which break framing of text protocols
+    pub reject_whitespace: bool,
+}
+
+/// Why [`KeyRules::check`] rejected a key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyError {
+    TooLong { max: usize },
+    Whitespace,
+    Disallowed(char),
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyError::TooLong { max } => write!(f, "key is longer than {} bytes", max),
+            KeyError::Whitespace => write!(f, "key contains whitespace or control characters"),
+            KeyError::Disallowed(char) => write!(f, "key contains disallowed character {:?}", char),
+        }
+    }
+}
+
+impl KeyRules {
+    pub fn is_restrictive(&self) -> bool {
+        *self != KeyRules::default()
+    }
+
+    pub fn check(&self, key: &str) -> Result<(), KeyError> {
+        if let Some(max) = self.max_len.filter(|&max| key.len() > max) {
+            return Err(KeyError::TooLong { max })
+        }
+        for char in key.chars() {
+            if self.reject_whitespace && (char.is_whitespace() || char.is_control()) {
+                return Err(KeyError::Whitespace)
+            }
+            match &self.allowed_chars {
+                Some(allowed) if !char.is_ascii_alphanumeric() && !allowed.contains(char) => {
+                    return Err(KeyError::Disallowed(char))
+                },
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Memcached-style slab allocation of values: pages of `page_size` bytes are carved
+/// into chunks of a size class, each class `growth_factor` times larger than the
+/// previous one. A value takes a chunk of the smallest class fitting it, so small
+/// values don't pay an allocation each and freed chunks are reused by values of
+/// similar size. Values larger than a page are allocated on their own.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SlabConfig {
+    pub page_size: usize,
+    /// chunk size of the smallest class
+    pub min_chunk: usize,
+    /// greater than 1
+    pub growth_factor: f64,
+}
+
+impl Default for SlabConfig {
+    fn default() -> SlabConfig {
+        SlabConfig { page_size: 1 << 20, min_chunk: 64, growth_factor: 1.25 }
+    }
+}
+
+/// Hash function of the key map, both are seeded randomly per store.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeyHasher {
+    /// several times faster than SipHash on long keys
+    #[default]
+    AHash,
+    /// slower, but the most studied one against collision flooding
+    #[cfg(feature = "std")]
+    SipHash,
+}
+
+/// Optional store behaviour, everything is off by default.
+#[derive(Clone, Default, Debug)]
+pub struct Options {
+    pub ttl_jitter: Option<TtlJitter>,
+    /// default for [`Memcached::set`](crate::Memcached::set):
+    /// whether reads restart ttl countdown of an item
+    pub sliding_ttl: bool,
+    /// how long expired items are kept for [`Memcached::get_stale`](crate::Memcached::get_stale)
+    pub stale_grace: Duration,
+    pub watermarks: Option<Watermarks>,
+    pub hasher: KeyHasher,
+    /// Values are kept in slabs, and memory limit counts their whole pages instead of value sizes.
+    /// Every value has an allocation of its own if not set.
+    pub slabs: Option<SlabConfig>,
+    /// Expected number of keys of a [`KeyFilter`](crate::KeyFilter) telling definite misses,
+    /// it takes 10 bytes per key. There is no filter if not set.
+    pub key_filter: Option<usize>,
+    /// Items held at most alongside the memory limit, oldest ones are displaced to make room for new keys.
+    /// Collections and negative markers count as items.
+    pub max_items: Option<usize>,
+    /// ttl of items written without one, they never expire if not set
+    pub default_ttl: Option<Duration>,
+    /// Ttls given by writes are capped at it, items written without one get it unless
+    /// [`Options::default_ttl`] is set. Sliding refreshes and touches don't extend items past it
+    /// since they were written.
+    pub max_ttl: Option<Duration>,
+    /// Items with keys breaking them are not stored.
+    pub key_rules: KeyRules,
+    /// `beta` of [`Memcached::refresh_early`](crate::Memcached::refresh_early), greater values
+    /// refresh earlier, 1 is the usual choice. Items are refreshed only once expired if not set.
+    pub early_expiration: Option<f64>,
+}
+
+/// maps random u64 to [0, 1]
+pub(crate) fn unit(random: u64) -> f64 {
+    (random >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// `e^-x` for non negative x without float math of std:
+/// `e^-x = (e^(-x / 2^16))^(2^16)` with the small power taken from its Taylor series.
+pub(crate) fn exp_neg(x: f64) -> f64 {
+    if x > 700.0 {
+        return 0.0
+    }
+    let y = x / 65536.0;
+    let mut exp = 1.0 - y + y * y / 2.0 - y * y * y / 6.0;
+    for _ in 0..16 {
+        exp *= exp;
+    }
+    exp
+}
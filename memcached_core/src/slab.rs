@@ -0,0 +1,512 @@
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    vec,
+    vec::Vec,
+};
+
+use core::time::Duration;
+
+use crate::{options::SlabConfig, ClassStats, End, Overflow};
+
+/// bytes accounted for a counter
+pub(crate) const COUNTER_LEN: usize = core::mem::size_of::<i64>();
+
+/// bytes accounted for a collection element besides its own: the header of its allocation
+pub(crate) const ELEMENT_LEN: usize = core::mem::size_of::<Vec<u8>>();
+
+/// bytes accounted for a collection element, a field and its value are two elements
+pub(crate) fn element_len(data: &[u8]) -> usize {
+    data.len() + ELEMENT_LEN
+}
+
+/// Value of an item, either in a slab chunk or in an allocation of its own.
+pub(crate) enum Value {
+    Heap(Vec<u8>),
+    Slab { class: u8, page: u32, chunk: u32, len: u32 },
+    /// elements are allocated on their own, `len` is their total [`element_len`]
+    List { items: VecDeque<Vec<u8>>, len: usize },
+    /// members are allocated on their own, `len` is their total [`element_len`]
+    Set { members: BTreeSet<Vec<u8>>, len: usize },
+    /// fields and their values are allocated on their own, `len` is their total [`element_len`]
+    Map { fields: BTreeMap<Vec<u8>, Vec<u8>>, len: usize },
+    /// `refresh` is the ttl restarted by every change
+    Counter { value: i64, overflow: Overflow, refresh: Option<Duration> },
+}
+
+impl Value {
+    /// payload bytes, with element headers for collections
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Value::Heap(data) => data.len(),
+            Value::Slab { len, .. } => *len as usize,
+            Value::List { len, .. } | Value::Set { len, .. } | Value::Map { len, .. } => *len,
+            Value::Counter { .. } => COUNTER_LEN,
+        }
+    }
+
+    /// bytes in a single piece rather than a collection
+    pub(crate) fn is_plain(&self) -> bool {
+        matches!(self, Value::Heap(_) | Value::Slab { .. })
+    }
+
+    pub(crate) fn is_list(&self) -> bool {
+        matches!(self, Value::List { .. })
+    }
+
+    pub(crate) fn is_set(&self) -> bool {
+        matches!(self, Value::Set { .. })
+    }
+
+    pub(crate) fn is_map(&self) -> bool {
+        matches!(self, Value::Map { .. })
+    }
+
+    pub(crate) fn is_counter(&self) -> bool {
+        matches!(self, Value::Counter { .. })
+    }
+
+    /// collection without elements, which is deleted rather than kept
+    pub(crate) fn is_drained(&self) -> bool {
+        match self {
+            Value::List { items, .. } => items.is_empty(),
+            Value::Set { members, .. } => members.is_empty(),
+            Value::Map { fields, .. } => fields.is_empty(),
+            _ => false,
+        }
+    }
+
+    /// elements of a list, `None` for other values
+    pub(crate) fn list(&self) -> Option<&VecDeque<Vec<u8>>> {
+        match self {
+            Value::List { items, .. } => Some(items),
+            _ => None,
+        }
+    }
+
+    /// members of a set, `None` for other values
+    pub(crate) fn set(&self) -> Option<&BTreeSet<Vec<u8>>> {
+        match self {
+            Value::Set { members, .. } => Some(members),
+            _ => None,
+        }
+    }
+
+    /// fields of a map, `None` for other values
+    pub(crate) fn map(&self) -> Option<&BTreeMap<Vec<u8>, Vec<u8>>> {
+        match self {
+            Value::Map { fields, .. } => Some(fields),
+            _ => None,
+        }
+    }
+
+    /// value of a counter, `None` for other values
+    pub(crate) fn counter(&self) -> Option<i64> {
+        match self {
+            Value::Counter { value, .. } => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// value of a counter to change, its overflow policy and refreshed ttl
+    pub(crate) fn counter_mut(&mut self) -> Option<(&mut i64, Overflow, Option<Duration>)> {
+        match self {
+            Value::Counter { value, overflow, refresh } => Some((value, *overflow, *refresh)),
+            _ => None,
+        }
+    }
+}
+
+struct Page {
+    data: Box<[u8]>,
+    /// indexes of chunks not taken by values
+    free: Vec<u32>,
+}
+
+/// Pages carved into chunks of the same size.
+struct Class {
+    chunk_size: usize,
+    chunks_per_page: usize,
+    /// slots of released pages are reused by the next page
+    pages: Vec<Option<Page>>,
+    /// pages with free chunks, the last one is allocated from
+    partial: Vec<u32>,
+    vacant: Vec<u32>,
+    /// values displaced from the class to make room
+    evicted: u64,
+}
+
+impl Class {
+    fn new(chunk_size: usize, page_size: usize) -> Class {
+        Class {
+            chunk_size,
+            chunks_per_page: page_size / chunk_size,
+            pages: Vec::new(),
+            partial: Vec::new(),
+            vacant: Vec::new(),
+            evicted: 0,
+        }
+    }
+
+    /// Returns page and chunk index, and whether a new page was taken for it.
+    fn allocate(&mut self) -> (u32, u32, bool) {
+        let (page, new_page) = match self.partial.last() {
+            Some(&page) => (page, false),
+            None => {
+                let page = Page {
+                    data: vec![0; self.page_bytes()].into_boxed_slice(),
+                    free: (0..self.chunks_per_page as u32).rev().collect(),
+                };
+                let slot = match self.vacant.pop() {
+                    Some(slot) => {
+                        self.pages[slot as usize] = Some(page);
+                        slot
+                    },
+                    None => {
+                        self.pages.push(Some(page));
+                        self.pages.len() as u32 - 1
+                    },
+                };
+                self.partial.push(slot);
+                (slot, true)
+            },
+        };
+
+        let free = &mut self.pages[page as usize].as_mut().unwrap().free;
+        let chunk = free.pop().unwrap();
+        if free.is_empty() {
+            self.partial.pop();
+        }
+        (page, chunk, new_page)
+    }
+
+    /// Returns true if the page became empty and was released.
+    fn free(&mut self, page: u32, chunk: u32) -> bool {
+        let free = &mut self.pages[page as usize].as_mut().unwrap().free;
+        let was_full = free.is_empty();
+        free.push(chunk);
+
+        if free.len() == self.chunks_per_page {
+            self.pages[page as usize] = None;
+            self.vacant.push(page);
+            if !was_full {
+                self.partial.retain(|partial| *partial != page);
+            }
+            return true
+        }
+        if was_full {
+            self.partial.push(page);
+        }
+        false
+    }
+
+    /// page size rounded down to whole chunks
+    fn page_bytes(&self) -> usize {
+        self.chunk_size * self.chunks_per_page
+    }
+
+    fn chunk(&self, page: u32, chunk: u32) -> &[u8] {
+        let start = chunk as usize * self.chunk_size;
+        &self.pages[page as usize].as_ref().unwrap().data[start..start + self.chunk_size]
+    }
+
+    fn chunk_mut(&mut self, page: u32, chunk: u32) -> &mut [u8] {
+        let start = chunk as usize * self.chunk_size;
+        &mut self.pages[page as usize].as_mut().unwrap().data[start..start + self.chunk_size]
+    }
+}
+
+/// Size-class allocator of values, see [`SlabConfig`].
+/// Without classes every value is kept in its own allocation.
+#[derive(Default)]
+pub(crate) struct Slabs {
+    /// ascending by chunk size
+    classes: Vec<Class>,
+    /// bytes of pages and values allocated on their own
+    reserved: usize,
+    /// displaced values which were allocated on their own
+    evicted_own: u64,
+}
+
+impl Slabs {
+    pub(crate) fn new(config: SlabConfig) -> Slabs {
+        let mut classes = Vec::new();
+        let mut chunk_size = align(config.min_chunk.max(1));
+        // the last class takes a whole page, so there are 255 classes at most
+        while chunk_size <= config.page_size / 2 && classes.len() < u8::MAX as usize - 1 {
+            classes.push(Class::new(chunk_size, config.page_size));
+            let next = align((chunk_size as f64 * config.growth_factor) as usize);
+            chunk_size = next.max(chunk_size + 8);
+        }
+        classes.push(Class::new(config.page_size, config.page_size));
+
+        Slabs { classes, reserved: 0, evicted_own: 0 }
+    }
+
+    fn class_of(&self, len: usize) -> Option<usize> {
+        let class = self.classes.partition_point(|class| class.chunk_size < len);
+        Some(class).filter(|class| *class < self.classes.len())
+    }
+
+    /// bytes a value of `len` bytes takes once stored
+    pub(crate) fn size_for(&self, len: usize) -> usize {
+        match self.class_of(len) {
+            Some(class) => self.classes[class].chunk_size,
+            None => len,
+        }
+    }
+
+    pub(crate) fn size_of(&self, value: &Value) -> usize {
+        match value {
+            Value::Heap(data) => data.len(),
+            Value::Slab { class, .. } => self.classes[*class as usize].chunk_size,
+            Value::List { len, .. } | Value::Set { len, .. } | Value::Map { len, .. } => *len,
+            Value::Counter { .. } => COUNTER_LEN,
+        }
+    }
+
+    /// Index of the class of the value in [`Slabs::class_stats`],
+    /// values allocated on their own follow every class.
+    pub(crate) fn class_index(&self, value: &Value) -> usize {
+        match value {
+            Value::Slab { class, .. } => *class as usize,
+            _ => self.classes.len(),
+        }
+    }
+
+    pub(crate) fn count_eviction(&mut self, value: &Value) {
+        match value {
+            Value::Slab { class, .. } => self.classes[*class as usize].evicted += 1,
+            _ => self.evicted_own += 1,
+        }
+    }
+
+    /// Pages and evictions of every class followed by those of values allocated on their own,
+    /// items are left for the caller to count.
+    pub(crate) fn class_stats(&self) -> Vec<ClassStats> {
+        let classes = self.classes.iter().map(|class| {
+            let pages = class.pages.iter().flatten().count();
+            ClassStats {
+                chunk_size: Some(class.chunk_size),
+                pages,
+                chunks: pages * class.chunks_per_page,
+                evicted: class.evicted,
+                ..ClassStats::default()
+            }
+        });
+        let own = ClassStats { evicted: self.evicted_own, ..ClassStats::default() };
+        classes.chain(core::iter::once(own)).collect()
+    }
+
+    /// bytes of slab pages, including free chunks, and of values allocated on their own
+    pub(crate) fn reserved(&self) -> usize {
+        self.reserved
+    }
+
+    /// Bytes reserved anew by storing a value taking `size`, see [`Slabs::size_for`]: a page
+    /// if the class of a plain value has no free chunk, the size of anything allocated on its own.
+    pub(crate) fn growth(&self, size: usize, plain: bool) -> usize {
+        match self.class_of(size).filter(|_| plain) {
+            Some(class) if self.classes[class].partial.is_empty() => self.classes[class].page_bytes(),
+            Some(_) => 0,
+            None => size,
+        }
+    }
+
+    pub(crate) fn store(&mut self, data: Vec<u8>) -> Value {
+        let class = match self.class_of(data.len()) {
+            Some(class) => class,
+            None => {
+                self.reserved += data.len();
+                return Value::Heap(data)
+            },
+        };
+
+        let (page, chunk, new_page) = self.classes[class].allocate();
+        if new_page {
+            self.reserved += self.classes[class].page_bytes();
+        }
+        self.classes[class].chunk_mut(page, chunk)[..data.len()].copy_from_slice(&data);
+        Value::Slab { class: class as u8, page, chunk, len: data.len() as u32 }
+    }
+
+    pub(crate) fn store_list(&mut self, items: Vec<Vec<u8>>) -> Value {
+        let mut list = Value::List { items: VecDeque::with_capacity(items.len()), len: 0 };
+        items.into_iter().for_each(|data| self.push(&mut list, End::Back, data));
+        list
+    }
+
+    /// Adds an element to a list, other values are left as they are.
+    pub(crate) fn push(&mut self, list: &mut Value, end: End, data: Vec<u8>) {
+        if let Value::List { items, len } = list {
+            self.reserved += element_len(&data);
+            *len += element_len(&data);
+            match end {
+                End::Front => items.push_front(data),
+                End::Back => items.push_back(data),
+            }
+        }
+    }
+
+    pub(crate) fn store_set(&mut self, members: BTreeSet<Vec<u8>>) -> Value {
+        let len = members.iter().map(|member| element_len(member)).sum();
+        self.reserved += len;
+        Value::Set { members, len }
+    }
+
+    /// Adds a member to a set, false if it is there already or the value is not a set.
+    pub(crate) fn insert_member(&mut self, set: &mut Value, member: Vec<u8>) -> bool {
+        match set {
+            Value::Set { members, len } if !members.contains(&member) => {
+                self.reserved += element_len(&member);
+                *len += element_len(&member);
+                members.insert(member)
+            },
+            _ => false,
+        }
+    }
+
+    /// Removes a member of a set, false if it is not there or the value is not a set.
+    pub(crate) fn remove_member(&mut self, set: &mut Value, member: &[u8]) -> bool {
+        let (members, len) = match set {
+            Value::Set { members, len } => (members, len),
+            _ => return false,
+        };
+        let removed = members.remove(member);
+        if removed {
+            self.reserved -= element_len(member);
+            *len -= element_len(member);
+        }
+        removed
+    }
+
+    pub(crate) fn store_map(&mut self, fields: BTreeMap<Vec<u8>, Vec<u8>>) -> Value {
+        let len = fields.iter().map(|(field, value)| element_len(field) + element_len(value)).sum();
+        self.reserved += len;
+        Value::Map { fields, len }
+    }
+
+    /// Sets a field of a map, returning its previous value.
+    /// `None` if the field is new or the value is not a map.
+    pub(crate) fn insert_field(&mut self, map: &mut Value, field: Vec<u8>, value: Vec<u8>) -> Option<Vec<u8>> {
+        let (fields, len) = match map {
+            Value::Map { fields, len } => (fields, len),
+            _ => return None,
+        };
+        // a new field brings headers of itself and its value
+        let field_len = element_len(&field) + ELEMENT_LEN;
+        let (added, previous) = (value.len(), fields.insert(field, value));
+        let freed = match &previous {
+            Some(previous) => previous.len(),
+            None => {
+                self.reserved += field_len;
+                *len += field_len;
+                0
+            },
+        };
+        self.reserved = self.reserved + added - freed;
+        *len = *len + added - freed;
+        previous
+    }
+
+    /// Removes a field of a map, returning its value.
+    pub(crate) fn remove_field(&mut self, map: &mut Value, field: &[u8]) -> Option<Vec<u8>> {
+        let (fields, len) = match map {
+            Value::Map { fields, len } => (fields, len),
+            _ => return None,
+        };
+        let value = fields.remove(field)?;
+        self.reserved -= element_len(field) + element_len(&value);
+        *len -= element_len(field) + element_len(&value);
+        Some(value)
+    }
+
+    pub(crate) fn store_counter(&mut self, value: i64, overflow: Overflow, refresh: Option<Duration>) -> Value {
+        self.reserved += COUNTER_LEN;
+        Value::Counter { value, overflow, refresh }
+    }
+
+    /// Takes an element of a list, `None` if it is empty or not a list.
+    pub(crate) fn pop(&mut self, list: &mut Value, end: End) -> Option<Vec<u8>> {
+        let (items, len) = match list {
+            Value::List { items, len } => (items, len),
+            _ => return None,
+        };
+        let data = match end {
+            End::Front => items.pop_front(),
+            End::Back => items.pop_back(),
+        }?;
+        self.reserved -= element_len(&data);
+        *len -= element_len(&data);
+        Some(data)
+    }
+
+    /// bytes of a plain value, collections have none in a single piece
+    pub(crate) fn get<'a>(&'a self, value: &'a Value) -> &'a [u8] {
+        match value {
+            Value::Heap(data) => data,
+            Value::Slab { class, page, chunk, len } => {
+                &self.classes[*class as usize].chunk(*page, *chunk)[..*len as usize]
+            },
+            Value::List { .. } | Value::Set { .. } | Value::Map { .. } | Value::Counter { .. } => &[],
+        }
+    }
+
+    /// Copy of the value which slabs don't account for, see [`Slabs::attach`].
+    pub(crate) fn detach(&self, value: &Value) -> Value {
+        match value {
+            Value::Heap(_) | Value::Slab { .. } => Value::Heap(self.get(value).to_vec()),
+            Value::List { items, len } => Value::List { items: items.clone(), len: *len },
+            Value::Set { members, len } => Value::Set { members: members.clone(), len: *len },
+            Value::Map { fields, len } => Value::Map { fields: fields.clone(), len: *len },
+            Value::Counter { value, overflow, refresh } => {
+                Value::Counter { value: *value, overflow: *overflow, refresh: *refresh }
+            },
+        }
+    }
+
+    /// Stores a detached value again.
+    pub(crate) fn attach(&mut self, value: Value) -> Value {
+        match value {
+            Value::Heap(data) => self.store(data),
+            value => {
+                self.reserved += self.size_of(&value);
+                value
+            },
+        }
+    }
+
+    /// Frees the value, returning its bytes.
+    pub(crate) fn release(&mut self, value: Value) -> Vec<u8> {
+        match value {
+            Value::Heap(data) => {
+                self.reserved -= data.len();
+                data
+            },
+            value => {
+                let data = self.get(&value).to_vec();
+                self.free(value);
+                data
+            },
+        }
+    }
+
+    pub(crate) fn free(&mut self, value: Value) {
+        match value {
+            Value::Heap(data) => self.reserved -= data.len(),
+            Value::List { len, .. } | Value::Set { len, .. } | Value::Map { len, .. } => self.reserved -= len,
+            Value::Counter { .. } => self.reserved -= COUNTER_LEN,
+            Value::Slab { class, page, chunk, .. } => {
+                let class = &mut self.classes[class as usize];
+                if class.free(page, chunk) {
+                    self.reserved -= class.page_bytes();
+                }
+            },
+        }
+    }
+}
+
+/// chunks are kept 8 byte aligned
+fn align(size: usize) -> usize {
+    (size + 7) & !7
+}
@@ -0,0 +1,17 @@
+use alloc::vec::Vec;
+
+use crate::clock::Timestamp;
+
+/// Second tier receiving items displaced from memory instead of dropping them,
+/// see [`crate::Memcached::set_cold_tier`].
+pub trait ColdTier: Send + Sync {
+    /// Keeps displaced item, `ttl` is the deadline on the store clock.
+    /// Tier may drop the item, e.g. if it is full.
+    fn spill(&mut self, key: &str, data: &[u8], ttl: Option<Timestamp>);
+
+    /// removes and returns the item with its deadline
+    fn take(&mut self, key: &str) -> Option<(Vec<u8>, Option<Timestamp>)>;
+
+    /// removes the item without reading it
+    fn forget(&mut self, key: &str);
+}
@@ -0,0 +1,334 @@
+use core::cmp::max;
+use alloc::vec::Vec;
+
+use crate::{Key, clock::Timestamp};
+
+const SLOT_BITS: u32 = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+/// 11 levels of 6 bits cover the whole u64 tick range, so nothing overflows the wheel
+const LEVELS: usize = 11;
+/// list of entries which deadline has passed but which were not taken yet
+const DUE: usize = LEVELS * SLOTS;
+const NIL: usize = usize::MAX;
+/// bytes a registered deadline takes
+pub(crate) const ENTRY_SIZE: usize = core::mem::size_of::<Entry>();
+
+/// Handle of a registered deadline, valid until it is removed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimerHandle(usize);
+
+struct Entry {
+    /// none for free entries
+    key: Option<Key>,
+    tick: u64,
+    list: usize,
+    prev: usize,
+    next: usize,
+}
+
+/// Hierarchical timer wheel with millisecond resolution.
+///
+/// Level `n` has 64 slots each covering `64^n` ticks. Registering and removing
+/// a deadline is O(1), entries cascade to finer levels as time approaches them,
+/// so advancing only touches slots which are due.
+pub struct TimerWheel {
+    /// every tick up to and including this one is processed,
+    /// so all entries in slots have greater ticks
+    elapsed: u64,
+    heads: Vec<usize>,
+    occupied: [u64; LEVELS],
+    entries: Vec<Entry>,
+    free: Vec<usize>,
+    len: usize,
+}
+
+impl Default for TimerWheel {
+    fn default() -> TimerWheel {
+        TimerWheel {
+            elapsed: 0,
+            heads: alloc::vec![NIL; DUE + 1],
+            occupied: [0; LEVELS],
+            entries: Vec::new(),
+            free: Vec::new(),
+            len: 0,
+        }
+    }
+}
+
+impl TimerWheel {
+    pub fn insert(&mut self, key: Key, deadline: Timestamp) -> TimerHandle {
+        let entry = Entry { key: Some(key), tick: tick(deadline), list: NIL, prev: NIL, next: NIL };
+        let idx = match self.free.pop() {
+            Some(idx) => {
+                self.entries[idx] = entry;
+                idx
+            },
+            None => {
+                self.entries.push(entry);
+                self.entries.len() - 1
+            },
+        };
+
+        self.link(idx);
+        self.len += 1;
+
+        TimerHandle(idx)
+    }
+
+    pub fn remove(&mut self, handle: TimerHandle) {
+        self.unlink(handle.0);
+        self.entries[handle.0].key = None;
+        self.free.push(handle.0);
+        self.len -= 1;
+    }
+
+    /// moves deadline of a registered key, handle stays valid
+    pub fn reschedule(&mut self, handle: TimerHandle, deadline: Timestamp) {
+        self.unlink(handle.0);
+        self.entries[handle.0].tick = tick(deadline);
+        self.link(handle.0);
+    }
+
+    /// Returns a key which deadline is before `now` (it stays registered until removed)
+    /// or None if there are no such keys.
+    pub fn next_expired(&mut self, now: Timestamp) -> Option<Key> {
+        // ticks are shifted by one, so everything up to `upto` is strictly before `now`
+        let upto = tick(now) - 1;
+
+        loop {
+            if self.heads[DUE] != NIL {
+                return self.entries[self.heads[DUE]].key.clone()
+            }
+
+            match self.next_slot() {
+                Some((list, deadline)) if deadline <= upto => self.cascade(list, deadline),
+                _ => {
+                    self.elapsed = max(self.elapsed, upto);
+                    return None
+                },
+            }
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    #[cfg(test)]
+    pub(crate) fn key(&self, handle: TimerHandle) -> &Key {
+        self.entries[handle.0].key.as_ref().unwrap()
+    }
+}
+
+impl TimerWheel {
+    /// finds the earliest non empty slot and the first tick it covers
+    fn next_slot(&self) -> Option<(usize, u64)> {
+        (0..LEVELS).find_map(|level| {
+            let current = digit(self.elapsed, level);
+            let ahead = match current {
+                63 => 0,
+                current => self.occupied[level] & (!0u64 << (current + 1)),
+            };
+            if ahead == 0 {
+                return None
+            }
+
+            let slot = ahead.trailing_zeros() as u64;
+            let level_start = match SLOT_BITS as usize * (level + 1) {
+                shift if shift >= 64 => 0,
+                shift => self.elapsed & !((1u64 << shift) - 1),
+            };
+            let deadline = level_start + (slot << (SLOT_BITS as usize * level));
+
+            Some((level * SLOTS + slot as usize, deadline))
+        })
+    }
+
+    /// moves entries of the slot to finer levels or to due list
+    fn cascade(&mut self, list: usize, deadline: u64) {
+        self.elapsed = deadline;
+
+        let mut idx = self.heads[list];
+        self.heads[list] = NIL;
+        self.occupied[list / SLOTS] &= !(1 << (list % SLOTS));
+
+        while idx != NIL {
+            let next = self.entries[idx].next;
+            self.link(idx);
+            idx = next;
+        }
+    }
+
+    fn link(&mut self, idx: usize) {
+        let tick = self.entries[idx].tick;
+        let list = match tick <= self.elapsed {
+            true => DUE,
+            false => {
+                let level = level_for(self.elapsed, tick);
+                level * SLOTS + digit(tick, level) as usize
+            },
+        };
+
+        let head = self.heads[list];
+        if head != NIL {
+            self.entries[head].prev = idx;
+        }
+
+        let entry = &mut self.entries[idx];
+        entry.list = list;
+        entry.prev = NIL;
+        entry.next = head;
+
+        self.heads[list] = idx;
+        if list != DUE {
+            self.occupied[list / SLOTS] |= 1 << (list % SLOTS);
+        }
+    }
+
+    fn unlink(&mut self, idx: usize) {
+        let Entry { list, prev, next, .. } = self.entries[idx];
+
+        match prev {
+            NIL => self.heads[list] = next,
+            prev => self.entries[prev].next = next,
+        }
+        if next != NIL {
+            self.entries[next].prev = prev;
+        }
+
+        if list != DUE && self.heads[list] == NIL {
+            self.occupied[list / SLOTS] &= !(1 << (list % SLOTS));
+        }
+    }
+}
+
+/// milliseconds shifted by one, so tick 0 is never a deadline
+fn tick(ts: Timestamp) -> u64 {
+    let millis = ts.as_millis();
+    if millis >= u64::MAX as u128 {
+        return u64::MAX
+    }
+    millis as u64 + 1
+}
+
+fn digit(tick: u64, level: usize) -> u64 {
+    (tick >> (SLOT_BITS as usize * level)) & (SLOTS as u64 - 1)
+}
+
+/// level is defined by the most significant digit in which tick differs from elapsed
+fn level_for(elapsed: u64, tick: u64) -> usize {
+    let masked = (elapsed ^ tick) | (SLOTS as u64 - 1);
+    let significant = 63 - masked.leading_zeros();
+    (significant / SLOT_BITS) as usize
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::time::Duration;
+
+    fn drain(wheel: &mut TimerWheel, handles: &mut Vec<(TimerHandle, Timestamp)>, now: Timestamp) {
+        while let Some(key) = wheel.next_expired(now) {
+            let pos = handles.iter().position(|&(h, _)| *wheel.key(h) == key).unwrap();
+            let (handle, deadline) = handles.swap_remove(pos);
+            assert!(deadline < now, "{:?} is not before {:?}", deadline, now);
+            wheel.remove(handle);
+        }
+    }
+
+    #[test]
+    fn expires_strictly_after_deadline() {
+        let mut wheel = TimerWheel::default();
+        wheel.insert("a".into(), Duration::from_millis(100));
+
+        assert_eq!(wheel.next_expired(Duration::from_millis(100)).as_deref(), None);
+        assert_eq!(wheel.next_expired(Duration::from_micros(100_999)).as_deref(), None);
+        assert_eq!(wheel.next_expired(Duration::from_millis(101)).as_deref(), Some("a"));
+        assert_eq!(wheel.len(), 1);
+    }
+
+    #[test]
+    fn remove_before_deadline() {
+        let mut wheel = TimerWheel::default();
+        let a = wheel.insert("a".into(), Duration::from_millis(100));
+        wheel.insert("b".into(), Duration::from_millis(100));
+        wheel.remove(a);
+
+        assert_eq!(wheel.next_expired(Duration::from_secs(1)).as_deref(), Some("b"));
+        assert_eq!(wheel.len(), 1);
+    }
+
+    #[test]
+    fn far_deadlines() {
+        let mut wheel = TimerWheel::default();
+        let a = wheel.insert("a".into(), Duration::from_secs(86400 * 365 * 100));
+        let b = wheel.insert("b".into(), Duration::MAX);
+
+        assert_eq!(wheel.next_expired(Duration::from_secs(86400 * 365 * 99)).as_deref(), None);
+        assert_eq!(wheel.next_expired(Duration::from_secs(86400 * 365 * 101)).as_deref(), Some("a"));
+        wheel.remove(a);
+        assert_eq!(wheel.next_expired(Duration::from_secs(86400 * 365 * 1000)).as_deref(), None);
+        wheel.remove(b);
+        assert_eq!(wheel.len(), 0);
+    }
+
+    #[test]
+    fn reschedule() {
+        let mut wheel = TimerWheel::default();
+        let a = wheel.insert("a".into(), Duration::from_millis(100));
+        wheel.reschedule(a, Duration::from_secs(10));
+
+        assert_eq!(wheel.next_expired(Duration::from_secs(1)).as_deref(), None);
+        wheel.reschedule(a, Duration::from_millis(500));
+        assert_eq!(wheel.next_expired(Duration::from_secs(1)).as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn insert_already_due() {
+        let mut wheel = TimerWheel::default();
+        assert_eq!(wheel.next_expired(Duration::from_secs(10)).as_deref(), None);
+        wheel.insert("a".into(), Duration::from_secs(5));
+        assert_eq!(wheel.next_expired(Duration::from_secs(10)).as_deref(), Some("a"));
+    }
+
+    /// compares the wheel with a naive model on pseudo random operations
+    #[test]
+    fn matches_model() {
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut random = move |bound: u64| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed % bound
+        };
+
+        let mut wheel = TimerWheel::default();
+        let mut handles = Vec::new();
+        let mut now = Duration::from_millis(0);
+        let mut keys = (0..).map(|i: u64| Key::from(i.to_string()));
+
+        for _ in 0..20_000 {
+            match random(10) {
+                0..=5 => {
+                    let horizon = [10, 1_000, 100_000, 10_000_000][random(4) as usize];
+                    let deadline = now + Duration::from_micros(random(horizon * 1000));
+                    let key = keys.next().unwrap();
+                    handles.push((wheel.insert(key, deadline), deadline));
+                },
+                6 if !handles.is_empty() => {
+                    let (handle, _) = handles.swap_remove(random(handles.len() as u64) as usize);
+                    wheel.remove(handle);
+                },
+                _ => {
+                    now += Duration::from_micros(random(5_000_000));
+                    drain(&mut wheel, &mut handles, now);
+                    // only deadlines within the current millisecond may be left behind
+                    assert!(handles.iter().all(|&(_, deadline)| deadline.as_millis() >= now.as_millis()));
+                },
+            }
+            assert_eq!(wheel.len(), handles.len());
+        }
+    }
+}
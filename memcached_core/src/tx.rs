@@ -0,0 +1,197 @@
+//! Transactions: writes of a transaction are either all kept or all undone, and the listener
+//! hears about them only once they are kept.
+
+use alloc::{string::String, vec::Vec};
+use core::time::Duration;
+
+use crate::{
+    clock::{Clock, Timestamp},
+    events::{Event, EventKind},
+    slab::Value,
+    hasher::Prehashed,
+    report, Item, Key, Memcached, Priority,
+};
+
+/// Open transaction, see [`Memcached::begin`].
+#[derive(Default)]
+pub(crate) struct Tx {
+    /// keys saved in the transaction with their items as they were, `None` for missing ones
+    saved: Vec<(String, Option<Saved>)>,
+    /// events held back until commit
+    held: Vec<HeldEvent>,
+}
+
+/// Item detached from slabs and indexes.
+struct Saved {
+    data: Value,
+    touch: Timestamp,
+    ttl: Option<Timestamp>,
+    sliding: Option<Duration>,
+    version: u64,
+    written: Timestamp,
+    pinned: bool,
+    priority: Priority,
+    revalidating: bool,
+    negative: bool,
+}
+
+/// [`Event`] owning its key and data.
+pub(crate) struct HeldEvent {
+    kind: EventKind,
+    key: String,
+    size: usize,
+    data: Vec<u8>,
+    negative: bool,
+    collection: bool,
+    ttl: Option<Timestamp>,
+    stored_at: Timestamp,
+    priority: Priority,
+    at: Timestamp,
+}
+
+impl HeldEvent {
+    pub(crate) fn new(event: &Event) -> HeldEvent {
+        HeldEvent {
+            kind: event.kind,
+            key: event.key.into(),
+            size: event.size,
+            data: event.data.to_vec(),
+            negative: event.negative,
+            collection: event.collection,
+            ttl: event.ttl,
+            stored_at: event.stored_at,
+            priority: event.priority,
+            at: event.at,
+        }
+    }
+
+    fn event(&self) -> Event<'_> {
+        Event {
+            kind: self.kind,
+            key: &self.key,
+            size: self.size,
+            data: &self.data,
+            negative: self.negative,
+            collection: self.collection,
+            ttl: self.ttl,
+            stored_at: self.stored_at,
+            priority: self.priority,
+            at: self.at,
+        }
+    }
+}
+
+impl Tx {
+    /// Holds the event back, `false` if the transaction is not open.
+    pub(crate) fn hold(tx: &mut Option<Tx>, event: &Event) -> bool {
+        match tx {
+            Some(tx) => {
+                tx.held.push(HeldEvent::new(event));
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+impl<C: Clock> Memcached<C> {
+    /// Opens a transaction: keys [`Memcached::save`]d in it are put back as they were by
+    /// [`Memcached::rollback`], and events are held back until [`Memcached::commit`].
+    /// Items displaced or expired meanwhile are not put back.
+    pub fn begin(&mut self) {
+        debug_assert!(self.tx.is_none(), "transaction is already open");
+        self.tx = Some(Tx::default());
+    }
+
+    /// Remembers the item of the key as it is now, any kind of item including expired ones,
+    /// so rollback puts it back. Keys should be saved before they are written, only the first
+    /// save of a key in a transaction counts. Does nothing without an open transaction.
+    pub fn save(&mut self, key: &str) {
+        let hash = self.hash(key);
+        let saved = self.cache.find(hash, key).map(|item| Saved {
+            data: self.slabs.detach(&item.data),
+            touch: item.touch,
+            ttl: item.ttl,
+            sliding: item.sliding,
+            version: item.version,
+            written: item.written,
+            pinned: item.pinned,
+            priority: item.priority,
+            revalidating: item.revalidating,
+            negative: item.negative,
+        });
+        match &mut self.tx {
+            Some(tx) if !tx.saved.iter().any(|(saved, _)| saved == key) => tx.saved.push((key.into(), saved)),
+            _ => (),
+        }
+    }
+
+    /// Keeps writes of the transaction and reports held events.
+    pub fn commit(&mut self) {
+        if let Some(tx) = self.tx.take() {
+            tx.held.iter().for_each(|held| report(&self.listener, &mut None, &held.event()));
+        }
+    }
+
+    /// Puts saved keys back as they were, only displacements and expirations are reported.
+    /// Returns false if an item doesn't fit back, which may happen only when slab pages
+    /// of its class were taken meanwhile, the key is missing then.
+    pub fn rollback(&mut self) -> bool {
+        let tx = match self.tx.take() {
+            Some(tx) => tx,
+            None => return true,
+        };
+        let mut restored = true;
+        for (key, saved) in tx.saved {
+            let hash = self.hash(&key);
+            if let Some((_, item)) = self.remove(hash, &key) {
+                self.slabs.free(item.data);
+            }
+            if let Some(saved) = saved {
+                restored &= self.reinstate(hash, key, saved);
+            }
+        }
+        tx.held.iter()
+            .filter(|held| matches!(held.kind, EventKind::Evicted | EventKind::Expired))
+            .for_each(|held| report(&self.listener, &mut None, &held.event()));
+        restored
+    }
+
+    fn reinstate(&mut self, hash: u64, key: String, saved: Saved) -> bool {
+        let (size, plain) = match &saved.data {
+            Value::Heap(data) => (self.slabs.size_for(data.len()), true),
+            data => (self.slabs.size_of(data), false),
+        };
+        if !self.make_room(size, plain, true) {
+            return false
+        }
+        let data = self.slabs.attach(saved.data);
+        let key = Key::from(key);
+        if !saved.pinned {
+            self.add_to_touch(key.clone(), saved.priority, saved.touch);
+        }
+        let grace = self.options.stale_grace;
+        let timer = saved.ttl.map(|ttl| self.keys_by_ttl.insert(key.clone(), ttl + grace));
+        let size = self.slabs.size_of(&data);
+        self.current_size += size;
+        self.account(&key, size, 0);
+
+        let item = Item {
+            data, timer,
+            touch: saved.touch,
+            ttl: saved.ttl,
+            sliding: saved.sliding,
+            version: saved.version,
+            written: saved.written,
+            pinned: saved.pinned,
+            priority: saved.priority,
+            revalidating: saved.revalidating,
+            negative: saved.negative,
+        };
+        self.cache.put(hash, key, item);
+        if let Some(filter) = &self.filter {
+            filter.add(hash);
+        }
+        true
+    }
+}
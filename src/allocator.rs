@@ -0,0 +1,82 @@
+use serde::Serialize;
+use utoipa::ToSchema;
+
+#[cfg(all(feature = "jemalloc", feature = "mimalloc"))]
+compile_error!("jemalloc and mimalloc features can't be enabled together");
+
+#[cfg(feature = "jemalloc")]
+#[global_allocator]
+static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+#[cfg(feature = "mimalloc")]
+#[global_allocator]
+static GLOBAL: mimalloc_alloc::MiMalloc = mimalloc_alloc::MiMalloc;
+
+/// Memory as seen by the global allocator, fields it can't tell are omitted.
+/// Resident far above the store size means the heap is fragmented.
+#[derive(Serialize, Default, ToSchema)]
+pub struct AllocatorStats {
+    allocator: &'static str,
+    /// bytes of live allocations
+    #[serde(skip_serializing_if = "Option::is_none")]
+    allocated: Option<u64>,
+    /// bytes of pages holding live allocations, or committed by the allocator
+    #[serde(skip_serializing_if = "Option::is_none")]
+    active: Option<u64>,
+    /// bytes of physically resident pages
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resident: Option<u64>,
+    /// share of resident memory not taken by live allocations
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fragmentation: Option<f64>,
+}
+
+#[cfg(feature = "jemalloc")]
+pub fn stats() -> AllocatorStats {
+    use tikv_jemalloc_ctl::{epoch, stats};
+
+    // statistics are cached by jemalloc until the epoch is advanced
+    let _ = epoch::advance();
+    let allocated = stats::allocated::read().ok().map(|bytes| bytes as u64);
+    let resident = stats::resident::read().ok().map(|bytes| bytes as u64);
+    AllocatorStats {
+        allocator: "jemalloc",
+        allocated,
+        active: stats::active::read().ok().map(|bytes| bytes as u64),
+        resident,
+        fragmentation: fragmentation(allocated, resident),
+    }
+}
+
+#[cfg(feature = "mimalloc")]
+pub fn stats() -> AllocatorStats {
+    let (mut elapsed, mut user, mut system) = (0, 0, 0);
+    let (mut rss, mut peak_rss, mut commit, mut peak_commit, mut faults) = (0, 0, 0, 0, 0);
+    unsafe {
+        libmimalloc_sys::mi_process_info(
+            &mut elapsed, &mut user, &mut system,
+            &mut rss, &mut peak_rss, &mut commit, &mut peak_commit, &mut faults,
+        );
+    }
+    AllocatorStats {
+        allocator: "mimalloc",
+        active: Some(commit as u64),
+        resident: Some(rss as u64),
+        ..Default::default()
+    }
+}
+
+#[cfg(not(any(feature = "jemalloc", feature = "mimalloc")))]
+pub fn stats() -> AllocatorStats {
+    AllocatorStats { allocator: "system", ..Default::default() }
+}
+
+#[cfg(feature = "jemalloc")]
+fn fragmentation(allocated: Option<u64>, resident: Option<u64>) -> Option<f64> {
+    match (allocated, resident) {
+        (Some(allocated), Some(resident)) if resident > 0 => {
+            Some(1.0 - allocated as f64 / resident as f64)
+        },
+        _ => None,
+    }
+}
@@ -1,105 +1,4412 @@
 use serde::{Serialize, Deserialize};
 use actix_web::{
-    post, HttpResponse as Code,
-    Responder, Scope,
-    web::{Data, scope, Json},
+    delete, get, patch, post, put, HttpRequest, HttpResponse, HttpResponse as Code,
+    ResponseError, Scope,
+    http::{StatusCode, header::{CONTENT_TYPE, ETAG, IF_MATCH, IF_NONE_MATCH, HeaderValue}},
+    web::{Bytes, Data, scope, Json, Path, Payload, PayloadConfig, Query},
 };
 use std::{
-    thread,
-    sync::{RwLock, Arc},
+    collections::BTreeMap,
+    convert::TryFrom,
+    sync::Arc,
     time::Duration,
 };
 use duration_string::DurationString;
+use chrono::{DateTime, FixedOffset, Utc};
+use tracing::{error, instrument, Span, field::Empty};
+use utoipa::{IntoParams, OpenApi, ToSchema};
 
-use crate::memcached::Memcached;
-
-impl Memcached {
-    pub fn service(self, gc_interval: Duration) -> impl (Fn() -> Scope) + Clone {
-        let mc = Arc::new(RwLock::new(self));
+use crate::{
+    memcached::{self, Memcached, Store, Dump, Contents, Freshness, IncrError, End, CollectionError, CounterError, CounterOptions, Overflow, GcBudget},
+    namespaces::Namespaces,
+    decompress::{DecodedJson, DecompressConfig},
+    errors::{Error, ErrorResp, json_config},
+    jobs::Jobs,
+    l1::{L1, L1Config},
+    audit::{AuditKey, key_hash},
+    stats::{Bucket, Distribution, Forecast, ItemClass, ItemClasses},
+    events::EventBus,
+    auth::DEFAULT_NAMESPACE,
+    pubsub::PubSub,
+    origin::{Origin, OriginConfig, Write},
+    cluster::{self, Cluster},
+    jsonpatch::{self, PatchOp, PatchError},
+    logging::{LogFilter, Outcome},
+    slowlog::{SlowLog, SlowOp},
+    allocator::AllocatorStats,
+    jobs::{JobState, JobStatus},
+    export::{Imported, ImportLimits, PriorityLevel},
+    digest::{self, Digest},
+};
 
-        let mc_for_gc = mc.clone();
-        thread::spawn(move || gc(mc_for_gc, gc_interval));
+#[allow(clippy::too_many_arguments)]
+pub fn service(
+    mc: Arc<Store>, namespaces: Arc<Namespaces>, events: Arc<EventBus>,
+    json_limit: usize, decompress_limit: usize, import_limit: usize, l1: L1Config, origin: Option<OriginConfig>,
+    cluster: Option<Cluster>, slow_ops: Arc<SlowLog>, log_filter: Arc<LogFilter>,
+) -> impl (Fn() -> Scope) + Clone {
+    let jobs = Arc::new(Jobs::default());
+    let pubsub = Arc::new(PubSub::default());
+    let origin = origin.map(|origin| Arc::new(Origin::new(origin)));
+    let cluster = cluster.map(Arc::new);
+    let clock = mc.blocking_read().clock().clone();
 
-        move || scope("/")
+    // called once per worker, so every worker gets its own L1
+    move || {
+        let api = scope("/")
+            .app_data(Data::new(L1::new(clock.clone(), l1.clone())))
             .app_data(Data::from(mc.clone()))
+            .app_data(Data::from(namespaces.clone()))
+            .app_data(Data::from(jobs.clone()))
+            .app_data(Data::from(events.clone()))
+            .app_data(Data::from(pubsub.clone()))
+            .app_data(Data::from(slow_ops.clone()))
+            .app_data(Data::from(log_filter.clone()))
+            .app_data(json_config(json_limit))
+            .app_data(DecompressConfig { payload_limit: json_limit, limit: decompress_limit })
+            .app_data(PayloadConfig::new(json_limit))
+            .app_data(Data::new(ImportLimits { line: json_limit, body: import_limit }))
             .service(get)
             .service(set)
+            .service(set_negative)
             .service(delete)
+            .service(get_key)
+            .service(put_key)
+            .service(delete_key)
+            .service(get_json)
+            .service(put_json)
+            .service(patch_json)
+            .service(getset)
+            .service(gat)
+            .service(lpush)
+            .service(rpush)
+            .service(lpop)
+            .service(rpop)
+            .service(lrange)
+            .service(sadd)
+            .service(srem)
+            .service(sismember)
+            .service(smembers)
+            .service(hset)
+            .service(hget)
+            .service(hdel)
+            .service(hgetall)
+            .service(count)
+            .service(counter)
+            .service(pin)
+            .service(unpin)
+            .service(forecast)
+            .service(distribution)
+            .service(item_classes)
+            .service(memory_usage)
+            .service(allocator_stats)
+            .service(slowlog)
+            .service(acquire_lock)
+            .service(release_lock)
+            .service(dump_bulk)
+            .service(restore_bulk)
+            .service(export)
+            .service(import)
+            .service(batch)
+            .service(transaction)
+            .service(dump_key)
+            .service(restore)
+            .service(watch)
+            .service(sse)
+            .service(publish)
+            .service(subscribe)
+            .service(openapi_json)
+            .service(scope("/ns/{name}")
+                .service(ns_get)
+                .service(ns_set)
+                .service(ns_set_negative)
+                .service(ns_getset)
+                .service(ns_gat)
+                .service(ns_lpush)
+                .service(ns_rpush)
+                .service(ns_lpop)
+                .service(ns_rpop)
+                .service(ns_lrange)
+                .service(ns_sadd)
+                .service(ns_srem)
+                .service(ns_sismember)
+                .service(ns_smembers)
+                .service(ns_hset)
+                .service(ns_hget)
+                .service(ns_hdel)
+                .service(ns_hgetall)
+                .service(ns_count)
+                .service(ns_counter)
+                .service(ns_pin)
+                .service(ns_unpin)
+                .service(ns_forecast)
+                .service(ns_distribution)
+                .service(ns_item_classes)
+                .service(ns_memory_usage)
+                .service(ns_acquire_lock)
+                .service(ns_release_lock)
+                .service(ns_dump_bulk)
+                .service(ns_restore_bulk)
+                .service(ns_export)
+                .service(ns_import)
+                .service(ns_batch)
+                .service(ns_transaction)
+                .service(ns_dump_key)
+                .service(ns_restore)
+                .service(ns_watch)
+                .service(ns_sse)
+                .service(ns_delete)
+                .service(ns_get_key)
+                .service(ns_put_key)
+                .service(ns_delete_key)
+                .service(ns_get_json)
+                .service(ns_put_json)
+                .service(ns_patch_json)
+            )
+            .service(scope("/admin/ns")
+                .service(create_namespace)
+                .service(drop_namespace)
+            )
+            .service(scope("/admin/jobs")
+                .service(job_status)
+                .service(cancel_job)
+            )
+            .service(flush)
+            .service(run_gc)
+            .service(keyspace_digest)
+            .service(set_log_level);
+        let api = match &origin {
+            Some(origin) => api.app_data(Data::from(origin.clone())),
+            None => api,
+        };
+        match &cluster {
+            Some(cluster) => api.app_data(Data::from(cluster.clone())),
+            None => api,
+        }
     }
 }
 
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 struct GetReq {
     key: String,
+    /// return value expired within grace period instead of not found
+    #[serde(default)]
+    allow_stale: bool,
+    /// on miss, ask for a lease to fill the key for this long, see [`LeaseResp`]
+    #[schema(value_type = Option<String>, example = "10s")]
+    lease: Option<DurationString>,
+    /// a key owned by a cluster peer is asked for again if the peer is slow, see `cluster_hedge_delay`.
+    /// Keys served here are not, their only copy is behind the store lock a second attempt would wait for as well
+    #[serde(default)]
+    latency_critical: bool,
+}
+
+/// Miss response to a get with `lease`: the caller holds the lease and is expected
+/// to set the key passing the token. Gets meanwhile are answered with conflict.
+///
+/// With `early_expiration_beta` set, a get of a value near its deadline may be answered
+/// with `refresh` instead: the value is still there, but the caller is picked to refresh it.
+/// Gets meanwhile keep getting the value while the caller holds a lease.
+#[derive(Serialize, ToSchema)]
+struct LeaseResp {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lease: Option<u64>,
+    /// miss reported ahead of the deadline
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    refresh: bool,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Default, ToSchema)]
 struct GetResp {
     data: String,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stale: bool,
+    /// caller is the first to get stale value and is expected to set a fresh one
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    revalidate: bool,
 }
 
+#[utoipa::path(
+    post,
+    path = "/get",
+    request_body = GetReq,
+    responses(
+        (status = 200, description = "value, `ETag` is its version", body = GetResp),
+        (status = 204, description = "key is marked missing by set_negative"),
+        (status = 304, description = "value has the version from `If-None-Match`"),
+        (status = 404, description = "key not found, with a lease if it was requested", body = LeaseResp),
+        (status = 409, description = "value is being filled by a lease holder", body = ErrorResp),
+    ),
+)]
 #[post("/get")]
 async fn get(
-    mc: Data<RwLock<Memcached>>,
+    mc: Data<Store>,
+    l1: Data<L1>,
+    origin: Option<Data<Origin>>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<GetReq>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied_get(cluster.as_ref().map(Data::get_ref), &http, &req).await {
+        return tagged(key, proxied)
+    }
+    let requested = req.key.clone();
+    let found = match l1.get(&req.key) {
+        Some((data, version)) => json_value(data, Some(version)),
+        None => get_from(&mc, req.0, Some(&l1), json_value).await,
+    };
+    let found = read_through(found, origin.as_ref().map(Data::get_ref), &mc, requested, json_value).await;
+    tagged(key, not_modified(&http, found))
+}
+
+#[utoipa::path(
+    post,
+    path = "/ns/{name}/get",
+    params(("name" = String, Path, description = "namespace")),
+    request_body = GetReq,
+    responses(
+        (status = 200, description = "value, `ETag` is its version", body = GetResp),
+        (status = 204, description = "key is marked missing by set_negative"),
+        (status = 304, description = "value has the version from `If-None-Match`"),
+        (status = 404, description = "key not found, with a lease if it was requested", body = LeaseResp),
+        (status = 409, description = "value is being filled by a lease holder", body = ErrorResp),
+    ),
+)]
+#[post("/get")]
+async fn ns_get(
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
     req: Json<GetReq>,
-) -> impl Responder {
-    match mc.read().unwrap().get(&req.key) {
-        Some(data) => Code::Ok().json(GetResp { data: as_string(data) }),
-        None => Code::NotFound().finish(),
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied_get(cluster.as_ref().map(Data::get_ref), &http, &req).await {
+        return tagged(key, proxied)
+    }
+    let mc = match namespace(&namespaces, &name) {
+        Ok(mc) => mc,
+        Err(err) => return tagged(key, Err(err)),
+    };
+    let requested = req.key.clone();
+    let found = get_from(&mc, req.0, None, json_value).await;
+    let origin = namespaces.origin(&name);
+    let found = read_through(found, origin.as_deref(), &mc, requested, json_value).await;
+    tagged(key, not_modified(&http, found))
+}
+
+/// Forwards the request to the cluster node owning `key`, `None` if it is served here.
+async fn proxied(
+    cluster: Option<&Cluster>, http: &HttpRequest, key: &str, req: &impl Serialize,
+) -> Option<Result<HttpResponse, Error>> {
+    let cluster = cluster.filter(|_| !http.headers().contains_key(cluster::FORWARDED))?;
+    let owner = cluster.owner(key)?;
+    Some(cluster.forward(owner, http, req).await)
+}
+
+/// [`proxied`] hedging latency critical gets, see [`Cluster::forward_hedged`]
+async fn proxied_get(
+    cluster: Option<&Cluster>, http: &HttpRequest, req: &GetReq,
+) -> Option<Result<HttpResponse, Error>> {
+    let cluster = cluster.filter(|_| !http.headers().contains_key(cluster::FORWARDED))?;
+    let owner = cluster.owner(&req.key)?;
+    Some(match req.latency_critical {
+        true => cluster.forward_hedged(owner, http, req).await,
+        false => cluster.forward(owner, http, req).await,
+    })
+}
+
+/// [`proxied`] for key routes, the body is forwarded as it is
+async fn proxied_raw(
+    cluster: Option<&Cluster>, http: &HttpRequest, key: &str, body: Bytes,
+) -> Option<Result<HttpResponse, Error>> {
+    let cluster = cluster.filter(|_| !http.headers().contains_key(cluster::FORWARDED))?;
+    let owner = cluster.owner(key)?;
+    let content_type = http.headers().get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("text/plain");
+    Some(cluster.forward_body(owner, http, content_type, body).await)
+}
+
+/// write to forward if the store has an origin taking writes
+fn forwarded(origin: Option<&Origin>, write: impl FnOnce() -> Write) -> Option<Write> {
+    origin.filter(|origin| origin.takes_writes()).map(|_| write())
+}
+
+fn set_write(req: &SetReq) -> Write {
+    Write::Set { key: req.key.clone(), data: req.data.clone().into_bytes() }
+}
+
+/// What the store kept under a key before a write forwarded to origin.
+type Prior = Option<(Dump, Contents)>;
+
+/// [`Prior`] of the key, taken only if the write will be forwarded.
+async fn before_write(origin: Option<&Origin>, mc: &Store, key: &str) -> Prior {
+    match origin {
+        Some(origin) if origin.takes_writes() => mc.read().await.snapshot(key),
+        _ => None,
+    }
+}
+
+/// Forwards a write which succeeded in the store to its origin.
+/// Failed write-through of a set puts back what the store kept before it,
+/// so the cache doesn't serve what origin lacks. A value written meanwhile by others is kept.
+async fn write_through(
+    stored: Result<HttpResponse, Error>, origin: Option<&Origin>,
+    mc: &Store, write: Option<Write>, prior: Prior,
+) -> Result<HttpResponse, Error> {
+    let forward = match (&stored, &write) {
+        (Ok(_), _) => true,
+        // key missing in the cache may still be present in origin
+        (Err(Error::NotFound("key")), Some(Write::Delete { .. })) => true,
+        _ => false,
+    };
+    let (origin, write) = match (origin, write) {
+        (Some(origin), Some(write)) if forward => (origin, write),
+        _ => return stored,
+    };
+
+    let rollback = match &write {
+        Write::Set { key, data } => Some((key.clone(), data.clone())),
+        Write::Delete { .. } => None,
+    };
+    match origin.write(write).await {
+        Ok(()) => stored,
+        Err(err) => {
+            if let Some((key, data)) = rollback {
+                let mut mc = mc.write().await;
+                if mc.get(&key).as_ref() == Some(&data) {
+                    match prior {
+                        Some((dump, contents)) => {
+                            if mc.restore_snapshot(key, dump, contents).is_err() {
+                                error!("failed to restore a value after failed write-through");
+                            }
+                        },
+                        None => {
+                            mc.delete(&key);
+                        },
+                    }
+                }
+            }
+            Err(err)
+        },
+    }
+}
+
+/// How long a value fetched from origin is awaited before others may fill the key.
+const FILL_LEASE: Duration = Duration::from_secs(10);
+
+/// Builds the response to a found value and its version.
+type Respond = fn(Vec<u8>, Option<u64>) -> Result<HttpResponse, Error>;
+
+/// On miss looks the key up in cold tier of the store and then in its origin,
+/// storing and returning the value.
+async fn read_through(
+    found: Result<HttpResponse, Error>, origin: Option<&Origin>,
+    mc: &Store, key: String, respond: Respond,
+) -> Result<HttpResponse, Error> {
+    if !matches!(found, Err(Error::NotFound("key"))) {
+        return found
     }
+
+    let promoted = {
+        let mut mc = mc.write().await;
+        mc.promote(&key).map(|data| (data, mc.version(&key)))
+    };
+    let (data, version) = match (promoted, origin) {
+        (Some(promoted), _) => return respond(promoted.0, promoted.1),
+        (None, Some(origin)) => {
+            // the lease is ended by any write of the key made while fetching,
+            // so the fetched value never overwrites a newer one
+            let (limit, lease) = {
+                let mut mc = mc.write().await;
+                (mc.limit(), mc.lease(&key, FILL_LEASE))
+            };
+            let fetched = origin.fetch(&key, limit).await;
+
+            let mut mc = mc.write().await;
+            let held = lease.filter(|&token| mc.holds_lease(&key, token));
+            if let (false, Some(token)) = (matches!(fetched, Ok(Some(_))), held) {
+                mc.release_lease(&key, token);
+            }
+            let data = fetched?.ok_or_else(key_not_found)?;
+            match held {
+                // value is returned even if the store can't keep it
+                Some(_) => {
+                    let _ = mc.set(key.clone(), data.clone(), origin.ttl());
+                    (data, mc.version(&key))
+                },
+                None => match mc.get_with_version(&key) {
+                    Some((data, version)) => (data, Some(version)),
+                    None => (data, None),
+                },
+            }
+        },
+        (None, None) => return found,
+    };
+    let mut resp = respond(data, version)?;
+    resp.extensions_mut().insert(Outcome::Filled);
+    Ok(resp)
+}
+
+/// value in [`GetResp`], `ETag` is its version
+fn json_value(data: Vec<u8>, version: Option<u64>) -> Result<HttpResponse, Error> {
+    let mut resp = Code::Ok();
+    if let Some(version) = version {
+        resp.set_header(ETAG, etag(version));
+    }
+    Ok(resp.json(GetResp { data: as_string(data)?, ..Default::default() }))
+}
+
+/// value as the body, for key routes
+fn raw_value(data: Vec<u8>, version: Option<u64>) -> Result<HttpResponse, Error> {
+    let mut resp = Code::Ok();
+    if let Some(version) = version {
+        resp.set_header(ETAG, etag(version));
+    }
+    Ok(resp.content_type("text/plain; charset=utf-8").body(as_string(data)?))
+}
+
+#[instrument(level = "debug", skip(mc, req, l1, respond), fields(key_hash = key_hash(&req.key), value_size = Empty, lock_wait_us = Empty))]
+async fn get_from(mc: &Store, req: GetReq, l1: Option<&L1>, respond: Respond) -> Result<HttpResponse, Error> {
+    let present = mc.may_contain(&req.key);
+    let (data, expired, sliding, negative, early) = match present {
+        true => {
+            let mc = mc.read().await;
+            match mc.get_with_version(&req.key) {
+                Some(data) => {
+                    // drawn under the read lock, the write lock is taken only when a lease is to be granted
+                    let early = mc.refresh_early(&req.key, rand::random());
+                    (Some(data), false, mc.is_sliding(&req.key), false, early)
+                },
+                None => (None, mc.is_expired(&req.key), false, mc.is_negative(&req.key), false),
+            }
+        },
+        false => (None, false, false, false, false),
+    };
+
+    if negative {
+        let mut resp = Code::NoContent().finish();
+        resp.extensions_mut().insert(Outcome::Negative);
+        return Ok(resp)
+    }
+    if data.is_none() && req.allow_stale && !expired && present {
+        return get_stale_from(mc, &req.key).await
+    }
+    if expired {
+        mc.write().await.remove_expired(&req.key);
+    }
+    if sliding {
+        mc.write().await.refresh(&req.key);
+    }
+    if let (true, Some((_, version))) = (early, &data) {
+        if let Some(early) = refresh_early(mc, &req.key, *version, req.lease).await {
+            return Ok(early)
+        }
+    }
+
+    let (data, version) = match data {
+        Some(found) => found,
+        None => return miss(mc, &req.key, req.lease).await,
+    };
+    Span::current().record("value_size", &data.len());
+    // sliding items must reach the store on every read to stay alive
+    if let (Some(l1), false) = (l1, sliding) {
+        l1.put(&req.key, &data, version);
+    }
+    respond(data, Some(version))
+}
+
+async fn miss(mc: &Store, key: &str, lease: Option<DurationString>) -> Result<HttpResponse, Error> {
+    let ttl = lease.ok_or_else(key_not_found)?;
+    match mc.write().await.lease(key, ttl.into()) {
+        Some(lease) => Ok(Code::NotFound().json(LeaseResp { lease: Some(lease), refresh: false })),
+        None => Err(Error::Conflict("value is being filled, retry later")),
+    }
+}
+
+/// Early miss of [`Memcached::refresh_early`], with a lease if the caller asked for one.
+/// The value is served after all if it changed since the draw or somebody else got the lease.
+async fn refresh_early(mc: &Store, key: &str, version: u64, lease: Option<DurationString>) -> Option<HttpResponse> {
+    let lease = match lease {
+        Some(ttl) => {
+            let mut mc = mc.write().await;
+            if mc.version(key) != Some(version) {
+                return None
+            }
+            Some(mc.lease(key, ttl.into())?)
+        },
+        None => None,
+    };
+    let mut resp = Code::NotFound().json(LeaseResp { lease, refresh: true });
+    resp.extensions_mut().insert(Outcome::Miss);
+    Some(resp)
+}
+
+async fn get_stale_from(mc: &Store, key: &str) -> Result<HttpResponse, Error> {
+    let (data, freshness) = mc.write().await.get_stale(key).ok_or_else(key_not_found)?;
+    let resp = match freshness {
+        Freshness::Fresh => GetResp { data: as_string(data)?, ..Default::default() },
+        Freshness::Stale { revalidate } => GetResp { data: as_string(data)?, stale: true, revalidate },
+    };
+    Ok(Code::Ok().json(resp))
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 struct SetReq {
     key: String,
     data: String,
+    /// server `default_ttl` if omitted, capped at server `max_ttl`
+    #[schema(value_type = Option<String>, example = "10s")]
     ttl: Option<DurationString>,
+    /// RFC3339 wall clock deadline, alternative to `ttl`
+    expire_at: Option<DateTime<FixedOffset>>,
+    /// restart ttl on every get, server default if omitted
+    sliding: Option<bool>,
+    /// exempt from eviction until set again or unpinned
+    #[serde(default)]
+    pinned: bool,
+    /// eviction order until set again, lower priority items are evicted first
+    #[serde(default)]
+    priority: PriorityLevel,
+    /// store only if current version is this one (see `ETag` of get), `If-Match` header also works
+    if_version: Option<u64>,
+    /// token of a miss lease, value is not stored if the key was set or deleted since it was granted
+    lease: Option<u64>,
 }
 
+#[utoipa::path(
+    post,
+    path = "/set",
+    request_body = SetReq,
+    responses(
+        (status = 200, description = "stored, `ETag` is the new version"),
+        (status = 304, description = "store can't fit the value"),
+        (status = 412, description = "version or lease doesn't match", body = ErrorResp),
+    ),
+)]
 #[post("/set")]
 async fn set(
-    mc: Data<RwLock<Memcached>>,
-    req: Json<SetReq>,
-) -> impl Responder {
-    let SetReq { key, data, ttl } = req.0;
-    match mc.write().unwrap().set(
-        key, data.into_bytes(),
-        ttl.map(Into::into),
-    ) {
-        Ok(_) => Code::Ok(),
-        Err(_) => Code::NotModified(),
-    }.finish()
-}
-
-#[derive(Deserialize)]
+    mc: Data<Store>,
+    l1: Data<L1>,
+    origin: Option<Data<Origin>>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: DecodedJson<SetReq>,
+) -> Result<HttpResponse, Error> {
+    l1.invalidate(&req.key);
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    let origin = origin.as_ref().map(Data::get_ref);
+    let write = forwarded(origin, || set_write(&req));
+    let prior = before_write(origin, &mc, &req.key).await;
+    let stored = match with_if_match(&http, req.0) {
+        Ok(req) => set_into(&mc, req).await,
+        Err(err) => Err(err),
+    };
+    tagged(key, write_through(stored, origin, &mc, write, prior).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/ns/{name}/set",
+    params(("name" = String, Path, description = "namespace")),
+    request_body = SetReq,
+    responses(
+        (status = 200, description = "stored, `ETag` is the new version"),
+        (status = 304, description = "store can't fit the value"),
+        (status = 412, description = "version or lease doesn't match", body = ErrorResp),
+        (status = 404, description = "namespace not found", body = ErrorResp),
+    ),
+)]
+#[post("/set")]
+async fn ns_set(
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: DecodedJson<SetReq>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    let mc = match namespace(&namespaces, &name) {
+        Ok(mc) => mc,
+        Err(err) => return tagged(key, Err(err)),
+    };
+    let origin = namespaces.origin(&name);
+    let write = forwarded(origin.as_deref(), || set_write(&req));
+    let prior = before_write(origin.as_deref(), &mc, &req.key).await;
+    let stored = match with_if_match(&http, req.0) {
+        Ok(req) => set_into(&mc, req).await,
+        Err(err) => Err(err),
+    };
+    tagged(key, write_through(stored, origin.as_deref(), &mc, write, prior).await)
+}
+
+#[instrument(level = "debug", skip(mc, req), fields(key_hash = key_hash(&req.key), value_size = req.data.len(), lock_wait_us = Empty))]
+async fn set_into(mc: &Store, req: SetReq) -> Result<HttpResponse, Error> {
+    let SetReq { key, data, ttl, expire_at, sliding, pinned, priority, if_version, lease } = req;
+    let ttl = expiry(ttl, expire_at)?;
+
+    let mut mc = mc.write().await;
+    check_version(&mc, &key, if_version)?;
+    check_lease(&mc, &key, lease)?;
+    let sliding = sliding.unwrap_or(mc.options().sliding_ttl);
+    let evicted = mc.stats().evicted_bytes;
+    mc.set_with(key.clone(), data.into_bytes(), ttl, sliding)
+        .map_err(|_| Error::NotStored)?;
+    if priority != PriorityLevel::Normal {
+        mc.set_priority(&key, priority.into());
+    }
+    if pinned {
+        mc.pin(&key);
+    }
+
+    let version = mc.version(&key).unwrap_or_default();
+    let mut resp = Code::Ok().set_header(ETAG, etag(version)).finish();
+    resp.extensions_mut().insert(stored(&mc, evicted));
+    Ok(resp)
+}
+
+/// takes `if_version` from `If-Match` header unless it is set in the body
+fn with_if_match(http: &HttpRequest, mut req: SetReq) -> Result<SetReq, Error> {
+    if req.if_version.is_none() {
+        req.if_version = if_match(http)?;
+    }
+    Ok(req)
+}
+
+/// version from `If-Match` header, `None` if there is none. A weak tag of
+/// a compressed response names the same version, so it is accepted too.
+fn if_match(http: &HttpRequest) -> Result<Option<u64>, Error> {
+    http.headers().get(IF_MATCH)
+        .map(|if_match| if_match.to_str().ok()
+            .map(|tag| tag.trim().trim_start_matches("W/").trim_matches('"'))
+            .and_then(|tag| tag.parse().ok())
+            .ok_or(Error::BadRequest("If-Match must be a version from ETag")))
+        .transpose()
+}
+
+fn check_version(mc: &Memcached, key: &str, if_version: Option<u64>) -> Result<(), Error> {
+    match if_version {
+        Some(expected) if mc.version(key) != Some(expected) => Err(Error::PreconditionFailed),
+        _ => Ok(()),
+    }
+}
+
+fn check_lease(mc: &Memcached, key: &str, lease: Option<u64>) -> Result<(), Error> {
+    match lease {
+        Some(token) if !mc.holds_lease(key, token) => Err(Error::PreconditionFailed),
+        _ => Ok(()),
+    }
+}
+
+fn etag(version: u64) -> String {
+    format!("\"{}\"", version)
+}
+
+fn expiry(ttl: Option<DurationString>, expire_at: Option<DateTime<FixedOffset>>) -> Result<Option<Duration>, Error> {
+    match (ttl, expire_at) {
+        (Some(_), Some(_)) => Err(Error::BadRequest("ttl and expire_at are mutually exclusive")),
+        (ttl, None) => Ok(ttl.map(Into::into)),
+        (None, Some(expire_at)) => Ok(Some(until(expire_at))),
+    }
+}
+
+/// time left until wall clock deadline, zero if it has passed
+fn until(deadline: DateTime<FixedOffset>) -> Duration {
+    (deadline.with_timezone(&Utc) - Utc::now())
+        .to_std()
+        .unwrap_or_default()
+}
+
+#[derive(Serialize, ToSchema)]
+struct GetSetResp {
+    /// previous value, null if there was none
+    data: Option<String>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/getset",
+    request_body = SetReq,
+    responses(
+        (status = 200, description = "stored, previous value returned", body = GetSetResp),
+        (status = 304, description = "store can't fit the value"),
+        (status = 412, description = "version or lease doesn't match", body = ErrorResp),
+    ),
+)]
+#[post("/getset")]
+async fn getset(
+    mc: Data<Store>,
+    l1: Data<L1>,
+    origin: Option<Data<Origin>>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: DecodedJson<SetReq>,
+) -> Result<HttpResponse, Error> {
+    l1.invalidate(&req.key);
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    let origin = origin.as_ref().map(Data::get_ref);
+    let write = forwarded(origin, || set_write(&req));
+    let prior = before_write(origin, &mc, &req.key).await;
+    let stored = getset_into(&mc, req.0).await;
+    tagged(key, write_through(stored, origin, &mc, write, prior).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/ns/{name}/getset",
+    params(("name" = String, Path, description = "namespace")),
+    request_body = SetReq,
+    responses(
+        (status = 200, description = "stored, previous value returned", body = GetSetResp),
+        (status = 304, description = "store can't fit the value"),
+        (status = 412, description = "version or lease doesn't match", body = ErrorResp),
+        (status = 404, description = "namespace not found", body = ErrorResp),
+    ),
+)]
+#[post("/getset")]
+async fn ns_getset(
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: DecodedJson<SetReq>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    let mc = match namespace(&namespaces, &name) {
+        Ok(mc) => mc,
+        Err(err) => return tagged(key, Err(err)),
+    };
+    let origin = namespaces.origin(&name);
+    let write = forwarded(origin.as_deref(), || set_write(&req));
+    let prior = before_write(origin.as_deref(), &mc, &req.key).await;
+    let stored = getset_into(&mc, req.0).await;
+    tagged(key, write_through(stored, origin.as_deref(), &mc, write, prior).await)
+}
+
+async fn getset_into(mc: &Store, req: SetReq) -> Result<HttpResponse, Error> {
+    let SetReq { key, data, ttl, expire_at, sliding, pinned, priority, if_version, lease } = req;
+    let ttl = expiry(ttl, expire_at)?;
+
+    let mut mc = mc.write().await;
+    check_version(&mc, &key, if_version)?;
+    check_lease(&mc, &key, lease)?;
+    let sliding = sliding.unwrap_or(mc.options().sliding_ttl);
+    let evicted = mc.stats().evicted_bytes;
+    let previous = mc.getset_with(key.clone(), data.into_bytes(), ttl, sliding)
+        .map_err(|_| Error::NotStored)?;
+    if priority != PriorityLevel::Normal {
+        mc.set_priority(&key, priority.into());
+    }
+    if pinned {
+        mc.pin(&key);
+    }
+
+    let mut resp = Code::Ok().json(GetSetResp { data: previous.map(as_string).transpose()? });
+    resp.extensions_mut().insert(stored(&mc, evicted));
+    Ok(resp)
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct GatReq {
+    key: String,
+    /// new ttl, item never expires if neither this nor `expire_at` is set, unless `max_ttl` is configured
+    #[schema(value_type = Option<String>, example = "10s")]
+    ttl: Option<DurationString>,
+    expire_at: Option<DateTime<FixedOffset>>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/gat",
+    request_body = GatReq,
+    responses(
+        (status = 200, description = "value with the new ttl", body = GetResp),
+        (status = 404, description = "key not found", body = ErrorResp),
+    ),
+)]
+#[post("/gat")]
+async fn gat(
+    mc: Data<Store>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<GatReq>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    tagged(key, gat_from(&mc, req.0).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/ns/{name}/gat",
+    params(("name" = String, Path, description = "namespace")),
+    request_body = GatReq,
+    responses(
+        (status = 200, description = "value with the new ttl", body = GetResp),
+        (status = 404, description = "key not found", body = ErrorResp),
+    ),
+)]
+#[post("/gat")]
+async fn ns_gat(
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<GatReq>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    let mc = match namespace(&namespaces, &name) {
+        Ok(mc) => mc,
+        Err(err) => return tagged(key, Err(err)),
+    };
+    tagged(key, gat_from(&mc, req.0).await)
+}
+
+async fn gat_from(mc: &Store, req: GatReq) -> Result<HttpResponse, Error> {
+    let GatReq { key, ttl, expire_at } = req;
+    let ttl = expiry(ttl, expire_at)?;
+
+    let data = mc.write().await.gat(&key, ttl).ok_or_else(key_not_found)?;
+    Ok(Code::Ok().json(GetResp { data: as_string(data)?, ..Default::default() }))
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
 struct DeleteReq {
     key: String,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 struct DeleteResp {
     data: String,
 }
 
+#[utoipa::path(
+    post,
+    path = "/delete",
+    request_body = DeleteReq,
+    responses(
+        (status = 200, description = "deleted value", body = DeleteResp),
+        (status = 404, description = "key not found", body = ErrorResp),
+    ),
+)]
 #[post("/delete")]
 async fn delete(
-    mc: Data<RwLock<Memcached>>,
+    mc: Data<Store>,
+    l1: Data<L1>,
+    origin: Option<Data<Origin>>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<DeleteReq>,
+) -> Result<HttpResponse, Error> {
+    l1.invalidate(&req.key);
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    let origin = origin.as_ref().map(Data::get_ref);
+    let write = forwarded(origin, || Write::Delete { key: req.key.clone() });
+    let deleted = delete_from(&mc, req.0).await;
+    tagged(key, write_through(deleted, origin, &mc, write, None).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/ns/{name}/delete",
+    params(("name" = String, Path, description = "namespace")),
+    request_body = DeleteReq,
+    responses(
+        (status = 200, description = "deleted value", body = DeleteResp),
+        (status = 404, description = "key not found", body = ErrorResp),
+    ),
+)]
+#[post("/delete")]
+async fn ns_delete(
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
     req: Json<DeleteReq>,
-) -> impl Responder {
-    match mc.write().unwrap().delete(&req.key) {
-        Some(data) => Code::Ok().json(DeleteResp { data: as_string(data) }),
-        None => Code::NotFound().finish(),
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    let mc = match namespace(&namespaces, &name) {
+        Ok(mc) => mc,
+        Err(err) => return tagged(key, Err(err)),
+    };
+    let origin = namespaces.origin(&name);
+    let write = forwarded(origin.as_deref(), || Write::Delete { key: req.key.clone() });
+    let deleted = delete_from(&mc, req.0).await;
+    tagged(key, write_through(deleted, origin.as_deref(), &mc, write, None).await)
+}
+
+#[instrument(level = "debug", skip(mc, req), fields(key_hash = key_hash(&req.key), value_size = Empty, lock_wait_us = Empty))]
+async fn delete_from(mc: &Store, req: DeleteReq) -> Result<HttpResponse, Error> {
+    let data = mc.write().await.delete(&req.key).ok_or_else(key_not_found)?;
+    Span::current().record("value_size", &data.len());
+    Ok(Code::Ok().json(DeleteResp { data: as_string(data)? }))
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct PushReq {
+    key: String,
+    values: Vec<String>,
+    /// ttl of a new list, an existing one keeps its ttl
+    #[schema(value_type = Option<String>, example = "10s")]
+    ttl: Option<DurationString>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct PushResp {
+    /// length of the list
+    len: usize,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct PopReq {
+    key: String,
+    /// values taken at most, an emptied list is deleted
+    #[serde(default = "single")]
+    count: usize,
+}
+
+fn single() -> usize {
+    1
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct RangeReq {
+    key: String,
+    #[serde(default)]
+    start: i64,
+    /// inclusive, negative indexes count from the back
+    #[serde(default = "last")]
+    stop: i64,
+}
+
+fn last() -> i64 {
+    -1
+}
+
+#[derive(Serialize, ToSchema)]
+struct ListResp {
+    values: Vec<String>,
+}
+
+/// Pushes values one by one to the front of a list, so the last one ends up first.
+#[utoipa::path(
+    post,
+    path = "/lpush",
+    request_body = PushReq,
+    responses(
+        (status = 200, description = "length of the list", body = PushResp),
+        (status = 400, description = "no values to add", body = ErrorResp),
+        (status = 304, description = "values don't fit into memory limit"),
+        (status = 409, description = "key holds a value of another type", body = ErrorResp),
+    ),
+)]
+#[post("/lpush")]
+async fn lpush(
+    mc: Data<Store>,
+    l1: Data<L1>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<PushReq>,
+) -> Result<HttpResponse, Error> {
+    l1.invalidate(&req.key);
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    tagged(key, push_into(&mc, End::Front, req.0).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/ns/{name}/lpush",
+    params(("name" = String, Path, description = "namespace")),
+    request_body = PushReq,
+    responses(
+        (status = 200, description = "length of the list", body = PushResp),
+        (status = 400, description = "no values to add", body = ErrorResp),
+        (status = 304, description = "values don't fit into memory limit"),
+        (status = 409, description = "key holds a value of another type", body = ErrorResp),
+        (status = 404, description = "namespace not found", body = ErrorResp),
+    ),
+)]
+#[post("/lpush")]
+async fn ns_lpush(
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<PushReq>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    let mc = match namespace(&namespaces, &name) {
+        Ok(mc) => mc,
+        Err(err) => return tagged(key, Err(err)),
+    };
+    tagged(key, push_into(&mc, End::Front, req.0).await)
+}
+
+/// Appends values to a list, a missing key becomes a new one.
+#[utoipa::path(
+    post,
+    path = "/rpush",
+    request_body = PushReq,
+    responses(
+        (status = 200, description = "length of the list", body = PushResp),
+        (status = 400, description = "no values to add", body = ErrorResp),
+        (status = 304, description = "values don't fit into memory limit"),
+        (status = 409, description = "key holds a value of another type", body = ErrorResp),
+    ),
+)]
+#[post("/rpush")]
+async fn rpush(
+    mc: Data<Store>,
+    l1: Data<L1>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<PushReq>,
+) -> Result<HttpResponse, Error> {
+    l1.invalidate(&req.key);
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    tagged(key, push_into(&mc, End::Back, req.0).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/ns/{name}/rpush",
+    params(("name" = String, Path, description = "namespace")),
+    request_body = PushReq,
+    responses(
+        (status = 200, description = "length of the list", body = PushResp),
+        (status = 400, description = "no values to add", body = ErrorResp),
+        (status = 304, description = "values don't fit into memory limit"),
+        (status = 409, description = "key holds a value of another type", body = ErrorResp),
+        (status = 404, description = "namespace not found", body = ErrorResp),
+    ),
+)]
+#[post("/rpush")]
+async fn ns_rpush(
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<PushReq>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    let mc = match namespace(&namespaces, &name) {
+        Ok(mc) => mc,
+        Err(err) => return tagged(key, Err(err)),
+    };
+    tagged(key, push_into(&mc, End::Back, req.0).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/lpop",
+    request_body = PopReq,
+    responses(
+        (status = 200, description = "taken values, fewer than `count` if the list is shorter", body = ListResp),
+        (status = 404, description = "key not found", body = ErrorResp),
+        (status = 409, description = "key holds a value of another type", body = ErrorResp),
+    ),
+)]
+#[post("/lpop")]
+async fn lpop(
+    mc: Data<Store>,
+    l1: Data<L1>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<PopReq>,
+) -> Result<HttpResponse, Error> {
+    l1.invalidate(&req.key);
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    tagged(key, pop_from(&mc, End::Front, req.0).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/ns/{name}/lpop",
+    params(("name" = String, Path, description = "namespace")),
+    request_body = PopReq,
+    responses(
+        (status = 200, description = "taken values, fewer than `count` if the list is shorter", body = ListResp),
+        (status = 404, description = "key not found", body = ErrorResp),
+        (status = 409, description = "key holds a value of another type", body = ErrorResp),
+        (status = 404, description = "namespace not found", body = ErrorResp),
+    ),
+)]
+#[post("/lpop")]
+async fn ns_lpop(
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<PopReq>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    let mc = match namespace(&namespaces, &name) {
+        Ok(mc) => mc,
+        Err(err) => return tagged(key, Err(err)),
+    };
+    tagged(key, pop_from(&mc, End::Front, req.0).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/rpop",
+    request_body = PopReq,
+    responses(
+        (status = 200, description = "taken values, fewer than `count` if the list is shorter", body = ListResp),
+        (status = 404, description = "key not found", body = ErrorResp),
+        (status = 409, description = "key holds a value of another type", body = ErrorResp),
+    ),
+)]
+#[post("/rpop")]
+async fn rpop(
+    mc: Data<Store>,
+    l1: Data<L1>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<PopReq>,
+) -> Result<HttpResponse, Error> {
+    l1.invalidate(&req.key);
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    tagged(key, pop_from(&mc, End::Back, req.0).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/ns/{name}/rpop",
+    params(("name" = String, Path, description = "namespace")),
+    request_body = PopReq,
+    responses(
+        (status = 200, description = "taken values, fewer than `count` if the list is shorter", body = ListResp),
+        (status = 404, description = "key not found", body = ErrorResp),
+        (status = 409, description = "key holds a value of another type", body = ErrorResp),
+        (status = 404, description = "namespace not found", body = ErrorResp),
+    ),
+)]
+#[post("/rpop")]
+async fn ns_rpop(
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<PopReq>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    let mc = match namespace(&namespaces, &name) {
+        Ok(mc) => mc,
+        Err(err) => return tagged(key, Err(err)),
+    };
+    tagged(key, pop_from(&mc, End::Back, req.0).await)
+}
+
+/// Values of a list from `start` to `stop`, the whole list by default.
+#[utoipa::path(
+    post,
+    path = "/lrange",
+    request_body = RangeReq,
+    responses(
+        (status = 200, description = "values in the range", body = ListResp),
+        (status = 404, description = "key not found", body = ErrorResp),
+        (status = 409, description = "key holds a value of another type", body = ErrorResp),
+    ),
+)]
+#[post("/lrange")]
+async fn lrange(
+    mc: Data<Store>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<RangeReq>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    tagged(key, range_of(&mc, req.0).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/ns/{name}/lrange",
+    params(("name" = String, Path, description = "namespace")),
+    request_body = RangeReq,
+    responses(
+        (status = 200, description = "values in the range", body = ListResp),
+        (status = 404, description = "key not found", body = ErrorResp),
+        (status = 409, description = "key holds a value of another type", body = ErrorResp),
+        (status = 404, description = "namespace not found", body = ErrorResp),
+    ),
+)]
+#[post("/lrange")]
+async fn ns_lrange(
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<RangeReq>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    let mc = match namespace(&namespaces, &name) {
+        Ok(mc) => mc,
+        Err(err) => return tagged(key, Err(err)),
+    };
+    tagged(key, range_of(&mc, req.0).await)
+}
+
+async fn push_into(mc: &Store, end: End, req: PushReq) -> Result<HttpResponse, Error> {
+    let PushReq { key, values, ttl } = req;
+    let values = values.into_iter().map(String::into_bytes).collect();
+    let len = mc.write().await.push(&key, end, values, ttl.map(Into::into)).map_err(collection_error)?;
+    Ok(Code::Ok().json(PushResp { len }))
+}
+
+async fn pop_from(mc: &Store, end: End, req: PopReq) -> Result<HttpResponse, Error> {
+    let popped = mc.write().await.pop(&req.key, end, req.count).map_err(collection_error)?;
+    list_resp(popped.ok_or_else(key_not_found)?)
+}
+
+async fn range_of(mc: &Store, req: RangeReq) -> Result<HttpResponse, Error> {
+    let values = mc.read().await.range(&req.key, req.start, req.stop).map_err(collection_error)?;
+    list_resp(values.ok_or_else(key_not_found)?)
+}
+
+fn list_resp(values: Vec<Vec<u8>>) -> Result<HttpResponse, Error> {
+    let values = values.into_iter().map(as_string).collect::<Result<_, _>>()?;
+    Ok(Code::Ok().json(ListResp { values }))
+}
+
+fn collection_error(err: CollectionError) -> Error {
+    match err {
+        CollectionError::WrongType => Error::Conflict("key holds a value of another type"),
+        CollectionError::NotStored => Error::NotStored,
+        CollectionError::Empty => Error::BadRequest("nothing to add, collections are never empty"),
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct MembersReq {
+    key: String,
+    members: Vec<String>,
+    /// ttl of a new set, an existing one keeps its ttl
+    #[schema(value_type = Option<String>, example = "10s")]
+    ttl: Option<DurationString>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct CountResp {
+    count: usize,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct IsMemberReq {
+    key: String,
+    member: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct IsMemberResp {
+    member: bool,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct MembersOfReq {
+    key: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct MembersResp {
+    members: Vec<String>,
+}
+
+/// Adds members to a set, a missing key becomes a new one.
+#[utoipa::path(
+    post,
+    path = "/sadd",
+    request_body = MembersReq,
+    responses(
+        (status = 200, description = "how many members are new to the set", body = CountResp),
+        (status = 400, description = "no members to add", body = ErrorResp),
+        (status = 304, description = "members don't fit into memory limit"),
+        (status = 409, description = "key holds a value of another type", body = ErrorResp),
+    ),
+)]
+#[post("/sadd")]
+async fn sadd(
+    mc: Data<Store>,
+    l1: Data<L1>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<MembersReq>,
+) -> Result<HttpResponse, Error> {
+    l1.invalidate(&req.key);
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    tagged(key, add_members(&mc, req.0).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/ns/{name}/sadd",
+    params(("name" = String, Path, description = "namespace")),
+    request_body = MembersReq,
+    responses(
+        (status = 200, description = "how many members are new to the set", body = CountResp),
+        (status = 400, description = "no members to add", body = ErrorResp),
+        (status = 304, description = "members don't fit into memory limit"),
+        (status = 409, description = "key holds a value of another type", body = ErrorResp),
+        (status = 404, description = "namespace not found", body = ErrorResp),
+    ),
+)]
+#[post("/sadd")]
+async fn ns_sadd(
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<MembersReq>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    let mc = match namespace(&namespaces, &name) {
+        Ok(mc) => mc,
+        Err(err) => return tagged(key, Err(err)),
+    };
+    tagged(key, add_members(&mc, req.0).await)
+}
+
+/// Removes members of a set, an emptied set is deleted.
+#[utoipa::path(
+    post,
+    path = "/srem",
+    request_body = MembersReq,
+    responses(
+        (status = 200, description = "how many members were in the set", body = CountResp),
+        (status = 404, description = "key not found", body = ErrorResp),
+        (status = 409, description = "key holds a value of another type", body = ErrorResp),
+    ),
+)]
+#[post("/srem")]
+async fn srem(
+    mc: Data<Store>,
+    l1: Data<L1>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<MembersReq>,
+) -> Result<HttpResponse, Error> {
+    l1.invalidate(&req.key);
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    tagged(key, remove_members(&mc, req.0).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/ns/{name}/srem",
+    params(("name" = String, Path, description = "namespace")),
+    request_body = MembersReq,
+    responses(
+        (status = 200, description = "how many members were in the set", body = CountResp),
+        (status = 404, description = "key not found", body = ErrorResp),
+        (status = 409, description = "key holds a value of another type", body = ErrorResp),
+        (status = 404, description = "namespace not found", body = ErrorResp),
+    ),
+)]
+#[post("/srem")]
+async fn ns_srem(
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<MembersReq>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    let mc = match namespace(&namespaces, &name) {
+        Ok(mc) => mc,
+        Err(err) => return tagged(key, Err(err)),
+    };
+    tagged(key, remove_members(&mc, req.0).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/sismember",
+    request_body = IsMemberReq,
+    responses(
+        (status = 200, description = "whether the set has the member, false for a missing key", body = IsMemberResp),
+        (status = 409, description = "key holds a value of another type", body = ErrorResp),
+    ),
+)]
+#[post("/sismember")]
+async fn sismember(
+    mc: Data<Store>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<IsMemberReq>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    tagged(key, is_member(&mc, req.0).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/ns/{name}/sismember",
+    params(("name" = String, Path, description = "namespace")),
+    request_body = IsMemberReq,
+    responses(
+        (status = 200, description = "whether the set has the member, false for a missing key", body = IsMemberResp),
+        (status = 409, description = "key holds a value of another type", body = ErrorResp),
+        (status = 404, description = "namespace not found", body = ErrorResp),
+    ),
+)]
+#[post("/sismember")]
+async fn ns_sismember(
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<IsMemberReq>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    let mc = match namespace(&namespaces, &name) {
+        Ok(mc) => mc,
+        Err(err) => return tagged(key, Err(err)),
+    };
+    tagged(key, is_member(&mc, req.0).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/smembers",
+    request_body = MembersOfReq,
+    responses(
+        (status = 200, description = "members in ascending order", body = MembersResp),
+        (status = 404, description = "key not found", body = ErrorResp),
+        (status = 409, description = "key holds a value of another type", body = ErrorResp),
+    ),
+)]
+#[post("/smembers")]
+async fn smembers(
+    mc: Data<Store>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<MembersOfReq>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    tagged(key, members_of(&mc, req.0).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/ns/{name}/smembers",
+    params(("name" = String, Path, description = "namespace")),
+    request_body = MembersOfReq,
+    responses(
+        (status = 200, description = "members in ascending order", body = MembersResp),
+        (status = 404, description = "key not found", body = ErrorResp),
+        (status = 409, description = "key holds a value of another type", body = ErrorResp),
+        (status = 404, description = "namespace not found", body = ErrorResp),
+    ),
+)]
+#[post("/smembers")]
+async fn ns_smembers(
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<MembersOfReq>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    let mc = match namespace(&namespaces, &name) {
+        Ok(mc) => mc,
+        Err(err) => return tagged(key, Err(err)),
+    };
+    tagged(key, members_of(&mc, req.0).await)
+}
+
+async fn add_members(mc: &Store, req: MembersReq) -> Result<HttpResponse, Error> {
+    let MembersReq { key, members, ttl } = req;
+    let members = members.into_iter().map(String::into_bytes).collect();
+    let added = mc.write().await.add_members(&key, members, ttl.map(Into::into)).map_err(collection_error)?;
+    Ok(Code::Ok().json(CountResp { count: added }))
+}
+
+async fn remove_members(mc: &Store, req: MembersReq) -> Result<HttpResponse, Error> {
+    let members: Vec<Vec<u8>> = req.members.into_iter().map(String::into_bytes).collect();
+    let removed = mc.write().await.remove_members(&req.key, &members).map_err(collection_error)?;
+    Ok(Code::Ok().json(CountResp { count: removed.ok_or_else(key_not_found)? }))
+}
+
+async fn is_member(mc: &Store, req: IsMemberReq) -> Result<HttpResponse, Error> {
+    let member = mc.read().await.is_member(&req.key, req.member.as_bytes()).map_err(collection_error)?;
+    Ok(Code::Ok().json(IsMemberResp { member }))
+}
+
+async fn members_of(mc: &Store, req: MembersOfReq) -> Result<HttpResponse, Error> {
+    let members = mc.read().await.members(&req.key).map_err(collection_error)?.ok_or_else(key_not_found)?;
+    let members = members.into_iter().map(as_string).collect::<Result<_, _>>()?;
+    Ok(Code::Ok().json(MembersResp { members }))
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct HsetReq {
+    key: String,
+    fields: BTreeMap<String, String>,
+    /// ttl of a new map, an existing one keeps its ttl
+    #[schema(value_type = Option<String>, example = "10s")]
+    ttl: Option<DurationString>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct HgetReq {
+    key: String,
+    field: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct FieldResp {
+    value: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct HdelReq {
+    key: String,
+    fields: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct HgetallReq {
+    key: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct FieldsResp {
+    fields: BTreeMap<String, String>,
+}
+
+/// Sets fields of a map, a missing key becomes a new one.
+#[utoipa::path(
+    post,
+    path = "/hset",
+    request_body = HsetReq,
+    responses(
+        (status = 200, description = "how many fields are new to the map", body = CountResp),
+        (status = 400, description = "no fields to add", body = ErrorResp),
+        (status = 304, description = "fields don't fit into memory limit"),
+        (status = 409, description = "key holds a value of another type", body = ErrorResp),
+    ),
+)]
+#[post("/hset")]
+async fn hset(
+    mc: Data<Store>,
+    l1: Data<L1>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<HsetReq>,
+) -> Result<HttpResponse, Error> {
+    l1.invalidate(&req.key);
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    tagged(key, set_fields(&mc, req.0).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/ns/{name}/hset",
+    params(("name" = String, Path, description = "namespace")),
+    request_body = HsetReq,
+    responses(
+        (status = 200, description = "how many fields are new to the map", body = CountResp),
+        (status = 400, description = "no fields to add", body = ErrorResp),
+        (status = 304, description = "fields don't fit into memory limit"),
+        (status = 409, description = "key holds a value of another type", body = ErrorResp),
+        (status = 404, description = "namespace not found", body = ErrorResp),
+    ),
+)]
+#[post("/hset")]
+async fn ns_hset(
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<HsetReq>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
     }
+    let mc = match namespace(&namespaces, &name) {
+        Ok(mc) => mc,
+        Err(err) => return tagged(key, Err(err)),
+    };
+    tagged(key, set_fields(&mc, req.0).await)
 }
 
-fn gc(mc: Arc<RwLock<Memcached>>, interval: Duration) {
-    loop {
-        thread::sleep(interval);
-        mc.write().unwrap().collect_garbage();
+#[utoipa::path(
+    post,
+    path = "/hget",
+    request_body = HgetReq,
+    responses(
+        (status = 200, description = "value of the field", body = FieldResp),
+        (status = 404, description = "key or field not found", body = ErrorResp),
+        (status = 409, description = "key holds a value of another type", body = ErrorResp),
+    ),
+)]
+#[post("/hget")]
+async fn hget(
+    mc: Data<Store>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<HgetReq>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
     }
+    tagged(key, get_field(&mc, req.0).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/ns/{name}/hget",
+    params(("name" = String, Path, description = "namespace")),
+    request_body = HgetReq,
+    responses(
+        (status = 200, description = "value of the field", body = FieldResp),
+        (status = 404, description = "key or field not found", body = ErrorResp),
+        (status = 409, description = "key holds a value of another type", body = ErrorResp),
+        (status = 404, description = "namespace not found", body = ErrorResp),
+    ),
+)]
+#[post("/hget")]
+async fn ns_hget(
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<HgetReq>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    let mc = match namespace(&namespaces, &name) {
+        Ok(mc) => mc,
+        Err(err) => return tagged(key, Err(err)),
+    };
+    tagged(key, get_field(&mc, req.0).await)
+}
+
+/// Deletes fields of a map, an emptied map is deleted.
+#[utoipa::path(
+    post,
+    path = "/hdel",
+    request_body = HdelReq,
+    responses(
+        (status = 200, description = "how many fields were in the map", body = CountResp),
+        (status = 404, description = "key not found", body = ErrorResp),
+        (status = 409, description = "key holds a value of another type", body = ErrorResp),
+    ),
+)]
+#[post("/hdel")]
+async fn hdel(
+    mc: Data<Store>,
+    l1: Data<L1>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<HdelReq>,
+) -> Result<HttpResponse, Error> {
+    l1.invalidate(&req.key);
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    tagged(key, delete_fields(&mc, req.0).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/ns/{name}/hdel",
+    params(("name" = String, Path, description = "namespace")),
+    request_body = HdelReq,
+    responses(
+        (status = 200, description = "how many fields were in the map", body = CountResp),
+        (status = 404, description = "key not found", body = ErrorResp),
+        (status = 409, description = "key holds a value of another type", body = ErrorResp),
+        (status = 404, description = "namespace not found", body = ErrorResp),
+    ),
+)]
+#[post("/hdel")]
+async fn ns_hdel(
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<HdelReq>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    let mc = match namespace(&namespaces, &name) {
+        Ok(mc) => mc,
+        Err(err) => return tagged(key, Err(err)),
+    };
+    tagged(key, delete_fields(&mc, req.0).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/hgetall",
+    request_body = HgetallReq,
+    responses(
+        (status = 200, description = "every field of the map", body = FieldsResp),
+        (status = 404, description = "key not found", body = ErrorResp),
+        (status = 409, description = "key holds a value of another type", body = ErrorResp),
+    ),
+)]
+#[post("/hgetall")]
+async fn hgetall(
+    mc: Data<Store>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<HgetallReq>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    tagged(key, fields_of(&mc, req.0).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/ns/{name}/hgetall",
+    params(("name" = String, Path, description = "namespace")),
+    request_body = HgetallReq,
+    responses(
+        (status = 200, description = "every field of the map", body = FieldsResp),
+        (status = 404, description = "key not found", body = ErrorResp),
+        (status = 409, description = "key holds a value of another type", body = ErrorResp),
+        (status = 404, description = "namespace not found", body = ErrorResp),
+    ),
+)]
+#[post("/hgetall")]
+async fn ns_hgetall(
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<HgetallReq>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    let mc = match namespace(&namespaces, &name) {
+        Ok(mc) => mc,
+        Err(err) => return tagged(key, Err(err)),
+    };
+    tagged(key, fields_of(&mc, req.0).await)
+}
+
+async fn set_fields(mc: &Store, req: HsetReq) -> Result<HttpResponse, Error> {
+    let HsetReq { key, fields, ttl } = req;
+    let fields = fields.into_iter().map(|(field, value)| (field.into_bytes(), value.into_bytes())).collect();
+    let added = mc.write().await.set_fields(&key, fields, ttl.map(Into::into)).map_err(collection_error)?;
+    Ok(Code::Ok().json(CountResp { count: added }))
+}
+
+async fn get_field(mc: &Store, req: HgetReq) -> Result<HttpResponse, Error> {
+    let value = mc.read().await.get_field(&req.key, req.field.as_bytes()).map_err(collection_error)?;
+    let value = value.ok_or(Error::NotFound("key or field"))?;
+    Ok(Code::Ok().json(FieldResp { value: as_string(value)? }))
+}
+
+async fn delete_fields(mc: &Store, req: HdelReq) -> Result<HttpResponse, Error> {
+    let fields: Vec<Vec<u8>> = req.fields.into_iter().map(String::into_bytes).collect();
+    let removed = mc.write().await.delete_fields(&req.key, &fields).map_err(collection_error)?;
+    Ok(Code::Ok().json(CountResp { count: removed.ok_or_else(key_not_found)? }))
+}
+
+async fn fields_of(mc: &Store, req: HgetallReq) -> Result<HttpResponse, Error> {
+    let fields = mc.read().await.fields(&req.key).map_err(collection_error)?.ok_or_else(key_not_found)?;
+    let fields = fields.into_iter()
+        .map(|(field, value)| Ok((as_string(field)?, as_string(value)?)))
+        .collect::<Result<_, Error>>()?;
+    Ok(Code::Ok().json(FieldsResp { fields }))
+}
+
+/// What a counter does with a change which doesn't fit into 64 bits.
+#[derive(Clone, Copy, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+enum OverflowPolicy {
+    /// the change fails with 409 and the counter keeps its value
+    #[default]
+    Error,
+    /// the counter stops at the bound it reached
+    Saturate,
+    /// the counter wraps around to the other bound
+    Wrap,
+}
+
+impl From<OverflowPolicy> for Overflow {
+    fn from(policy: OverflowPolicy) -> Overflow {
+        match policy {
+            OverflowPolicy::Error => Overflow::Error,
+            OverflowPolicy::Saturate => Overflow::Saturate,
+            OverflowPolicy::Wrap => Overflow::Wrap,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct CountReq {
+    key: String,
+    /// added to the counter, negative decrements it
+    #[serde(default = "one")]
+    delta: i64,
+    /// ttl of a new counter, an existing one keeps its deadline unless it refreshes ttl
+    #[schema(value_type = Option<String>, example = "1m")]
+    ttl: Option<DurationString>,
+    /// value of a new counter before the change
+    #[serde(default)]
+    initial: i64,
+    /// overflow policy of a new counter
+    #[serde(default)]
+    overflow: OverflowPolicy,
+    /// whether every change of a new counter restarts its ttl
+    #[serde(default)]
+    refresh_ttl: bool,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct CounterReq {
+    key: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct CounterResp {
+    value: i64,
+}
+
+/// Changes a counter, a missing key becomes a new one with the options of the request.
+/// An existing counter keeps the options it was created with, so every client gets the same semantics.
+#[utoipa::path(
+    post,
+    path = "/count",
+    request_body = CountReq,
+    responses(
+        (status = 200, description = "value of the counter", body = CounterResp),
+        (status = 304, description = "new counter doesn't fit into memory limit"),
+        (status = 409, description = "change overflows or key holds a value of another type", body = ErrorResp),
+    ),
+)]
+#[post("/count")]
+async fn count(
+    mc: Data<Store>,
+    l1: Data<L1>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<CountReq>,
+) -> Result<HttpResponse, Error> {
+    l1.invalidate(&req.key);
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    tagged(key, count_in(&mc, req.0).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/ns/{name}/count",
+    params(("name" = String, Path, description = "namespace")),
+    request_body = CountReq,
+    responses(
+        (status = 200, description = "value of the counter", body = CounterResp),
+        (status = 304, description = "new counter doesn't fit into memory limit"),
+        (status = 409, description = "change overflows or key holds a value of another type", body = ErrorResp),
+        (status = 404, description = "namespace not found", body = ErrorResp),
+    ),
+)]
+#[post("/count")]
+async fn ns_count(
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<CountReq>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    let mc = match namespace(&namespaces, &name) {
+        Ok(mc) => mc,
+        Err(err) => return tagged(key, Err(err)),
+    };
+    tagged(key, count_in(&mc, req.0).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/counter",
+    request_body = CounterReq,
+    responses(
+        (status = 200, description = "value of the counter", body = CounterResp),
+        (status = 404, description = "key not found", body = ErrorResp),
+        (status = 409, description = "key holds a value of another type", body = ErrorResp),
+    ),
+)]
+#[post("/counter")]
+async fn counter(
+    mc: Data<Store>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<CounterReq>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    tagged(key, counter_of(&mc, req.0).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/ns/{name}/counter",
+    params(("name" = String, Path, description = "namespace")),
+    request_body = CounterReq,
+    responses(
+        (status = 200, description = "value of the counter", body = CounterResp),
+        (status = 404, description = "key or namespace not found", body = ErrorResp),
+        (status = 409, description = "key holds a value of another type", body = ErrorResp),
+    ),
+)]
+#[post("/counter")]
+async fn ns_counter(
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<CounterReq>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    let mc = match namespace(&namespaces, &name) {
+        Ok(mc) => mc,
+        Err(err) => return tagged(key, Err(err)),
+    };
+    tagged(key, counter_of(&mc, req.0).await)
+}
+
+async fn count_in(mc: &Store, req: CountReq) -> Result<HttpResponse, Error> {
+    let options = CounterOptions { initial: req.initial, overflow: req.overflow.into(), refresh_ttl: req.refresh_ttl };
+    let value = mc.write().await.count(&req.key, req.delta, req.ttl.map(Into::into), options)
+        .map_err(counter_error)?;
+    Ok(Code::Ok().json(CounterResp { value }))
+}
+
+async fn counter_of(mc: &Store, req: CounterReq) -> Result<HttpResponse, Error> {
+    let value = mc.read().await.counter(&req.key).map_err(counter_error)?.ok_or_else(key_not_found)?;
+    Ok(Code::Ok().json(CounterResp { value }))
+}
+
+fn counter_error(err: CounterError) -> Error {
+    match err {
+        CounterError::WrongType => Error::Conflict("key holds a value of another type"),
+        CounterError::Overflow => Error::Conflict("change overflows the counter"),
+        CounterError::NotStored => Error::NotStored,
+    }
+}
+
+/// header with ttl of a value put to a key route, alternative to the `ttl` query parameter
+pub const TTL_HEADER: &str = "x-ttl";
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct KeyQuery {
+    /// ttl of a put value, `X-Ttl` header also works
+    #[param(value_type = Option<String>, example = "10s")]
+    ttl: Option<DurationString>,
+}
+
+/// Value as the body, a conditional get with its `ETag` in `If-None-Match` is not modified.
+#[utoipa::path(
+    get,
+    path = "/keys/{key}",
+    params(("key" = String, Path, description = "key")),
+    responses(
+        (status = 200, description = "value, `ETag` is its version", body = String, content_type = "text/plain"),
+        (status = 204, description = "key is marked missing by set_negative"),
+        (status = 304, description = "value has the version from `If-None-Match`"),
+        (status = 404, description = "key not found", body = ErrorResp),
+    ),
+)]
+#[get("/keys/{key:.+}")]
+async fn get_key(
+    mc: Data<Store>,
+    l1: Data<L1>,
+    origin: Option<Data<Origin>>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    path: Path<String>,
+) -> Result<HttpResponse, Error> {
+    let req = GetReq { key: path.into_inner(), allow_stale: false, lease: None, latency_critical: false };
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied_raw(cluster.as_ref().map(Data::get_ref), &http, &req.key, Bytes::new()).await {
+        return tagged(key, proxied)
+    }
+    let requested = req.key.clone();
+    let found = match l1.get(&req.key) {
+        Some((data, version)) => raw_value(data, Some(version)),
+        None => get_from(&mc, req, Some(&l1), raw_value).await,
+    };
+    let found = read_through(found, origin.as_ref().map(Data::get_ref), &mc, requested, raw_value).await;
+    tagged(key, not_modified(&http, found))
+}
+
+#[utoipa::path(
+    get,
+    path = "/ns/{name}/keys/{key}",
+    params(("name" = String, Path, description = "namespace"), ("key" = String, Path, description = "key")),
+    responses(
+        (status = 200, description = "value, `ETag` is its version", body = String, content_type = "text/plain"),
+        (status = 204, description = "key is marked missing by set_negative"),
+        (status = 304, description = "value has the version from `If-None-Match`"),
+        (status = 404, description = "key or namespace not found", body = ErrorResp),
+    ),
+)]
+#[get("/keys/{key:.+}")]
+async fn ns_get_key(
+    namespaces: Data<Namespaces>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    path: Path<(String, String)>,
+) -> Result<HttpResponse, Error> {
+    let (name, requested) = path.into_inner();
+    let req = GetReq { key: requested.clone(), allow_stale: false, lease: None, latency_critical: false };
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied_raw(cluster.as_ref().map(Data::get_ref), &http, &req.key, Bytes::new()).await {
+        return tagged(key, proxied)
+    }
+    let mc = match namespace(&namespaces, &name) {
+        Ok(mc) => mc,
+        Err(err) => return tagged(key, Err(err)),
+    };
+    let found = get_from(&mc, req, None, raw_value).await;
+    let origin = namespaces.origin(&name);
+    let found = read_through(found, origin.as_deref(), &mc, requested, raw_value).await;
+    tagged(key, not_modified(&http, found))
+}
+
+/// Stores the body as the value, `If-Match` makes it conditional like `if_version` of set.
+#[utoipa::path(
+    put,
+    path = "/keys/{key}",
+    params(("key" = String, Path, description = "key"), KeyQuery),
+    request_body(content = String, content_type = "text/plain"),
+    responses(
+        (status = 200, description = "stored, `ETag` is the new version"),
+        (status = 304, description = "store can't fit the value"),
+        (status = 412, description = "version doesn't match", body = ErrorResp),
+    ),
+)]
+#[put("/keys/{key:.+}")]
+#[allow(clippy::too_many_arguments)]
+async fn put_key(
+    mc: Data<Store>,
+    l1: Data<L1>,
+    origin: Option<Data<Origin>>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    path: Path<String>,
+    query: Query<KeyQuery>,
+    body: Bytes,
+) -> Result<HttpResponse, Error> {
+    l1.invalidate(&path);
+    let key = AuditKey::new(&path);
+    if let Some(proxied) = proxied_raw(cluster.as_ref().map(Data::get_ref), &http, &path, body.clone()).await {
+        return tagged(key, proxied)
+    }
+    let req = match put_req(&http, path.into_inner(), query.into_inner(), body) {
+        Ok(req) => req,
+        Err(err) => return tagged(key, Err(err)),
+    };
+    let origin = origin.as_ref().map(Data::get_ref);
+    let write = forwarded(origin, || set_write(&req));
+    let prior = before_write(origin, &mc, &req.key).await;
+    let stored = set_into(&mc, req).await;
+    tagged(key, write_through(stored, origin, &mc, write, prior).await)
+}
+
+#[utoipa::path(
+    put,
+    path = "/ns/{name}/keys/{key}",
+    params(("name" = String, Path, description = "namespace"), ("key" = String, Path, description = "key"), KeyQuery),
+    request_body(content = String, content_type = "text/plain"),
+    responses(
+        (status = 200, description = "stored, `ETag` is the new version"),
+        (status = 304, description = "store can't fit the value"),
+        (status = 404, description = "namespace not found", body = ErrorResp),
+        (status = 412, description = "version doesn't match", body = ErrorResp),
+    ),
+)]
+#[put("/keys/{key:.+}")]
+async fn ns_put_key(
+    namespaces: Data<Namespaces>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    path: Path<(String, String)>,
+    query: Query<KeyQuery>,
+    body: Bytes,
+) -> Result<HttpResponse, Error> {
+    let (name, requested) = path.into_inner();
+    let key = AuditKey::new(&requested);
+    if let Some(proxied) = proxied_raw(cluster.as_ref().map(Data::get_ref), &http, &requested, body.clone()).await {
+        return tagged(key, proxied)
+    }
+    let mc = match namespace(&namespaces, &name) {
+        Ok(mc) => mc,
+        Err(err) => return tagged(key, Err(err)),
+    };
+    let req = match put_req(&http, requested, query.into_inner(), body) {
+        Ok(req) => req,
+        Err(err) => return tagged(key, Err(err)),
+    };
+    let origin = namespaces.origin(&name);
+    let write = forwarded(origin.as_deref(), || set_write(&req));
+    let prior = before_write(origin.as_deref(), &mc, &req.key).await;
+    let stored = set_into(&mc, req).await;
+    tagged(key, write_through(stored, origin.as_deref(), &mc, write, prior).await)
+}
+
+/// [`SetReq`] of a value put to a key route, ttl from the query takes precedence over the header
+fn put_req(http: &HttpRequest, key: String, query: KeyQuery, body: Bytes) -> Result<SetReq, Error> {
+    let ttl = match (query.ttl, http.headers().get(TTL_HEADER)) {
+        (Some(ttl), _) => Some(ttl),
+        (None, Some(header)) => header.to_str().ok()
+            .and_then(|ttl| DurationString::try_from(ttl.to_owned()).ok())
+            .map(Some)
+            .ok_or(Error::BadRequest("X-Ttl must be a duration such as 10s"))?,
+        (None, None) => None,
+    };
+    let req = SetReq {
+        key,
+        data: String::from_utf8(body.to_vec())?,
+        ttl,
+        expire_at: None,
+        sliding: None,
+        pinned: false,
+        priority: PriorityLevel::Normal,
+        if_version: None,
+        lease: None,
+    };
+    with_if_match(http, req)
+}
+
+#[utoipa::path(
+    delete,
+    path = "/keys/{key}",
+    params(("key" = String, Path, description = "key")),
+    responses(
+        (status = 200, description = "deleted value", body = DeleteResp),
+        (status = 404, description = "key not found", body = ErrorResp),
+    ),
+)]
+#[delete("/keys/{key:.+}")]
+async fn delete_key(
+    mc: Data<Store>,
+    l1: Data<L1>,
+    origin: Option<Data<Origin>>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    path: Path<String>,
+) -> Result<HttpResponse, Error> {
+    let req = DeleteReq { key: path.into_inner() };
+    l1.invalidate(&req.key);
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied_raw(cluster.as_ref().map(Data::get_ref), &http, &req.key, Bytes::new()).await {
+        return tagged(key, proxied)
+    }
+    let origin = origin.as_ref().map(Data::get_ref);
+    let write = forwarded(origin, || Write::Delete { key: req.key.clone() });
+    let deleted = delete_from(&mc, req).await;
+    tagged(key, write_through(deleted, origin, &mc, write, None).await)
+}
+
+#[utoipa::path(
+    delete,
+    path = "/ns/{name}/keys/{key}",
+    params(("name" = String, Path, description = "namespace"), ("key" = String, Path, description = "key")),
+    responses(
+        (status = 200, description = "deleted value", body = DeleteResp),
+        (status = 404, description = "key or namespace not found", body = ErrorResp),
+    ),
+)]
+#[delete("/keys/{key:.+}")]
+async fn ns_delete_key(
+    namespaces: Data<Namespaces>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    path: Path<(String, String)>,
+) -> Result<HttpResponse, Error> {
+    let (name, requested) = path.into_inner();
+    let req = DeleteReq { key: requested };
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied_raw(cluster.as_ref().map(Data::get_ref), &http, &req.key, Bytes::new()).await {
+        return tagged(key, proxied)
+    }
+    let mc = match namespace(&namespaces, &name) {
+        Ok(mc) => mc,
+        Err(err) => return tagged(key, Err(err)),
+    };
+    let origin = namespaces.origin(&name);
+    let write = forwarded(origin.as_deref(), || Write::Delete { key: req.key.clone() });
+    let deleted = delete_from(&mc, req).await;
+    tagged(key, write_through(deleted, origin.as_deref(), &mc, write, None).await)
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct JsonQuery {
+    /// JSON Pointer to the returned part, the whole document if empty
+    #[serde(default)]
+    #[param(example = "/a/b")]
+    path: String,
+}
+
+/// Part of a JSON document at `path`, so reading a field doesn't ship the whole document.
+/// `ETag` is the version of the document, it works with `If-None-Match` like on key routes.
+#[utoipa::path(
+    get,
+    path = "/json/{key}",
+    params(("key" = String, Path, description = "key"), JsonQuery),
+    responses(
+        (status = 200, description = "part of the document, `ETag` is its version", body = Object),
+        (status = 304, description = "document has the version from `If-None-Match`"),
+        (status = 400, description = "path is not a JSON Pointer", body = ErrorResp),
+        (status = 404, description = "key or path not found", body = ErrorResp),
+        (status = 409, description = "value is not a JSON document", body = ErrorResp),
+    ),
+)]
+#[get("/json/{key:.+}")]
+async fn get_json(
+    mc: Data<Store>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    path: Path<String>,
+    query: Query<JsonQuery>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&path);
+    if let Some(proxied) = proxied_raw(cluster.as_ref().map(Data::get_ref), &http, &path, Bytes::new()).await {
+        return tagged(key, proxied)
+    }
+    tagged(key, not_modified(&http, document_part(&mc, &path, &query.path).await))
+}
+
+#[utoipa::path(
+    get,
+    path = "/ns/{name}/json/{key}",
+    params(("name" = String, Path, description = "namespace"), ("key" = String, Path, description = "key"), JsonQuery),
+    responses(
+        (status = 200, description = "part of the document, `ETag` is its version", body = Object),
+        (status = 304, description = "document has the version from `If-None-Match`"),
+        (status = 400, description = "path is not a JSON Pointer", body = ErrorResp),
+        (status = 404, description = "key, path or namespace not found", body = ErrorResp),
+        (status = 409, description = "value is not a JSON document", body = ErrorResp),
+    ),
+)]
+#[get("/json/{key:.+}")]
+async fn ns_get_json(
+    namespaces: Data<Namespaces>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    path: Path<(String, String)>,
+    query: Query<JsonQuery>,
+) -> Result<HttpResponse, Error> {
+    let (name, requested) = path.into_inner();
+    let key = AuditKey::new(&requested);
+    if let Some(proxied) = proxied_raw(cluster.as_ref().map(Data::get_ref), &http, &requested, Bytes::new()).await {
+        return tagged(key, proxied)
+    }
+    let mc = match namespace(&namespaces, &name) {
+        Ok(mc) => mc,
+        Err(err) => return tagged(key, Err(err)),
+    };
+    tagged(key, not_modified(&http, document_part(&mc, &requested, &query.path).await))
+}
+
+/// Stores the body as the value like a put to a key route, once it is checked to be a JSON document.
+#[utoipa::path(
+    put,
+    path = "/json/{key}",
+    params(("key" = String, Path, description = "key"), KeyQuery),
+    request_body(content = Object, content_type = "application/json"),
+    responses(
+        (status = 200, description = "stored, `ETag` is the new version"),
+        (status = 304, description = "store can't fit the value"),
+        (status = 400, description = "body is not a JSON document", body = ErrorResp),
+        (status = 412, description = "version doesn't match", body = ErrorResp),
+    ),
+)]
+#[put("/json/{key:.+}")]
+#[allow(clippy::too_many_arguments)]
+async fn put_json(
+    mc: Data<Store>,
+    l1: Data<L1>,
+    origin: Option<Data<Origin>>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    path: Path<String>,
+    query: Query<KeyQuery>,
+    body: Bytes,
+) -> Result<HttpResponse, Error> {
+    l1.invalidate(&path);
+    let key = AuditKey::new(&path);
+    if let Some(proxied) = proxied_raw(cluster.as_ref().map(Data::get_ref), &http, &path, body.clone()).await {
+        return tagged(key, proxied)
+    }
+    let req = match put_json_req(&http, path.into_inner(), query.into_inner(), body) {
+        Ok(req) => req,
+        Err(err) => return tagged(key, Err(err)),
+    };
+    let origin = origin.as_ref().map(Data::get_ref);
+    let write = forwarded(origin, || set_write(&req));
+    let prior = before_write(origin, &mc, &req.key).await;
+    let stored = set_into(&mc, req).await;
+    tagged(key, write_through(stored, origin, &mc, write, prior).await)
+}
+
+#[utoipa::path(
+    put,
+    path = "/ns/{name}/json/{key}",
+    params(("name" = String, Path, description = "namespace"), ("key" = String, Path, description = "key"), KeyQuery),
+    request_body(content = Object, content_type = "application/json"),
+    responses(
+        (status = 200, description = "stored, `ETag` is the new version"),
+        (status = 304, description = "store can't fit the value"),
+        (status = 400, description = "body is not a JSON document", body = ErrorResp),
+        (status = 404, description = "namespace not found", body = ErrorResp),
+        (status = 412, description = "version doesn't match", body = ErrorResp),
+    ),
+)]
+#[put("/json/{key:.+}")]
+async fn ns_put_json(
+    namespaces: Data<Namespaces>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    path: Path<(String, String)>,
+    query: Query<KeyQuery>,
+    body: Bytes,
+) -> Result<HttpResponse, Error> {
+    let (name, requested) = path.into_inner();
+    let key = AuditKey::new(&requested);
+    if let Some(proxied) = proxied_raw(cluster.as_ref().map(Data::get_ref), &http, &requested, body.clone()).await {
+        return tagged(key, proxied)
+    }
+    let mc = match namespace(&namespaces, &name) {
+        Ok(mc) => mc,
+        Err(err) => return tagged(key, Err(err)),
+    };
+    let req = match put_json_req(&http, requested, query.into_inner(), body) {
+        Ok(req) => req,
+        Err(err) => return tagged(key, Err(err)),
+    };
+    let origin = namespaces.origin(&name);
+    let write = forwarded(origin.as_deref(), || set_write(&req));
+    let prior = before_write(origin.as_deref(), &mc, &req.key).await;
+    let stored = set_into(&mc, req).await;
+    tagged(key, write_through(stored, origin.as_deref(), &mc, write, prior).await)
+}
+
+/// Applies a JSON Patch to the stored document, keeping its ttl. Either every operation
+/// applies or the document is left as it was, `If-Match` makes the patch conditional.
+#[utoipa::path(
+    patch,
+    path = "/json/{key}",
+    params(("key" = String, Path, description = "key")),
+    request_body(content = Vec<PatchOp>, content_type = "application/json-patch+json"),
+    responses(
+        (status = 200, description = "patched, `ETag` is the new version"),
+        (status = 304, description = "store can't fit the patched document"),
+        (status = 400, description = "path is not a JSON Pointer", body = ErrorResp),
+        (status = 404, description = "key not found", body = ErrorResp),
+        (status = 409, description = "value is not a JSON document, a path is missing or a test failed", body = ErrorResp),
+        (status = 412, description = "version doesn't match", body = ErrorResp),
+    ),
+)]
+#[patch("/json/{key:.+}")]
+async fn patch_json(
+    mc: Data<Store>,
+    l1: Data<L1>,
+    origin: Option<Data<Origin>>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    path: Path<String>,
+    body: Bytes,
+) -> Result<HttpResponse, Error> {
+    l1.invalidate(&path);
+    let key = AuditKey::new(&path);
+    if let Some(proxied) = proxied_raw(cluster.as_ref().map(Data::get_ref), &http, &path, body.clone()).await {
+        return tagged(key, proxied)
+    }
+    let origin = origin.as_ref().map(Data::get_ref);
+    let prior = before_write(origin, &mc, &path).await;
+    let (patched, write) = match patch_document(&mc, &http, &path, &body).await {
+        Ok((resp, data)) => (Ok(resp), forwarded(origin, || Write::Set { key: path.into_inner(), data })),
+        Err(err) => (Err(err), None),
+    };
+    tagged(key, write_through(patched, origin, &mc, write, prior).await)
+}
+
+#[utoipa::path(
+    patch,
+    path = "/ns/{name}/json/{key}",
+    params(("name" = String, Path, description = "namespace"), ("key" = String, Path, description = "key")),
+    request_body(content = Vec<PatchOp>, content_type = "application/json-patch+json"),
+    responses(
+        (status = 200, description = "patched, `ETag` is the new version"),
+        (status = 304, description = "store can't fit the patched document"),
+        (status = 400, description = "path is not a JSON Pointer", body = ErrorResp),
+        (status = 404, description = "key or namespace not found", body = ErrorResp),
+        (status = 409, description = "value is not a JSON document, a path is missing or a test failed", body = ErrorResp),
+        (status = 412, description = "version doesn't match", body = ErrorResp),
+    ),
+)]
+#[patch("/json/{key:.+}")]
+async fn ns_patch_json(
+    namespaces: Data<Namespaces>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    path: Path<(String, String)>,
+    body: Bytes,
+) -> Result<HttpResponse, Error> {
+    let (name, requested) = path.into_inner();
+    let key = AuditKey::new(&requested);
+    if let Some(proxied) = proxied_raw(cluster.as_ref().map(Data::get_ref), &http, &requested, body.clone()).await {
+        return tagged(key, proxied)
+    }
+    let mc = match namespace(&namespaces, &name) {
+        Ok(mc) => mc,
+        Err(err) => return tagged(key, Err(err)),
+    };
+    let origin = namespaces.origin(&name);
+    let prior = before_write(origin.as_deref(), &mc, &requested).await;
+    let (patched, write) = match patch_document(&mc, &http, &requested, &body).await {
+        Ok((resp, data)) => (Ok(resp), forwarded(origin.as_deref(), || Write::Set { key: requested, data })),
+        Err(err) => (Err(err), None),
+    };
+    tagged(key, write_through(patched, origin.as_deref(), &mc, write, prior).await)
+}
+
+async fn document_part(mc: &Store, key: &str, path: &str) -> Result<HttpResponse, Error> {
+    let (data, version, sliding) = {
+        let mc = mc.read().await;
+        let (data, version) = mc.get_with_version(key).ok_or_else(key_not_found)?;
+        (data, version, mc.is_sliding(key))
+    };
+    // sliding items must reach the store on every read to stay alive
+    if sliding {
+        mc.write().await.refresh(key);
+    }
+    let doc = document(&data)?;
+    let part = jsonpatch::get(&doc, path).map_err(|err| match err {
+        PatchError::NoPath => Error::NotFound("path"),
+        err => patch_error(err),
+    })?;
+    Ok(Code::Ok().set_header(ETAG, etag(version)).json(part))
+}
+
+/// Patches the document under the write lock, so concurrent patches don't lose updates.
+/// Returns the response and the patched document.
+async fn patch_document(mc: &Store, http: &HttpRequest, key: &str, body: &[u8]) -> Result<(HttpResponse, Vec<u8>), Error> {
+    let ops: Vec<PatchOp> = serde_json::from_slice(body)
+        .map_err(|_| Error::BadRequest("body must be an array of JSON Patch operations"))?;
+    let if_version = if_match(http)?;
+
+    let mut mc = mc.write().await;
+    check_version(&mc, key, if_version)?;
+    let mut doc = document(&mc.get(key).ok_or_else(key_not_found)?)?;
+    jsonpatch::apply(&mut doc, ops).map_err(patch_error)?;
+
+    let data = doc.to_string().into_bytes();
+    let evicted = mc.stats().evicted_bytes;
+    match mc.replace(key, data.clone()) {
+        Ok(true) => (),
+        Ok(false) => return Err(key_not_found()),
+        Err(_) => return Err(Error::NotStored),
+    }
+    let version = mc.version(key).unwrap_or_default();
+    let mut resp = Code::Ok().set_header(ETAG, etag(version)).finish();
+    resp.extensions_mut().insert(stored(&mc, evicted));
+    Ok((resp, data))
+}
+
+/// [`put_req`] of a body which must be a JSON document
+fn put_json_req(http: &HttpRequest, key: String, query: KeyQuery, body: Bytes) -> Result<SetReq, Error> {
+    serde_json::from_slice::<serde_json::Value>(&body)
+        .map_err(|_| Error::BadRequest("body is not a JSON document"))?;
+    put_req(http, key, query, body)
+}
+
+fn document(data: &[u8]) -> Result<serde_json::Value, Error> {
+    serde_json::from_slice(data).map_err(|_| Error::Conflict("value is not a JSON document"))
+}
+
+fn patch_error(err: PatchError) -> Error {
+    match err {
+        PatchError::InvalidPath => Error::BadRequest("path must be empty or start with /"),
+        PatchError::NoPath => Error::Conflict("path doesn't exist in the document"),
+        PatchError::TestFailed => Error::Conflict("test operation of the patch failed"),
+    }
+}
+
+/// Answers with not modified if `If-None-Match` has the `ETag` of the found value.
+fn not_modified(http: &HttpRequest, found: Result<HttpResponse, Error>) -> Result<HttpResponse, Error> {
+    let found = found?;
+    let tag = match (found.headers().get(ETAG), http.headers().get(IF_NONE_MATCH)) {
+        (Some(tag), Some(expected)) if found.status().is_success() && lists_tag(expected, tag) => tag.clone(),
+        _ => return Ok(found),
+    };
+    let mut resp = Code::NotModified().set_header(ETAG, tag).finish();
+    resp.extensions_mut().insert(Outcome::Hit);
+    Ok(resp)
+}
+
+/// Whether `If-None-Match` is `*` or lists `tag`, compared weakly so `W/"1"` matches `"1"`.
+fn lists_tag(if_none_match: &HeaderValue, tag: &HeaderValue) -> bool {
+    let (expected, tag) = match (if_none_match.to_str(), tag.to_str()) {
+        (Ok(expected), Ok(tag)) => (expected, tag),
+        _ => return false,
+    };
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_owned();
+    let tag = opaque(tag);
+    expected.trim() == "*" || expected.split(',').any(|expected| opaque(expected) == tag)
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct NegativeReq {
+    key: String,
+    #[schema(value_type = String, example = "10s")]
+    ttl: DurationString,
+    /// store only if the caller holds this miss lease, see [`LeaseResp`]
+    lease: Option<u64>,
+}
+
+/// Marks the key known missing for `ttl`, get answers it with no content instead of not found.
+#[utoipa::path(
+    post,
+    path = "/set_negative",
+    request_body = NegativeReq,
+    responses(
+        (status = 200, description = "marker stored"),
+        (status = 304, description = "store can't fit the marker"),
+        (status = 412, description = "lease doesn't match", body = ErrorResp),
+    ),
+)]
+#[post("/set_negative")]
+async fn set_negative(
+    mc: Data<Store>,
+    l1: Data<L1>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<NegativeReq>,
+) -> Result<HttpResponse, Error> {
+    l1.invalidate(&req.key);
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    tagged(key, set_negative_into(&mc, req.0).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/ns/{name}/set_negative",
+    params(("name" = String, Path, description = "namespace")),
+    request_body = NegativeReq,
+    responses(
+        (status = 200, description = "marker stored"),
+        (status = 304, description = "store can't fit the marker"),
+        (status = 412, description = "lease doesn't match", body = ErrorResp),
+        (status = 404, description = "namespace not found", body = ErrorResp),
+    ),
+)]
+#[post("/set_negative")]
+async fn ns_set_negative(
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<NegativeReq>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    let mc = match namespace(&namespaces, &name) {
+        Ok(mc) => mc,
+        Err(err) => return tagged(key, Err(err)),
+    };
+    tagged(key, set_negative_into(&mc, req.0).await)
+}
+
+async fn set_negative_into(mc: &Store, req: NegativeReq) -> Result<HttpResponse, Error> {
+    let mut mc = mc.write().await;
+    check_lease(&mc, &req.key, req.lease)?;
+    let evicted = mc.stats().evicted_bytes;
+    mc.set_negative(req.key, req.ttl.into())
+        .map_err(|_| Error::NotStored)?;
+
+    let mut resp = Code::Ok().finish();
+    resp.extensions_mut().insert(stored(&mc, evicted));
+    Ok(resp)
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct PinReq {
+    key: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/pin",
+    request_body = PinReq,
+    responses(
+        (status = 200, description = "pinned"),
+        (status = 404, description = "key not found", body = ErrorResp),
+    ),
+)]
+#[post("/pin")]
+async fn pin(
+    mc: Data<Store>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<PinReq>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    tagged(key, pin_in(&mc, &req.key, true).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/ns/{name}/pin",
+    params(("name" = String, Path, description = "namespace")),
+    request_body = PinReq,
+    responses(
+        (status = 200, description = "pinned"),
+        (status = 404, description = "key not found", body = ErrorResp),
+    ),
+)]
+#[post("/pin")]
+async fn ns_pin(
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<PinReq>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    let mc = match namespace(&namespaces, &name) {
+        Ok(mc) => mc,
+        Err(err) => return tagged(key, Err(err)),
+    };
+    tagged(key, pin_in(&mc, &req.key, true).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/unpin",
+    request_body = PinReq,
+    responses(
+        (status = 200, description = "unpinned"),
+        (status = 404, description = "key not found", body = ErrorResp),
+    ),
+)]
+#[post("/unpin")]
+async fn unpin(
+    mc: Data<Store>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<PinReq>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    tagged(key, pin_in(&mc, &req.key, false).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/ns/{name}/unpin",
+    params(("name" = String, Path, description = "namespace")),
+    request_body = PinReq,
+    responses(
+        (status = 200, description = "unpinned"),
+        (status = 404, description = "key not found", body = ErrorResp),
+    ),
+)]
+#[post("/unpin")]
+async fn ns_unpin(
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<PinReq>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    let mc = match namespace(&namespaces, &name) {
+        Ok(mc) => mc,
+        Err(err) => return tagged(key, Err(err)),
+    };
+    tagged(key, pin_in(&mc, &req.key, false).await)
+}
+
+async fn pin_in(mc: &Store, key: &str, pinned: bool) -> Result<HttpResponse, Error> {
+    let mut mc = mc.write().await;
+    let found = match pinned {
+        true => mc.pin(key),
+        false => mc.unpin(key),
+    };
+    match found {
+        true => Ok(Code::Ok().finish()),
+        false => Err(key_not_found()),
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct AcquireLockReq {
+    key: String,
+    #[schema(value_type = String, example = "10s")]
+    ttl: DurationString,
+    /// stored as the lock value, so holder can be looked up with get
+    #[serde(default)]
+    owner: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct ReleaseLockReq {
+    key: String,
+    token: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct LockResp {
+    /// fencing token, greater than any issued before
+    token: u64,
+}
+
+#[utoipa::path(
+    post,
+    path = "/lock/acquire",
+    request_body = AcquireLockReq,
+    responses(
+        (status = 200, description = "lock acquired", body = LockResp),
+        (status = 304, description = "store can't fit the lock"),
+        (status = 409, description = "lock is held", body = ErrorResp),
+    ),
+)]
+#[post("/lock/acquire")]
+async fn acquire_lock(
+    mc: Data<Store>,
+    l1: Data<L1>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<AcquireLockReq>,
+) -> Result<HttpResponse, Error> {
+    l1.invalidate(&req.key);
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    tagged(key, acquire_lock_in(&mc, req.into_inner()).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/ns/{name}/lock/acquire",
+    params(("name" = String, Path, description = "namespace")),
+    request_body = AcquireLockReq,
+    responses(
+        (status = 200, description = "lock acquired", body = LockResp),
+        (status = 304, description = "store can't fit the lock"),
+        (status = 409, description = "lock is held", body = ErrorResp),
+        (status = 404, description = "namespace not found", body = ErrorResp),
+    ),
+)]
+#[post("/lock/acquire")]
+async fn ns_acquire_lock(
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<AcquireLockReq>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    let mc = match namespace(&namespaces, &name) {
+        Ok(mc) => mc,
+        Err(err) => return tagged(key, Err(err)),
+    };
+    tagged(key, acquire_lock_in(&mc, req.into_inner()).await)
+}
+
+async fn acquire_lock_in(mc: &Store, req: AcquireLockReq) -> Result<HttpResponse, Error> {
+    let token = mc.write().await
+        .acquire_lock(req.key, req.owner.into_bytes(), req.ttl.into())
+        .map_err(|_| Error::NotStored)?
+        .ok_or(Error::Conflict("lock is held"))?;
+    Ok(Code::Ok().json(LockResp { token }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/lock/release",
+    request_body = ReleaseLockReq,
+    responses(
+        (status = 200, description = "lock released"),
+        (status = 409, description = "lock is not held with this token", body = ErrorResp),
+    ),
+)]
+#[post("/lock/release")]
+async fn release_lock(
+    mc: Data<Store>,
+    l1: Data<L1>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<ReleaseLockReq>,
+) -> Result<HttpResponse, Error> {
+    l1.invalidate(&req.key);
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    tagged(key, release_lock_in(&mc, &req).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/ns/{name}/lock/release",
+    params(("name" = String, Path, description = "namespace")),
+    request_body = ReleaseLockReq,
+    responses(
+        (status = 200, description = "lock released"),
+        (status = 409, description = "lock is not held with this token", body = ErrorResp),
+        (status = 404, description = "namespace not found", body = ErrorResp),
+    ),
+)]
+#[post("/lock/release")]
+async fn ns_release_lock(
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<ReleaseLockReq>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    let mc = match namespace(&namespaces, &name) {
+        Ok(mc) => mc,
+        Err(err) => return tagged(key, Err(err)),
+    };
+    tagged(key, release_lock_in(&mc, &req).await)
+}
+
+async fn release_lock_in(mc: &Store, req: &ReleaseLockReq) -> Result<HttpResponse, Error> {
+    match mc.write().await.release_lock(&req.key, req.token) {
+        true => Ok(Code::Ok().finish()),
+        false => Err(Error::Conflict("lock is not held with this token")),
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct DumpReq {
+    key: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct DumpResp {
+    /// value, remaining ttl, flags and version in opaque form taken by restore
+    dump: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct RestoreReq {
+    key: String,
+    dump: String,
+    /// overwrite existing key instead of failing with conflict
+    #[serde(default)]
+    replace: bool,
+}
+
+#[utoipa::path(
+    post,
+    path = "/dump",
+    request_body = DumpReq,
+    responses(
+        (status = 200, description = "dumped item", body = DumpResp),
+        (status = 404, description = "key not found", body = ErrorResp),
+    ),
+)]
+#[post("/dump")]
+async fn dump_key(
+    mc: Data<Store>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<DumpReq>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    tagged(key, dump_from(&mc, &req.key).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/ns/{name}/dump",
+    params(("name" = String, Path, description = "namespace")),
+    request_body = DumpReq,
+    responses(
+        (status = 200, description = "dumped item", body = DumpResp),
+        (status = 404, description = "key not found", body = ErrorResp),
+    ),
+)]
+#[post("/dump")]
+async fn ns_dump_key(
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: Json<DumpReq>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    let mc = match namespace(&namespaces, &name) {
+        Ok(mc) => mc,
+        Err(err) => return tagged(key, Err(err)),
+    };
+    tagged(key, dump_from(&mc, &req.key).await)
+}
+
+async fn dump_from(mc: &Store, key: &str) -> Result<HttpResponse, Error> {
+    let dumped = mc.read().await.dump(key).ok_or_else(key_not_found)?;
+    Ok(Code::Ok().json(DumpResp { dump: crate::dump::encode(&dumped) }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/restore",
+    request_body = RestoreReq,
+    responses(
+        (status = 200, description = "restored"),
+        (status = 304, description = "store can't fit the value"),
+        (status = 400, description = "malformed dump", body = ErrorResp),
+        (status = 409, description = "key already exists", body = ErrorResp),
+    ),
+)]
+#[post("/restore")]
+async fn restore(
+    mc: Data<Store>,
+    l1: Data<L1>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: DecodedJson<RestoreReq>,
+) -> Result<HttpResponse, Error> {
+    l1.invalidate(&req.key);
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    tagged(key, restore_into(&mc, req.0).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/ns/{name}/restore",
+    params(("name" = String, Path, description = "namespace")),
+    request_body = RestoreReq,
+    responses(
+        (status = 200, description = "restored"),
+        (status = 304, description = "store can't fit the value"),
+        (status = 400, description = "malformed dump", body = ErrorResp),
+        (status = 409, description = "key already exists", body = ErrorResp),
+        (status = 404, description = "namespace not found", body = ErrorResp),
+    ),
+)]
+#[post("/restore")]
+async fn ns_restore(
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    req: DecodedJson<RestoreReq>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&req.key);
+    if let Some(proxied) = proxied(cluster.as_ref().map(Data::get_ref), &http, &req.key, &*req).await {
+        return tagged(key, proxied)
+    }
+    let mc = match namespace(&namespaces, &name) {
+        Ok(mc) => mc,
+        Err(err) => return tagged(key, Err(err)),
+    };
+    tagged(key, restore_into(&mc, req.0).await)
+}
+
+async fn restore_into(mc: &Store, req: RestoreReq) -> Result<HttpResponse, Error> {
+    let RestoreReq { key, dump, replace } = req;
+    let dumped = crate::dump::decode(&dump)?;
+
+    let mut mc = mc.write().await;
+    if !replace && mc.version(&key).is_some() {
+        return Err(Error::Conflict("key already exists"))
+    }
+    mc.restore(key, dumped).map_err(|_| Error::NotStored)?;
+    Ok(Code::Ok().finish())
+}
+
+#[derive(Deserialize, ToSchema)]
+struct DumpBulkReq {
+    /// only keys starting with it are dumped, every key if empty
+    #[serde(default)]
+    prefix: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+struct DumpedItem {
+    key: String,
+    dump: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct DumpBulkResp {
+    items: Vec<DumpedItem>,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct RestoreBulkReq {
+    items: Vec<DumpedItem>,
+    #[serde(default)]
+    replace: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+struct RestoreBulkResp {
+    restored: usize,
+    /// existing keys left as they are and values the store couldn't fit
+    skipped: usize,
+}
+
+#[utoipa::path(
+    post,
+    path = "/dump/bulk",
+    request_body = DumpBulkReq,
+    responses(
+        (status = 200, description = "dumped items, in cluster mode only those the node owns", body = DumpBulkResp),
+    ),
+)]
+#[post("/dump/bulk")]
+async fn dump_bulk(
+    mc: Data<Store>,
+    cluster: Option<Data<Cluster>>,
+    req: Json<DumpBulkReq>,
+) -> HttpResponse {
+    dump_bulk_from(&mc, cluster.as_ref().map(Data::get_ref), &req.prefix).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/ns/{name}/dump/bulk",
+    params(("name" = String, Path, description = "namespace")),
+    request_body = DumpBulkReq,
+    responses(
+        (status = 200, description = "dumped items, in cluster mode only those the node owns", body = DumpBulkResp),
+        (status = 404, description = "namespace not found", body = ErrorResp),
+    ),
+)]
+#[post("/dump/bulk")]
+async fn ns_dump_bulk(
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+    cluster: Option<Data<Cluster>>,
+    req: Json<DumpBulkReq>,
+) -> Result<HttpResponse, Error> {
+    let mc = namespace(&namespaces, &name)?;
+    Ok(dump_bulk_from(&mc, cluster.as_ref().map(Data::get_ref), &req.prefix).await)
+}
+
+/// Keys the node keeps but doesn't own are left out, other nodes may have newer values of them.
+async fn dump_bulk_from(mc: &Store, cluster: Option<&Cluster>, prefix: &str) -> HttpResponse {
+    let mc = mc.read().await;
+    let items = mc.keys()
+        .filter(|key| key.starts_with(prefix))
+        .filter(|key| cluster.is_none_or(|cluster| cluster.owner(key).is_none()))
+        .filter_map(|key| Some(DumpedItem { key: key.to_owned(), dump: crate::dump::encode(&mc.dump(key)?) }))
+        .collect();
+    Code::Ok().json(DumpBulkResp { items })
+}
+
+#[utoipa::path(
+    post,
+    path = "/restore/bulk",
+    request_body = RestoreBulkReq,
+    responses(
+        (status = 200, description = "restored and skipped counts", body = RestoreBulkResp),
+        (status = 400, description = "malformed dump or keys owned by other cluster nodes", body = ErrorResp),
+    ),
+)]
+#[post("/restore/bulk")]
+async fn restore_bulk(
+    mc: Data<Store>,
+    l1: Data<L1>,
+    cluster: Option<Data<Cluster>>,
+    req: DecodedJson<RestoreBulkReq>,
+) -> Result<HttpResponse, Error> {
+    owned_here(cluster.as_ref().map(Data::get_ref), req.items.iter().map(|item| item.key.as_str()))?;
+    req.items.iter().for_each(|item| l1.invalidate(&item.key));
+    restore_bulk_into(&mc, req.0).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/ns/{name}/restore/bulk",
+    params(("name" = String, Path, description = "namespace")),
+    request_body = RestoreBulkReq,
+    responses(
+        (status = 200, description = "restored and skipped counts", body = RestoreBulkResp),
+        (status = 400, description = "malformed dump or keys owned by other cluster nodes", body = ErrorResp),
+        (status = 404, description = "namespace not found", body = ErrorResp),
+    ),
+)]
+#[post("/restore/bulk")]
+async fn ns_restore_bulk(
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+    cluster: Option<Data<Cluster>>,
+    req: DecodedJson<RestoreBulkReq>,
+) -> Result<HttpResponse, Error> {
+    let mc = namespace(&namespaces, &name)?;
+    owned_here(cluster.as_ref().map(Data::get_ref), req.items.iter().map(|item| item.key.as_str()))?;
+    restore_bulk_into(&mc, req.0).await
+}
+
+/// Malformed dump fails the whole request before anything is restored.
+async fn restore_bulk_into(mc: &Store, req: RestoreBulkReq) -> Result<HttpResponse, Error> {
+    let items = req.items.into_iter()
+        .map(|item| Ok((item.key, crate::dump::decode(&item.dump)?)))
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let total = items.len();
+    let mut mc = mc.write().await;
+    let mut restored = 0;
+    for (key, dumped) in items {
+        if (req.replace || mc.version(&key).is_none()) && mc.restore(key, dumped).is_ok() {
+            restored += 1;
+        }
+    }
+    Ok(Code::Ok().json(RestoreBulkResp { restored, skipped: total - restored }))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ExportQuery {
+    /// only keys starting with it are exported, every key if empty
+    #[serde(default)]
+    prefix: String,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ImportQuery {
+    /// overwrite existing keys
+    #[serde(default)]
+    replace: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ImportResp {
+    restored: usize,
+    /// existing keys left as they are and values the store couldn't fit
+    skipped: usize,
+}
+
+impl From<Imported> for ImportResp {
+    fn from(imported: Imported) -> ImportResp {
+        ImportResp { restored: imported.restored, skipped: imported.skipped }
+    }
+}
+
+/// Items as newline delimited json of the versioned format described in [`crate::export`].
+#[utoipa::path(
+    get,
+    path = "/export",
+    params(ExportQuery),
+    responses(
+        (status = 200, description = "header line followed by item lines", content_type = "application/x-ndjson"),
+    ),
+)]
+#[get("/export")]
+async fn export(
+    mc: Data<Store>,
+    query: Query<ExportQuery>,
+) -> HttpResponse {
+    crate::export::stream(mc.into_inner(), &query.prefix).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/ns/{name}/export",
+    params(("name" = String, Path, description = "namespace"), ExportQuery),
+    responses(
+        (status = 200, description = "header line followed by item lines", content_type = "application/x-ndjson"),
+        (status = 404, description = "namespace not found", body = ErrorResp),
+    ),
+)]
+#[get("/export")]
+async fn ns_export(
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+    query: Query<ExportQuery>,
+) -> Result<HttpResponse, Error> {
+    let mc = namespace(&namespaces, &name)?;
+    Ok(crate::export::stream(mc, &query.prefix).await)
+}
+
+/// Restores an export made by `/export` of this or an older server version.
+#[utoipa::path(
+    post,
+    path = "/import",
+    params(ImportQuery),
+    request_body(content = String, content_type = "application/x-ndjson"),
+    responses(
+        (status = 200, description = "restored and skipped counts", body = ImportResp),
+        (status = 400, description = "malformed or unsupported export", body = ErrorResp),
+        (status = 413, description = "body above import_limit or a line above json_limit", body = ErrorResp),
+    ),
+)]
+#[post("/import")]
+async fn import(
+    mc: Data<Store>,
+    l1: Data<L1>,
+    limits: Data<ImportLimits>,
+    query: Query<ImportQuery>,
+    body: Payload,
+) -> Result<HttpResponse, Error> {
+    let imported = crate::export::import(&mc, Some(&l1), body, &limits, query.replace).await?;
+    Ok(Code::Ok().json(ImportResp::from(imported)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/ns/{name}/import",
+    params(("name" = String, Path, description = "namespace"), ImportQuery),
+    request_body(content = String, content_type = "application/x-ndjson"),
+    responses(
+        (status = 200, description = "restored and skipped counts", body = ImportResp),
+        (status = 400, description = "malformed or unsupported export", body = ErrorResp),
+        (status = 413, description = "body above import_limit or a line above json_limit", body = ErrorResp),
+        (status = 404, description = "namespace not found", body = ErrorResp),
+    ),
+)]
+#[post("/import")]
+async fn ns_import(
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+    limits: Data<ImportLimits>,
+    query: Query<ImportQuery>,
+    body: Payload,
+) -> Result<HttpResponse, Error> {
+    let mc = namespace(&namespaces, &name)?;
+    let imported = crate::export::import(&mc, None, body, &limits, query.replace).await?;
+    Ok(Code::Ok().json(ImportResp::from(imported)))
+}
+
+/// Operation of a batch, `op` tells which one.
+#[derive(Deserialize, ToSchema)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum BatchOp {
+    Get {
+        key: String,
+    },
+    Set {
+        key: String,
+        data: String,
+        #[schema(value_type = Option<String>, example = "10s")]
+        ttl: Option<DurationString>,
+    },
+    Delete {
+        key: String,
+    },
+    /// replaces ttl, the item never expires without one unless `max_ttl` is configured
+    Touch {
+        key: String,
+        #[schema(value_type = Option<String>, example = "10s")]
+        ttl: Option<DurationString>,
+    },
+    /// adds to a decimal integer value keeping its ttl
+    Incr {
+        key: String,
+        /// negative one decrements
+        #[serde(default = "one")]
+        delta: i64,
+    },
+}
+
+fn one() -> i64 {
+    1
+}
+
+impl BatchOp {
+    fn key(&self) -> &str {
+        match self {
+            BatchOp::Get { key } | BatchOp::Set { key, .. } | BatchOp::Delete { key }
+            | BatchOp::Touch { key, .. } | BatchOp::Incr { key, .. } => key,
+        }
+    }
+
+    fn is_write(&self) -> bool {
+        !matches!(self, BatchOp::Get { .. })
+    }
+}
+
+/// Outcome of a batch operation, `status` is the one it would get as a separate request.
+#[derive(Serialize, Default, ToSchema)]
+struct BatchResult {
+    status: u16,
+    /// value of get, deleted value of delete
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<String>,
+    /// sum of incr
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Runs operations in order under a single write lock, so other requests see either none
+/// or all of them. A failed operation doesn't stop the following ones.
+/// Origins are neither read nor written through.
+#[utoipa::path(
+    post,
+    path = "/batch",
+    request_body = [BatchOp],
+    responses(
+        (status = 200, description = "results in the order of operations", body = [BatchResult]),
+        (status = 400, description = "a key is owned by another cluster node", body = ErrorResp),
+    ),
+)]
+#[post("/batch")]
+async fn batch(
+    mc: Data<Store>,
+    l1: Data<L1>,
+    cluster: Option<Data<Cluster>>,
+    ops: DecodedJson<Vec<BatchOp>>,
+) -> Result<HttpResponse, Error> {
+    ops.iter()
+        .filter(|op| op.is_write())
+        .for_each(|op| l1.invalidate(op.key()));
+    owned_here(cluster.as_ref().map(Data::get_ref), ops.iter().map(BatchOp::key))?;
+    Ok(batch_in(&mc, ops.0).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/ns/{name}/batch",
+    params(("name" = String, Path, description = "namespace")),
+    request_body = [BatchOp],
+    responses(
+        (status = 200, description = "results in the order of operations", body = [BatchResult]),
+        (status = 400, description = "a key is owned by another cluster node", body = ErrorResp),
+        (status = 404, description = "namespace not found", body = ErrorResp),
+    ),
+)]
+#[post("/batch")]
+async fn ns_batch(
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+    cluster: Option<Data<Cluster>>,
+    ops: DecodedJson<Vec<BatchOp>>,
+) -> Result<HttpResponse, Error> {
+    let mc = namespace(&namespaces, &name)?;
+    owned_here(cluster.as_ref().map(Data::get_ref), ops.iter().map(BatchOp::key))?;
+    Ok(batch_in(&mc, ops.0).await)
+}
+
+/// A batch can't be split between nodes without losing its single lock,
+/// nor can a bulk restore without losing its all or nothing decoding.
+fn owned_here<'a>(cluster: Option<&Cluster>, mut keys: impl Iterator<Item = &'a str>) -> Result<(), Error> {
+    match cluster {
+        Some(cluster) if keys.any(|key| cluster.owner(key).is_some()) => {
+            Err(Error::BadRequest("keys must be owned by the node serving the request"))
+        },
+        _ => Ok(()),
+    }
+}
+
+async fn batch_in(mc: &Store, ops: Vec<BatchOp>) -> HttpResponse {
+    let mut mc = mc.write().await;
+    let results: Vec<BatchResult> = ops.into_iter()
+        .map(|op| run_op(&mut mc, op).unwrap_or_else(op_failed))
+        .collect();
+    Code::Ok().json(results)
+}
+
+fn op_failed(err: Error) -> BatchResult {
+    BatchResult {
+        status: err.status_code().as_u16(),
+        error: Some(err.to_string()),
+        ..Default::default()
+    }
+}
+
+/// Operation of a transaction, a batch operation with an optional precondition.
+#[derive(Deserialize, ToSchema)]
+struct TxOp {
+    #[serde(flatten)]
+    op: BatchOp,
+    /// version from `ETag` the key must have, 0 requires the key to be missing
+    if_version: Option<u64>,
+}
+
+/// Why a transaction was rolled back.
+#[derive(Serialize, ToSchema)]
+struct TxFailure {
+    /// index of the operation which failed
+    failed: usize,
+    result: BatchResult,
+}
+
+/// Like a batch, but either every operation succeeds or none is applied, a get of
+/// a missing key fails it too. Preconditions are checked before anything is applied.
+/// Rollback recreates previous values with their versions, values evicted
+/// to make room for the transaction are not brought back.
+#[utoipa::path(
+    post,
+    path = "/transaction",
+    request_body = [TxOp],
+    responses(
+        (status = 200, description = "results in the order of operations", body = [BatchResult]),
+        (status = 400, description = "a key is owned by another cluster node", body = ErrorResp),
+        (status = 409, description = "an operation or precondition failed, nothing is applied", body = TxFailure),
+    ),
+)]
+#[post("/transaction")]
+async fn transaction(
+    mc: Data<Store>,
+    l1: Data<L1>,
+    cluster: Option<Data<Cluster>>,
+    ops: DecodedJson<Vec<TxOp>>,
+) -> Result<HttpResponse, Error> {
+    ops.iter()
+        .filter(|tx| tx.op.is_write())
+        .for_each(|tx| l1.invalidate(tx.op.key()));
+    owned_here(cluster.as_ref().map(Data::get_ref), ops.iter().map(|tx| tx.op.key()))?;
+    Ok(transaction_in(&mc, ops.0).await)
+}
+
+#[utoipa::path(
+    post,
+    path = "/ns/{name}/transaction",
+    params(("name" = String, Path, description = "namespace")),
+    request_body = [TxOp],
+    responses(
+        (status = 200, description = "results in the order of operations", body = [BatchResult]),
+        (status = 400, description = "a key is owned by another cluster node", body = ErrorResp),
+        (status = 404, description = "namespace not found", body = ErrorResp),
+        (status = 409, description = "an operation or precondition failed, nothing is applied", body = TxFailure),
+    ),
+)]
+#[post("/transaction")]
+async fn ns_transaction(
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+    cluster: Option<Data<Cluster>>,
+    ops: DecodedJson<Vec<TxOp>>,
+) -> Result<HttpResponse, Error> {
+    let mc = namespace(&namespaces, &name)?;
+    owned_here(cluster.as_ref().map(Data::get_ref), ops.iter().map(|tx| tx.op.key()))?;
+    Ok(transaction_in(&mc, ops.0).await)
+}
+
+async fn transaction_in(mc: &Store, ops: Vec<TxOp>) -> HttpResponse {
+    let mut mc = mc.write().await;
+    let mismatch = ops.iter().position(|tx| {
+        matches!(tx.if_version, Some(expected) if mc.version(tx.op.key()).unwrap_or_default() != expected)
+    });
+    if let Some(failed) = mismatch {
+        return rolled_back(failed, Error::PreconditionFailed)
+    }
+
+    // writes are journaled, so rollback puts back any kind of item and nobody hears about them
+    mc.begin();
+    let mut results = Vec::with_capacity(ops.len());
+    for (at, TxOp { op, .. }) in ops.into_iter().enumerate() {
+        // reads are saved too, they refresh sliding ttls
+        mc.save(op.key());
+        match run_op(&mut mc, op) {
+            Ok(result) => results.push(result),
+            Err(err) => {
+                if !mc.rollback() {
+                    error!("transaction is not fully rolled back, some of its keys are missing");
+                }
+                return rolled_back(at, err)
+            },
+        }
+    }
+    mc.commit();
+    Code::Ok().json(results)
+}
+
+fn rolled_back(failed: usize, err: Error) -> HttpResponse {
+    Code::Conflict().json(TxFailure { failed, result: op_failed(err) })
+}
+
+fn run_op(mc: &mut Memcached, op: BatchOp) -> Result<BatchResult, Error> {
+    let ok = |data, value| BatchResult { status: StatusCode::OK.as_u16(), data, value, error: None };
+    match op {
+        BatchOp::Get { key } => {
+            let data = mc.get(&key).ok_or_else(key_not_found)?;
+            // sliding items must reach the store on every read to stay alive
+            if mc.is_sliding(&key) {
+                mc.refresh(&key);
+            }
+            Ok(ok(Some(as_string(data)?), None))
+        },
+        BatchOp::Set { key, data, ttl } => {
+            mc.set(key, data.into_bytes(), ttl.map(Into::into)).map_err(|_| Error::NotStored)?;
+            Ok(ok(None, None))
+        },
+        BatchOp::Delete { key } => {
+            let data = mc.delete(&key).ok_or_else(key_not_found)?;
+            Ok(ok(Some(as_string(data)?), None))
+        },
+        BatchOp::Touch { key, ttl } => match mc.touch(&key, ttl.map(Into::into)) {
+            true => Ok(ok(None, None)),
+            false => Err(key_not_found()),
+        },
+        BatchOp::Incr { key, delta } => match mc.incr(&key, delta) {
+            Ok(Some(sum)) => Ok(ok(None, Some(sum))),
+            Ok(None) => Err(key_not_found()),
+            Err(IncrError::NotANumber) => Err(Error::BadRequest("value is not a decimal integer")),
+            Err(IncrError::Overflow) => Err(Error::BadRequest("sum doesn't fit into 64 bits")),
+            Err(IncrError::NotStored) => Err(Error::NotStored),
+        },
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/stats/forecast",
+    responses(
+        (status = 200, description = "capacity outlook", body = Forecast),
+    ),
+)]
+#[get("/stats/forecast")]
+async fn forecast(
+    mc: Data<Store>,
+) -> Result<HttpResponse, Error> {
+    Ok(Code::Ok().json(Forecast::of(&*mc.read().await)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/ns/{name}/stats/forecast",
+    params(("name" = String, Path, description = "namespace")),
+    responses(
+        (status = 200, description = "capacity outlook", body = Forecast),
+        (status = 404, description = "namespace not found", body = ErrorResp),
+    ),
+)]
+#[get("/stats/forecast")]
+async fn ns_forecast(
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+) -> Result<HttpResponse, Error> {
+    let mc = namespace(&namespaces, &name)?;
+    let outlook = Forecast::of(&*mc.read().await);
+    Ok(Code::Ok().json(outlook))
+}
+
+#[utoipa::path(
+    get,
+    path = "/stats/distribution",
+    responses(
+        (status = 200, description = "items by ttl and value size", body = Distribution),
+    ),
+)]
+#[get("/stats/distribution")]
+async fn distribution(
+    mc: Data<Store>,
+) -> Result<HttpResponse, Error> {
+    Ok(Code::Ok().json(Distribution::of(&*mc.read().await)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/ns/{name}/stats/distribution",
+    params(("name" = String, Path, description = "namespace")),
+    responses(
+        (status = 200, description = "items by ttl and value size", body = Distribution),
+        (status = 404, description = "namespace not found", body = ErrorResp),
+    ),
+)]
+#[get("/stats/distribution")]
+async fn ns_distribution(
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+) -> Result<HttpResponse, Error> {
+    let mc = namespace(&namespaces, &name)?;
+    let buckets = Distribution::of(&*mc.read().await);
+    Ok(Code::Ok().json(buckets))
+}
+
+#[utoipa::path(
+    get,
+    path = "/stats/items",
+    responses(
+        (status = 200, description = "items by slab class", body = ItemClasses),
+    ),
+)]
+#[get("/stats/items")]
+async fn item_classes(
+    mc: Data<Store>,
+) -> Result<HttpResponse, Error> {
+    Ok(Code::Ok().json(ItemClasses::of(&*mc.read().await)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/ns/{name}/stats/items",
+    params(("name" = String, Path, description = "namespace")),
+    responses(
+        (status = 200, description = "items by slab class", body = ItemClasses),
+        (status = 404, description = "namespace not found", body = ErrorResp),
+    ),
+)]
+#[get("/stats/items")]
+async fn ns_item_classes(
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+) -> Result<HttpResponse, Error> {
+    let mc = namespace(&namespaces, &name)?;
+    let classes = ItemClasses::of(&*mc.read().await);
+    Ok(Code::Ok().json(classes))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct UsageQuery {
+    key: String,
+}
+
+/// Estimated bytes a key takes, see [`memcached::Usage`].
+#[derive(Serialize, ToSchema)]
+struct UsageResp {
+    /// key and the counts of its shared allocation
+    key: usize,
+    /// value as it is reserved, collections with their element headers
+    value: usize,
+    /// slot of the hash table and entries of the ttl and eviction indexes
+    overhead: usize,
+    total: usize,
+}
+
+impl From<memcached::Usage> for UsageResp {
+    fn from(usage: memcached::Usage) -> UsageResp {
+        UsageResp { key: usage.key, value: usage.value, overhead: usage.overhead, total: usage.total() }
+    }
+}
+
+/// Estimated memory a key takes, expired items count until gc frees them.
+#[utoipa::path(
+    get,
+    path = "/memory/usage",
+    params(UsageQuery),
+    responses(
+        (status = 200, description = "bytes the key takes", body = UsageResp),
+        (status = 404, description = "key not found", body = ErrorResp),
+    ),
+)]
+#[get("/memory/usage")]
+async fn memory_usage(
+    mc: Data<Store>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    query: Query<UsageQuery>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&query.key);
+    if let Some(proxied) = proxied_raw(cluster.as_ref().map(Data::get_ref), &http, &query.key, Bytes::new()).await {
+        return tagged(key, proxied)
+    }
+    tagged(key, usage_of(&mc, &query.key).await)
+}
+
+#[utoipa::path(
+    get,
+    path = "/ns/{name}/memory/usage",
+    params(("name" = String, Path, description = "namespace"), UsageQuery),
+    responses(
+        (status = 200, description = "bytes the key takes", body = UsageResp),
+        (status = 404, description = "key or namespace not found", body = ErrorResp),
+    ),
+)]
+#[get("/memory/usage")]
+async fn ns_memory_usage(
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+    cluster: Option<Data<Cluster>>,
+    http: HttpRequest,
+    query: Query<UsageQuery>,
+) -> Result<HttpResponse, Error> {
+    let key = AuditKey::new(&query.key);
+    if let Some(proxied) = proxied_raw(cluster.as_ref().map(Data::get_ref), &http, &query.key, Bytes::new()).await {
+        return tagged(key, proxied)
+    }
+    let mc = match namespace(&namespaces, &name) {
+        Ok(mc) => mc,
+        Err(err) => return tagged(key, Err(err)),
+    };
+    tagged(key, usage_of(&mc, &query.key).await)
+}
+
+async fn usage_of(mc: &Store, key: &str) -> Result<HttpResponse, Error> {
+    let usage = mc.read().await.usage(key).ok_or_else(key_not_found)?;
+    Ok(Code::Ok().json(UsageResp::from(usage)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/stats/allocator",
+    responses(
+        (status = 200, description = "global allocator memory", body = AllocatorStats),
+    ),
+)]
+#[get("/stats/allocator")]
+async fn allocator_stats() -> HttpResponse {
+    Code::Ok().json(crate::allocator::stats())
+}
+
+#[derive(Serialize, ToSchema)]
+struct SlowLogResp {
+    /// newest first
+    ops: Vec<SlowOp>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/slowlog",
+    responses(
+        (status = 200, description = "slow operations", body = SlowLogResp),
+    ),
+)]
+#[get("/slowlog")]
+async fn slowlog(
+    log: Data<SlowLog>,
+) -> HttpResponse {
+    Code::Ok().json(SlowLogResp { ops: log.ops() })
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct WatchReq {
+    /// keys to watch from the start, e.g. `user:*`
+    pattern: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/watch",
+    params(WatchReq),
+    responses(
+        (status = 101, description = "websocket of key events"),
+    ),
+)]
+#[get("/watch")]
+async fn watch(
+    events: Data<EventBus>,
+    query: Query<WatchReq>,
+    http: HttpRequest,
+    stream: Payload,
+) -> Result<HttpResponse, actix_web::Error> {
+    crate::watch::start(&events, DEFAULT_NAMESPACE.to_owned(), query.into_inner().pattern, &http, stream)
+}
+
+#[utoipa::path(
+    get,
+    path = "/ns/{name}/watch",
+    params(("name" = String, Path, description = "namespace"), WatchReq),
+    responses(
+        (status = 101, description = "websocket of key events"),
+        (status = 404, description = "namespace not found", body = ErrorResp),
+    ),
+)]
+#[get("/watch")]
+async fn ns_watch(
+    events: Data<EventBus>,
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+    query: Query<WatchReq>,
+    http: HttpRequest,
+    stream: Payload,
+) -> Result<HttpResponse, actix_web::Error> {
+    namespace(&namespaces, &name)?;
+    crate::watch::start(&events, name.into_inner(), query.into_inner().pattern, &http, stream)
+}
+
+#[utoipa::path(
+    get,
+    path = "/events",
+    params(WatchReq),
+    responses(
+        (status = 200, description = "server-sent key events"),
+    ),
+)]
+#[get("/events")]
+async fn sse(
+    events: Data<EventBus>,
+    query: Query<WatchReq>,
+) -> HttpResponse {
+    crate::sse::stream(&events, DEFAULT_NAMESPACE.to_owned(), query.into_inner().pattern)
+}
+
+#[utoipa::path(
+    get,
+    path = "/ns/{name}/events",
+    params(("name" = String, Path, description = "namespace"), WatchReq),
+    responses(
+        (status = 200, description = "server-sent key events"),
+        (status = 404, description = "namespace not found", body = ErrorResp),
+    ),
+)]
+#[get("/events")]
+async fn ns_sse(
+    events: Data<EventBus>,
+    namespaces: Data<Namespaces>,
+    name: Path<String>,
+    query: Query<WatchReq>,
+) -> Result<HttpResponse, Error> {
+    namespace(&namespaces, &name)?;
+    Ok(crate::sse::stream(&events, name.into_inner(), query.into_inner().pattern))
+}
+
+#[derive(Deserialize, ToSchema)]
+struct PublishReq {
+    channel: String,
+    message: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct PublishResp {
+    /// subscribers the message was delivered to
+    receivers: usize,
+}
+
+#[utoipa::path(
+    post,
+    path = "/publish",
+    request_body = PublishReq,
+    responses(
+        (status = 200, description = "message delivered", body = PublishResp),
+    ),
+)]
+#[post("/publish")]
+async fn publish(
+    pubsub: Data<PubSub>,
+    req: Json<PublishReq>,
+) -> HttpResponse {
+    let PublishReq { channel, message } = req.into_inner();
+    Code::Ok().json(PublishResp { receivers: pubsub.publish(&channel, message) })
+}
+
+#[utoipa::path(
+    get,
+    path = "/subscribe/{channel}",
+    params(("channel" = String, Path, description = "channel")),
+    responses(
+        (status = 200, description = "server-sent messages of the channel"),
+    ),
+)]
+#[get("/subscribe/{channel}")]
+async fn subscribe(
+    pubsub: Data<PubSub>,
+    channel: Path<String>,
+) -> HttpResponse {
+    pubsub.subscribe(channel.into_inner())
+}
+
+#[derive(Deserialize, ToSchema)]
+struct CreateNamespaceReq {
+    name: String,
+    memory_limit: u64,
+    #[schema(value_type = Option<String>, example = "10s")]
+    gc_interval: Option<DurationString>,
+    /// read-through backend fetching missing keys
+    #[schema(value_type = Option<Object>)]
+    origin: Option<OriginConfig>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/ns/create",
+    request_body = CreateNamespaceReq,
+    responses(
+        (status = 200, description = "namespace created"),
+        (status = 409, description = "namespace already exists", body = ErrorResp),
+    ),
+)]
+#[post("/create")]
+async fn create_namespace(
+    namespaces: Data<Namespaces>,
+    req: Json<CreateNamespaceReq>,
+) -> Result<HttpResponse, Error> {
+    let CreateNamespaceReq { name, memory_limit, gc_interval, origin } = req.0;
+    match namespaces.create(name, memory_limit as usize, gc_interval.map(Into::into), origin) {
+        true => Ok(Code::Ok().finish()),
+        false => Err(Error::Conflict("namespace already exists")),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+struct DropNamespaceReq {
+    name: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/ns/drop",
+    request_body = DropNamespaceReq,
+    responses(
+        (status = 200, description = "namespace dropped"),
+        (status = 404, description = "namespace not found", body = ErrorResp),
+    ),
+)]
+#[post("/drop")]
+async fn drop_namespace(
+    namespaces: Data<Namespaces>,
+    req: Json<DropNamespaceReq>,
+) -> Result<HttpResponse, Error> {
+    match namespaces.remove(&req.name) {
+        true => Ok(Code::Ok().finish()),
+        false => Err(namespace_not_found()),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+struct FlushReq {
+    namespace: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct JobResp {
+    job_id: u64,
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/flush",
+    request_body = FlushReq,
+    responses(
+        (status = 202, description = "flush job started", body = JobResp),
+        (status = 404, description = "namespace not found", body = ErrorResp),
+    ),
+)]
+#[post("/admin/flush")]
+async fn flush(
+    mc: Data<Store>,
+    namespaces: Data<Namespaces>,
+    jobs: Data<Jobs>,
+    req: Json<FlushReq>,
+) -> Result<HttpResponse, Error> {
+    let mc = match &req.namespace {
+        None => mc.into_inner(),
+        Some(name) => namespace(&namespaces, name)?,
+    };
+
+    let job_id = jobs.spawn("flush", move |job| {
+        memcached::flush(&mc, job);
+        Ok(())
+    });
+    Ok(Code::Accepted().json(JobResp { job_id }))
+}
+
+#[derive(Deserialize, ToSchema)]
+struct GcReq {
+    namespace: Option<String>,
+    /// stops after removing this many keys, unlimited by default
+    max_keys: Option<usize>,
+    /// stops after working this long, unlimited by default
+    #[schema(value_type = Option<String>, example = "5ms")]
+    max_duration: Option<DurationString>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct GcResp {
+    keys_removed: usize,
+    bytes_reclaimed: usize,
+    /// false if the budget ran out before every expired key was removed
+    done: bool,
+    duration_ms: f64,
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/gc",
+    request_body = GcReq,
+    responses(
+        (status = 200, description = "expired keys collected", body = GcResp),
+        (status = 404, description = "namespace not found", body = ErrorResp),
+    ),
+)]
+#[post("/admin/gc")]
+async fn run_gc(
+    mc: Data<Store>,
+    namespaces: Data<Namespaces>,
+    req: Json<GcReq>,
+) -> Result<HttpResponse, Error> {
+    let mc = match &req.namespace {
+        None => mc.into_inner(),
+        Some(name) => namespace(&namespaces, name)?,
+    };
+    let budget = GcBudget { max_keys: req.max_keys, max_duration: req.max_duration.map(Into::into) };
+
+    let (report, duration) = memcached::collect_garbage(mc, budget).await
+        .ok_or(Error::Conflict("gc was cancelled"))?;
+    Ok(Code::Ok().json(GcResp {
+        keys_removed: report.removed,
+        bytes_reclaimed: report.reclaimed,
+        done: report.done,
+        duration_ms: duration.as_secs_f64() * 1000.0,
+    }))
+}
+
+#[derive(Deserialize, ToSchema)]
+struct DigestReq {
+    namespace: Option<String>,
+    /// digests keys grouped by this many first characters as well, 1 to 16
+    prefix_len: Option<usize>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct DigestBucket {
+    keys: u64,
+    /// hex
+    digest: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct DigestResp {
+    keys: u64,
+    /// hex, equal on instances holding the same keys and values
+    digest: String,
+    /// by key prefix, only if `prefix_len` is set
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    buckets: BTreeMap<String, DigestBucket>,
+}
+
+impl From<digest::Bucket> for DigestBucket {
+    fn from(bucket: digest::Bucket) -> DigestBucket {
+        DigestBucket { keys: bucket.keys, digest: format!("{:016x}", bucket.digest) }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/digest",
+    request_body = DigestReq,
+    responses(
+        (status = 200, description = "digest of keys and values, see `digest` module", body = DigestResp),
+        (status = 400, description = "prefix_len is out of range", body = ErrorResp),
+        (status = 404, description = "namespace not found", body = ErrorResp),
+    ),
+)]
+#[post("/admin/digest")]
+async fn keyspace_digest(
+    mc: Data<Store>,
+    namespaces: Data<Namespaces>,
+    req: Json<DigestReq>,
+) -> Result<HttpResponse, Error> {
+    let mc = match &req.namespace {
+        None => mc.into_inner(),
+        Some(name) => namespace(&namespaces, name)?,
+    };
+
+    if req.prefix_len.is_some_and(|len| len == 0 || len > digest::MAX_PREFIX_LEN) {
+        return Err(Error::BadRequest("prefix_len must be between 1 and 16"))
+    }
+    let Digest { total, buckets } = digest::compute(mc, req.prefix_len).await;
+    let total = DigestBucket::from(total);
+    Ok(Code::Ok().json(DigestResp {
+        keys: total.keys,
+        digest: total.digest,
+        buckets: buckets.into_iter().map(|(prefix, bucket)| (prefix, bucket.into())).collect(),
+    }))
+}
+
+#[derive(Deserialize, ToSchema)]
+struct LogLevelReq {
+    /// directives in `RUST_LOG` syntax
+    #[schema(example = "info,memcached_core=debug")]
+    filter: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct LogLevelResp {
+    filter: String,
+    /// filter replaced by this one, to restore it afterwards
+    previous: String,
+}
+
+#[utoipa::path(
+    put,
+    path = "/admin/loglevel",
+    request_body = LogLevelReq,
+    responses(
+        (status = 200, description = "log filter changed", body = LogLevelResp),
+        (status = 400, description = "invalid filter", body = ErrorResp),
+    ),
+)]
+#[put("/admin/loglevel")]
+async fn set_log_level(log_filter: Data<LogFilter>, req: Json<LogLevelReq>) -> Result<HttpResponse, Error> {
+    let previous = log_filter.set(&req.filter)
+        .map_err(|_| Error::BadRequest("invalid log filter"))?;
+    Ok(Code::Ok().json(LogLevelResp { filter: req.0.filter, previous }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/jobs/{id}",
+    params(("id" = u64, Path, description = "job id")),
+    responses(
+        (status = 200, description = "job progress", body = JobStatus),
+        (status = 404, description = "job not found", body = ErrorResp),
+    ),
+)]
+#[get("/{id}")]
+async fn job_status(
+    jobs: Data<Jobs>,
+    id: Path<u64>,
+) -> Result<HttpResponse, Error> {
+    let status = jobs.status(*id).ok_or_else(job_not_found)?;
+    Ok(Code::Ok().json(status))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/jobs/{id}/cancel",
+    params(("id" = u64, Path, description = "job id")),
+    responses(
+        (status = 200, description = "cancellation requested"),
+        (status = 404, description = "job not found", body = ErrorResp),
+    ),
+)]
+#[post("/{id}/cancel")]
+async fn cancel_job(
+    jobs: Data<Jobs>,
+    id: Path<u64>,
+) -> Result<HttpResponse, Error> {
+    match jobs.cancel(*id) {
+        true => Ok(Code::Ok().finish()),
+        false => Err(job_not_found()),
+    }
+}
+
+/// OpenAPI 3 description of the api, derived from the handlers and their bodies.
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        get, ns_get, set, ns_set, set_negative, ns_set_negative, getset, ns_getset,
+        gat, ns_gat, delete, ns_delete,
+        lpush, ns_lpush, rpush, ns_rpush, lpop, ns_lpop, rpop, ns_rpop, lrange, ns_lrange,
+        sadd, ns_sadd, srem, ns_srem, sismember, ns_sismember, smembers, ns_smembers,
+        hset, ns_hset, hget, ns_hget, hdel, ns_hdel, hgetall, ns_hgetall,
+        count, ns_count, counter, ns_counter, pin, ns_pin, unpin, ns_unpin,
+        get_key, ns_get_key, put_key, ns_put_key, delete_key, ns_delete_key,
+        get_json, ns_get_json, put_json, ns_put_json, patch_json, ns_patch_json,
+        acquire_lock, ns_acquire_lock, release_lock, ns_release_lock,
+        dump_key, ns_dump_key, restore, ns_restore, dump_bulk, ns_dump_bulk, restore_bulk, ns_restore_bulk,
+        export, ns_export, import, ns_import,
+        batch, ns_batch, transaction, ns_transaction, forecast, ns_forecast,
+        distribution, ns_distribution, item_classes, ns_item_classes,
+        memory_usage, ns_memory_usage, allocator_stats, slowlog,
+        watch, ns_watch, sse, ns_sse, publish, subscribe,
+        create_namespace, drop_namespace, flush, run_gc, keyspace_digest, set_log_level, job_status, cancel_job,
+    ),
+    components(schemas(
+        GetReq, GetResp, LeaseResp, SetReq, GetSetResp, GatReq, DeleteReq, DeleteResp,
+        PushReq, PushResp, PopReq, RangeReq, ListResp,
+        MembersReq, CountResp, IsMemberReq, IsMemberResp, MembersOfReq, MembersResp,
+        HsetReq, HgetReq, FieldResp, HdelReq, HgetallReq, FieldsResp, PatchOp,
+        OverflowPolicy, PriorityLevel, CountReq, CounterReq, CounterResp,
+        NegativeReq, PinReq, AcquireLockReq, ReleaseLockReq, LockResp,
+        DumpReq, DumpResp, RestoreReq, DumpBulkReq, DumpedItem, DumpBulkResp,
+        RestoreBulkReq, RestoreBulkResp, ImportResp, BatchOp, BatchResult, TxOp, TxFailure,
+        Forecast, Distribution, Bucket, ItemClasses, ItemClass, UsageResp, AllocatorStats, SlowOp, SlowLogResp,
+        PublishReq, PublishResp, CreateNamespaceReq, DropNamespaceReq, FlushReq, GcReq, GcResp, DigestReq, DigestResp, DigestBucket, LogLevelReq, LogLevelResp, JobResp,
+        JobStatus, JobState, ErrorResp,
+    )),
+)]
+pub struct ApiDoc;
+
+#[get("/openapi.json")]
+async fn openapi_json() -> HttpResponse {
+    Code::Ok().json(ApiDoc::openapi())
+}
+
+/// Swagger UI of [`ApiDoc`], its assets are loaded from a CDN by the browser.
+#[get("/docs")]
+pub async fn swagger_ui() -> HttpResponse {
+    Code::Ok().content_type("text/html; charset=utf-8").body(SWAGGER_UI)
+}
+
+const SWAGGER_UI: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+    <title>rust_memcached api</title>
+    <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+    <div id="swagger-ui"></div>
+    <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+    <script>SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });</script>
+</body>
+</html>
+"##;
+
+/// Attaches the key to the response, errors included, for the operation audit and access log.
+/// Outcome is a hit or a miss unless the handler has set another one.
+fn tagged(key: AuditKey, res: Result<HttpResponse, Error>) -> Result<HttpResponse, Error> {
+    let outcome = match &res {
+        Ok(resp) if resp.status().is_success() => Some(Outcome::Hit),
+        Err(Error::NotFound("key")) => Some(Outcome::Miss),
+        _ => None,
+    };
+    let mut resp = res.unwrap_or_else(|err| err.error_response());
+    let mut extensions = resp.extensions_mut();
+    extensions.insert(key);
+    if let (Some(outcome), false) = (outcome, extensions.contains::<Outcome>()) {
+        extensions.insert(outcome);
+    }
+    drop(extensions);
+    Ok(resp)
+}
+
+/// `evicted` is the store evicted bytes counter before the write
+fn stored(mc: &Memcached, evicted: u64) -> Outcome {
+    match mc.stats().evicted_bytes > evicted {
+        true => Outcome::EvictedOnWrite,
+        false => Outcome::Stored,
+    }
+}
+
+fn namespace(namespaces: &Namespaces, name: &str) -> Result<Arc<Store>, Error> {
+    namespaces.get(name).ok_or_else(namespace_not_found)
+}
+
+fn key_not_found() -> Error {
+    Error::NotFound("key")
+}
+
+fn namespace_not_found() -> Error {
+    Error::NotFound("namespace")
+}
+
+fn job_not_found() -> Error {
+    Error::NotFound("job")
 }
 
-fn as_string(vec: Vec<u8>) -> String {
-    String::from_utf8(vec).unwrap()
+fn as_string(vec: Vec<u8>) -> Result<String, Error> {
+    Ok(String::from_utf8(vec)?)
 }
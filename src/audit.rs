@@ -0,0 +1,195 @@
+use actix_web::{
+    dev::{ServiceRequest, ServiceResponse, BodySize, MessageBody},
+    http::header::CONTENT_LENGTH,
+};
+use tracing::{info, error};
+use rand::Rng;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write, BufWriter},
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    sync::mpsc::{sync_channel, SyncSender, Receiver},
+    thread,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// records waiting for the writer thread, beyond that samples are dropped
+const QUEUE: usize = 4096;
+
+const HEADER: &str = "ts_ms,op,key_hash,req_size,resp_size,latency_us,status\n";
+
+/// Key a response is about, attached by handlers to response extensions.
+/// Only its hash reaches the audit file, the access log shows it as configured.
+pub struct AuditKey {
+    hash: u64,
+    key: String,
+}
+
+impl AuditKey {
+    pub fn new(key: &str) -> AuditKey {
+        AuditKey { hash: key_hash(key), key: key.to_owned() }
+    }
+
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+}
+
+/// Identifies a key in audit records and traces without revealing it.
+pub fn key_hash(key: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+struct Record {
+    ts_ms: u128,
+    op: String,
+    key_hash: Option<u64>,
+    req_size: Option<u64>,
+    resp_size: Option<u64>,
+    latency_us: u128,
+    status: u16,
+}
+
+/// Sampled operation log written as csv to a file rotated by size,
+/// meant for offline hit ratio and sizing analysis.
+pub struct Audit {
+    rate: f64,
+    records: SyncSender<Record>,
+}
+
+/// Sampled request which is not responded yet.
+pub struct Pending {
+    records: SyncSender<Record>,
+    ts: SystemTime,
+    started: Instant,
+    op: String,
+    req_size: Option<u64>,
+}
+
+impl Audit {
+    /// Starts writer thread appending to `path`, which is moved aside
+    /// with a timestamp suffix once it exceeds `rotate_size`.
+    pub fn start(path: String, rate: f64, rotate_size: u64) -> io::Result<Audit> {
+        let file = AuditFile::open(path, rotate_size)?;
+        let (records, queue) = sync_channel(QUEUE);
+        thread::spawn(move || file.run(queue));
+
+        Ok(Audit { rate, records })
+    }
+
+    /// Decides whether the request is sampled.
+    pub fn begin(&self, req: &ServiceRequest) -> Option<Pending> {
+        if !rand::thread_rng().gen_bool(self.rate) {
+            return None
+        }
+
+        Some(Pending {
+            records: self.records.clone(),
+            ts: SystemTime::now(),
+            started: Instant::now(),
+            op: req.path().rsplit('/').next().unwrap_or_default().to_owned(),
+            req_size: req.headers().get(CONTENT_LENGTH)
+                .and_then(|len| len.to_str().ok())
+                .and_then(|len| len.parse().ok()),
+        })
+    }
+}
+
+impl Pending {
+    pub fn finish<B: MessageBody>(self, res: &ServiceResponse<B>) {
+        let response = res.response();
+        let record = Record {
+            ts_ms: self.ts.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis(),
+            op: self.op,
+            key_hash: response.extensions().get::<AuditKey>().map(AuditKey::hash),
+            req_size: self.req_size,
+            resp_size: match response.body().size() {
+                BodySize::Sized(size) => Some(size),
+                _ => None,
+            },
+            latency_us: self.started.elapsed().as_micros(),
+            status: response.status().as_u16(),
+        };
+
+        // audit must never slow down requests, so samples are dropped if writer lags behind
+        let _ = self.records.try_send(record);
+    }
+}
+
+struct AuditFile {
+    path: String,
+    rotate_size: u64,
+    written: u64,
+    out: BufWriter<File>,
+}
+
+impl AuditFile {
+    fn open(path: String, rotate_size: u64) -> io::Result<AuditFile> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let mut written = file.metadata()?.len();
+        let mut out = BufWriter::new(file);
+        if written == 0 {
+            out.write_all(HEADER.as_bytes())?;
+            written = HEADER.len() as u64;
+        }
+
+        Ok(AuditFile { path, rotate_size, written, out })
+    }
+
+    fn run(mut self, queue: Receiver<Record>) {
+        while let Ok(record) = queue.recv() {
+            self.append(&record);
+            // flush once the queue is drained, so the file is complete between bursts
+            while let Ok(record) = queue.try_recv() {
+                self.append(&record);
+            }
+            if let Err(err) = self.out.flush() {
+                error!("can't write audit file {}: {}", self.path, err);
+            }
+        }
+    }
+
+    fn append(&mut self, record: &Record) {
+        if let Err(err) = self.write(record) {
+            error!("can't write audit file {}: {}", self.path, err);
+        }
+    }
+
+    fn write(&mut self, record: &Record) -> io::Result<()> {
+        let line = format!(
+            "{},{},{},{},{},{},{}\n",
+            record.ts_ms, record.op,
+            optional(record.key_hash), optional(record.req_size), optional(record.resp_size),
+            record.latency_us, record.status,
+        );
+        self.out.write_all(line.as_bytes())?;
+        self.written += line.len() as u64;
+
+        if self.written >= self.rotate_size {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.out.flush()?;
+        let suffix = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let rotated = format!("{}.{}", self.path, suffix);
+        fs::rename(&self.path, &rotated)?;
+        info!("audit file rotated to {}", rotated);
+
+        *self = AuditFile::open(self.path.clone(), self.rotate_size)?;
+        Ok(())
+    }
+}
+
+fn optional(value: Option<u64>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
@@ -0,0 +1,200 @@
+use actix_web::{
+    Error, HttpMessage, HttpResponse,
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    http::{Method, StatusCode, header::AUTHORIZATION},
+};
+use futures::future::{ok, Either, Ready};
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    task::{Context, Poll},
+};
+
+use crate::{
+    errors::error_response,
+    tenants::Tenant,
+};
+
+/// name under which the default store is referenced in acl
+pub const DEFAULT_NAMESPACE: &str = "default";
+
+/// operations allowed for read only keys
+const READ_OPS: &[&str] = &["get", "lrange", "sismember", "smembers", "hget", "hgetall", "counter", "memory", "stats", "watch", "events", "subscribe", "dump", "export", "openapi.json", "docs"];
+/// resource routes, reading them is told by the method
+pub(crate) const RESOURCE_OPS: &[&str] = &["keys", "json"];
+
+pub struct ApiKey {
+    /// namespaces key has access to, `*` means any
+    pub namespaces: Vec<String>,
+    pub admin: bool,
+    pub read_only: bool,
+    /// tenant whose quotas requests with this key are held to
+    pub tenant: Option<String>,
+}
+
+/// Api keys and their permissions. Everything is allowed while there are no keys.
+#[derive(Default)]
+pub struct Acl {
+    keys: HashMap<String, ApiKey>,
+    tenants: HashMap<String, Arc<Tenant>>,
+}
+
+impl Acl {
+    pub fn new(keys: HashMap<String, ApiKey>) -> Acl {
+        Acl { keys, tenants: HashMap::new() }
+    }
+
+    /// Tenants referenced by api keys, keys of unknown tenants are not held to any quota.
+    pub fn with_tenants(mut self, tenants: Vec<Tenant>) -> Acl {
+        self.tenants = tenants.into_iter()
+            .map(|tenant| (tenant.name().to_owned(), Arc::new(tenant)))
+            .collect();
+        self
+    }
+
+    /// whether requests must carry an api key
+    pub fn is_enabled(&self) -> bool {
+        !self.keys.is_empty()
+    }
+
+    pub fn tenant(&self, name: &str) -> Option<&Arc<Tenant>> {
+        self.tenants.get(name)
+    }
+
+    pub fn tenants(&self) -> impl Iterator<Item = &Arc<Tenant>> {
+        self.tenants.values()
+    }
+
+    /// Api key is taken from `X-Api-Key` or `Authorization: Bearer` header.
+    /// Returns the tenant of the key, if it has one.
+    pub fn check(&self, req: &ServiceRequest) -> Result<Option<Arc<Tenant>>, HttpResponse> {
+        if !self.is_enabled() {
+            return Ok(None)
+        }
+
+        let key = api_key(req)
+            .ok_or_else(|| error_response(StatusCode::UNAUTHORIZED, "api key required"))?;
+        let key = self.keys.get(key)
+            .ok_or_else(|| error_response(StatusCode::UNAUTHORIZED, "unknown api key"))?;
+
+        let allowed = match Target::of(routed_path(req)) {
+            Target::Admin => key.admin,
+            Target::Namespace(name, op) => {
+                key.namespaces.iter().any(|ns| ns == "*" || ns == name)
+                    && (!key.read_only || is_read_op(req.method(), op))
+            },
+        };
+
+        match allowed {
+            true => Ok(key.tenant.as_ref().and_then(|tenant| self.tenants.get(tenant)).cloned()),
+            false => Err(error_response(StatusCode::FORBIDDEN, "access denied")),
+        }
+    }
+}
+
+/// Middleware answering requests the acl denies, the tenant of an allowed key
+/// is put into request extensions for [`Quotas`](crate::tenants::Quotas).
+pub struct AclCheck(pub Arc<RwLock<Acl>>);
+
+impl<S> Transform<S> for AclCheck
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = AclCheckMiddleware<S>;
+    type Future = Ready<Result<AclCheckMiddleware<S>, ()>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(AclCheckMiddleware { service, acl: self.0.clone() })
+    }
+}
+
+pub struct AclCheckMiddleware<S> {
+    service: S,
+    acl: Arc<RwLock<Acl>>,
+}
+
+impl<S> Service for AclCheckMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Future = Either<S::Future, Ready<Result<ServiceResponse, Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let checked = self.acl.read().unwrap().check(&req);
+        match checked {
+            Ok(tenant) => {
+                if let Some(tenant) = tenant {
+                    req.extensions_mut().insert(tenant);
+                }
+                Either::Left(self.service.call(req))
+            },
+            Err(denied) => Either::Right(ok(req.into_response(denied))),
+        }
+    }
+}
+
+/// Path as the router matches it. Percent-encoded characters are decoded except `/`, `+` and `%`,
+/// so `/%61dmin` is checked as the `/admin` it is routed to rather than as a key operation.
+pub fn routed_path(req: &ServiceRequest) -> &str {
+    req.match_info().path()
+}
+
+/// Whether the request leaves stores as they are, e.g. it is allowed on a replica.
+/// `path` must be the [`routed_path`].
+pub fn is_read(method: &Method, path: &str) -> bool {
+    match Target::of(path) {
+        Target::Admin => false,
+        Target::Namespace(_, op) => is_read_op(method, op),
+    }
+}
+
+/// Whether the path is one of `/admin` endpoints.
+pub fn is_admin(path: &str) -> bool {
+    matches!(Target::of(path), Target::Admin)
+}
+
+fn is_read_op(method: &Method, op: &str) -> bool {
+    READ_OPS.contains(&op)
+        || (RESOURCE_OPS.contains(&op) && matches!(*method, Method::GET | Method::HEAD))
+}
+
+enum Target<'a> {
+    Admin,
+    Namespace(&'a str, &'a str),
+}
+
+impl<'a> Target<'a> {
+    fn of(path: &'a str) -> Target<'a> {
+        let mut segments = path.trim_start_matches('/').split('/');
+        match (segments.next(), segments.next(), segments.next()) {
+            (Some("admin"), _, _) => Target::Admin,
+            (Some("ns"), Some(name), Some(op)) => Target::Namespace(name, op),
+            (Some(op), _, _) => Target::Namespace(DEFAULT_NAMESPACE, op),
+            (None, _, _) => Target::Namespace(DEFAULT_NAMESPACE, ""),
+        }
+    }
+}
+
+fn api_key(req: &ServiceRequest) -> Option<&str> {
+    let headers = req.headers();
+    if let Some(key) = headers.get("x-api-key") {
+        return key.to_str().ok()
+    }
+
+    headers.get(AUTHORIZATION)?
+        .to_str().ok()?
+        .strip_prefix("Bearer ")
+}
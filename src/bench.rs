@@ -0,0 +1,337 @@
+//! Load generator of the `bench` subcommand: drives either a running server over http
+//! or a store in this process with random gets and sets, reporting throughput and latency.
+
+use actix_web::{client::Client, http::StatusCode};
+use config::{Config, ConfigError, Environment};
+use futures::{StreamExt, future::join_all};
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+use serde_json::json;
+use std::{
+    fmt,
+    ops::RangeInclusive,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    memcached::{self, Store, Options, StdClock},
+    settings,
+};
+
+/// Taken from `MEMCACHED_BENCH_*` environment variables.
+#[derive(Deserialize, Serialize)]
+pub struct BenchSettings {
+    /// base url of the server, e.g. `http://127.0.0.1:8080`, a store in this process is driven if not set
+    pub url: Option<String>,
+    /// sent as `X-Api-Key` to the server at `url`, needed once it has api keys
+    pub api_key: Option<String>,
+    /// memory limit of the store in this process
+    pub memory_limit: u64,
+    /// key_hasher of the store in this process
+    pub key_hasher: String,
+    /// keys are picked uniformly among this many
+    pub keys: u64,
+    /// size of set values, either fixed or `min-max` picked uniformly
+    pub value_size: String,
+    /// fraction of operations which are gets, the rest are sets
+    pub read_ratio: f64,
+    /// operations in flight at once, threads for the store in this process
+    pub concurrency: u64,
+    /// measured operations
+    pub operations: u64,
+    /// sets every key before measuring, so gets hit unless the key is evicted
+    pub prefill: bool,
+}
+
+impl BenchSettings {
+    pub fn new() -> Result<BenchSettings, ConfigError> {
+        let mut cfg = Config::new();
+        cfg.merge(
+            Environment::with_prefix("memcached_bench")
+        )?
+        .set_default("memory_limit", 64 << 20)?
+        .set_default("key_hasher", "ahash")?
+        .set_default("keys", 10_000)?
+        .set_default("value_size", "100")?
+        .set_default("read_ratio", 0.9)?
+        .set_default("concurrency", 8)?
+        .set_default("operations", 100_000)?
+        .set_default("prefill", true)?;
+
+        cfg.try_into()
+    }
+}
+
+/// Parsed and checked [`BenchSettings`].
+#[derive(Clone, Debug)]
+pub struct Workload {
+    pub keys: u64,
+    pub value_size: RangeInclusive<usize>,
+    pub read_ratio: f64,
+    pub concurrency: usize,
+    pub operations: u64,
+    pub prefill: bool,
+}
+
+impl Workload {
+    pub fn new(settings: &BenchSettings) -> Result<Workload, String> {
+        if settings.keys == 0 || settings.concurrency == 0 {
+            return Err("keys and concurrency must be positive".to_owned())
+        }
+        if !(0.0..=1.0).contains(&settings.read_ratio) {
+            return Err("read_ratio must be between 0 and 1".to_owned())
+        }
+        Ok(Workload {
+            keys: settings.keys,
+            value_size: parse_value_size(&settings.value_size)?,
+            read_ratio: settings.read_ratio,
+            concurrency: settings.concurrency as usize,
+            operations: settings.operations,
+            prefill: settings.prefill,
+        })
+    }
+
+    fn random_key(&self) -> String {
+        key(rand::thread_rng().gen_range(0..self.keys))
+    }
+
+    fn random_value(&self) -> String {
+        "x".repeat(rand::thread_rng().gen_range(self.value_size.clone()))
+    }
+
+    fn is_read(&self) -> bool {
+        rand::thread_rng().gen_bool(self.read_ratio)
+    }
+
+    /// operations of `worker`, the first ones take the remainder
+    fn share(&self, worker: usize) -> u64 {
+        let concurrency = self.concurrency as u64;
+        self.operations / concurrency + (((worker as u64) < self.operations % concurrency) as u64)
+    }
+
+    /// prefilled keys of `worker`
+    fn prefilled(&self, worker: usize) -> impl Iterator<Item = String> {
+        (worker as u64..self.keys).step_by(self.concurrency).map(key)
+    }
+}
+
+fn key(index: u64) -> String {
+    format!("bench:{}", index)
+}
+
+fn parse_value_size(value: &str) -> Result<RangeInclusive<usize>, String> {
+    let invalid = || format!("invalid value_size {}, expected size or min-max", value);
+    let (min, max) = match value.split_once('-') {
+        Some((min, max)) => (min.trim(), max.trim()),
+        None => (value.trim(), value.trim()),
+    };
+    match (min.parse::<usize>(), max.parse::<usize>()) {
+        (Ok(min), Ok(max)) if min <= max => Ok(min..=max),
+        _ => Err(invalid()),
+    }
+}
+
+/// What a single worker measured.
+#[derive(Default)]
+struct Tally {
+    latencies: Vec<Duration>,
+    reads: u64,
+    hits: u64,
+    errors: u64,
+}
+
+impl Tally {
+    fn read(&mut self, started: Instant, hit: bool) {
+        self.latencies.push(started.elapsed());
+        self.reads += 1;
+        self.hits += hit as u64;
+    }
+
+    fn write(&mut self, started: Instant, stored: bool) {
+        self.latencies.push(started.elapsed());
+        self.errors += !stored as u64;
+    }
+}
+
+/// Outcome of a benchmark run, prefill is not included.
+pub struct Report {
+    pub elapsed: Duration,
+    pub reads: u64,
+    pub hits: u64,
+    /// failed requests and values which were not stored
+    pub errors: u64,
+    /// ascending
+    latencies: Vec<Duration>,
+}
+
+impl Report {
+    fn new(elapsed: Duration, tallies: Vec<Tally>) -> Report {
+        let mut report = Report { elapsed, reads: 0, hits: 0, errors: 0, latencies: Vec::new() };
+        for tally in tallies {
+            report.reads += tally.reads;
+            report.hits += tally.hits;
+            report.errors += tally.errors;
+            report.latencies.extend(tally.latencies);
+        }
+        report.latencies.sort_unstable();
+        report
+    }
+
+    pub fn operations(&self) -> u64 {
+        self.latencies.len() as u64
+    }
+
+    pub fn throughput(&self) -> f64 {
+        self.operations() as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// Latency `quantile` of operations lasted at most, zero if there were none.
+    pub fn percentile(&self, quantile: f64) -> Duration {
+        match self.latencies.len() {
+            0 => Duration::default(),
+            len => self.latencies[((len as f64 * quantile).ceil() as usize).clamp(1, len) - 1],
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "operations: {} in {:?}", self.operations(), self.elapsed)?;
+        writeln!(f, "throughput: {:.0} ops/s", self.throughput())?;
+        let hit_ratio = match self.reads {
+            0 => 0.0,
+            reads => self.hits as f64 / reads as f64,
+        };
+        writeln!(f, "gets: {}, hit ratio {:.3}", self.reads, hit_ratio)?;
+        writeln!(f, "errors: {}", self.errors)?;
+        for &(name, quantile) in &[("p50", 0.5), ("p90", 0.9), ("p99", 0.99), ("p99.9", 0.999), ("max", 1.0)] {
+            writeln!(f, "{}: {:?}", name, self.percentile(quantile))?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs the benchmark `settings` describe.
+pub async fn run(settings: &BenchSettings) -> Result<Report, String> {
+    let workload = Workload::new(settings)?;
+    match &settings.url {
+        Some(url) => Ok(run_remote(url, settings.api_key.as_deref(), &workload).await),
+        None => {
+            let hasher = settings::parse_key_hasher(&settings.key_hasher)?;
+            let options = Options { hasher, ..Options::default() };
+            let mc = memcached::new(settings.memory_limit as usize, StdClock::new(), options);
+            Ok(run_local(Arc::new(Store::new(mc)), workload))
+        },
+    }
+}
+
+/// Drives `mc` from `workload.concurrency` threads, so lock contention is measured as well.
+pub fn run_local(mc: Arc<Store>, workload: Workload) -> Report {
+    let workload = Arc::new(workload);
+    let spawn = |step: fn(&Store, &Workload, usize) -> Tally| {
+        (0..workload.concurrency)
+            .map(|worker| {
+                let (mc, workload) = (mc.clone(), workload.clone());
+                thread::spawn(move || step(&mc, &workload, worker))
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|thread| thread.join().expect("bench worker panicked"))
+            .collect::<Vec<_>>()
+    };
+
+    if workload.prefill {
+        spawn(prefill_local);
+    }
+    let started = Instant::now();
+    let tallies = spawn(drive_local);
+    Report::new(started.elapsed(), tallies)
+}
+
+fn prefill_local(mc: &Store, workload: &Workload, worker: usize) -> Tally {
+    for key in workload.prefilled(worker) {
+        let _ = mc.blocking_write().set(key, workload.random_value().into_bytes(), None);
+    }
+    Tally::default()
+}
+
+fn drive_local(mc: &Store, workload: &Workload, worker: usize) -> Tally {
+    let mut tally = Tally::default();
+    for _ in 0..workload.share(worker) {
+        let key = workload.random_key();
+        if workload.is_read() {
+            let started = Instant::now();
+            let hit = mc.blocking_read().get(&key).is_some();
+            tally.read(started, hit);
+        } else {
+            let data = workload.random_value().into_bytes();
+            let started = Instant::now();
+            let stored = mc.blocking_write().set(key, data, None).is_ok();
+            tally.write(started, stored);
+        }
+    }
+    tally
+}
+
+/// Drives the server at `url` with `workload.concurrency` requests in flight.
+pub async fn run_remote(url: &str, api_key: Option<&str>, workload: &Workload) -> Report {
+    let client = match api_key {
+        Some(api_key) => Client::builder().header("x-api-key", api_key).finish(),
+        None => Client::default(),
+    };
+    let url = url.trim_end_matches('/');
+
+    if workload.prefill {
+        let client = &client;
+        join_all((0..workload.concurrency).map(|worker| async move {
+            for key in workload.prefilled(worker) {
+                let _ = set_remote(client, url, &key, &workload.random_value()).await;
+            }
+        })).await;
+    }
+    let started = Instant::now();
+    let tallies = join_all((0..workload.concurrency).map(|worker| {
+        drive_remote(&client, url, workload, worker)
+    })).await;
+    Report::new(started.elapsed(), tallies)
+}
+
+async fn drive_remote(client: &Client, url: &str, workload: &Workload, worker: usize) -> Tally {
+    let mut tally = Tally::default();
+    for _ in 0..workload.share(worker) {
+        let key = workload.random_key();
+        if workload.is_read() {
+            let started = Instant::now();
+            match post(client, &format!("{}/get", url), &json!({ "key": key })).await {
+                Some(StatusCode::OK) => tally.read(started, true),
+                Some(StatusCode::NOT_FOUND) => tally.read(started, false),
+                _ => {
+                    tally.read(started, false);
+                    tally.errors += 1;
+                },
+            }
+        } else {
+            let data = workload.random_value();
+            let started = Instant::now();
+            let stored = set_remote(client, url, &key, &data).await;
+            tally.write(started, stored);
+        }
+    }
+    tally
+}
+
+/// whether the value is stored
+async fn set_remote(client: &Client, url: &str, key: &str, data: &str) -> bool {
+    post(client, &format!("{}/set", url), &json!({ "key": key, "data": data })).await == Some(StatusCode::OK)
+}
+
+/// Response status, `None` if the request failed. The body is read, so the connection is reused.
+async fn post(client: &Client, url: &str, req: &impl Serialize) -> Option<StatusCode> {
+    let mut resp = client.post(url).send_json(req).await.ok()?;
+    while let Some(chunk) = resp.next().await {
+        chunk.ok()?;
+    }
+    Some(resp.status())
+}
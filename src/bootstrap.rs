@@ -0,0 +1,152 @@
+use serde::Deserialize;
+use config::{Config, ConfigError, File};
+use duration_string::DurationString;
+use tracing::{info, error};
+use actix_web::rt::{self, signal::unix::{signal, SignalKind}};
+use std::{
+    collections::HashSet,
+    sync::{Arc, RwLock, Mutex},
+};
+
+use crate::{
+    auth::{Acl, ApiKey},
+    tenants::{Quota, Tenant},
+    namespaces::Namespaces,
+    origin::OriginConfig,
+};
+
+#[derive(Deserialize)]
+struct NamespaceSpec {
+    name: String,
+    memory_limit: u64,
+    gc_interval: Option<DurationString>,
+    origin: Option<OriginConfig>,
+}
+
+#[derive(Deserialize)]
+struct ApiKeySpec {
+    key: String,
+    #[serde(default)]
+    namespaces: Vec<String>,
+    #[serde(default)]
+    admin: bool,
+    #[serde(default)]
+    read_only: bool,
+    tenant: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TenantSpec {
+    name: String,
+    max_bytes: Option<u64>,
+    max_requests_per_sec: Option<u64>,
+    #[serde(default)]
+    key_prefixes: Vec<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Spec {
+    namespaces: Vec<NamespaceSpec>,
+    api_keys: Vec<ApiKeySpec>,
+    tenants: Vec<TenantSpec>,
+}
+
+/// Declarative namespaces and acl loaded from a file (toml, yaml or json).
+pub struct Bootstrap {
+    path: String,
+    /// namespaces created by previous application of the file
+    declared: Mutex<HashSet<String>>,
+}
+
+impl Bootstrap {
+    pub fn new(path: String) -> Bootstrap {
+        Bootstrap { path, declared: Default::default() }
+    }
+
+    /// Brings namespaces and acl to the state described in the file.
+    /// Namespaces which disappeared from the file since the last application are dropped,
+    /// the ones created through admin api are left intact.
+    pub async fn apply(&self, namespaces: &Namespaces, acl: &RwLock<Acl>) -> Result<(), ConfigError> {
+        let spec = self.load()?;
+        spec.validate()?;
+
+        let mut now_declared = HashSet::with_capacity(spec.namespaces.len());
+        for NamespaceSpec { name, memory_limit, gc_interval, origin } in spec.namespaces {
+            now_declared.insert(name.clone());
+            namespaces.ensure(name, memory_limit as usize, gc_interval.map(Into::into), origin).await;
+        }
+        let mut declared = self.declared.lock().unwrap();
+        for name in declared.difference(&now_declared) {
+            namespaces.remove(name);
+        }
+        *declared = now_declared;
+
+        let keys = spec.api_keys.into_iter()
+            .map(|ApiKeySpec { key, namespaces, admin, read_only, tenant }| {
+                (key, ApiKey { namespaces, admin, read_only, tenant })
+            })
+            .collect();
+        let mut acl = acl.write().unwrap();
+        // measured usage carries over until the next measurement
+        let tenants = spec.tenants.into_iter()
+            .map(|TenantSpec { name, max_bytes, max_requests_per_sec, key_prefixes }| {
+                let bytes = acl.tenant(&name).map_or(0, |tenant| tenant.bytes());
+                Tenant::new(name, Quota { max_bytes, max_requests_per_sec, key_prefixes }, bytes)
+            })
+            .collect();
+        *acl = Acl::new(keys).with_tenants(tenants);
+
+        info!("bootstrap file {} applied", self.path);
+        Ok(())
+    }
+
+    /// parses the file without applying it
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        self.load()?.validate()
+    }
+
+    fn load(&self) -> Result<Spec, ConfigError> {
+        let mut cfg = Config::new();
+        cfg.merge(File::with_name(&self.path))?;
+        cfg.try_into()
+    }
+}
+
+impl Spec {
+    fn validate(&self) -> Result<(), ConfigError> {
+        for tenant in &self.tenants {
+            if tenant.max_bytes.is_some() && tenant.key_prefixes.is_empty() {
+                return Err(ConfigError::Message(format!("tenant {} has max_bytes without key_prefixes", tenant.name)))
+            }
+        }
+        for key in &self.api_keys {
+            match &key.tenant {
+                Some(name) if !self.tenants.iter().any(|tenant| &tenant.name == name) => {
+                    return Err(ConfigError::Message(format!("unknown tenant {} of an api key", name)))
+                },
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Re-applies bootstrap file on every SIGHUP.
+pub fn reload_on_hangup(bootstrap: Bootstrap, namespaces: Arc<Namespaces>, acl: Arc<RwLock<Acl>>) {
+    rt::spawn(async move {
+        let mut hangups = match signal(SignalKind::hangup()) {
+            Ok(hangups) => hangups,
+            Err(err) => {
+                error!("can't listen for SIGHUP: {}", err);
+                return
+            },
+        };
+
+        while hangups.recv().await.is_some() {
+            if let Err(err) = bootstrap.apply(&namespaces, &acl).await {
+                error!("bootstrap file {} is not applied: {}", bootstrap.path, err);
+            }
+        }
+    });
+}
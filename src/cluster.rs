@@ -0,0 +1,180 @@
+use serde::Serialize;
+use actix_web::{
+    HttpRequest, HttpResponse, rt,
+    client::Client,
+    http::header::{AUTHORIZATION, CONTENT_TYPE, ETAG, IF_MATCH, IF_NONE_MATCH},
+    web::Bytes,
+};
+use futures::{future::{select, Either}, pin_mut};
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    api::TTL_HEADER,
+    errors::Error,
+    logging::{Outcome, RequestId, REQUEST_ID},
+};
+
+/// points of every node on the ring, more of them even out key distribution
+const POINTS_PER_NODE: usize = 128;
+/// weight of the last response in the latency average of a peer is 1/2^LATENCY_SHIFT
+const LATENCY_SHIFT: u32 = 3;
+
+/// Marks a request forwarded by a peer. It is served where it arrives,
+/// so nodes disagreeing on membership can't bounce it around.
+pub const FORWARDED: &str = "x-cluster-forwarded";
+
+/// Static cluster: every key is owned by one node picked by consistent hashing,
+/// so adding a node moves only a share of keys.
+pub struct Cluster {
+    /// base urls of every node, e.g. `http://10.0.0.1:8080`
+    nodes: Vec<String>,
+    /// index of this node in `nodes`
+    me: usize,
+    /// points sorted by hash, a key belongs to the first point at or after its hash
+    ring: Vec<(u64, usize)>,
+    /// max size of a peer response
+    body_limit: usize,
+    /// latency critical gets not answered this long are sent again, see [`Cluster::forward_hedged`]
+    hedge_delay: Option<Duration>,
+    /// moving average of response time of every node in microseconds, 0 until one answers
+    latency: Vec<AtomicU64>,
+}
+
+impl Cluster {
+    /// Returns `None` if `me` is not one of `nodes`.
+    pub fn new(nodes: Vec<String>, me: &str, body_limit: usize) -> Option<Cluster> {
+        let me = nodes.iter().position(|node| node == me)?;
+        let mut ring: Vec<(u64, usize)> = nodes.iter().enumerate()
+            .flat_map(|(i, node)| (0..POINTS_PER_NODE).map(move |point| {
+                (hash(format!("{}#{}", node, point).as_bytes()), i)
+            }))
+            .collect();
+        ring.sort_unstable();
+        let latency = nodes.iter().map(|_| AtomicU64::new(0)).collect();
+        Some(Cluster { nodes, me, ring, body_limit, hedge_delay: None, latency })
+    }
+
+    /// Makes [`forward_hedged`](Cluster::forward_hedged) send a request again once it is unanswered for `delay`.
+    pub fn with_hedge_delay(mut self, delay: Duration) -> Cluster {
+        self.hedge_delay = Some(delay);
+        self
+    }
+
+    /// base url of the node owning `key`, `None` if it is this one
+    pub fn owner(&self, key: &str) -> Option<&str> {
+        let hash = hash(key.as_bytes());
+        let at = self.ring.partition_point(|&(point, _)| point < hash);
+        let (_, node) = self.ring.get(at).unwrap_or(&self.ring[0]);
+        match *node == self.me {
+            true => None,
+            false => Some(&self.nodes[*node]),
+        }
+    }
+
+    /// Moving average of response time of the node, `None` until it answered.
+    pub fn latency(&self, node: &str) -> Option<Duration> {
+        let node = self.nodes.iter().position(|known| known == node)?;
+        match self.latency[node].load(Ordering::Relaxed) {
+            0 => None,
+            micros => Some(Duration::from_micros(micros)),
+        }
+    }
+
+    fn observe(&self, node: &str, took: Duration) {
+        let node = match self.nodes.iter().position(|known| known == node) {
+            Some(node) => node,
+            None => return,
+        };
+        let took = (took.as_micros() as u64).max(1);
+        let _ = self.latency[node].fetch_update(Ordering::Relaxed, Ordering::Relaxed, |average| Some(match average {
+            0 => took,
+            average => average - (average >> LATENCY_SHIFT) + (took >> LATENCY_SHIFT),
+        }));
+    }
+
+    /// Sends the request to `owner` with the same path and credentials, relaying its response.
+    pub async fn forward(&self, owner: &str, http: &HttpRequest, req: &impl Serialize) -> Result<HttpResponse, Error> {
+        let body = serde_json::to_vec(req).map_err(|err| Error::Peer(err.to_string()))?;
+        self.forward_body(owner, http, "application/json", body.into()).await
+    }
+
+    /// [`forward`](Cluster::forward) sending the request again over another connection unless the owner
+    /// answers within the hedge delay, the first response is taken. Meant for reads, which are safe to repeat:
+    /// a connection stalled by a lost packet or a peer paused by a gc sweep doesn't hold up the reply.
+    /// The delay is stretched to twice the usual response time of the owner, so a peer which is slow
+    /// all the time rather than now and then doesn't get every request twice.
+    pub async fn forward_hedged(&self, owner: &str, http: &HttpRequest, req: &impl Serialize) -> Result<HttpResponse, Error> {
+        let delay = match self.hedge_delay {
+            Some(delay) => delay.max(self.latency(owner).unwrap_or_default() * 2),
+            None => return self.forward(owner, http, req).await,
+        };
+        let first = self.forward(owner, http, req);
+        let hedged = async {
+            rt::time::delay_for(delay).await;
+            self.forward(owner, http, req).await
+        };
+        pin_mut!(first, hedged);
+        match select(first, hedged).await {
+            Either::Left((Ok(resp), _)) | Either::Right((Ok(resp), _)) => Ok(resp),
+            // the other attempt may still make it
+            Either::Left((Err(_), other)) => other.await,
+            Either::Right((Err(_), other)) => other.await,
+        }
+    }
+
+    /// [`forward`](Cluster::forward) with a body as it is, e.g. a value put to a key route.
+    pub async fn forward_body(
+        &self, owner: &str, http: &HttpRequest, content_type: &str, body: Bytes,
+    ) -> Result<HttpResponse, Error> {
+        let url = format!("{}{}", owner, http.uri());
+        let mut request = Client::default()
+            .request(http.method().clone(), &url)
+            .header(FORWARDED, "1")
+            .content_type(content_type);
+        for header in [IF_MATCH, IF_NONE_MATCH, AUTHORIZATION] {
+            if let Some(value) = http.headers().get(&header) {
+                request = request.header(header, value.clone());
+            }
+        }
+        for header in ["x-api-key", TTL_HEADER] {
+            if let Some(value) = http.headers().get(header) {
+                request = request.header(header, value.clone());
+            }
+        }
+        if let Some(RequestId(id)) = http.extensions().get::<RequestId>() {
+            request = request.header(REQUEST_ID, id.as_str());
+        }
+
+        let started = Instant::now();
+        let mut res = request.send_body(body).await
+            .map_err(|err| Error::Peer(format!("{}: {}", url, err)))?;
+        let body = res.body().limit(self.body_limit).await
+            .map_err(|err| Error::Peer(format!("{}: {}", url, err)))?;
+        self.observe(owner, started.elapsed());
+
+        let mut resp = HttpResponse::build(res.status());
+        for header in [CONTENT_TYPE, ETAG] {
+            if let Some(value) = res.headers().get(&header) {
+                resp.set_header(header, value.clone());
+            }
+        }
+        let mut resp = resp.body(body);
+        resp.extensions_mut().insert(Outcome::Proxied);
+        Ok(resp)
+    }
+}
+
+/// FNV-1a with a final mix, stable across nodes and builds unlike `DefaultHasher`
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^ (hash >> 33)
+}
@@ -0,0 +1,121 @@
+use actix_web::{
+    dev::{ServiceResponse, Body, BodyEncoding, BodySize, MessageBody, ResponseBody},
+    http::{
+        header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE, ETAG, VARY},
+        ContentEncoding, HeaderMap, HeaderValue,
+    },
+};
+
+use crate::settings::split_list;
+
+/// fast level, values are encoded on every read
+const ZSTD_LEVEL: i32 = 3;
+/// encodings `Compress` middleware picks from
+const COMPRESS_ENCODINGS: [&str; 4] = ["br", "gzip", "deflate", "*"];
+
+/// Decides which responses are worth compressing.
+#[derive(Clone)]
+pub struct CompressionFilter {
+    enabled: bool,
+    min_size: u64,
+    content_types: Vec<String>,
+}
+
+impl CompressionFilter {
+    /// `content_types` is a comma separated list of mime type prefixes
+    pub fn new(enabled: bool, min_size: u64, content_types: &str) -> CompressionFilter {
+        CompressionFilter { enabled, min_size, content_types: split_list(content_types) }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Marks response as identity encoded, so `Compress` middleware leaves it as is.
+    /// `Compress` has no zstd, so responses to clients preferring it are encoded here.
+    /// `ETag` of a response to be encoded is made weak, its bytes differ per encoding.
+    pub fn apply(&self, mut res: ServiceResponse) -> ServiceResponse {
+        if !self.enabled || !self.should_compress(&res) {
+            CompressionFilter::skip(&mut res);
+            return res
+        }
+        let (zstd, others) = qualities(res.request().headers());
+        if zstd > 0.0 || others > 0.0 {
+            weaken_etag(res.headers_mut());
+        }
+        match zstd > 0.0 && zstd >= others {
+            true => encode_zstd(res),
+            false => res,
+        }
+    }
+
+    /// marks response as identity encoded regardless of filter
+    pub fn skip<B>(res: &mut ServiceResponse<B>) {
+        res.response_mut().encoding(ContentEncoding::Identity);
+    }
+
+    fn should_compress<B: MessageBody>(&self, res: &ServiceResponse<B>) -> bool {
+        let big_enough = match res.response().body().size() {
+            BodySize::Sized(size) => size >= self.min_size,
+            BodySize::Stream => true,
+            BodySize::None | BodySize::Empty => false,
+        };
+
+        let content_type = res.headers().get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+
+        big_enough && self.content_types.iter()
+            .any(|allowed| content_type.starts_with(allowed.as_str()))
+    }
+}
+
+/// Highest qualities `Accept-Encoding` gives to zstd and to anything `Compress` could pick.
+fn qualities(headers: &HeaderMap) -> (f32, f32) {
+    let (mut zstd, mut others) = (0.0, 0.0);
+    let accepted = headers.get_all(ACCEPT_ENCODING)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','));
+    for accepted in accepted {
+        let mut params = accepted.split(';');
+        let encoding = params.next().unwrap_or_default().trim();
+        let quality = params
+            .find_map(|param| param.trim().strip_prefix("q="))
+            .map_or(1.0, |quality| quality.parse().unwrap_or(0.0));
+        match encoding {
+            "zstd" => zstd = quality,
+            encoding if COMPRESS_ENCODINGS.contains(&encoding) => others = f32::max(others, quality),
+            _ => (),
+        }
+    }
+    (zstd, others)
+}
+
+fn weaken_etag(headers: &mut HeaderMap) {
+    let weak = headers.get(ETAG)
+        .and_then(|tag| tag.to_str().ok())
+        .filter(|tag| !tag.starts_with("W/"))
+        .and_then(|tag| HeaderValue::from_str(&format!("W/{}", tag)).ok());
+    if let Some(weak) = weak {
+        headers.insert(ETAG, weak);
+    }
+}
+
+/// Encodes a body in memory, streams are left to `Compress`.
+fn encode_zstd(res: ServiceResponse) -> ServiceResponse {
+    res.map_body(|head, body| {
+        let data = match body {
+            ResponseBody::Body(Body::Bytes(data)) | ResponseBody::Other(Body::Bytes(data)) => data,
+            body => return body,
+        };
+        match zstd::stream::encode_all(&data[..], ZSTD_LEVEL) {
+            Ok(encoded) => {
+                // `Compress` leaves responses which already have an encoding
+                head.headers_mut().insert(CONTENT_ENCODING, HeaderValue::from_static("zstd"));
+                head.headers_mut().append(VARY, HeaderValue::from_static("accept-encoding"));
+                ResponseBody::Body(Body::from(encoded))
+            },
+            Err(_) => ResponseBody::Body(Body::Bytes(data)),
+        }
+    })
+}
@@ -0,0 +1,41 @@
+use actix_cors::Cors;
+
+use crate::settings::split_list;
+
+/// CORS policy built from settings, `*` in origins allows any origin.
+#[derive(Clone)]
+pub struct CorsConfig {
+    origins: Vec<String>,
+    methods: Vec<String>,
+    headers: Vec<String>,
+}
+
+impl CorsConfig {
+    pub fn new(origins: &str, methods: &str, headers: &str) -> CorsConfig {
+        CorsConfig {
+            origins: split_list(origins),
+            methods: split_list(methods),
+            headers: split_list(headers),
+        }
+    }
+
+    /// CORS is disabled unless at least one origin is configured
+    pub fn is_enabled(&self) -> bool {
+        !self.origins.is_empty()
+    }
+
+    pub fn build(&self) -> Cors {
+        let mut cors = Cors::default()
+            .allowed_methods(self.methods.iter().map(String::as_str))
+            .allowed_headers(self.headers.iter().map(String::as_str));
+
+        for origin in &self.origins {
+            cors = match origin.as_str() {
+                "*" => cors.allow_any_origin(),
+                origin => cors.allowed_origin(origin),
+            };
+        }
+
+        cors
+    }
+}
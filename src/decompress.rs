@@ -0,0 +1,101 @@
+use actix_web::{
+    dev::Payload, Error, FromRequest, HttpRequest,
+    http::{header::CONTENT_ENCODING, StatusCode},
+    web::BytesMut,
+};
+use futures::{future::LocalBoxFuture, FutureExt, StreamExt};
+use flate2::read::GzDecoder;
+use serde::de::DeserializeOwned;
+use std::{
+    fmt::Display,
+    io::Read,
+    ops::Deref,
+};
+
+use crate::errors::json_error;
+
+/// Limits applied by [`DecodedJson`], registered via scope `app_data`.
+#[derive(Clone)]
+pub struct DecompressConfig {
+    /// max body size as received
+    pub payload_limit: usize,
+    /// max body size after decompression
+    pub limit: usize,
+}
+
+impl Default for DecompressConfig {
+    fn default() -> DecompressConfig {
+        DecompressConfig { payload_limit: 1 << 20, limit: 8 << 20 }
+    }
+}
+
+/// Json extractor which accepts `gzip` and `zstd` encoded bodies.
+pub struct DecodedJson<T>(pub T);
+
+impl<T> Deref for DecodedJson<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T { &self.0 }
+}
+
+impl<T: DeserializeOwned + 'static> FromRequest for DecodedJson<T> {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Error>>;
+    type Config = DecompressConfig;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let DecompressConfig { payload_limit, limit } = req.app_data::<DecompressConfig>()
+            .cloned().unwrap_or_default();
+        let encoding = req.headers().get(CONTENT_ENCODING)
+            .map(|value| value.to_str().map(str::to_lowercase))
+            .transpose();
+        let mut payload = payload.take();
+
+        async move {
+            let encoding = encoding.map_err(bad_request)?;
+
+            let mut body = BytesMut::new();
+            while let Some(chunk) = payload.next().await {
+                let chunk = chunk.map_err(bad_request)?;
+                if body.len() + chunk.len() > payload_limit {
+                    return Err(json_error(StatusCode::PAYLOAD_TOO_LARGE, "payload is too large"))
+                }
+                body.extend_from_slice(&chunk);
+            }
+
+            let body = match encoding.as_deref() {
+                None | Some("identity") => read_limited(&body[..], limit)?,
+                Some("gzip") => read_limited(GzDecoder::new(&body[..]), limit)?,
+                Some("zstd") => read_limited(
+                    zstd::stream::read::Decoder::new(&body[..]).map_err(bad_request)?,
+                    limit,
+                )?,
+                Some(other) => return Err(json_error(
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    format!("unsupported content encoding {}", other),
+                )),
+            };
+
+            serde_json::from_slice(&body)
+                .map(DecodedJson)
+                .map_err(bad_request)
+        }.boxed_local()
+    }
+}
+
+fn read_limited(reader: impl Read, limit: usize) -> Result<Vec<u8>, Error> {
+    let mut decoded = Vec::new();
+    reader.take(limit as u64 + 1)
+        .read_to_end(&mut decoded)
+        .map_err(bad_request)?;
+
+    if decoded.len() > limit {
+        return Err(json_error(StatusCode::PAYLOAD_TOO_LARGE, "decompressed payload is too large"))
+    }
+
+    Ok(decoded)
+}
+
+fn bad_request(err: impl Display) -> Error {
+    json_error(StatusCode::BAD_REQUEST, err)
+}
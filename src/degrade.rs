@@ -0,0 +1,133 @@
+use serde::Serialize;
+use actix_web::{get, rt, HttpResponse, web::Data};
+use futures::future::{abortable, AbortHandle};
+use tracing::{info, warn};
+use std::{
+    sync::{
+        Arc, Weak,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+/// log2 buckets of request latency in microseconds
+const BUCKETS: usize = 40;
+
+/// Thresholds of p99 latency switching degraded mode on and off.
+#[derive(Clone, Copy)]
+pub struct Thresholds {
+    pub enter: Duration,
+    pub exit: Duration,
+}
+
+/// Load shedding controller: while p99 latency stays above threshold
+/// optional subsystems (response compression, operation audit) are switched off.
+pub struct Degradation {
+    degraded: AtomicBool,
+    /// times degraded mode was entered
+    entered: AtomicU64,
+    /// p99 of the last finished window
+    p99_us: AtomicU64,
+    histogram: Vec<AtomicU64>,
+}
+
+impl Default for Degradation {
+    fn default() -> Degradation {
+        Degradation {
+            degraded: AtomicBool::new(false),
+            entered: AtomicU64::new(0),
+            p99_us: AtomicU64::new(0),
+            histogram: (0..BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct DegradationStatus {
+    degraded: bool,
+    entered: u64,
+    p99_us: u64,
+}
+
+impl Degradation {
+    pub fn record(&self, latency: Duration) {
+        let micros = latency.as_micros().max(1) as u64;
+        let bucket = (63 - micros.leading_zeros() as usize).min(BUCKETS - 1);
+        self.histogram[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn is_degraded(&self) -> bool {
+        self.degraded.load(Ordering::Relaxed)
+    }
+
+    pub fn status(&self) -> DegradationStatus {
+        DegradationStatus {
+            degraded: self.is_degraded(),
+            entered: self.entered.load(Ordering::Relaxed),
+            p99_us: self.p99_us.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Closes current window and switches mode if p99 crossed a threshold.
+    fn evaluate(&self, thresholds: Thresholds) {
+        let counts: Vec<u64> = self.histogram.iter()
+            .map(|bucket| bucket.swap(0, Ordering::Relaxed))
+            .collect();
+        let p99 = match p99(&counts) {
+            Some(p99) => p99,
+            None => return,
+        };
+        self.p99_us.store(p99.as_micros() as u64, Ordering::Relaxed);
+
+        let degraded = self.is_degraded();
+        if !degraded && p99 > thresholds.enter {
+            self.degraded.store(true, Ordering::Relaxed);
+            self.entered.fetch_add(1, Ordering::Relaxed);
+            warn!("p99 latency {:?} is above {:?}, optional features disabled", p99, thresholds.enter);
+        } else if degraded && p99 < thresholds.exit {
+            self.degraded.store(false, Ordering::Relaxed);
+            info!("p99 latency {:?} is below {:?}, optional features enabled", p99, thresholds.exit);
+        }
+    }
+}
+
+/// Evaluates latency every `interval` until the controller is dropped or task is aborted.
+pub fn spawn_controller(degradation: &Arc<Degradation>, interval: Duration, thresholds: Thresholds) -> AbortHandle {
+    let (task, handle) = abortable(control(Arc::downgrade(degradation), interval, thresholds));
+    rt::spawn(async move {
+        let _ = task.await;
+    });
+    handle
+}
+
+async fn control(degradation: Weak<Degradation>, interval: Duration, thresholds: Thresholds) {
+    loop {
+        rt::time::delay_for(interval).await;
+        match degradation.upgrade() {
+            Some(degradation) => degradation.evaluate(thresholds),
+            None => return,
+        }
+    }
+}
+
+#[get("/stats/degradation")]
+pub async fn status(degradation: Data<Degradation>) -> HttpResponse {
+    HttpResponse::Ok().json(degradation.status())
+}
+
+/// upper bound of the bucket holding 99th percentile, none for an empty window
+fn p99(counts: &[u64]) -> Option<Duration> {
+    let total: u64 = counts.iter().sum();
+    if total == 0 {
+        return None
+    }
+
+    let mut above = 0;
+    for (bucket, count) in counts.iter().enumerate().rev() {
+        above += count;
+        if above * 100 > total {
+            return Some(Duration::from_micros(2u64 << bucket))
+        }
+    }
+    None
+}
@@ -0,0 +1,72 @@
+//! Digest of store contents for anti-entropy checks: instances holding the same items get
+//! the same digest, digests of key prefix buckets tell where they diverge.
+//!
+//! An item contributes xxh3 of its key and value and contributions are summed, so the digest
+//! doesn't depend on the order keys are visited in. Versions and ttls are local to a store
+//! and are left out, as are collections and negative markers, which are not replicated.
+
+use std::{collections::BTreeMap, sync::Arc};
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::memcached::Store;
+
+/// items hashed per lock acquisition
+const CHUNK: usize = 1024;
+/// longest key prefix buckets are kept by, longer ones would make a bucket of nearly every key
+pub const MAX_PREFIX_LEN: usize = 16;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Bucket {
+    pub keys: u64,
+    pub digest: u64,
+}
+
+impl Bucket {
+    fn add(&mut self, item: u64) {
+        self.keys += 1;
+        self.digest = self.digest.wrapping_add(item);
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Digest {
+    pub total: Bucket,
+    /// by key prefix, empty unless asked for
+    pub buckets: BTreeMap<String, Bucket>,
+}
+
+/// Digests items of `mc`, bucketed by first `prefix_len` characters of keys if it is set.
+/// The lock is released between chunks, so items changed meanwhile may be either way:
+/// a mismatch under writes should be confirmed by another run.
+pub async fn compute(mc: Arc<Store>, prefix_len: Option<usize>) -> Digest {
+    let keys: Vec<String> = mc.read().await.keys().map(ToOwned::to_owned).collect();
+    let mut digest = Digest::default();
+    for chunk in keys.chunks(CHUNK) {
+        let mc = mc.read().await;
+        for key in chunk {
+            let dump = match mc.dump(key) {
+                Some(dump) => dump,
+                None => continue,
+            };
+            let item = item_hash(key, &dump.data);
+            digest.total.add(item);
+            if let Some(len) = prefix_len {
+                digest.buckets.entry(prefix(key, len).to_owned()).or_default().add(item);
+            }
+        }
+    }
+    digest
+}
+
+fn item_hash(key: &str, data: &[u8]) -> u64 {
+    let mut hasher = Xxh3::new();
+    // length keeps the boundary of key and value
+    hasher.update(&(key.len() as u64).to_le_bytes());
+    hasher.update(key.as_bytes());
+    hasher.update(data);
+    hasher.digest()
+}
+
+fn prefix(key: &str, len: usize) -> &str {
+    key.char_indices().nth(len).map_or(key, |(at, _)| &key[..at])
+}
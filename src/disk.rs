@@ -0,0 +1,375 @@
+use aes_gcm::{
+    Aes256Gcm, Key, Nonce,
+    aead::{Aead, NewAead, Payload},
+};
+use tracing::{debug, error};
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind::{InvalidData, InvalidInput}},
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Condvar, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    thread,
+};
+
+use crate::memcached::{Clock, ColdTier, StdClock, Timestamp};
+
+struct Entry {
+    offset: u64,
+    len: usize,
+    ttl: Option<Timestamp>,
+}
+
+/// bytes of the nonce prefixing every encrypted value
+const NONCE: usize = 12;
+/// bytes of the tag following every encrypted value
+const TAG: usize = 16;
+/// bytes of the AES-256 key
+pub const KEY: usize = 32;
+
+/// Cold tier keeping displaced values in an append only file with the index in memory.
+/// Space of taken and expired values is reclaimed by rewriting the file once it doubles the limit.
+///
+/// Spills are queued and written by a thread of the tier, which also rewrites the file, so
+/// the store lock is not held for either. Values are taken from the queue while they wait there,
+/// taking a written one reads just that value.
+pub struct DiskTier {
+    /// bytes of live values kept at most, spills beyond that are dropped
+    limit: u64,
+    shared: Arc<Shared>,
+    writer: bool,
+}
+
+struct Shared {
+    path: PathBuf,
+    limit: u64,
+    /// clock of the store, which deadlines of values are on
+    clock: StdClock,
+    queue: Mutex<Queue>,
+    /// signals queued spills to the writer
+    queued: Condvar,
+    /// signals written batches to [`DiskTier::flush`]
+    written: Condvar,
+    stored: Mutex<Stored>,
+    encryption: Option<Encryption>,
+}
+
+#[derive(Default)]
+struct Queue {
+    /// spills waiting for the writer
+    pending: HashMap<String, Spilled>,
+    /// spills the writer is writing, a key taken or forgotten meanwhile is not indexed
+    writing: HashMap<String, Spilled>,
+    /// bytes values in both take once written
+    bytes: u64,
+    closed: bool,
+}
+
+struct Spilled {
+    data: Arc<Vec<u8>>,
+    ttl: Option<Timestamp>,
+}
+
+struct Stored {
+    file: Arc<File>,
+    index: HashMap<String, Entry>,
+    /// bytes of indexed values
+    live: u64,
+}
+
+/// Values are sealed with their item key as associated data,
+/// so a sealed value moved to another offset of the file doesn't open as another item.
+struct Encryption {
+    cipher: Aes256Gcm,
+    /// values sealed so far, the nonce of the next one
+    sealed: AtomicU64,
+}
+
+impl DiskTier {
+    /// Truncates the file, values of a previous run are not recovered.
+    /// `clock` must be the one of the store, expired values are dropped by compaction.
+    pub fn open(path: impl Into<PathBuf>, limit: u64, clock: StdClock) -> io::Result<DiskTier> {
+        let path = path.into();
+        let file = Arc::new(create(&path)?);
+        let shared = Shared {
+            path, limit, clock,
+            queue: Mutex::new(Queue::default()),
+            queued: Condvar::new(),
+            written: Condvar::new(),
+            stored: Mutex::new(Stored { file, index: HashMap::new(), live: 0 }),
+            encryption: None,
+        };
+        Ok(DiskTier { limit, shared: Arc::new(shared), writer: false })
+    }
+
+    /// Encrypts values with AES-256-GCM under `key`, see [`read_key`].
+    /// Must be called before anything is spilled.
+    pub fn encrypted(mut self, key: [u8; KEY]) -> DiskTier {
+        let shared = Arc::get_mut(&mut self.shared).expect("disk tier is already written");
+        shared.encryption = Some(Encryption { cipher: Aes256Gcm::new(Key::from_slice(&key)), sealed: AtomicU64::new(0) });
+        self
+    }
+
+    /// Waits until spills queued so far are written.
+    pub fn flush(&self) {
+        let mut queue = self.shared.queue.lock().unwrap();
+        while !queue.pending.is_empty() || !queue.writing.is_empty() {
+            queue = self.shared.written.wait(queue).unwrap();
+        }
+    }
+
+    /// Removes the key from the queue, returns its value if it was not written yet.
+    fn unqueue(&self, key: &str) -> Option<Spilled> {
+        let mut queue = self.shared.queue.lock().unwrap();
+        let spilled = queue.pending.remove(key).or_else(|| queue.writing.remove(key))?;
+        queue.bytes -= self.shared.size(&spilled.data);
+        Some(spilled)
+    }
+
+    fn remove(&self, key: &str) -> Option<(Entry, Arc<File>)> {
+        let mut stored = self.shared.stored.lock().unwrap();
+        let entry = stored.remove(key)?;
+        Some((entry, stored.file.clone()))
+    }
+}
+
+impl Drop for DiskTier {
+    fn drop(&mut self) {
+        self.shared.queue.lock().unwrap().closed = true;
+        self.shared.queued.notify_one();
+    }
+}
+
+impl ColdTier for DiskTier {
+    fn spill(&mut self, key: &str, data: &[u8], ttl: Option<Timestamp>) {
+        self.forget(key);
+        let live = self.shared.stored.lock().unwrap().live;
+        let size = self.shared.size(data);
+        let mut queue = self.shared.queue.lock().unwrap();
+        if live + queue.bytes + size > self.limit {
+            debug!("disk tier {} is full, {} is dropped", self.shared.path.display(), key);
+            return
+        }
+        queue.bytes += size;
+        queue.pending.insert(key.to_owned(), Spilled { data: Arc::new(data.to_vec()), ttl });
+        drop(queue);
+
+        if !self.writer {
+            let shared = self.shared.clone();
+            thread::spawn(move || shared.run());
+            self.writer = true;
+        }
+        self.shared.queued.notify_one();
+    }
+
+    fn take(&mut self, key: &str) -> Option<(Vec<u8>, Option<Timestamp>)> {
+        if let Some(spilled) = self.unqueue(key) {
+            let data = Arc::try_unwrap(spilled.data).unwrap_or_else(|data| data.to_vec());
+            return Some((data, spilled.ttl))
+        }
+
+        let (entry, file) = self.remove(key)?;
+        match self.shared.read(&file, key, &entry) {
+            Ok(data) => Some((data, entry.ttl)),
+            Err(err) => {
+                error!("can't read disk tier {}: {}", self.shared.path.display(), err);
+                None
+            },
+        }
+    }
+
+    fn forget(&mut self, key: &str) {
+        if self.unqueue(key).is_none() {
+            self.remove(key);
+        }
+    }
+}
+
+impl Shared {
+    /// Bytes the value takes in the file, sealing adds its nonce and tag.
+    fn size(&self, data: &[u8]) -> u64 {
+        let overhead = match self.encryption {
+            Some(_) => NONCE + TAG,
+            None => 0,
+        };
+        (data.len() + overhead) as u64
+    }
+
+    /// Writes queued spills in batches until the tier is dropped.
+    fn run(&self) {
+        let (mut file, mut file_size) = {
+            let stored = self.stored.lock().unwrap();
+            (stored.file.clone(), 0)
+        };
+        loop {
+            let batch: Vec<(String, Arc<Vec<u8>>)> = {
+                let mut queue = self.queue.lock().unwrap();
+                while queue.pending.is_empty() && !queue.closed {
+                    queue = self.queued.wait(queue).unwrap();
+                }
+                if queue.closed {
+                    return
+                }
+                queue.writing = std::mem::take(&mut queue.pending);
+                queue.writing.iter().map(|(key, spilled)| (key.clone(), spilled.data.clone())).collect()
+            };
+
+            let written = self.write(&mut file, &mut file_size, batch)
+                .unwrap_or_else(|err| {
+                    error!("can't write disk tier {}: {}", self.path.display(), err);
+                    Vec::new()
+                });
+
+            let mut queue = self.queue.lock().unwrap();
+            let mut stored = self.stored.lock().unwrap();
+            for (key, offset, len) in written {
+                if let Some(spilled) = queue.writing.remove(&key) {
+                    queue.bytes -= self.size(&spilled.data);
+                    stored.live += len as u64;
+                    stored.index.insert(key, Entry { offset, len, ttl: spilled.ttl });
+                }
+            }
+            // spills the write failed for are dropped
+            let failed: u64 = queue.writing.drain().map(|(_, spilled)| self.size(&spilled.data)).sum();
+            queue.bytes -= failed;
+            drop(stored);
+            drop(queue);
+            self.written.notify_all();
+        }
+    }
+
+    /// Appends the values, the file is compacted first if they would make it exceed twice the limit.
+    fn write(
+        &self, file: &mut Arc<File>, file_size: &mut u64, batch: Vec<(String, Arc<Vec<u8>>)>,
+    ) -> io::Result<Vec<(String, u64, usize)>> {
+        let sealed: Vec<(String, Vec<u8>)> = batch.into_iter()
+            .map(|(key, data)| match &self.encryption {
+                Some(encryption) => {
+                    let sealed = encryption.seal(&key, &data)?;
+                    Ok((key, sealed))
+                },
+                None => Ok((key, Arc::try_unwrap(data).unwrap_or_else(|data| data.to_vec()))),
+            })
+            .collect::<io::Result<_>>()?;
+        let size: u64 = sealed.iter().map(|(_, data)| data.len() as u64).sum();
+        if *file_size + size > 2 * self.limit {
+            self.compact(file, file_size)?;
+        }
+
+        let mut written = Vec::with_capacity(sealed.len());
+        for (key, data) in sealed {
+            file.write_all_at(&data, *file_size)?;
+            written.push((key, *file_size, data.len()));
+            *file_size += data.len() as u64;
+        }
+        Ok(written)
+    }
+
+    fn read(&self, file: &File, key: &str, entry: &Entry) -> io::Result<Vec<u8>> {
+        let mut data = vec![0; entry.len];
+        file.read_exact_at(&mut data, entry.offset)?;
+        match &self.encryption {
+            Some(encryption) => encryption.open(key, &data),
+            None => Ok(data),
+        }
+    }
+
+    /// Rewrites live values into a fresh file, expired ones are dropped. Only the writer adds
+    /// values, so those indexed while copying are the copied ones unless taken meanwhile.
+    fn compact(&self, file: &mut Arc<File>, file_size: &mut u64) -> io::Result<()> {
+        let now = self.clock.now();
+        let live: Vec<(String, u64, usize)> = {
+            let mut stored = self.stored.lock().unwrap();
+            let expired: Vec<String> = stored.index.iter()
+                .filter(|(_, entry)| entry.ttl.is_some_and(|ttl| ttl <= now))
+                .map(|(key, _)| key.clone())
+                .collect();
+            expired.iter().for_each(|key| {
+                stored.remove(key);
+            });
+            stored.index.iter().map(|(key, entry)| (key.clone(), entry.offset, entry.len)).collect()
+        };
+
+        let compacted = self.path.with_extension("compact");
+        let out = create(&compacted)?;
+        let mut moved = Vec::with_capacity(live.len());
+        let mut offset = 0;
+        for (key, from, len) in live {
+            let mut data = vec![0; len];
+            file.read_exact_at(&mut data, from)?;
+            out.write_all_at(&data, offset)?;
+            moved.push((key, offset));
+            offset += len as u64;
+        }
+        fs::rename(&compacted, &self.path)?;
+
+        let out = Arc::new(out);
+        let mut stored = self.stored.lock().unwrap();
+        for (key, to) in moved {
+            if let Some(entry) = stored.index.get_mut(&key) {
+                entry.offset = to;
+            }
+        }
+        stored.file = out.clone();
+        drop(stored);
+
+        *file = out;
+        *file_size = offset;
+        debug!("disk tier {} compacted to {}B", self.path.display(), offset);
+        Ok(())
+    }
+}
+
+impl Stored {
+    fn remove(&mut self, key: &str) -> Option<Entry> {
+        let entry = self.index.remove(key)?;
+        self.live -= entry.len as u64;
+        Some(entry)
+    }
+}
+
+impl Encryption {
+    /// nonce followed by the ciphertext and its tag
+    fn seal(&self, key: &str, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut nonce = [0; NONCE];
+        nonce[..8].copy_from_slice(&self.sealed.fetch_add(1, Ordering::Relaxed).to_be_bytes());
+
+        let payload = Payload { msg: data, aad: key.as_bytes() };
+        let ciphertext = self.cipher.encrypt(Nonce::from_slice(&nonce), payload)
+            .map_err(|_| io::Error::new(InvalidData, "can't encrypt value"))?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    fn open(&self, key: &str, sealed: &[u8]) -> io::Result<Vec<u8>> {
+        let tampered = || io::Error::new(InvalidData, "value is tampered with");
+        if sealed.len() < NONCE {
+            return Err(tampered())
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE);
+        let payload = Payload { msg: ciphertext, aad: key.as_bytes() };
+        self.cipher.decrypt(Nonce::from_slice(nonce), payload).map_err(|_| tampered())
+    }
+}
+
+/// Reads a base64 encoded 32 byte key of [`DiskTier::encrypted`], surrounding whitespace is ignored.
+pub fn read_key(path: impl AsRef<Path>) -> io::Result<[u8; KEY]> {
+    let encoded = fs::read_to_string(path)?;
+    let key = base64::decode(encoded.trim())
+        .map_err(|err| io::Error::new(InvalidInput, format!("disk tier key is not base64: {}", err)))?;
+    if key.len() != KEY {
+        return Err(io::Error::new(InvalidInput, format!("disk tier key must be {} bytes", KEY)))
+    }
+    let mut read = [0; KEY];
+    read.copy_from_slice(&key);
+    Ok(read)
+}
+
+fn create(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).read(true).write(true).truncate(true).open(path)
+}
@@ -0,0 +1,76 @@
+use std::{convert::TryInto, time::Duration};
+
+use crate::{
+    errors::Error,
+    memcached::{Dump, Priority},
+};
+
+/// bumped whenever the layout changes, older dumps are rejected
+const FORMAT: u8 = 1;
+
+const PINNED: u8 = 1;
+const TTL: u8 = 2;
+const SLIDING: u8 = 4;
+/// normal priority sets neither
+const LOW: u8 = 8;
+const HIGH: u8 = 16;
+
+/// Opaque form of a dumped item: base64 of format, flags, version,
+/// optional ttl and sliding period in milliseconds, then the value.
+pub fn encode(dump: &Dump) -> String {
+    let mut flags = 0;
+    let mut buf = vec![FORMAT, 0];
+    buf.extend_from_slice(&dump.version.to_be_bytes());
+    if dump.pinned {
+        flags |= PINNED;
+    }
+    match dump.priority {
+        Priority::Low => flags |= LOW,
+        Priority::Normal => (),
+        Priority::High => flags |= HIGH,
+    }
+    if let Some(ttl) = dump.ttl {
+        flags |= TTL;
+        buf.extend_from_slice(&(ttl.as_millis() as u64).to_be_bytes());
+    }
+    if let Some(sliding) = dump.sliding {
+        flags |= SLIDING;
+        buf.extend_from_slice(&(sliding.as_millis() as u64).to_be_bytes());
+    }
+    buf[1] = flags;
+    buf.extend_from_slice(&dump.data);
+
+    base64::encode(buf)
+}
+
+pub fn decode(encoded: &str) -> Result<Dump, Error> {
+    let malformed = || Error::BadRequest("malformed dump");
+    let buf = base64::decode(encoded).map_err(|_| malformed())?;
+
+    let (&format, rest) = buf.split_first().ok_or_else(malformed)?;
+    if format != FORMAT {
+        return Err(Error::BadRequest("unsupported dump format"))
+    }
+    let (&flags, mut rest) = rest.split_first().ok_or_else(malformed)?;
+    let version = take_u64(&mut rest).ok_or_else(malformed)?;
+    let mut take_millis = |flag| match flags & flag {
+        0 => Ok(None),
+        _ => take_u64(&mut rest).map(|millis| Some(Duration::from_millis(millis))).ok_or_else(malformed),
+    };
+    let ttl = take_millis(TTL)?;
+    let sliding = take_millis(SLIDING)?;
+    let priority = match (flags & LOW != 0, flags & HIGH != 0) {
+        (false, false) => Priority::Normal,
+        (true, false) => Priority::Low,
+        (false, true) => Priority::High,
+        (true, true) => return Err(malformed()),
+    };
+
+    Ok(Dump { data: rest.to_vec(), ttl, sliding, pinned: flags & PINNED != 0, priority, version })
+}
+
+fn take_u64(buf: &mut &[u8]) -> Option<u64> {
+    let bytes = buf.get(..8)?.try_into().ok()?;
+    *buf = &buf[8..];
+    Some(u64::from_be_bytes(bytes))
+}
@@ -0,0 +1,100 @@
+use serde::Serialize;
+use utoipa::ToSchema;
+use actix_web::{
+    HttpRequest, HttpResponse, ResponseError,
+    http::StatusCode,
+    error::{InternalError, JsonPayloadError},
+    web::JsonConfig,
+};
+use config::ConfigError;
+use std::{
+    fmt::Display,
+    string::FromUtf8Error,
+};
+
+/// Failures of request handling and startup, each mapped to its http response.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("stored value is not valid utf-8")]
+    Encoding(#[from] FromUtf8Error),
+    /// value is rejected by the store, e.g. it doesn't fit into memory limit
+    #[error("value is not stored")]
+    NotStored,
+    #[error("{0} not found")]
+    NotFound(&'static str),
+    #[error("{0}")]
+    Conflict(&'static str),
+    #[error("{0}")]
+    BadRequest(&'static str),
+    #[error("{0}")]
+    PayloadTooLarge(&'static str),
+    /// conditional write found a different version or its lease is gone
+    #[error("precondition failed")]
+    PreconditionFailed,
+    /// read-through fetch failed
+    #[error("origin failed: {0}")]
+    Origin(String),
+    /// cluster node owning the key can't be reached
+    #[error("cluster peer failed: {0}")]
+    Peer(String),
+    #[error("invalid configuration: {0}")]
+    Config(#[from] ConfigError),
+}
+
+impl ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Error::Encoding(_) | Error::Config(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::NotStored => StatusCode::NOT_MODIFIED,
+            Error::NotFound(_) => StatusCode::NOT_FOUND,
+            Error::Conflict(_) => StatusCode::CONFLICT,
+            Error::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Error::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Error::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            Error::Origin(_) | Error::Peer(_) => StatusCode::BAD_GATEWAY,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            // 304 must not have a body
+            Error::NotStored => HttpResponse::NotModified().finish(),
+            err => error_response(err.status_code(), err),
+        }
+    }
+}
+
+/// Body of every non successful response.
+#[derive(Serialize, ToSchema)]
+pub struct ErrorResp {
+    error: String,
+    code: u16,
+}
+
+pub fn error_response(status: StatusCode, error: impl Display) -> HttpResponse {
+    HttpResponse::build(status).json(ErrorResp {
+        error: error.to_string(),
+        code: status.as_u16(),
+    })
+}
+
+pub fn json_error(status: StatusCode, error: impl Display) -> actix_web::Error {
+    let error = error.to_string();
+    let response = error_response(status, &error);
+    InternalError::from_response(error, response).into()
+}
+
+pub fn json_config(limit: usize) -> JsonConfig {
+    JsonConfig::default()
+        .limit(limit)
+        .error_handler(json_payload_error)
+}
+
+fn json_payload_error(err: JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    let status = match err {
+        JsonPayloadError::Overflow => StatusCode::PAYLOAD_TOO_LARGE,
+        JsonPayloadError::ContentType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        _ => StatusCode::BAD_REQUEST,
+    };
+    json_error(status, err)
+}
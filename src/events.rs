@@ -0,0 +1,121 @@
+use serde::Serialize;
+use futures::channel::mpsc::{channel, Sender, Receiver};
+use std::{
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::memcached::{Clock, Event, Listener, StdClock, Timestamp};
+
+/// Store mutation as seen by subscribers.
+#[derive(Serialize, Debug)]
+pub struct KeyEvent {
+    pub namespace: String,
+    pub key: String,
+    /// set, deleted, expired, evicted or prioritized
+    pub reason: &'static str,
+    pub size: usize,
+    /// the key was set known missing instead of a value
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub negative: bool,
+    /// unix milliseconds when the value was set
+    pub stored_at: u128,
+    /// unix milliseconds of the event
+    pub at: u128,
+}
+
+type Filter = Box<dyn Fn(&KeyEvent) -> bool + Send + Sync>;
+
+struct Subscriber {
+    filter: Filter,
+    events: Mutex<Sender<Arc<KeyEvent>>>,
+}
+
+/// Fans out mutations of every store to subscribers (webhooks, watchers).
+/// Subscribers which can't keep up lose events instead of slowing the stores down.
+pub struct EventBus {
+    clock: StdClock,
+    subscribers: RwLock<Vec<Subscriber>>,
+}
+
+impl EventBus {
+    pub fn new(clock: StdClock) -> EventBus {
+        EventBus { clock, subscribers: Default::default() }
+    }
+
+    /// Receives events matching `filter`, at most `capacity` of them may wait to be taken.
+    pub fn subscribe(&self, capacity: usize, filter: Filter) -> Receiver<Arc<KeyEvent>> {
+        let (sender, receiver) = channel(capacity);
+        self.subscribers.write().unwrap().push(Subscriber { filter, events: Mutex::new(sender) });
+        receiver
+    }
+
+    /// Listener publishing events of the store with the given name.
+    pub fn listener(self: &Arc<EventBus>, namespace: String) -> Listener {
+        let bus = self.clone();
+        Box::new(move |event: &Event| bus.publish(&namespace, event))
+    }
+
+    fn publish(&self, namespace: &str, event: &Event) {
+        let subscribers = self.subscribers.read().unwrap();
+        if subscribers.is_empty() {
+            return
+        }
+
+        let event = Arc::new(KeyEvent {
+            namespace: namespace.to_owned(),
+            key: event.key.to_owned(),
+            reason: event.kind.as_str(),
+            size: event.size,
+            negative: event.negative,
+            stored_at: self.unix_millis(event.stored_at),
+            at: self.unix_millis(event.at),
+        });
+
+        let mut disconnected = false;
+        for subscriber in subscribers.iter().filter(|subscriber| (subscriber.filter)(&event)) {
+            if let Err(err) = subscriber.events.lock().unwrap().try_send(event.clone()) {
+                disconnected |= err.is_disconnected();
+            }
+        }
+
+        if disconnected {
+            drop(subscribers);
+            self.subscribers.write().unwrap()
+                .retain(|subscriber| !subscriber.events.lock().unwrap().is_closed());
+        }
+    }
+
+    /// converts store timestamp to wall clock
+    fn unix_millis(&self, ts: Timestamp) -> u128 {
+        let ago = self.clock.now().checked_sub(ts).unwrap_or_default();
+        SystemTime::now().checked_sub(ago)
+            .and_then(|wall| wall.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or(Duration::from_secs(0))
+            .as_millis()
+    }
+}
+
+/// Glob match where `*` stands for any sequence of characters.
+pub fn matches(pattern: &str, key: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let mut rest = match key.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let (last, middle) = match parts.split_last() {
+        Some(split) => split,
+        // no wildcard at all
+        None => return rest.is_empty(),
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
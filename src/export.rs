@@ -0,0 +1,263 @@
+//! Stable export format of store contents, independent of the opaque `/dump` encoding,
+//! so data moves between versions of the server and into test fixtures.
+//!
+//! An export is newline delimited json. The first line is the header
+//! `{"format":"rust_memcached","version":1}`, every following line is an item:
+//!
+//! ```json
+//! {"key":"a","data":"ZGF0YQ==","ttl_ms":1500,"sliding_ms":60000,"pinned":true,"priority":"high"}
+//! ```
+//!
+//! - `data` is base64 of the value
+//! - `ttl_ms` is time left until expiry, absent if the item doesn't expire
+//! - `sliding_ms` is the period of sliding ttl, absent for a fixed one
+//! - `pinned` is false if absent
+//! - `priority` is `low`, `normal` or `high`, normal if absent
+//!
+//! Only plain values are exported, lists, sets, maps, counters and negative markers
+//! are left out like in `/dump`.
+//! Versions of items are local to a store and are not exported, imported items get fresh ones.
+//! Readers accept exports of [`VERSION`] or older and ignore unknown fields, so new
+//! optional fields don't need a new version.
+
+use actix_web::{HttpResponse, web::{Bytes, Payload}};
+use futures::{StreamExt, future::ready, stream};
+use serde::{Serialize, Deserialize};
+use std::{str, sync::Arc, time::Duration};
+
+use utoipa::ToSchema;
+
+use crate::{
+    errors::Error,
+    l1::L1,
+    memcached::{Dump, Priority, Store},
+};
+
+pub const FORMAT: &str = "rust_memcached";
+/// bumped when a change breaks readers of older versions
+pub const VERSION: u32 = 1;
+
+/// items exported or imported per lock acquisition
+const CHUNK: usize = 1024;
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    format: String,
+    version: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Record {
+    key: String,
+    data: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ttl_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sliding_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pinned: bool,
+    #[serde(default, skip_serializing_if = "is_normal")]
+    priority: PriorityLevel,
+}
+
+/// Eviction priority of an item: lower ones are evicted first, oldest first within a priority.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum PriorityLevel {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl From<PriorityLevel> for Priority {
+    fn from(level: PriorityLevel) -> Priority {
+        match level {
+            PriorityLevel::Low => Priority::Low,
+            PriorityLevel::Normal => Priority::Normal,
+            PriorityLevel::High => Priority::High,
+        }
+    }
+}
+
+impl From<Priority> for PriorityLevel {
+    fn from(priority: Priority) -> PriorityLevel {
+        match priority {
+            Priority::Low => PriorityLevel::Low,
+            Priority::Normal => PriorityLevel::Normal,
+            Priority::High => PriorityLevel::High,
+        }
+    }
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+fn is_normal(priority: &PriorityLevel) -> bool {
+    *priority == PriorityLevel::Normal
+}
+
+/// First line of an export, newline included.
+pub fn header() -> String {
+    line(&Header { format: FORMAT.to_owned(), version: VERSION })
+}
+
+/// Line of a dumped item, newline included.
+pub fn record(key: &str, dump: &Dump) -> String {
+    line(&Record {
+        key: key.to_owned(),
+        data: base64::encode(&dump.data),
+        ttl_ms: dump.ttl.map(|ttl| ttl.as_millis() as u64),
+        sliding_ms: dump.sliding.map(|sliding| sliding.as_millis() as u64),
+        pinned: dump.pinned,
+        priority: dump.priority.into(),
+    })
+}
+
+fn line(value: &impl Serialize) -> String {
+    let mut line = serde_json::to_string(value).expect("export lines always serialize");
+    line.push('\n');
+    line
+}
+
+/// Checks the first line of an export.
+pub fn check_header(line: &str) -> Result<(), Error> {
+    match serde_json::from_str::<Header>(line) {
+        Ok(header) if header.format == FORMAT && header.version <= VERSION => Ok(()),
+        Ok(header) if header.format == FORMAT => Err(Error::BadRequest("unsupported export version")),
+        _ => Err(Error::BadRequest("missing export header")),
+    }
+}
+
+/// Key and dump of an item line, restoring the dump gives the item a fresh version.
+pub fn parse_record(line: &str) -> Result<(String, Dump), Error> {
+    let malformed = || Error::BadRequest("malformed export item");
+    let record: Record = serde_json::from_str(line).map_err(|_| malformed())?;
+    let data = base64::decode(&record.data).map_err(|_| malformed())?;
+    let dump = Dump {
+        data,
+        ttl: record.ttl_ms.map(Duration::from_millis),
+        sliding: record.sliding_ms.map(Duration::from_millis),
+        pinned: record.pinned,
+        priority: record.priority.into(),
+        version: 0,
+    };
+    Ok((record.key, dump))
+}
+
+/// Streams items with keys starting with `prefix` as an export. The lock is released
+/// between chunks, so it is not a snapshot: items changed meanwhile may be either way.
+pub async fn stream(mc: Arc<Store>, prefix: &str) -> HttpResponse {
+    let keys: Vec<String> = mc.read().await.keys()
+        .filter(|key| key.starts_with(prefix))
+        .map(ToOwned::to_owned)
+        .collect();
+    let chunks: Vec<Vec<String>> = keys.chunks(CHUNK).map(<[String]>::to_vec).collect();
+
+    let items = stream::iter(chunks).then(move |chunk| {
+        let mc = mc.clone();
+        async move {
+            let mc = mc.read().await;
+            let lines: String = chunk.iter()
+                .filter_map(|key| Some(record(key, &mc.dump(key)?)))
+                .collect();
+            Ok::<_, actix_web::Error>(Bytes::from(lines))
+        }
+    });
+    let body = stream::once(ready(Ok(Bytes::from(header())))).chain(items);
+
+    HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .streaming(Box::pin(body))
+}
+
+/// Bounds of an import body, see [`import`].
+pub struct ImportLimits {
+    /// bytes of a line
+    pub line: usize,
+    /// bytes of the whole body
+    pub body: usize,
+}
+
+/// Items restored and skipped by [`import`].
+#[derive(Default)]
+pub struct Imported {
+    pub restored: usize,
+    /// existing keys left as they are and values the store couldn't fit
+    pub skipped: usize,
+}
+
+/// Restores items of an export streamed in `body`, existing keys are overwritten only if `replace` is set.
+/// Items are restored as whole lines arrive, so those before a malformed line or one above
+/// the limits stay restored.
+pub async fn import(
+    mc: &Store, l1: Option<&L1>, mut body: Payload, limits: &ImportLimits, replace: bool,
+) -> Result<Imported, Error> {
+    let mut import = Import { imported: Imported::default(), header: false, replace };
+    let mut pending = Vec::new();
+    let mut read = 0;
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|_| Error::BadRequest("import body is incomplete"))?;
+        read += chunk.len();
+        if read > limits.body {
+            return Err(Error::PayloadTooLarge("import body is above import_limit"))
+        }
+        pending.extend_from_slice(&chunk);
+        if let Some(end) = pending.iter().rposition(|&byte| byte == b'\n') {
+            let lines: Vec<u8> = pending.drain(..=end).collect();
+            import.lines(mc, l1, &lines, limits.line).await?;
+        }
+        // what is left is a part of a single line
+        if pending.len() > limits.line {
+            return Err(Error::PayloadTooLarge("export line is above json_limit"))
+        }
+    }
+    // the last line may lack its newline
+    import.lines(mc, l1, &pending, limits.line).await?;
+
+    match import.header {
+        true => Ok(import.imported),
+        false => Err(Error::BadRequest("missing export header")),
+    }
+}
+
+struct Import {
+    imported: Imported,
+    header: bool,
+    replace: bool,
+}
+
+impl Import {
+    async fn lines(&mut self, mc: &Store, l1: Option<&L1>, lines: &[u8], line_limit: usize) -> Result<(), Error> {
+        let lines = str::from_utf8(lines).map_err(|_| Error::BadRequest("malformed export item"))?;
+        let mut items = Vec::new();
+        for line in lines.lines().filter(|line| !line.trim().is_empty()) {
+            if line.len() > line_limit {
+                return Err(Error::PayloadTooLarge("export line is above json_limit"))
+            }
+            match self.header {
+                true => items.push(parse_record(line)?),
+                false => {
+                    check_header(line)?;
+                    self.header = true;
+                },
+            }
+        }
+
+        let mut items = items.into_iter().peekable();
+        while items.peek().is_some() {
+            let mut mc = mc.write().await;
+            for (key, dump) in items.by_ref().take(CHUNK) {
+                if let Some(l1) = l1 {
+                    l1.invalidate(&key);
+                }
+                match (self.replace || mc.version(&key).is_none()) && mc.restore(key, dump).is_ok() {
+                    true => self.imported.restored += 1,
+                    false => self.imported.skipped += 1,
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,41 @@
+use actix_http::{Error, HttpService, Request, Response};
+use actix_service::{map_config, IntoServiceFactory, Service, ServiceFactory};
+use actix_web::dev::{AppConfig, MessageBody, Server};
+use std::{fmt, io, net};
+
+use crate::settings::ConnectionLimits;
+
+/// Serves the app over cleartext HTTP/2 with prior knowledge, so internal clients can
+/// multiplex many small requests over a single connection. HTTP/1.1 requests and upgrades
+/// are not accepted on this listener, `addr` keeps serving them.
+pub fn start<F, I, S, B>(
+    listener: net::TcpListener, workers: Option<usize>, limits: ConnectionLimits, app: F,
+) -> io::Result<Server>
+where
+    F: Fn() -> I + Send + Clone + 'static,
+    I: IntoServiceFactory<S>,
+    S: ServiceFactory<Config = AppConfig, Request = Request>,
+    S::Error: Into<Error> + 'static,
+    S::InitError: fmt::Debug,
+    S::Response: Into<Response<B>> + 'static,
+    <S::Service as Service>::Future: 'static,
+    B: MessageBody + 'static,
+{
+    let mut builder = Server::build()
+        .maxconn(limits.max_connections)
+        .shutdown_timeout(limits.shutdown_timeout.as_secs());
+    if let Some(workers) = workers {
+        builder = builder.workers(workers);
+    }
+    let server = builder
+        .listen("h2c", listener, move || {
+            HttpService::build()
+                .keep_alive(limits.keep_alive_policy())
+                .client_timeout(limits.client_request_timeout.as_millis() as u64)
+                .client_disconnect(limits.client_shutdown_timeout.as_millis() as u64)
+                .h2(map_config(app(), |_| AppConfig::default()))
+                .tcp()
+        })?
+        .run();
+    Ok(server)
+}
@@ -0,0 +1,250 @@
+//! Hot restart: a new process takes the default store over from the running one through
+//! a unix socket, so deploys don't empty the cache. Both processes bind the api with
+//! SO_REUSEPORT and the new one binds before asking for the store, so connections queue
+//! on its listeners while the old one drains its requests and streams the items.
+//! Items of every kind go along with namespaces, and only to a process of the same user.
+
+use actix_web::{dev::Server, rt};
+use futures::{
+    channel::oneshot,
+    future::{abortable, AbortHandle},
+};
+use serde::{Serialize, Deserialize};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+};
+use tracing::{info, warn};
+use std::{
+    fs::{self, Permissions},
+    io::{self, ErrorKind},
+    os::unix::fs::{MetadataExt, PermissionsExt},
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::{
+    dump,
+    memcached::{Contents, Memcached, Overflow, Store},
+    namespaces::Namespaces,
+};
+
+/// bytes of lines buffered before writing them to the new process
+const CHUNK: usize = 64 << 10;
+
+/// Line of the stream: an item of the store the latest namespace line named,
+/// of the default store before any.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Line {
+    /// namespace is created with the limit unless it exists already
+    Namespace { namespace: String, limit: usize },
+    /// `dump` has the metadata of the item and the data of a plain one
+    Item {
+        key: String,
+        dump: String,
+        #[serde(default, skip_serializing_if = "Kind::is_plain")]
+        kind: Kind,
+    },
+}
+
+/// Contents of an item besides a plain value, byte strings are base64.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+enum Kind {
+    #[default]
+    Plain,
+    List(Vec<String>),
+    Set(Vec<String>),
+    Map(Vec<(String, String)>),
+    /// `overflow` is `error`, `saturate` or `wrap`
+    Counter { value: i64, overflow: String, refresh_ms: Option<u64> },
+    Negative,
+}
+
+impl Kind {
+    fn is_plain(&self) -> bool {
+        matches!(self, Kind::Plain)
+    }
+
+    fn new(contents: Contents) -> Kind {
+        let encode = |values: Vec<Vec<u8>>| values.into_iter().map(base64::encode).collect();
+        match contents {
+            Contents::Plain => Kind::Plain,
+            Contents::List(items) => Kind::List(encode(items)),
+            Contents::Set(members) => Kind::Set(encode(members)),
+            Contents::Map(fields) => Kind::Map(
+                fields.into_iter().map(|(field, value)| (base64::encode(field), base64::encode(value))).collect(),
+            ),
+            Contents::Counter { value, overflow, refresh } => Kind::Counter {
+                value,
+                overflow: match overflow {
+                    Overflow::Error => "error",
+                    Overflow::Saturate => "saturate",
+                    Overflow::Wrap => "wrap",
+                }.to_owned(),
+                refresh_ms: refresh.map(|refresh| refresh.as_millis() as u64),
+            },
+            Contents::Negative => Kind::Negative,
+        }
+    }
+
+    fn contents(self) -> Result<Contents, String> {
+        let decode = |values: Vec<String>| values.iter()
+            .map(|value| base64::decode(value).map_err(|err| err.to_string()))
+            .collect::<Result<Vec<_>, _>>();
+        Ok(match self {
+            Kind::Plain => Contents::Plain,
+            Kind::List(items) => Contents::List(decode(items)?),
+            Kind::Set(members) => Contents::Set(decode(members)?),
+            Kind::Map(fields) => {
+                let (names, values) = fields.into_iter().unzip();
+                Contents::Map(decode(names)?.into_iter().zip(decode(values)?).collect())
+            },
+            Kind::Counter { value, overflow, refresh_ms } => Contents::Counter {
+                value,
+                overflow: match overflow.as_str() {
+                    "error" => Overflow::Error,
+                    "saturate" => Overflow::Saturate,
+                    "wrap" => Overflow::Wrap,
+                    other => return Err(format!("unknown overflow {}", other)),
+                },
+                refresh: refresh_ms.map(Duration::from_millis),
+            },
+            Kind::Negative => Contents::Negative,
+        })
+    }
+}
+
+/// Takes the store over from a process serving handover on `path` into `mc`, and namespaces
+/// into `namespaces`. Returns how many items are stored, `None` if no process serves it.
+/// Waits until the old process has drained its requests and sent everything.
+pub async fn take_over(mc: &mut Memcached, namespaces: &Namespaces, path: impl AsRef<Path>) -> io::Result<Option<usize>> {
+    let conn = match UnixStream::connect(path).await {
+        Ok(conn) => conn,
+        Err(err) if matches!(err.kind(), ErrorKind::NotFound | ErrorKind::ConnectionRefused) => {
+            return Ok(None)
+        },
+        Err(err) => return Err(err),
+    };
+
+    let (mut lines, mut n) = (BufReader::new(conn).lines(), 0);
+    let (mut namespace, mut stored) = (None::<Arc<Store>>, 0);
+    while let Some(line) = lines.next_line().await? {
+        n += 1;
+        let invalid = |err: String| io::Error::new(ErrorKind::InvalidData, format!("line {}: {}", n, err));
+        let (key, dump, kind) = match serde_json::from_str(&line).map_err(|err| invalid(err.to_string()))? {
+            Line::Namespace { namespace: name, limit } => {
+                namespaces.create(name.clone(), limit, None, None);
+                namespace = namespaces.get(&name);
+                continue
+            },
+            Line::Item { key, dump, kind } => (key, dump, kind),
+        };
+        let dump = dump::decode(&dump).map_err(|err| invalid(err.to_string()))?;
+        let contents = kind.contents().map_err(invalid)?;
+        let result = match &namespace {
+            Some(ns) => ns.write().await.restore_snapshot(key, dump, contents),
+            None => mc.restore_snapshot(key, dump, contents),
+        };
+        match result {
+            Ok(()) => stored += 1,
+            Err(err) => {
+                let (key, _) = err.into_kv();
+                warn!("handed over key {} is not stored", key);
+            },
+        }
+    }
+    Ok(Some(stored))
+}
+
+/// Handover side of a running server, see [`listen`].
+pub struct Handover {
+    task: AbortHandle,
+    taker: oneshot::Receiver<UnixStream>,
+}
+
+/// Waits on `path` for a process to take over, then stops `server` gracefully.
+/// A stale socket file left by a previous process is replaced. Only the owner of the socket
+/// may connect, and only processes of the same user are handed the store.
+pub fn listen(path: impl AsRef<Path>, server: Server) -> io::Result<Handover> {
+    match fs::remove_file(&path) {
+        Err(err) if err.kind() != ErrorKind::NotFound => return Err(err),
+        _ => (),
+    }
+    let listener = UnixListener::bind(&path)?;
+    fs::set_permissions(&path, Permissions::from_mode(0o600))?;
+    // the socket is created by this process, so it is owned by its user
+    let owner = fs::metadata(&path)?.uid();
+    let (taker, taken) = oneshot::channel();
+    let (task, handle) = abortable(wait(listener, owner, server, taker));
+    rt::spawn(async move {
+        let _ = task.await;
+    });
+    Ok(Handover { task: handle, taker: taken })
+}
+
+async fn wait(mut listener: UnixListener, owner: u32, server: Server, taker: oneshot::Sender<UnixStream>) {
+    loop {
+        match listener.accept().await {
+            Ok((conn, _)) => match conn.peer_cred() {
+                // a socket created before its mode was changed may still be connected by anyone
+                Ok(cred) if cred.uid == owner => {
+                    info!("handing the store over to a new process");
+                    let _ = taker.send(conn);
+                    server.stop(true).await;
+                    return
+                },
+                Ok(cred) => warn!("handover refused to a process of user {}", cred.uid),
+                Err(err) => warn!("can't check handover peer: {}", err),
+            },
+            Err(err) => warn!("can't accept handover: {}", err),
+        }
+    }
+}
+
+impl Handover {
+    /// Streams every item of `mc` and of every namespace to the process which took over, if any.
+    /// Must be called once the server is stopped, so no request changes the stores meanwhile.
+    /// Returns how many items are sent.
+    pub async fn finish(mut self, mc: &Store, namespaces: &Namespaces) -> io::Result<usize> {
+        self.task.abort();
+        let mut conn = match self.taker.try_recv() {
+            Ok(Some(conn)) => conn,
+            _ => return Ok(0),
+        };
+
+        let mut buf = Vec::with_capacity(CHUNK);
+        let mut sent = send(&mut conn, &mut buf, &*mc.read().await).await?;
+        for (name, ns) in namespaces.list() {
+            let ns = ns.read().await;
+            let line = Line::Namespace { namespace: name, limit: ns.limit() };
+            serde_json::to_writer(&mut buf, &line)?;
+            buf.push(b'\n');
+            sent += send(&mut conn, &mut buf, &ns).await?;
+        }
+        conn.write_all(&buf).await?;
+        Ok(sent)
+    }
+}
+
+/// Writes lines of every item of `mc`, the last chunk is left in `buf`.
+async fn send(conn: &mut UnixStream, buf: &mut Vec<u8>, mc: &Memcached) -> io::Result<usize> {
+    let mut sent = 0;
+    for key in mc.keys() {
+        let (dumped, contents) = match mc.snapshot(key) {
+            Some(snapshot) => snapshot,
+            None => continue,
+        };
+        let line = Line::Item { key: key.to_owned(), dump: dump::encode(&dumped), kind: Kind::new(contents) };
+        serde_json::to_writer(&mut *buf, &line)?;
+        buf.push(b'\n');
+        sent += 1;
+        if buf.len() >= CHUNK {
+            conn.write_all(buf).await?;
+            buf.clear();
+        }
+    }
+    Ok(sent)
+}
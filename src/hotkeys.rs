@@ -0,0 +1,157 @@
+//! Hot key detection: requests per key are estimated by a count-min sketch of constant size
+//! and keys with the highest estimates are kept as the top ones. Estimates are halved every
+//! [`DECAY_EVERY`] requests, so the top follows current traffic rather than all time.
+//!
+//! Counters are atomic, the top is locked only by keys estimated above its coldest one,
+//! and a request finding it locked by another one is only counted in the sketch.
+
+use actix_web::{get, HttpResponse, web::Data};
+use serde::Serialize;
+use std::{
+    fmt::Write,
+    sync::{
+        Mutex,
+        atomic::{AtomicU32, AtomicU64, Ordering},
+    },
+};
+use xxhash_rust::xxh3::xxh3_64_with_seed;
+
+use crate::audit::key_hash;
+
+/// rows of the sketch, an estimate is the least counter of the key among them
+const DEPTH: usize = 4;
+/// counters per row
+const WIDTH: usize = 4096;
+/// requests between halvings of every count
+pub const DECAY_EVERY: u64 = WIDTH as u64 * 16;
+/// top keys tracked at most, the top is scanned on its updates
+pub const MAX_HOT_KEYS: u64 = 100;
+
+struct Tracked {
+    key: String,
+    requests: u64,
+}
+
+/// Keys requested the most, see the module docs.
+pub struct HotKeys {
+    /// top keys tracked, nothing is tracked if 0
+    capacity: usize,
+    counters: Vec<AtomicU32>,
+    observed: AtomicU64,
+    /// estimate a key must exceed to get into the full top, 0 while it is not full
+    threshold: AtomicU64,
+    /// keys with the highest estimates, in no particular order
+    top: Mutex<Vec<Tracked>>,
+}
+
+#[derive(Serialize)]
+pub struct HotKey {
+    pub key: String,
+    /// hash the access log and the audit file show
+    pub key_hash: String,
+    /// estimated since the last decay, at least the actual count
+    pub requests: u64,
+}
+
+impl HotKeys {
+    pub fn new(capacity: usize) -> HotKeys {
+        let counters = match capacity {
+            0 => Vec::new(),
+            _ => (0..DEPTH * WIDTH).map(|_| AtomicU32::new(0)).collect(),
+        };
+        HotKeys {
+            capacity, counters,
+            observed: AtomicU64::new(0),
+            threshold: AtomicU64::new(0),
+            top: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity != 0
+    }
+
+    /// Counts a request of the key.
+    pub fn observe(&self, key: &str) {
+        if !self.is_enabled() {
+            return
+        }
+        let mut estimate = u32::MAX;
+        for row in 0..DEPTH {
+            let cell = row * WIDTH + xxh3_64_with_seed(key.as_bytes(), row as u64) as usize % WIDTH;
+            let counter = self.counters[cell].fetch_add(1, Ordering::Relaxed).saturating_add(1);
+            estimate = estimate.min(counter);
+        }
+        let estimate = estimate as u64;
+        let observed = self.observed.fetch_add(1, Ordering::Relaxed) + 1;
+
+        if estimate > self.threshold.load(Ordering::Relaxed) {
+            if let Ok(mut top) = self.top.try_lock() {
+                match top.iter().position(|tracked| tracked.key == key) {
+                    Some(i) => top[i].requests = estimate,
+                    None if top.len() < self.capacity => top.push(Tracked { key: key.to_owned(), requests: estimate }),
+                    None => {
+                        let coldest = (0..top.len()).min_by_key(|&i| top[i].requests).unwrap();
+                        if top[coldest].requests < estimate {
+                            top[coldest] = Tracked { key: key.to_owned(), requests: estimate };
+                        }
+                    },
+                }
+                self.update_threshold(&top);
+            }
+        }
+
+        if observed.is_multiple_of(DECAY_EVERY) {
+            for counter in &self.counters {
+                let _ = counter.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| Some(count / 2));
+            }
+            let mut top = self.top.lock().unwrap();
+            top.iter_mut().for_each(|tracked| tracked.requests /= 2);
+            top.retain(|tracked| tracked.requests != 0);
+            self.update_threshold(&top);
+        }
+    }
+
+    fn update_threshold(&self, top: &[Tracked]) {
+        let threshold = match top.len() < self.capacity {
+            true => 0,
+            false => top.iter().map(|tracked| tracked.requests).min().unwrap_or_default(),
+        };
+        self.threshold.store(threshold, Ordering::Relaxed);
+    }
+
+    /// Hottest keys first.
+    pub fn top(&self) -> Vec<HotKey> {
+        let mut top: Vec<HotKey> = self.top.lock().unwrap().iter()
+            .map(|tracked| HotKey {
+                key: tracked.key.clone(),
+                key_hash: format!("{:016x}", key_hash(&tracked.key)),
+                requests: tracked.requests,
+            })
+            .collect();
+        top.sort_by(|a, b| b.requests.cmp(&a.requests).then_with(|| a.key.cmp(&b.key)));
+        top
+    }
+
+    /// Prometheus gauges of the top keys, labeled by key hash so keys don't leak into metrics.
+    pub fn render(&self, out: &mut String) {
+        if !self.is_enabled() {
+            return
+        }
+        let name = "memcached_hot_key_requests";
+        let _ = writeln!(out, "# HELP {} estimated requests of the hottest keys since the last decay\n# TYPE {} gauge", name, name);
+        for hot in self.top() {
+            let _ = writeln!(out, "{}{{key_hash=\"{}\"}} {}", name, hot.key_hash, hot.requests);
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct HotKeysResp {
+    keys: Vec<HotKey>,
+}
+
+#[get("/admin/hotkeys")]
+pub async fn report(hot_keys: Data<HotKeys>) -> HttpResponse {
+    HttpResponse::Ok().json(HotKeysResp { keys: hot_keys.top() })
+}
@@ -0,0 +1,133 @@
+use serde::Serialize;
+use utoipa::ToSchema;
+use std::{
+    thread,
+    collections::BTreeMap,
+    sync::{
+        Arc, RwLock, Mutex,
+        atomic::{AtomicU64, AtomicBool, Ordering},
+    },
+};
+
+/// finished jobs beyond this count are forgotten, oldest first
+const MAX_JOBS: usize = 1024;
+
+#[derive(Serialize, Clone, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Running,
+    Done,
+    Cancelled,
+    Failed(String),
+}
+
+/// Progress of a background admin operation, shared with the thread doing the work.
+pub struct Job {
+    kind: &'static str,
+    done: AtomicU64,
+    total: AtomicU64,
+    cancelled: AtomicBool,
+    state: Mutex<JobState>,
+}
+
+impl Job {
+    pub fn set_total(&self, total: u64) {
+        self.total.store(total, Ordering::Relaxed);
+    }
+
+    pub fn advance(&self, by: u64) {
+        self.done.fetch_add(by, Ordering::Relaxed);
+    }
+
+    /// long running work is expected to check it between chunks and bail out
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    fn is_finished(&self) -> bool {
+        *self.state.lock().unwrap() != JobState::Running
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct JobStatus {
+    id: u64,
+    kind: &'static str,
+    state: JobState,
+    done: u64,
+    total: u64,
+}
+
+#[derive(Default)]
+pub struct Jobs {
+    next_id: AtomicU64,
+    jobs: RwLock<BTreeMap<u64, Arc<Job>>>,
+}
+
+impl Jobs {
+    /// Runs `work` in a separate thread and returns id to poll its status with.
+    pub fn spawn<F>(&self, kind: &'static str, work: F) -> u64
+    where F: FnOnce(&Job) -> Result<(), String> + Send + 'static {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let job = Arc::new(Job {
+            kind,
+            done: AtomicU64::new(0),
+            total: AtomicU64::new(0),
+            cancelled: AtomicBool::new(false),
+            state: Mutex::new(JobState::Running),
+        });
+
+        {
+            let mut jobs = self.jobs.write().unwrap();
+            jobs.insert(id, job.clone());
+            prune(&mut jobs);
+        }
+
+        thread::spawn(move || {
+            let state = match work(&job) {
+                Ok(_) if job.is_cancelled() => JobState::Cancelled,
+                Ok(_) => JobState::Done,
+                Err(err) => JobState::Failed(err),
+            };
+            *job.state.lock().unwrap() = state;
+        });
+
+        id
+    }
+
+    pub fn status(&self, id: u64) -> Option<JobStatus> {
+        let job = self.jobs.read().unwrap().get(&id)?.clone();
+        let state = job.state.lock().unwrap().clone();
+
+        Some(JobStatus {
+            id, state,
+            kind: job.kind,
+            done: job.done.load(Ordering::Relaxed),
+            total: job.total.load(Ordering::Relaxed),
+        })
+    }
+
+    /// returns false if there is no such job
+    pub fn cancel(&self, id: u64) -> bool {
+        match self.jobs.read().unwrap().get(&id) {
+            Some(job) => {
+                job.cancelled.store(true, Ordering::Relaxed);
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+fn prune(jobs: &mut BTreeMap<u64, Arc<Job>>) {
+    let excess = jobs.len().saturating_sub(MAX_JOBS);
+    let finished: Vec<u64> = jobs.iter()
+        .filter(|(_, job)| job.is_finished())
+        .map(|(&id, _)| id)
+        .take(excess)
+        .collect();
+
+    for id in finished {
+        jobs.remove(&id);
+    }
+}
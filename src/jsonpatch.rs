@@ -0,0 +1,127 @@
+//! Partial reads and updates of JSON documents stored as values: paths are JSON Pointers
+//! (RFC 6901), patches are the add, remove, replace and test operations of JSON Patch
+//! (RFC 6902). Move and copy are not supported.
+
+use serde::Deserialize;
+use serde_json::Value;
+use utoipa::ToSchema;
+
+/// Operation of a patch, operations are applied in order.
+#[derive(Deserialize, ToSchema)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum PatchOp {
+    /// sets a member of an object or inserts into an array, `-` as the last token appends
+    Add {
+        path: String,
+        #[schema(value_type = Object)]
+        value: Value,
+    },
+    Remove {
+        path: String,
+    },
+    /// replaces an existing part of the document
+    Replace {
+        path: String,
+        #[schema(value_type = Object)]
+        value: Value,
+    },
+    /// fails the patch unless the part at `path` equals `value`
+    Test {
+        path: String,
+        #[schema(value_type = Object)]
+        value: Value,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PatchError {
+    /// path is neither empty nor starts with `/`
+    InvalidPath,
+    /// path doesn't point into the document
+    NoPath,
+    TestFailed,
+}
+
+/// Part of `doc` at `path`, the whole document if it is empty.
+pub fn get<'a>(doc: &'a Value, path: &str) -> Result<&'a Value, PatchError> {
+    valid(path)?;
+    doc.pointer(path).ok_or(PatchError::NoPath)
+}
+
+/// Applies `ops` to `doc`. On failure it may be patched partially,
+/// so callers keep the document only if every operation applies.
+pub fn apply(doc: &mut Value, ops: Vec<PatchOp>) -> Result<(), PatchError> {
+    ops.into_iter().try_for_each(|op| match op {
+        PatchOp::Add { path, value } => add(doc, &path, value),
+        PatchOp::Remove { path } => remove(doc, &path),
+        PatchOp::Replace { path, value } => {
+            valid(&path)?;
+            *doc.pointer_mut(&path).ok_or(PatchError::NoPath)? = value;
+            Ok(())
+        },
+        PatchOp::Test { path, value } => match *get(doc, &path)? == value {
+            true => Ok(()),
+            false => Err(PatchError::TestFailed),
+        },
+    })
+}
+
+fn add(doc: &mut Value, path: &str, value: Value) -> Result<(), PatchError> {
+    let (parent, last) = match split(path)? {
+        Some(split) => split,
+        None => {
+            *doc = value;
+            return Ok(())
+        },
+    };
+    match doc.pointer_mut(parent).ok_or(PatchError::NoPath)? {
+        Value::Object(members) => {
+            members.insert(last, value);
+        },
+        Value::Array(items) => {
+            let at = match last.as_str() {
+                "-" => items.len(),
+                token => index(token, items.len() + 1)?,
+            };
+            items.insert(at, value);
+        },
+        _ => return Err(PatchError::NoPath),
+    }
+    Ok(())
+}
+
+fn remove(doc: &mut Value, path: &str) -> Result<(), PatchError> {
+    // the document itself can't be removed
+    let (parent, last) = split(path)?.ok_or(PatchError::NoPath)?;
+    match doc.pointer_mut(parent).ok_or(PatchError::NoPath)? {
+        Value::Object(members) => members.remove(&last).map(drop).ok_or(PatchError::NoPath),
+        Value::Array(items) => {
+            let at = index(&last, items.len())?;
+            items.remove(at);
+            Ok(())
+        },
+        _ => Err(PatchError::NoPath),
+    }
+}
+
+fn valid(path: &str) -> Result<(), PatchError> {
+    match path.is_empty() || path.starts_with('/') {
+        true => Ok(()),
+        false => Err(PatchError::InvalidPath),
+    }
+}
+
+/// Pointer to the parent and the unescaped last token, `None` for the whole document.
+fn split(path: &str) -> Result<Option<(&str, String)>, PatchError> {
+    valid(path)?;
+    Ok(path.rfind('/').map(|at| (&path[..at], path[at + 1..].replace("~1", "/").replace("~0", "~"))))
+}
+
+/// Array index below `bound`, leading zeros are not allowed.
+fn index(token: &str, bound: usize) -> Result<usize, PatchError> {
+    let digits = !token.is_empty() && token.bytes().all(|byte| byte.is_ascii_digit());
+    match token.parse::<usize>() {
+        Ok(at) if digits && (token == "0" || !token.starts_with('0')) && at < bound => Ok(at),
+        _ => Err(PatchError::NoPath),
+    }
+}
@@ -0,0 +1,181 @@
+//! Keys a request names in its path, query or json body, found before the handler runs,
+//! so middlewares can check them, and [`KeyCheck`] holding them to [`KeyRules`].
+
+use actix_web::{
+    web, Error, HttpMessage, HttpResponse,
+    dev::{Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    http::{StatusCode, header::CONTENT_ENCODING},
+    web::BytesMut,
+};
+use futures::{
+    StreamExt,
+    future::{ok, LocalBoxFuture, Ready},
+};
+use percent_encoding::percent_decode_str;
+use serde_json::Value;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    rc::Rc,
+    task::{Context, Poll},
+};
+
+use crate::{
+    auth,
+    errors::error_response,
+    memcached::KeyRules,
+};
+
+/// ops reading their bodies as a stream rather than a json document
+const STREAMED_OPS: &[&str] = &["import"];
+
+/// Op of a request path and the rest of it, `/ns/{name}/` prefix is skipped.
+pub(crate) fn route(path: &str) -> (&str, &str) {
+    let path = path.trim_start_matches('/');
+    let path = match path.strip_prefix("ns/") {
+        Some(rest) => rest.split_once('/').map(|(_, rest)| rest).unwrap_or_default(),
+        None => path,
+    };
+    match path.find('/') {
+        Some(at) => (&path[..at], &path[at + 1..]),
+        None => (path, ""),
+    }
+}
+
+/// Keys the request names: the key of a resource path, `key` and `prefix` query params
+/// and fields of a json body. The body is read up to `body_limit` and put back for the handler.
+/// `None` if the body is encoded, so its keys can't be told.
+pub(crate) async fn request_keys(req: &mut ServiceRequest, body_limit: usize) -> Result<Option<Vec<String>>, HttpResponse> {
+    let path = auth::routed_path(req).to_owned();
+    let (op, rest) = route(&path);
+
+    let mut keys = Vec::new();
+    if auth::RESOURCE_OPS.contains(&op) {
+        keys.push(percent_decode_str(rest).decode_utf8_lossy().into_owned());
+    }
+    let query = web::Query::<HashMap<String, String>>::from_query(req.query_string())
+        .map(web::Query::into_inner)
+        .unwrap_or_default();
+    keys.extend(query.get("key").cloned());
+    keys.extend(query.get("prefix").cloned());
+
+    // resource bodies are values and streamed ones are read line by line, json of other ops is decoded
+    // by their handlers whatever content type is declared, so it is checked without looking at it
+    if auth::RESOURCE_OPS.contains(&op) || STREAMED_OPS.contains(&op) {
+        return Ok(Some(keys))
+    }
+    if req.headers().get(CONTENT_ENCODING).is_some_and(|encoding| encoding != "identity") {
+        return Ok(None)
+    }
+    let body = read_body(req, body_limit).await?;
+    if let Ok(body) = serde_json::from_slice::<Value>(&body) {
+        named_keys(&body, 0, &mut keys);
+    }
+    Ok(Some(keys))
+}
+
+/// `key` and `prefix` fields of the body, of its items, e.g. a batch, and of their lists, e.g. bulk restore
+fn named_keys(value: &Value, depth: usize, keys: &mut Vec<String>) {
+    match value {
+        Value::Object(fields) => {
+            for (name, field) in fields {
+                match (name.as_str(), field) {
+                    ("key", Value::String(key)) | ("prefix", Value::String(key)) => keys.push(key.clone()),
+                    (_, Value::Array(_)) if depth < 2 => named_keys(field, depth + 1, keys),
+                    _ => (),
+                }
+            }
+        },
+        Value::Array(items) if depth < 2 => items.iter().for_each(|item| named_keys(item, depth + 1, keys)),
+        _ => (),
+    }
+}
+
+/// Reads the body and puts it back for the handler.
+async fn read_body(req: &mut ServiceRequest, limit: usize) -> Result<BytesMut, HttpResponse> {
+    let mut payload = req.take_payload();
+    let mut body = BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(|_| error_response(StatusCode::BAD_REQUEST, "body is incomplete"))?;
+        if body.len() + chunk.len() > limit {
+            return Err(error_response(StatusCode::PAYLOAD_TOO_LARGE, "payload is too large"))
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    let (_, mut restored) = actix_http::h1::Payload::create(true);
+    restored.unread_data(body.clone().freeze());
+    req.set_payload(Payload::from(restored));
+    Ok(body)
+}
+
+/// Middleware answering writes of keys breaking `rules` with 400 before they reach the store,
+/// which would refuse them anyway. Keys of encoded bodies are left to the store.
+pub struct KeyCheck {
+    pub rules: KeyRules,
+    pub body_limit: usize,
+}
+
+impl<S> Transform<S> for KeyCheck
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = Error> + 'static,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = KeyCheckMiddleware<S>;
+    type Future = Ready<Result<KeyCheckMiddleware<S>, ()>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(KeyCheckMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            rules: Rc::new(self.rules.clone()),
+            body_limit: self.body_limit,
+        })
+    }
+}
+
+pub struct KeyCheckMiddleware<S> {
+    service: Rc<RefCell<S>>,
+    rules: Rc<KeyRules>,
+    body_limit: usize,
+}
+
+impl<S> Service for KeyCheckMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = Error> + 'static,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<ServiceResponse, Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        // reads of such keys just miss
+        if !self.rules.is_restrictive() || auth::is_read(req.method(), auth::routed_path(&req)) {
+            return Box::pin(service.borrow_mut().call(req))
+        }
+        let (rules, body_limit) = (self.rules.clone(), self.body_limit);
+
+        Box::pin(async move {
+            let keys = match request_keys(&mut req, body_limit).await {
+                Ok(keys) => keys.unwrap_or_default(),
+                Err(failed) => return Ok(req.into_response(failed)),
+            };
+            if let Some(err) = keys.iter().find_map(|key| rules.check(key).err()) {
+                return Ok(req.into_response(error_response(StatusCode::BAD_REQUEST, err)))
+            }
+
+            let res = service.borrow_mut().call(req);
+            res.await
+        })
+    }
+}
@@ -0,0 +1,71 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    time::Duration,
+};
+
+use crate::memcached::{Clock, StdClock, Timestamp};
+
+/// Settings of [`L1`], capacity of zero disables it.
+#[derive(Clone, Default)]
+pub struct L1Config {
+    pub capacity: usize,
+    pub ttl: Duration,
+}
+
+struct Entry {
+    fetched: Timestamp,
+    data: Vec<u8>,
+    version: u64,
+}
+
+/// Tiny cache of recently read values of the default store, one per worker thread,
+/// so hot keys are served without touching the shared lock.
+///
+/// Writes through the same worker invalidate the entry, other workers
+/// (and expiry or flush in the shared store) are only noticed after `ttl`.
+pub struct L1 {
+    clock: StdClock,
+    config: L1Config,
+    entries: RefCell<HashMap<String, Entry>>,
+}
+
+impl L1 {
+    pub fn new(clock: StdClock, config: L1Config) -> L1 {
+        L1 { clock, config, entries: Default::default() }
+    }
+
+    /// value and its version
+    pub fn get(&self, key: &str) -> Option<(Vec<u8>, u64)> {
+        let entries = self.entries.borrow();
+        let entry = entries.get(key)?;
+        match self.clock.now() - entry.fetched < self.config.ttl {
+            true => Some((entry.data.clone(), entry.version)),
+            false => None,
+        }
+    }
+
+    pub fn put(&self, key: &str, data: &[u8], version: u64) {
+        if self.config.capacity == 0 {
+            return
+        }
+
+        let now = self.clock.now();
+        let mut entries = self.entries.borrow_mut();
+        if entries.len() >= self.config.capacity && !entries.contains_key(key) {
+            // capacity is tiny, so a scan is cheaper than keeping order
+            let oldest = entries.iter()
+                .min_by_key(|(_, entry)| entry.fetched)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+
+        entries.insert(key.to_owned(), Entry { fetched: now, data: data.to_owned(), version });
+    }
+
+    pub fn invalidate(&self, key: &str) {
+        self.entries.borrow_mut().remove(key);
+    }
+}
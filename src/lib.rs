@@ -0,0 +1,44 @@
+pub mod memcached;
+pub mod api;
+pub mod settings;
+pub mod namespaces;
+pub mod compression;
+pub mod cors;
+pub mod decompress;
+pub mod errors;
+pub mod jobs;
+pub mod auth;
+pub mod tenants;
+pub mod keys;
+pub mod bootstrap;
+pub mod l1;
+pub mod audit;
+pub mod stats;
+pub mod degrade;
+pub mod readonly;
+pub mod events;
+pub mod webhook;
+pub mod watch;
+pub mod sse;
+pub mod pubsub;
+pub mod origin;
+pub mod disk;
+pub mod replication;
+pub mod udp;
+pub mod h2c;
+pub mod systemd;
+pub mod cluster;
+pub mod jsonpatch;
+pub mod dump;
+pub mod export;
+pub mod digest;
+pub mod warmup;
+pub mod handover;
+pub mod logging;
+pub mod telemetry;
+pub mod slowlog;
+pub mod metrics;
+pub mod hotkeys;
+pub mod allocator;
+pub mod bench;
+pub mod testing;
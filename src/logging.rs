@@ -0,0 +1,226 @@
+use actix_web::{
+    HttpMessage,
+    dev::{ServiceRequest, ServiceResponse, BodySize, MessageBody},
+    http::{HeaderName, HeaderValue},
+};
+use opentelemetry::sdk::trace::Tracer;
+use tracing::{info, Subscriber};
+use tracing_subscriber::{
+    fmt, reload, EnvFilter, Registry,
+    filter::ParseError,
+    layer::SubscriberExt,
+    registry::LookupSpan,
+    util::SubscriberInitExt,
+};
+use std::{
+    env, mem,
+    str::FromStr,
+    sync::Mutex,
+    time::Instant,
+};
+
+use crate::audit::AuditKey;
+
+/// target of access log events
+const ACCESS: &str = "access";
+
+/// Correlates a request across clients, cluster peers and logs.
+/// Taken from the request if the client sent a sane one, generated otherwise.
+pub const REQUEST_ID: &str = "x-request-id";
+
+/// longest request id taken from a client
+const MAX_REQUEST_ID: usize = 128;
+
+/// filter when `RUST_LOG` is not set, same as `EnvFilter` has
+const DEFAULT_DIRECTIVES: &str = "error";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    /// human readable lines, fields as `name=value`
+    Text,
+    /// single line json objects with `timestamp`, `level`, `target`, event fields and enclosing spans
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<LogFormat, String> {
+        match format {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format {}, expected text or json", format)),
+        }
+    }
+}
+
+/// Installs the global subscriber, levels are taken from `RUST_LOG` and can be changed
+/// later with the returned filter. Records of crates using `log`, e.g. actix, are converted to events.
+/// Spans are exported with `tracer` if it is set.
+pub fn init(format: LogFormat, tracer: Option<Tracer>) -> LogFilter {
+    let directives = env::var(EnvFilter::DEFAULT_ENV).unwrap_or_else(|_| DEFAULT_DIRECTIVES.to_owned());
+    let (filter, reload) = reload::Layer::new(EnvFilter::new(&directives));
+    let registry = tracing_subscriber::registry().with(filter);
+    match format {
+        LogFormat::Text => install(registry.with(fmt::layer()), tracer),
+        LogFormat::Json => install(registry.with(fmt::layer().json().flatten_event(true)), tracer),
+    }
+    LogFilter { reload: Some(reload), directives: Mutex::new(directives) }
+}
+
+fn install<S>(subscriber: S, tracer: Option<Tracer>)
+where S: Subscriber + for<'span> LookupSpan<'span> + Send + Sync {
+    match tracer {
+        Some(tracer) => subscriber.with(tracing_opentelemetry::layer().with_tracer(tracer)).init(),
+        None => subscriber.init(),
+    }
+}
+
+/// Filter of the global subscriber, replaceable at runtime in `RUST_LOG` syntax.
+pub struct LogFilter {
+    reload: Option<reload::Handle<EnvFilter, Registry>>,
+    directives: Mutex<String>,
+}
+
+impl LogFilter {
+    /// Filter of no subscriber, changes are only validated and remembered.
+    pub fn detached() -> LogFilter {
+        LogFilter { reload: None, directives: Mutex::new(DEFAULT_DIRECTIVES.to_owned()) }
+    }
+
+    pub fn directives(&self) -> String {
+        self.directives.lock().unwrap().clone()
+    }
+
+    /// Starts filtering events with `directives`, returns the previous ones.
+    pub fn set(&self, directives: &str) -> Result<String, ParseError> {
+        let filter = EnvFilter::try_new(directives)?;
+        let mut current = self.directives.lock().unwrap();
+        if let Some(reload) = &self.reload {
+            // fails only once the subscriber is dropped, and the global one never is
+            let _ = reload.reload(filter);
+        }
+        Ok(mem::replace(&mut *current, directives.to_owned()))
+    }
+}
+
+/// How keys appear in the access log.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyLogging {
+    /// hex of the hash the audit file has, so both can be joined
+    Hash,
+    /// first chars of the key
+    Truncate(usize),
+}
+
+impl KeyLogging {
+    pub fn new(mode: &str, length: usize) -> Result<KeyLogging, String> {
+        match mode {
+            "hash" => Ok(KeyLogging::Hash),
+            "truncate" => Ok(KeyLogging::Truncate(length)),
+            _ => Err(format!("unknown key logging {}, expected hash or truncate", mode)),
+        }
+    }
+
+    fn render(self, key: &AuditKey) -> String {
+        match self {
+            KeyLogging::Hash => format!("{:016x}", key.hash()),
+            KeyLogging::Truncate(length) => key.key().chars().take(length).collect(),
+        }
+    }
+}
+
+/// What a key operation did with the cache, attached by handlers to response extensions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Hit,
+    Miss,
+    /// known missing marker was found
+    Negative,
+    Stored,
+    /// stored after other items were evicted to make room
+    EvictedOnWrite,
+    /// missing value was fetched from origin
+    Filled,
+    /// answered by the cluster node owning the key
+    Proxied,
+}
+
+impl Outcome {
+    fn as_str(self) -> &'static str {
+        match self {
+            Outcome::Hit => "hit",
+            Outcome::Miss => "miss",
+            Outcome::Negative => "negative",
+            Outcome::Stored => "stored",
+            Outcome::EvictedOnWrite => "evicted_on_write",
+            Outcome::Filled => "filled",
+            Outcome::Proxied => "proxied",
+        }
+    }
+}
+
+/// Id of the request, kept in request extensions so it can be passed on.
+#[derive(Clone)]
+pub struct RequestId(pub String);
+
+/// Request which is logged once it is responded, within the request span.
+pub struct Access {
+    started: Instant,
+    request_id: String,
+    keys: KeyLogging,
+    remote: Option<String>,
+    method: String,
+    path: String,
+}
+
+impl Access {
+    pub fn begin(req: &ServiceRequest, keys: KeyLogging) -> Access {
+        let request_id = req.headers().get(REQUEST_ID)
+            .and_then(|id| id.to_str().ok())
+            .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID && id.bytes().all(|byte| byte.is_ascii_graphic()))
+            .map(ToOwned::to_owned)
+            .unwrap_or_else(|| format!("{:032x}", rand::random::<u128>()));
+        req.extensions_mut().insert(RequestId(request_id.clone()));
+
+        Access {
+            started: Instant::now(),
+            request_id,
+            keys,
+            remote: req.connection_info().realip_remote_addr().map(ToOwned::to_owned),
+            method: req.method().to_string(),
+            path: req.path().to_owned(),
+        }
+    }
+
+    /// Logs the request and returns its id to the client.
+    pub fn finish<B: MessageBody>(self, res: &mut ServiceResponse<B>) {
+        if let Ok(id) = HeaderValue::from_str(&self.request_id) {
+            res.headers_mut().insert(HeaderName::from_static(REQUEST_ID), id);
+        }
+
+        let response = res.response();
+        let request_id = &self.request_id;
+        let key = response.extensions().get::<AuditKey>().map(|key| self.keys.render(key));
+        let key = key.as_deref().unwrap_or("-");
+        let outcome = response.extensions().get::<Outcome>().map_or("-", |outcome| outcome.as_str());
+        let remote = self.remote.as_deref().unwrap_or("-");
+        let status = response.status().as_u16();
+        let latency_us = self.started.elapsed().as_micros() as u64;
+        let (method, path) = (&self.method, &self.path);
+        let resp_size = match response.body().size() {
+            BodySize::Sized(size) => Some(size),
+            BodySize::None | BodySize::Empty => Some(0),
+            // size of a streamed body is unknown until it is sent
+            BodySize::Stream => None,
+        };
+        match resp_size {
+            Some(resp_size) => info!(
+                target: ACCESS, %request_id, remote, %method, %path, key, outcome, status, resp_size, latency_us
+            ),
+            None => info!(
+                target: ACCESS, %request_id, remote, %method, %path, key, outcome, status, latency_us
+            ),
+        }
+    }
+}
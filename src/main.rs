@@ -1,46 +1,524 @@
-mod memcached;
-mod api;
-mod settings;
-
 use actix_web::{
     HttpServer, App,
-    middleware::Logger,
+    web::Data,
+    dev::Service,
+    http::StatusCode,
+    middleware::{Compress, Condition},
 };
-use env_logger;
-use std::io::{
-    Result, Error,
-    ErrorKind::InvalidInput,
+use tracing::{info, warn};
+use tracing_actix_web::TracingLogger;
+use futures::future::{Either, ready};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+    env,
+    net::{self, SocketAddr, ToSocketAddrs},
+    rc::Rc,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+    io::{
+        Result, Error,
+        ErrorKind::InvalidInput,
+    },
 };
 
-use crate::{
-    memcached::Memcached,
-    settings::Settings,
+use rust_memcached::{
+    api, memcached, bootstrap,
+    settings::{self, Settings},
+    memcached::{Store, GcBudget, KeyRules, Options, SlabConfig, StdClock},
+    namespaces::Namespaces,
+    auth::{self, Acl, AclCheck, DEFAULT_NAMESPACE},
+    tenants::{self, Quotas},
+    keys::KeyCheck,
+    bootstrap::Bootstrap,
+    compression::CompressionFilter,
+    cors::CorsConfig,
+    l1::L1Config,
+    audit::{Audit, AuditKey},
+    degrade::{self, Degradation, Thresholds},
+    readonly::{self, ReadOnly, ReadOnlyCheck},
+    hotkeys::{self, HotKeys},
+    events::EventBus,
+    webhook::{self, WebhookConfig},
+    origin::OriginConfig,
+    disk::{self, DiskTier},
+    errors::error_response,
+    replication::{self, Primary},
+    udp,
+    h2c,
+    systemd,
+    handover,
+    cluster::Cluster,
+    warmup,
+    logging::{self, Access, LogFormat, KeyLogging},
+    telemetry::{self, Telemetry, OtlpConfig},
+    slowlog::SlowLog,
+    metrics::{self, Metrics},
+    bench::{self, BenchSettings},
 };
 
+/// pending connections per api listener, the actix default
+const BACKLOG: i32 = 2048;
 
 #[actix_web::main]
 async fn main() -> Result<()> {
-    env_logger::init();
+    if env::args().nth(1).as_deref() == Some("check-config") {
+        logging::init(LogFormat::Text, None);
+        return check_config()
+    }
+    if env::args().nth(1).as_deref() == Some("bench") {
+        logging::init(LogFormat::Text, None);
+        return bench().await
+    }
+
+    let settings = Settings::new()
+        .map_err(|err| Error::new(InvalidInput, err))?;
+    settings.validate()
+        .map_err(|err| Error::new(InvalidInput, err))?;
+    let log_format: LogFormat = settings.log_format.parse()
+        .map_err(|err| Error::new(InvalidInput, err))?;
+    let mut telemetry = match &settings.otlp_endpoint {
+        Some(endpoint) => Some(Telemetry::start(OtlpConfig {
+            endpoint: endpoint.clone(),
+            service_name: settings.otlp_service_name.clone(),
+            metrics_interval: settings.otlp_metrics_interval.into(),
+        })?),
+        None => None,
+    };
+    let log_filter = Arc::new(logging::init(log_format, telemetry.as_ref().map(Telemetry::tracer)));
+    let watermarks = settings.watermarks()
+        .map_err(|err| Error::new(InvalidInput, err))?;
+    let limits = settings.connection_limits()
+        .map_err(|err| Error::new(InvalidInput, err))?;
+
     let Settings {
-        memory_limit, gc_interval,
-        addr, workers
-    } = Settings::new()
+        memory_limit, max_items, gc_interval,
+        gc_max_keys, gc_max_duration, ttl_jitter, sliding_ttl, stale_grace, key_hasher,
+        default_ttl, max_ttl, early_expiration_beta, key_max_length, key_allowed_chars, key_reject_whitespace,
+        slab_page_size, slab_min_chunk, slab_growth_factor, key_filter_capacity,
+        evict_low_watermark: _, evict_high_watermark: _,
+        l1_capacity, l1_ttl, disk_tier_path, disk_tier_limit, disk_tier_encrypt, disk_tier_key_file,
+        origin_url, origin_ttl, origin_write, origin_write_retries,
+        replicas, replication_addr, replication_buffer, replication_secret,
+        cluster_nodes, cluster_self, cluster_hedge_delay,
+        addr, admin_addr, udp_addr, h2c_addr, handover_socket, workers, read_only, bootstrap_file, warmup_file,
+        tenant_usage_interval,
+        max_connections: _, client_request_timeout: _, client_shutdown_timeout: _,
+        keep_alive: _, shutdown_timeout: _,
+        audit_file, audit_sample_rate, audit_rotate_size,
+        degrade_p99_enter, degrade_p99_exit, degrade_interval,
+        webhook_url, webhook_batch_size, webhook_flush_interval, webhook_retries,
+        compress, compress_min_size, compress_content_types,
+        json_limit, decompress_limit, import_limit, swagger_ui,
+        cors_allowed_origins, cors_allowed_methods, cors_allowed_headers,
+        log_format: _, access_log_keys, access_log_key_length,
+        otlp_endpoint: _, otlp_service_name: _, otlp_metrics_interval: _,
+        slowlog_threshold, slowlog_capacity, latency_buckets, hot_keys,
+    } = settings;
+
+    let ttl_jitter = ttl_jitter.as_deref()
+        .map(settings::parse_ttl_jitter)
+        .transpose()
+        .map_err(|err| Error::new(InvalidInput, err))?;
+    let hasher = settings::parse_key_hasher(&key_hasher)
         .map_err(|err| Error::new(InvalidInput, err))?;
+    let options = Options {
+        ttl_jitter, sliding_ttl,
+        stale_grace: stale_grace.map(Into::into).unwrap_or_default(),
+        watermarks, hasher,
+        slabs: slab_page_size.map(|page_size| SlabConfig {
+            page_size: page_size as usize,
+            min_chunk: slab_min_chunk as usize,
+            growth_factor: slab_growth_factor,
+        }),
+        key_filter: key_filter_capacity.map(|capacity| capacity as usize),
+        max_items: max_items.map(|max| max as usize),
+        default_ttl: default_ttl.map(Into::into),
+        max_ttl: max_ttl.map(Into::into),
+        early_expiration: early_expiration_beta,
+        key_rules: KeyRules {
+            max_len: key_max_length.map(|max| max as usize),
+            allowed_chars: key_allowed_chars,
+            reject_whitespace: key_reject_whitespace,
+        },
+    };
 
-    let mc = Memcached::new(memory_limit as usize);
-    let service_factory = mc.service(gc_interval.into());
+    // bound before taking the store over, so connections queue on them meanwhile
+    let mut listeners = systemd::listen_fds();
+    if listeners.is_empty() {
+        for addr in settings::split_list(&addr) {
+            listeners.extend(listen(&addr, handover_socket.is_some())?);
+        }
+    }
+    let mut admin_listeners = Vec::new();
+    for addr in settings::split_list(admin_addr.as_deref().unwrap_or_default()) {
+        for listener in listen(&addr, handover_socket.is_some())? {
+            admin_listeners.push(listener.local_addr()?);
+            listeners.push(listener);
+        }
+    }
+    let admin_listeners = Arc::new(admin_listeners);
+
+    let clock = StdClock::new();
+    let events = Arc::new(EventBus::new(clock.clone()));
+    let mut mc = memcached::new(memory_limit as usize, clock.clone(), options.clone());
+    let replicas = settings::split_list(&replicas);
+    let primary = match replicas.is_empty() {
+        true => None,
+        false => Some(Arc::new(Primary::new(replication_buffer as usize))),
+    };
+    let listener = events.listener(DEFAULT_NAMESPACE.to_owned());
+    mc.set_listener(match &primary {
+        Some(primary) => primary.listener(listener),
+        None => listener,
+    });
+    if let Some(path) = disk_tier_path {
+        let tier = DiskTier::open(path, disk_tier_limit, clock.clone())?;
+        mc.set_cold_tier(Box::new(match (disk_tier_encrypt, disk_tier_key_file) {
+            (true, Some(file)) => tier.encrypted(disk::read_key(file)?),
+            (true, None) => tier.encrypted(rand::random()),
+            (false, _) => tier,
+        }));
+    }
+    if let Some(path) = warmup_file {
+        let stored = warmup::load(&mut mc, &path)?;
+        info!("{} keys loaded from {}", stored, path);
+    }
+    let slowlog = Arc::new(SlowLog::new(slowlog_threshold.into(), slowlog_capacity as usize));
+    let latency_buckets = settings::parse_buckets(&latency_buckets)
+        .map_err(|err| Error::new(InvalidInput, err))?;
+    let metrics = Arc::new(Metrics::new(latency_buckets));
+    let gc_interval: Duration = gc_interval.into();
+    let gc_budget = GcBudget {
+        max_keys: gc_max_keys.map(|max| max as usize),
+        max_duration: gc_max_duration.map(Into::into),
+    };
+    let key_rules = options.key_rules.clone();
+    let namespaces = Arc::new(Namespaces::new(
+        clock, gc_interval, gc_budget, options, events.clone(),
+        slowlog.clone(), metrics.clone(),
+    ));
+    if let Some(path) = &handover_socket {
+        if let Some(loaded) = handover::take_over(&mut mc, &namespaces, path).await? {
+            info!("{} keys taken over from {}", loaded, path);
+        }
+    }
+    let mc = Store::new(mc)
+        .with_slowlog(slowlog.clone())
+        .with_metrics(metrics.clone());
+    let mc = Arc::new(mc);
+    if let Some(telemetry) = &mut telemetry {
+        telemetry.export_metrics(&mc)?;
+    }
+    let gc = memcached::spawn_gc(&mc, gc_interval, gc_budget);
+    let watchdog = systemd::spawn_watchdog(&mc, gc_interval);
+    // validated to be set if either is used
+    let replication_secret = replication_secret.unwrap_or_default();
+    let replication = primary.map(|primary| {
+        replication::spawn_primary(&primary, &mc, replicas, replication_secret.clone())
+    });
+    let replica = match replication_addr {
+        Some(addr) => Some(replication::spawn_replica(&mc, net::TcpListener::bind(addr)?, replication_secret)?),
+        None => None,
+    };
+    let is_replica = replica.is_some();
+    let read_only = Arc::new(ReadOnly::new(read_only));
+    let acl = Arc::new(RwLock::new(Acl::default()));
+    let udp = match udp_addr {
+        Some(addr) => Some(udp::spawn(&mc, net::UdpSocket::bind(addr)?, is_replica, read_only.clone(), acl.clone())?),
+        None => None,
+    };
+
+    if let Some(path) = bootstrap_file {
+        let bootstrap = Bootstrap::new(path);
+        bootstrap.apply(&namespaces, &acl).await
+            .map_err(|err| Error::new(InvalidInput, err))?;
+        bootstrap::reload_on_hangup(bootstrap, namespaces.clone(), acl.clone());
+    }
+    let tenant_usage = tenants::spawn_usage(
+        acl.clone(), mc.clone(), namespaces.clone(), tenant_usage_interval.into(),
+    );
+
+    let origin_write = origin_write.parse()
+        .map_err(|err| Error::new(InvalidInput, err))?;
+    let cluster_nodes = settings::split_list(&cluster_nodes);
+    let cluster = match cluster_nodes.is_empty() {
+        true => None,
+        false => {
+            let me = cluster_self.unwrap_or_default();
+            let cluster = Cluster::new(cluster_nodes, &me, decompress_limit as usize)
+                .ok_or_else(|| Error::new(InvalidInput, format!("{} is not one of cluster_nodes", me)))?;
+            Some(match cluster_hedge_delay {
+                Some(delay) => cluster.with_hedge_delay(delay.into()),
+                None => cluster,
+            })
+        },
+    };
+    let store = mc.clone();
+    let handed_over = mc.clone();
+    let service_factory = api::service(
+        mc, namespaces.clone(), events.clone(),
+        json_limit as usize, decompress_limit as usize, import_limit as usize,
+        L1Config { capacity: l1_capacity as usize, ttl: l1_ttl.into() },
+        origin_url.map(|url| OriginConfig {
+            url,
+            ttl: origin_ttl,
+            write: origin_write,
+            write_retries: origin_write_retries as u32,
+        }),
+        cluster, slowlog, log_filter,
+    );
+
+    let audit = match audit_file {
+        Some(path) => Some(Arc::new(Audit::start(path, audit_sample_rate, audit_rotate_size)?)),
+        None => None,
+    };
+
+    let degradation = Arc::new(Degradation::default());
+    let controller = degrade_p99_enter.map(|enter| {
+        let enter: Duration = enter.into();
+        let exit = degrade_p99_exit.map(Into::into).unwrap_or(enter / 2);
+        degrade::spawn_controller(&degradation, degrade_interval.into(), Thresholds { enter, exit })
+    });
+
+    let webhook = webhook_url.map(|url| webhook::spawn(&events, WebhookConfig {
+        url,
+        batch_size: webhook_batch_size as usize,
+        flush_interval: webhook_flush_interval.into(),
+        retries: webhook_retries as u32,
+    }));
+
+    let hot_keys = Arc::new(HotKeys::new(hot_keys as usize));
+    let access_log_keys = KeyLogging::new(&access_log_keys, access_log_key_length as usize)
+        .map_err(|err| Error::new(InvalidInput, err))?;
+    let compression = CompressionFilter::new(compress, compress_min_size, &compress_content_types);
+    let cors = CorsConfig::new(
+        &cors_allowed_origins, &cors_allowed_methods, &cors_allowed_headers,
+    );
+
+    let app = move || {
+        let compression = Rc::new(compression.clone());
+        let acl = acl.clone();
+        let refusing = read_only.clone();
+        let audit = audit.clone();
+        let degradation = degradation.clone();
+        let (shedding, timing) = (degradation.clone(), degradation.clone());
+        let latencies = metrics.clone();
+        let hot = hot_keys.clone();
+        let admin_listeners = admin_listeners.clone();
 
-    let mut builder = HttpServer::new(move ||
         App::new()
+        .app_data(Data::from(degradation))
+        .app_data(Data::from(metrics.clone()))
+        .app_data(Data::from(store.clone()))
+        .app_data(Data::from(acl.clone()))
+        .app_data(Data::from(read_only.clone()))
+        .app_data(Data::from(hot_keys.clone()))
+        // must be registered before the api scope, which takes every path
+        .service(degrade::status)
+        .service(metrics::export)
+        .service(tenants::tenants_usage)
+        .service(readonly::status)
+        .service(readonly::toggle)
+        .service(hotkeys::report)
+        .configure(|cfg| {
+            if swagger_ui {
+                cfg.service(api::swagger_ui);
+            }
+        })
         .service(service_factory())
-        .wrap(Logger::default())
-    );
+        .wrap_fn(move |req, srv| {
+            let compression = compression.clone();
+            let degraded = shedding.is_degraded();
+            let res = srv.call(req);
+            async move {
+                let mut res = res.await?;
+                match degraded {
+                    true => {
+                        CompressionFilter::skip(&mut res);
+                        Ok(res)
+                    },
+                    false => Ok(compression.apply(res)),
+                }
+            }
+        })
+        .wrap(KeyCheck { rules: key_rules.clone(), body_limit: json_limit as usize })
+        // within the acl check, which tells the tenant
+        .wrap(Quotas { body_limit: json_limit as usize })
+        .wrap(AclCheck(acl))
+        .wrap_fn(move |req, srv| {
+            match is_replica && !auth::is_read(req.method(), auth::routed_path(&req)) {
+                false => Either::Left(srv.call(req)),
+                true => {
+                    let denied = error_response(StatusCode::FORBIDDEN, "replica is read only");
+                    Either::Right(ready(Ok(req.into_response(denied))))
+                },
+            }
+        })
+        .wrap(ReadOnlyCheck(refusing))
+        .wrap_fn(move |req, srv| {
+            let local = req.app_config().local_addr();
+            match !admin_listeners.is_empty() && auth::is_admin(auth::routed_path(&req)) && !admin_listeners.contains(&local) {
+                false => Either::Left(srv.call(req)),
+                true => {
+                    let hidden = error_response(StatusCode::NOT_FOUND, "admin endpoints are served on admin_addr");
+                    Either::Right(ready(Ok(req.into_response(hidden))))
+                },
+            }
+        })
+        .wrap_fn(move |req, srv| {
+            let pending = audit.as_ref()
+                .filter(|_| !timing.is_degraded())
+                .and_then(|audit| audit.begin(&req));
+            let timing = timing.clone();
+            let latencies = latencies.clone();
+            let hot = hot.clone();
+            let op = req.path().rsplit('/').next().unwrap_or_default().to_owned();
+            let started = Instant::now();
+            let res = srv.call(req);
+            async move {
+                let res = res.await?;
+                if let Some(pending) = pending {
+                    pending.finish(&res);
+                }
+                let elapsed = started.elapsed();
+                timing.record(elapsed);
+                latencies.observe_request(&op, elapsed);
+                if let Some(key) = res.response().extensions().get::<AuditKey>() {
+                    hot.observe(key.key());
+                }
+                Ok(res)
+            }
+        })
+        // wrapped even if disabled, as it changes the body type, `compression` marks what it leaves as is
+        .wrap(Compress::default())
+        .wrap(Condition::new(cors.is_enabled(), cors.build()))
+        .wrap_fn(move |req, srv| {
+            telemetry::continue_trace(&req);
+            let access = Access::begin(&req, access_log_keys);
+            let res = srv.call(req);
+            async move {
+                let mut res = res.await?;
+                access.finish(&mut res);
+                Ok(res)
+            }
+        })
+        // outermost, so everything below runs within the request span
+        .wrap(TracingLogger)
+    };
 
+    let workers = workers.map(|workers| workers as usize);
+    let h2c = match h2c_addr {
+        Some(addr) => Some(h2c::start(net::TcpListener::bind(addr)?, workers, limits, app.clone())?),
+        None => None,
+    };
+    let mut builder = HttpServer::new(app)
+        .max_connections(limits.max_connections)
+        .client_timeout(limits.client_request_timeout.as_millis() as u64)
+        .client_shutdown(limits.client_shutdown_timeout.as_millis() as u64)
+        .keep_alive(limits.keep_alive_policy())
+        .shutdown_timeout(limits.shutdown_timeout.as_secs());
     if let Some(workers) = workers {
-        builder = builder.workers(workers as usize);
+        builder = builder.workers(workers);
+    }
+
+    for listener in listeners {
+        builder = builder.listen(listener)?;
+    }
+
+    let server = builder.run();
+    let handover = match &handover_socket {
+        Some(path) => Some(handover::listen(path, server.clone())?),
+        None => None,
+    };
+    systemd::notify("READY=1");
+    let result = server.await;
+    systemd::notify("STOPPING=1");
+
+    if let Some(h2c) = h2c {
+        h2c.stop(true).await;
+    }
+    if let Some(handover) = handover {
+        match handover.finish(&handed_over, &namespaces).await {
+            Ok(0) => (),
+            Ok(sent) => info!("{} keys handed over", sent),
+            Err(err) => warn!("handover failed: {}", err),
+        }
+    }
+
+    if let Some(watchdog) = watchdog {
+        watchdog.abort();
+    }
+    gc.abort();
+    tenant_usage.abort();
+    namespaces.shutdown();
+    if let Some(replication) = replication {
+        replication.abort();
+    }
+    if let Some(replica) = replica {
+        replica.abort();
+    }
+    if let Some(udp) = udp {
+        udp.abort();
+    }
+    if let Some(controller) = controller {
+        controller.abort();
+    }
+    if let Some(webhook) = webhook {
+        webhook.abort();
+    }
+    if let Some(telemetry) = telemetry {
+        telemetry.shutdown();
+    }
+
+    result
+}
+
+/// Binds every address `addr` resolves to. IPv6 listeners don't take IPv4 connections,
+/// so `0.0.0.0` and `[::]` can share a port. `reuse_port` lets a restarted server bind
+/// while the old one is still serving.
+fn listen(addr: &str, reuse_port: bool) -> Result<Vec<net::TcpListener>> {
+    addr.to_socket_addrs()?
+        .map(|addr| {
+            let domain = match addr {
+                SocketAddr::V4(_) => Domain::ipv4(),
+                SocketAddr::V6(_) => Domain::ipv6(),
+            };
+            let socket = Socket::new(domain, Type::stream(), Some(Protocol::tcp()))?;
+            if addr.is_ipv6() {
+                socket.set_only_v6(true)?;
+            }
+            socket.set_reuse_address(true)?;
+            socket.set_reuse_port(reuse_port)?;
+            socket.bind(&addr.into())?;
+            socket.listen(BACKLOG)?;
+            Ok(socket.into_tcp_listener())
+        })
+        .collect()
+}
+
+/// Drives the server at `MEMCACHED_BENCH_URL`, or a store of its own, and prints the report.
+async fn bench() -> Result<()> {
+    let settings = BenchSettings::new()
+        .map_err(|err| Error::new(InvalidInput, err))?;
+    let report = bench::run(&settings).await
+        .map_err(|err| Error::new(InvalidInput, err))?;
+    print!("{}", report);
+    Ok(())
+}
+
+/// Prints effective configuration, fails if it is not valid.
+fn check_config() -> Result<()> {
+    let settings = Settings::new()
+        .map_err(|err| Error::new(InvalidInput, err))?;
+    println!("{}", serde_json::to_string_pretty(&settings)?);
+
+    settings.validate()
+        .map_err(|err| Error::new(InvalidInput, err))?;
+    if let Some(path) = &settings.bootstrap_file {
+        Bootstrap::new(path.clone()).validate()
+            .map_err(|err| Error::new(InvalidInput, err))?;
     }
 
-    builder.bind(addr)?
-    .run()
-    .await
+    println!("config is valid");
+    Ok(())
 }
@@ -1,262 +1,348 @@
 use std::{
-    slice, str, mem::take,
-    collections::{HashMap, BTreeMap},
-    time::{Instant, Duration}
+    future::Future,
+    ops::{Deref, DerefMut},
+    panic::Location,
+    sync::{
+        Arc, Mutex, Weak,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Instant, Duration},
+};
+use actix_web::{rt, web};
+use futures::{
+    executor,
+    future::{abortable, AbortHandle},
+};
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use rand::Rng;
+use tracing::{debug, instrument, Span, field::Empty};
+
+pub use memcached_core::{
+    Clock, Timestamp, GcBudget, Options, KeyHasher, KeyRules, KeyError, SlabConfig, TtlJitter, Watermarks, Freshness,
+    Event, EventKind, Listener, ColdTier, SetError, IncrError, End, CollectionError,
+    CounterError, CounterOptions, Overflow, Usage, Dump, Contents, Priority, KeyFilter,
+    Buckets, TTL_BOUNDS, SIZE_BOUNDS, ClassStats, GcReport,
 };
-use log::debug;
 
-struct Item {
-    touch: Instant,
-    ttl: Option<Instant>,
-    data: Vec<u8>,
-}
+use crate::{
+    jobs::Job,
+    audit::key_hash,
+    slowlog::SlowLog,
+    metrics::Metrics,
+};
 
-pub struct SetError(String, Vec<u8>);
+/// keys deleted per write lock acquisition while flushing
+const FLUSH_CHUNK: usize = 1024;
 
-impl SetError {
-    pub fn into_kv(self) -> (String, Vec<u8>) { (self.0, self.1) }
-}
+/// fraction of gc interval by which every cycle start is randomly shifted,
+/// so cycles don't align with periodic request spikes
+const GC_JITTER: f64 = 0.1;
 
-#[derive(Default)]
-pub struct Memcached {
-    limit: usize,
-    current_size: usize,
-    cache: HashMap<String, Item>,
-    keys_by_ttl: BTreeMap<Instant, Vec<&'static str>>,
-    keys_by_touch: BTreeMap<Instant, Vec<&'static str>>,
-}
+/// Store wired to the real monotonic clock.
+pub type Memcached = memcached_core::Memcached<StdClock>;
 
-impl Memcached {
-    pub fn new(limit: usize) -> Memcached {
-        Memcached { limit, ..Default::default() }
-    }
+pub fn new(limit: usize, clock: StdClock, options: Options) -> Memcached {
+    Memcached::with_options(limit, clock, options)
+}
 
-    pub fn delete(&mut self, key: &str) -> Option<Vec<u8>> {
-        let (_key_owned, item) = self.cache.remove_entry(key)?;
+/// Store shared between workers. Its lock is async, so a handler waiting
+/// for a contended store yields the worker thread instead of blocking it.
+pub struct Store {
+    mc: RwLock<Memcached>,
+    /// answers definite misses without the lock
+    filter: Option<Arc<KeyFilter>>,
+    slowlog: Option<Arc<SlowLog>>,
+    metrics: Option<Arc<Metrics>>,
+    /// end of the last gc sweep, creation of the store before the first one
+    last_gc: Mutex<Instant>,
+}
 
-        self.remove_from_touch(key, item.touch);
-        self.remove_from_ttl(key, item.ttl);
-        self.current_size -= item.data.len();
+pub type ReadGuard<'a> = Locked<'a, RwLockReadGuard<'a, Memcached>>;
+pub type WriteGuard<'a> = Locked<'a, RwLockWriteGuard<'a, Memcached>>;
 
-        Some(item.data)
+impl Store {
+    pub fn new(mc: Memcached) -> Store {
+        let filter = mc.key_filter();
+        Store { mc: RwLock::new(mc), filter, slowlog: None, metrics: None, last_gc: Mutex::new(Instant::now()) }
     }
 
-    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
-        let item = self.cache.get(key)?;
-
-        if let Some(ttl) = item.ttl {
-            if ttl < Instant::now() {
-                return None
-            }
-        }
-
-        Some(item.data.clone())
+    /// Reports locks held or waited for too long to `slowlog`.
+    pub fn with_slowlog(mut self, slowlog: Arc<SlowLog>) -> Store {
+        self.slowlog = Some(slowlog);
+        self
     }
 
-    pub fn set(&mut self, key: String, data: Vec<u8>, ttl: Option<Duration>) -> Result<(), SetError> {
-        let not_enough_space = |mc: &Self| (mc.current_size + data.len()) > mc.limit;
+    /// Reports lock waits to the latency histograms of `metrics`.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Store {
+        self.metrics = Some(metrics);
+        self
+    }
 
-        if not_enough_space(self) {
-            self.collect_garbage()
-        }
+    /// time since gc last finished a sweep, stays growing if it is stuck on the lock
+    pub fn since_gc(&self) -> Duration {
+        self.last_gc.lock().unwrap().elapsed()
+    }
 
-        while not_enough_space(self) && self.remove_oldest() {
-            debug!("oldest key displaced: current size {}", self.current_size);
+    /// Waiting for the lock is recorded as `lock_wait_us` of the current span, if it has such field.
+    #[track_caller]
+    pub fn read(&self) -> impl Future<Output = ReadGuard<'_>> {
+        let site = Location::caller();
+        async move {
+            let started = Instant::now();
+            let guard = self.mc.read().await;
+            let waited = started.elapsed();
+            Span::current().record("lock_wait_us", &(waited.as_micros() as u64));
+            self.locked("read", site, waited, guard)
         }
+    }
 
-        if not_enough_space(self) {
-            return Ok(())
+    #[track_caller]
+    pub fn write(&self) -> impl Future<Output = WriteGuard<'_>> {
+        let site = Location::caller();
+        async move {
+            let started = Instant::now();
+            let guard = self.mc.write().await;
+            let waited = started.elapsed();
+            Span::current().record("lock_wait_us", &(waited.as_micros() as u64));
+            self.locked("write", site, waited, guard)
         }
+    }
 
-        self.delete(&key);
-
-        let touch = Instant::now();
-        let ttl = ttl.map(|ttl| touch + ttl);
-
-        let key_owned = key;
-        let key = unsafe { as_str_unsafe(&key_owned) };
+    /// blocks the thread, meant for jobs, gc sweeps, metrics collection and startup
+    #[track_caller]
+    pub fn blocking_read(&self) -> ReadGuard<'_> {
+        let site = Location::caller();
+        let started = Instant::now();
+        let guard = executor::block_on(self.mc.read());
+        self.locked("read", site, started.elapsed(), guard)
+    }
 
-        let mut new_keys_by_touch = self.keys_by_touch
-            .remove(&touch).unwrap_or_else(|| Vec::with_capacity(1));
-        new_keys_by_touch.push(key);
-        self.keys_by_touch.insert(touch, new_keys_by_touch);
+    /// like `blocking_read`, never call it from a handler
+    #[track_caller]
+    pub fn blocking_write(&self) -> WriteGuard<'_> {
+        let site = Location::caller();
+        let started = Instant::now();
+        let guard = executor::block_on(self.mc.write());
+        self.locked("write", site, started.elapsed(), guard)
+    }
 
+    /// false if the key is definitely missing, checked without taking the lock
+    pub fn may_contain(&self, key: &str) -> bool {
+        self.filter.as_ref().is_none_or(|filter| filter.may_contain(key))
+    }
 
-        if let Some(ttl) = ttl {
-            let mut new_keys_by_ttl = self.keys_by_ttl
-                .remove(&ttl).unwrap_or_else(|| Vec::with_capacity(1));
-            new_keys_by_ttl.push(key);
-            self.keys_by_ttl.insert(ttl, new_keys_by_ttl);
+    fn locked<G>(&self, op: &'static str, site: &'static Location<'static>, waited: Duration, guard: G) -> Locked<'_, G> {
+        if let Some(metrics) = &self.metrics {
+            metrics.observe_lock_wait(op, waited);
         }
+        Locked { guard, slowlog: self.slowlog.as_deref(), op, site, waited, acquired: Instant::now() }
+    }
 
-        self.current_size += data.len();
+    #[instrument(level = "debug", skip(self, key), fields(key_hash = key_hash(key), value_size = Empty, lock_wait_us = Empty))]
+    pub async fn get(&self, key: &str) -> Option<Vec<u8>> {
+        if !self.may_contain(key) {
+            return None
+        }
+        let data = self.read().await.get(key);
+        Span::current().record("value_size", &data.as_ref().map_or(0, Vec::len));
+        data
+    }
 
-        self.cache.insert(key_owned, Item { touch, ttl, data });
+    #[instrument(level = "debug", skip(self, key, data, ttl), fields(key_hash = key_hash(&key), value_size = data.len(), lock_wait_us = Empty))]
+    pub async fn set(&self, key: String, data: Vec<u8>, ttl: Option<Duration>) -> Result<(), SetError> {
+        self.write().await.set(key, data, ttl)
+    }
 
-        Ok(())
+    #[instrument(level = "debug", skip(self, key), fields(key_hash = key_hash(key), value_size = Empty, lock_wait_us = Empty))]
+    pub async fn delete(&self, key: &str) -> Option<Vec<u8>> {
+        let data = self.write().await.delete(key);
+        Span::current().record("value_size", &data.as_ref().map_or(0, Vec::len));
+        data
     }
+}
 
-    pub fn collect_garbage(&mut self) {
-        let now = Instant::now();
-        let keys_sets: Vec<(Instant, Vec<&str>)> = self.keys_by_ttl
-            .iter_mut()
-            .take_while(|(&ttl, _)| ttl < now)
-            .map(|(&ttl, v)| { (ttl, take(v)) })
-            .collect();
+/// Lock guard of a store, reports itself to the slow log once released.
+pub struct Locked<'a, G> {
+    guard: G,
+    slowlog: Option<&'a SlowLog>,
+    op: &'static str,
+    site: &'static Location<'static>,
+    waited: Duration,
+    acquired: Instant,
+}
 
-        let mut memory_retrieved = self.current_size;
-        keys_sets.iter().for_each(|(ttl, keys)| keys.iter().for_each(|&key| {
-            let (_key_owned, item) = self.cache.remove_entry(key).unwrap();
+impl<G: Deref<Target = Memcached>> Deref for Locked<'_, G> {
+    type Target = Memcached;
 
-            self.remove_from_touch(key, item.touch);
-            self.keys_by_ttl.remove(ttl);
+    fn deref(&self) -> &Memcached {
+        &self.guard
+    }
+}
 
-            self.current_size -= item.data.len();
-        }));
+impl<G: DerefMut<Target = Memcached>> DerefMut for Locked<'_, G> {
+    fn deref_mut(&mut self) -> &mut Memcached {
+        &mut self.guard
+    }
+}
 
-        memory_retrieved -= self.current_size;
-        if memory_retrieved != 0 {
-            debug!("gc retrieved {}B in {:?}", memory_retrieved, now.elapsed());
+impl<G> Drop for Locked<'_, G> {
+    fn drop(&mut self) {
+        if let Some(slowlog) = self.slowlog {
+            slowlog.record(self.op, Some(self.site), self.waited, self.acquired.elapsed());
         }
     }
 }
 
-impl Memcached {
-    fn remove_from_ttl(&mut self, key: &str, ttl: Option<Instant>) {
-        if let Some(ttl) = ttl {
-            let mut keys = self.keys_by_ttl.remove(&ttl).unwrap();
-            keys.retain(|&k| k != key);
-            if !keys.is_empty() {
-                self.keys_by_ttl.insert(ttl, keys);
-            }
+/// Runs gc for the store about every `interval` until the store is dropped or task is aborted.
+/// If usage is above high watermark afterwards, oldest items are evicted down to low watermark.
+/// Each cycle is split into chunks limited by `budget`, releasing the lock in between.
+pub fn spawn_gc(mc: &Arc<Store>, interval: Duration, budget: GcBudget) -> AbortHandle {
+    let (task, handle) = abortable(gc(Arc::downgrade(mc), interval, budget));
+    rt::spawn(async move {
+        let _ = task.await;
+    });
+    handle
+}
+
+async fn gc(mc: Weak<Store>, interval: Duration, budget: GcBudget) {
+    loop {
+        rt::time::delay_for(jittered(interval)).await;
+        if !sweep(&mc, "gc", move |mc| mc.collect_garbage_step(&budget)).await {
+            return
         }
-    }
 
-    fn remove_from_touch(&mut self, key: &str, touch: Instant) {
-        let mut keys = self.keys_by_touch.remove(&touch).unwrap();
-        keys.retain(|&k| k != key);
-        if !keys.is_empty() {
-            self.keys_by_touch.insert(touch, keys);
+        let above_watermark = match mc.upgrade() {
+            Some(mc) => {
+                *mc.last_gc.lock().unwrap() = Instant::now();
+                mc.read().await.is_above_high_watermark()
+            },
+            None => return,
+        };
+        if above_watermark && !sweep(&mc, "evict", move |mc| mc.evict_step(&budget)).await {
+            return
         }
     }
+}
 
-    fn remove_oldest(&mut self) -> bool {
-        let key = match self.keys_by_touch.iter().next() {
-            Some((_, keys)) => keys.get(0).map(|&s| s)
-                .expect("empty vec in keys_by_touch (impossibre)"),
+/// Runs `step` chunk by chunk until it reports it is done.
+/// Returns false if the store is dropped meanwhile.
+/// Whole sweep is a single slow log entry, its chunks are not reported on their own.
+#[instrument(level = "debug", skip(mc, step))]
+async fn sweep<F>(mc: &Weak<Store>, kind: &'static str, step: F) -> bool
+where F: Fn(&mut Memcached) -> bool + Copy + Send + 'static {
+    let started = Instant::now();
+    let mut steps: u64 = 0;
+    let (mut waited, mut worked) = (Duration::default(), Duration::default());
+    loop {
+        steps += 1;
+        let mc = match mc.upgrade() {
+            Some(mc) => mc,
             None => return false,
         };
 
-        self.delete(&key).is_some()
+        // sweep holds the write lock, so it must not block the worker
+        let swept = mc.clone();
+        let done = web::block(move || {
+            let started = Instant::now();
+            let mut guard = executor::block_on(swept.mc.write());
+            let acquired = Instant::now();
+            let done = step(&mut guard);
+            Ok::<_, ()>((done, acquired - started, acquired.elapsed()))
+        }).await;
+
+        match done {
+            Ok((done, chunk_waited, chunk_worked)) => {
+                if let Some(metrics) = &mc.metrics {
+                    metrics.observe_lock_wait("write", chunk_waited);
+                }
+                waited += chunk_waited;
+                worked += chunk_worked;
+                if !done {
+                    continue
+                }
+                debug!(steps, elapsed_us = started.elapsed().as_micros() as u64, "sweep finished");
+                if let Some(slowlog) = &mc.slowlog {
+                    slowlog.record(kind, None, waited, worked);
+                }
+                return true
+            },
+            Err(_) => return false,
+        }
     }
 }
 
-
-unsafe fn as_str_unsafe(s: &String) -> &'static str {
-    str::from_utf8_unchecked(
-        slice::from_raw_parts(s.as_ptr(), s.len())
-    )
-}
-
-
-#[cfg(test)]
-mod public_tests {
-    use super::*;
-    use std::thread::sleep;
-
-    #[test]
-    fn set_get_ok() {
-        let mut mc = Memcached::new(300);
-        let _ = mc.set("a".to_owned(), "a".as_bytes().to_owned(), None);
-        assert_eq!(mc.get("a"), Some("a".into()));
+/// Collects garbage once within `budget` on demand, without waiting for the next gc cycle.
+/// Returns what was collected with time spent on it, not counting the wait for the lock.
+pub async fn collect_garbage(mc: Arc<Store>, budget: GcBudget) -> Option<(GcReport, Duration)> {
+    let swept = mc.clone();
+    let (report, waited, worked) = web::block(move || {
+        let started = Instant::now();
+        let mut guard = executor::block_on(swept.mc.write());
+        let acquired = Instant::now();
+        let report = guard.sweep(&budget);
+        Ok::<_, ()>((report, acquired - started, acquired.elapsed()))
+    }).await.ok()?;
+
+    if let Some(metrics) = &mc.metrics {
+        metrics.observe_lock_wait("write", waited);
     }
-
-    #[test]
-    fn get_none() {
-        let mc = Memcached::new(300);
-        assert_eq!(mc.get("b"), None);
+    if let Some(slowlog) = &mc.slowlog {
+        slowlog.record("gc", None, waited, worked);
     }
-
-    #[test]
-    fn displace_oldest() {
-        let mut mc = Memcached::new(3);
-        let _ = mc.set("a".to_owned(), "a".as_bytes().to_owned(), None);
-        let _ = mc.set("b".to_owned(), "a".as_bytes().to_owned(), None);
-        let _ = mc.set("c".to_owned(), "a".as_bytes().to_owned(), None);
-        let _ = mc.set("d".to_owned(), "a".as_bytes().to_owned(), None);
-
-        assert_eq!(mc.get("a"), None);
-        assert_eq!(mc.get("b"), Some("a".into()));
-        assert_eq!(mc.get("c"), Some("a".into()));
-        assert_eq!(mc.get("d"), Some("a".into()));
+    if report.done {
+        *mc.last_gc.lock().unwrap() = Instant::now();
     }
+    Some((report, worked))
+}
 
-    #[test]
-    fn expire() {
-        let mut mc = Memcached::new(300);
-        let _ = mc.set("a".to_owned(), "a".as_bytes().to_owned(), Some(Duration::from_millis(100)));
-        assert_eq!(mc.get("a"), Some("a".into()));
+fn jittered(interval: Duration) -> Duration {
+    interval.mul_f64(rand::thread_rng().gen_range(1.0 - GC_JITTER..=1.0 + GC_JITTER))
+}
 
-        sleep(Duration::from_millis(200));
+/// Deletes every key present at the moment of the call,
+/// releasing the lock between chunks so requests are served meanwhile.
+pub fn flush(mc: &Store, job: &Job) {
+    let keys: Vec<String> = mc.blocking_read().keys().map(ToOwned::to_owned).collect();
+    job.set_total(keys.len() as u64);
 
-        assert_eq!(mc.get("a"), None);
-    }
+    for chunk in keys.chunks(FLUSH_CHUNK) {
+        if job.is_cancelled() {
+            return
+        }
 
-    #[test]
-    fn overflow() {
-        let mut mc = Memcached::new(1);
-        let _ = mc.set("a".to_owned(), "aa".as_bytes().to_owned(), None);
-        assert_eq!(mc.get("a"), None);
+        let mut mc = mc.blocking_write();
+        for key in chunk {
+            mc.delete(key);
+        }
+        job.advance(chunk.len() as u64);
     }
 }
 
+/// Monotonic clock counting from the moment it was created.
+/// Clones share both the starting point and the offset.
+#[derive(Clone)]
+pub struct StdClock {
+    start: Instant,
+    offset: Arc<AtomicU64>,
+}
 
-#[cfg(test)]
-mod inner_tests {
-    use super::*;
-    use std::thread::sleep;
-
-    /// test validates that pointers to keys are equal in cache, keys_by_touch and keys_by_ttl
-    #[test]
-    fn valid_pointers() {
-        let mut mc = Memcached::new(300);
-        let _ = mc.set("a".to_owned(), "a".as_bytes().to_owned(), Some(Duration::from_secs(300)));
-
-        let (key, v) = mc.cache.get_key_value("a").unwrap();
-        let key_ttl = mc.keys_by_ttl[&v.ttl.unwrap()][0];
-        let key_touch = mc.keys_by_touch[&v.touch][0];
-        assert_eq!(key.as_ptr(), key_ttl.as_ptr());
-        assert_eq!(key.as_ptr(), key_touch.as_ptr());
+impl StdClock {
+    pub fn new() -> StdClock {
+        StdClock { start: Instant::now(), offset: Default::default() }
     }
 
-    #[test]
-    fn expire_without_gc() {
-        let mut mc = Memcached::new(300);
-        let _ = mc.set("a".to_owned(), "a".as_bytes().to_owned(), Some(Duration::from_millis(100)));
-        assert_eq!(mc.get("a"), Some("a".into()));
-
-        sleep(Duration::from_millis(200));
-
-        assert_eq!(mc.get("a"), None);
-        assert_eq!(mc.current_size, 1);
-        assert_eq!(mc.cache.len(), 1);
-        assert_eq!(mc.keys_by_ttl.len(), 1);
-        assert_eq!(mc.keys_by_touch.len(), 1);
+    /// Moves time forward for this clock and its clones, meant for tests.
+    pub fn advance(&self, by: Duration) {
+        self.offset.fetch_add(by.as_nanos() as u64, Ordering::SeqCst);
     }
+}
 
-    #[test]
-    fn expire_with_gc() {
-        let mut mc = Memcached::new(300);
-        let _ = mc.set("a".to_owned(), "a".as_bytes().to_owned(), Some(Duration::from_millis(100)));
-        assert_eq!(mc.get("a"), Some("a".into()));
-
-        sleep(Duration::from_millis(200));
-        mc.collect_garbage();
+impl Default for StdClock {
+    fn default() -> StdClock { StdClock::new() }
+}
 
-        assert_eq!(mc.get("a"), None);
-        assert_eq!(mc.current_size, 0);
-        assert_eq!(mc.cache.len(), 0);
-        assert_eq!(mc.keys_by_ttl.len(), 0);
-        assert_eq!(mc.keys_by_touch.len(), 0);
+impl Clock for StdClock {
+    fn now(&self) -> Timestamp {
+        self.start.elapsed() + Duration::from_nanos(self.offset.load(Ordering::Relaxed))
     }
 }
@@ -0,0 +1,164 @@
+use actix_web::{get, HttpResponse, web::Data};
+use std::{
+    fmt::Write,
+    sync::{
+        RwLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use crate::{
+    auth::Acl,
+    hotkeys::HotKeys,
+    tenants,
+    memcached::{Buckets, Memcached, Store, SIZE_BOUNDS, TTL_BOUNDS},
+};
+
+/// handlers with a latency histogram, identified by the last path segment
+const OPS: [&str; 3] = ["get", "set", "delete"];
+const LOCKS: [&str; 2] = ["read", "write"];
+
+struct Histogram {
+    /// per bucket, the last one is above every bound
+    counts: Vec<AtomicU64>,
+    sum_us: AtomicU64,
+}
+
+impl Histogram {
+    fn new(buckets: usize) -> Histogram {
+        Histogram {
+            counts: (0..=buckets).map(|_| AtomicU64::new(0)).collect(),
+            sum_us: AtomicU64::new(0),
+        }
+    }
+}
+
+/// Latency histograms of key handlers and store lock waits, exported in Prometheus
+/// text format next to counters and histograms of stored ttls and value sizes of the default store.
+/// Items by time left and size at the moment are served by `/stats/distribution` instead. Averages
+/// hide the slow mode of a bimodal latency, e.g. requests waiting for gc to release the write lock.
+pub struct Metrics {
+    /// ascending upper bounds of buckets
+    bounds: Vec<Duration>,
+    requests: Vec<Histogram>,
+    lock_waits: Vec<Histogram>,
+}
+
+impl Metrics {
+    pub fn new(bounds: Vec<Duration>) -> Metrics {
+        Metrics {
+            requests: OPS.iter().map(|_| Histogram::new(bounds.len())).collect(),
+            lock_waits: LOCKS.iter().map(|_| Histogram::new(bounds.len())).collect(),
+            bounds,
+        }
+    }
+
+    /// requests other than get, set and delete are ignored
+    pub fn observe_request(&self, op: &str, latency: Duration) {
+        if let Some(i) = OPS.iter().position(|known| *known == op) {
+            self.observe(&self.requests[i], latency);
+        }
+    }
+
+    /// `lock` is either `read` or `write`
+    pub fn observe_lock_wait(&self, lock: &str, wait: Duration) {
+        if let Some(i) = LOCKS.iter().position(|known| *known == lock) {
+            self.observe(&self.lock_waits[i], wait);
+        }
+    }
+
+    fn observe(&self, histogram: &Histogram, latency: Duration) {
+        let bucket = self.bounds.partition_point(|bound| *bound < latency);
+        histogram.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        histogram.sum_us.fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn render(&self, mc: &Memcached) -> String {
+        let mut out = String::new();
+        let stats = mc.stats();
+        let gauges = [
+            ("memcached_items", "items in the default store", mc.len() as u64),
+            ("memcached_used_bytes", "bytes used by the default store", mc.size() as u64),
+            ("memcached_limit_bytes", "memory limit of the default store", mc.limit() as u64),
+            ("memcached_reserved_bytes", "bytes allocated for values of the default store", mc.reserved() as u64),
+        ];
+        for (name, help, value) in gauges.iter() {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} gauge\n{} {}", name, help, name, name, value);
+        }
+        let counters = [
+            ("memcached_written_bytes_total", "bytes stored by sets", stats.written_bytes),
+            ("memcached_freed_bytes_total", "bytes released by deletes, overwrites and expiry", stats.freed_bytes),
+            ("memcached_evicted_bytes_total", "bytes displaced to make room", stats.evicted_bytes),
+        ];
+        for (name, help, value) in counters.iter() {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter\n{} {}", name, help, name, name, value);
+        }
+
+        // kept up to date by every set, unlike a walk over the items which scrapes would repeat
+        let ttl_bounds: Vec<String> = TTL_BOUNDS.iter().map(|bound| bound.as_secs_f64().to_string()).collect();
+        let size_bounds: Vec<String> = SIZE_BOUNDS.iter().map(ToString::to_string).collect();
+        render_buckets(
+            &mut out, "memcached_stored_ttl_seconds", "ttls values were stored with",
+            &ttl_bounds, &stats.ttls, stats.ttl_sum.as_secs_f64(),
+        );
+        render_buckets(
+            &mut out, "memcached_stored_value_bytes", "sizes of values stored by sets",
+            &size_bounds, &stats.sizes, stats.size_sum as f64,
+        );
+
+        self.render_histograms(
+            &mut out, "memcached_request_duration_seconds", "latency of key handlers",
+            "op", &OPS, &self.requests,
+        );
+        self.render_histograms(
+            &mut out, "memcached_lock_wait_seconds", "time spent waiting for a store lock",
+            "lock", &LOCKS, &self.lock_waits,
+        );
+        out
+    }
+
+    fn render_histograms(
+        &self, out: &mut String, name: &str, help: &str,
+        label: &str, values: &[&str], histograms: &[Histogram],
+    ) {
+        let _ = writeln!(out, "# HELP {} {}\n# TYPE {} histogram", name, help, name);
+        for (value, histogram) in values.iter().zip(histograms) {
+            let mut cumulative = 0;
+            for (i, count) in histogram.counts.iter().enumerate() {
+                cumulative += count.load(Ordering::Relaxed);
+                let le = match self.bounds.get(i) {
+                    Some(bound) => bound.as_secs_f64().to_string(),
+                    None => "+Inf".to_owned(),
+                };
+                let _ = writeln!(out, "{}_bucket{{{}=\"{}\",le=\"{}\"}} {}", name, label, value, le, cumulative);
+            }
+            let sum = histogram.sum_us.load(Ordering::Relaxed) as f64 / 1e6;
+            let _ = writeln!(out, "{}_sum{{{}=\"{}\"}} {}", name, label, value, sum);
+            let _ = writeln!(out, "{}_count{{{}=\"{}\"}} {}", name, label, value, cumulative);
+        }
+    }
+}
+
+fn render_buckets(out: &mut String, name: &str, help: &str, bounds: &[String], counts: &Buckets, sum: f64) {
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} histogram", name, help, name);
+    let mut cumulative = 0;
+    for (i, count) in counts.iter().enumerate() {
+        cumulative += count;
+        let le = bounds.get(i).map_or("+Inf", String::as_str);
+        let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, le, cumulative);
+    }
+    let _ = writeln!(out, "{}_sum {}\n{}_count {}", name, sum, name, cumulative);
+}
+
+#[get("/metrics")]
+pub async fn export(
+    metrics: Data<Metrics>, mc: Data<Store>, hot_keys: Data<HotKeys>, acl: Data<RwLock<Acl>>,
+) -> HttpResponse {
+    let mut body = metrics.render(&*mc.read().await);
+    hot_keys.render(&mut body);
+    tenants::render(&acl.read().unwrap(), &mut body);
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(body)
+}
@@ -0,0 +1,115 @@
+use std::{
+    collections::HashMap,
+    sync::{RwLock, Arc},
+    time::Duration,
+};
+use futures::future::AbortHandle;
+
+use crate::{
+    events::EventBus,
+    origin::{Origin, OriginConfig},
+    memcached::{self, Store, GcBudget, Options, StdClock},
+    slowlog::SlowLog,
+    metrics::Metrics,
+};
+
+struct Namespace {
+    mc: Arc<Store>,
+    gc: AbortHandle,
+    origin: Option<Arc<Origin>>,
+}
+
+/// Named stores living next to the default one, each with its own limit and gc.
+pub struct Namespaces {
+    clock: StdClock,
+    gc_interval: Duration,
+    gc_budget: GcBudget,
+    options: Options,
+    events: Arc<EventBus>,
+    slowlog: Arc<SlowLog>,
+    metrics: Arc<Metrics>,
+    stores: RwLock<HashMap<String, Namespace>>,
+}
+
+impl Namespaces {
+    pub fn new(
+        clock: StdClock, gc_interval: Duration, gc_budget: GcBudget,
+        options: Options, events: Arc<EventBus>, slowlog: Arc<SlowLog>, metrics: Arc<Metrics>,
+    ) -> Namespaces {
+        Namespaces {
+            clock, gc_interval, gc_budget, options, events, slowlog, metrics,
+            stores: Default::default(),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<Store>> {
+        self.stores.read().unwrap().get(name).map(|ns| ns.mc.clone())
+    }
+
+    /// store of every namespace
+    pub fn stores(&self) -> Vec<Arc<Store>> {
+        self.stores.read().unwrap().values().map(|ns| ns.mc.clone()).collect()
+    }
+
+    /// every namespace with its store
+    pub fn list(&self) -> Vec<(String, Arc<Store>)> {
+        self.stores.read().unwrap().iter().map(|(name, ns)| (name.clone(), ns.mc.clone())).collect()
+    }
+
+    /// read-through backend of the namespace
+    pub fn origin(&self, name: &str) -> Option<Arc<Origin>> {
+        self.stores.read().unwrap().get(name).and_then(|ns| ns.origin.clone())
+    }
+
+    /// returns false if namespace already exists
+    pub fn create(
+        &self, name: String, limit: usize,
+        gc_interval: Option<Duration>, origin: Option<OriginConfig>,
+    ) -> bool {
+        let mut stores = self.stores.write().unwrap();
+        if stores.contains_key(&name) {
+            return false
+        }
+
+        let mut mc = memcached::new(limit, self.clock.clone(), self.options.clone());
+        mc.set_listener(self.events.listener(name.clone()));
+        let mc = Store::new(mc)
+            .with_slowlog(self.slowlog.clone())
+            .with_metrics(self.metrics.clone());
+        let mc = Arc::new(mc);
+        let gc = memcached::spawn_gc(&mc, gc_interval.unwrap_or(self.gc_interval), self.gc_budget);
+        let origin = origin.map(|origin| Arc::new(Origin::new(origin)));
+        stores.insert(name, Namespace { mc, gc, origin });
+
+        true
+    }
+
+    /// Creates namespace or updates memory limit and origin of existing one,
+    /// gc interval is only taken into account on creation.
+    pub async fn ensure(&self, name: String, limit: usize, gc_interval: Option<Duration>, origin: Option<OriginConfig>) {
+        let existing = self.stores.write().unwrap().get_mut(&name).map(|ns| {
+            ns.origin = origin.clone().map(|origin| Arc::new(Origin::new(origin)));
+            ns.mc.clone()
+        });
+        match existing {
+            Some(mc) => mc.write().await.set_limit(limit),
+            None => { self.create(name, limit, gc_interval, origin); },
+        }
+    }
+
+    /// returns false if there is no such namespace
+    pub fn remove(&self, name: &str) -> bool {
+        match self.stores.write().unwrap().remove(name) {
+            Some(ns) => {
+                ns.gc.abort();
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// stops gc of every namespace
+    pub fn shutdown(&self) {
+        self.stores.read().unwrap().values().for_each(|ns| ns.gc.abort());
+    }
+}
@@ -0,0 +1,243 @@
+use serde::Deserialize;
+use actix_web::{rt, client::Client, http::StatusCode};
+use duration_string::DurationString;
+use futures::{
+    StreamExt,
+    channel::{mpsc, oneshot},
+};
+use tracing::{warn, error};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::Mutex,
+    time::Duration,
+};
+
+use crate::errors::Error;
+
+/// writes waiting to be forwarded in write-behind mode, beyond that writes are dropped
+const QUEUE: usize = 4096;
+
+/// delay before the first retry of write-behind, doubled for each next one
+const BACKOFF: Duration = Duration::from_millis(100);
+
+type Fetched = Result<Option<Vec<u8>>, String>;
+
+/// Upstream a store reads missing keys from and optionally forwards writes to.
+#[derive(Deserialize, Clone)]
+pub struct OriginConfig {
+    /// `{key}` is replaced with url encoded key, e.g. `http://origin/items/{key}`
+    pub url: String,
+    /// ttl of fetched values, they don't expire if not set
+    pub ttl: Option<DurationString>,
+    /// how sets and deletes reach the origin, as `PUT` and `DELETE` of the key url
+    #[serde(default)]
+    pub write: WriteMode,
+    /// write-behind attempts after the first failed one before the write is dropped
+    #[serde(default = "default_write_retries")]
+    pub write_retries: u32,
+}
+
+#[derive(Deserialize, Clone, Copy, Default, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WriteMode {
+    /// origin is read only
+    #[default]
+    Off,
+    /// writes are forwarded before responding, failure is reported to the client
+    Through,
+    /// writes are queued and forwarded in background with retries
+    Behind,
+}
+
+impl FromStr for WriteMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<WriteMode, String> {
+        match mode {
+            "off" => Ok(WriteMode::Off),
+            "through" => Ok(WriteMode::Through),
+            "behind" => Ok(WriteMode::Behind),
+            _ => Err(format!("unknown write mode {}, expected off, through or behind", mode)),
+        }
+    }
+}
+
+fn default_write_retries() -> u32 { 3 }
+
+/// Write forwarded to origin.
+pub enum Write {
+    Set { key: String, data: Vec<u8> },
+    Delete { key: String },
+}
+
+/// Read-through backend: fetches are deduplicated, so concurrent misses
+/// of a key wait for a single request to the origin.
+pub struct Origin {
+    config: OriginConfig,
+    /// callers waiting for fetches in flight
+    inflight: Mutex<HashMap<String, Vec<oneshot::Sender<Fetched>>>>,
+    /// queue of the write-behind task
+    writes: Option<Mutex<mpsc::Sender<Write>>>,
+}
+
+/// Removes fetch from inflight even if the leading request is dropped,
+/// so waiters fail instead of hanging.
+struct Leader<'a> {
+    origin: &'a Origin,
+    key: &'a str,
+    done: bool,
+}
+
+impl Leader<'_> {
+    /// takes callers waiting for the result
+    fn finish(mut self) -> Vec<oneshot::Sender<Fetched>> {
+        self.done = true;
+        self.origin.inflight.lock().unwrap().remove(self.key).unwrap_or_default()
+    }
+}
+
+impl Drop for Leader<'_> {
+    fn drop(&mut self) {
+        if !self.done {
+            self.origin.inflight.lock().unwrap().remove(self.key);
+        }
+    }
+}
+
+impl Origin {
+    /// Must be called within actix runtime, write-behind task runs until the origin is dropped.
+    pub fn new(config: OriginConfig) -> Origin {
+        let writes = match config.write {
+            WriteMode::Behind => {
+                let (writes, queue) = mpsc::channel(QUEUE);
+                rt::spawn(write_behind(queue, config.clone()));
+                Some(Mutex::new(writes))
+            },
+            _ => None,
+        };
+        Origin { config, inflight: Default::default(), writes }
+    }
+
+    pub fn ttl(&self) -> Option<Duration> {
+        self.config.ttl.map(Into::into)
+    }
+
+    pub fn takes_writes(&self) -> bool {
+        self.config.write != WriteMode::Off
+    }
+
+    /// Forwards the write according to write mode,
+    /// in write-behind mode only failure to queue it is reported.
+    pub async fn write(&self, write: Write) -> Result<(), Error> {
+        match (&self.writes, self.config.write) {
+            (Some(writes), _) => writes.lock().unwrap().try_send(write).map_err(|err| {
+                let dropped = err.into_inner();
+                error!("write-behind queue is full, {} is dropped", dropped.key());
+                Error::Origin("write-behind queue is full".to_owned())
+            }),
+            (None, WriteMode::Through) => self.config.send(&write).await.map_err(Error::Origin),
+            (None, _) => Ok(()),
+        }
+    }
+
+    /// Returns value of the key, `None` if origin doesn't have it.
+    /// Values above `limit` bytes are an error.
+    pub async fn fetch(&self, key: &str, limit: usize) -> Result<Option<Vec<u8>>, Error> {
+        let waiting = {
+            let mut inflight = self.inflight.lock().unwrap();
+            match inflight.get_mut(key) {
+                Some(waiters) => {
+                    let (sender, receiver) = oneshot::channel();
+                    waiters.push(sender);
+                    Some(receiver)
+                },
+                None => {
+                    inflight.insert(key.to_owned(), Vec::new());
+                    None
+                },
+            }
+        };
+
+        let fetched = match waiting {
+            Some(receiver) => receiver.await
+                .unwrap_or_else(|_| Err("fetch was abandoned".to_owned())),
+            None => {
+                let leader = Leader { origin: self, key, done: false };
+                let fetched = self.request(key, limit).await;
+                for waiter in leader.finish() {
+                    let _ = waiter.send(fetched.clone());
+                }
+                fetched
+            },
+        };
+        fetched.map_err(Error::Origin)
+    }
+
+    async fn request(&self, key: &str, limit: usize) -> Fetched {
+        let url = self.config.url(key);
+        let mut res = Client::default().get(&url).send().await
+            .map_err(|err| format!("{}: {}", url, err))?;
+
+        if res.status().as_u16() == 404 {
+            return Ok(None)
+        }
+        if !res.status().is_success() {
+            return Err(format!("{} responded {}", url, res.status()))
+        }
+        let body = res.body().limit(limit).await
+            .map_err(|err| format!("{}: {}", url, err))?;
+        Ok(Some(body.to_vec()))
+    }
+}
+
+impl OriginConfig {
+    fn url(&self, key: &str) -> String {
+        let key = utf8_percent_encode(key, NON_ALPHANUMERIC).to_string();
+        self.url.replace("{key}", &key)
+    }
+
+    async fn send(&self, write: &Write) -> Result<(), String> {
+        let url = self.url(write.key());
+        let client = Client::default();
+        let res = match write {
+            Write::Set { data, .. } => client.put(&url).send_body(data.clone()).await,
+            Write::Delete { .. } => client.delete(&url).send().await,
+        };
+        let status = res.map_err(|err| format!("{}: {}", url, err))?.status();
+
+        // deleting what origin doesn't have is fine
+        match (write, status) {
+            (_, status) if status.is_success() => Ok(()),
+            (Write::Delete { .. }, StatusCode::NOT_FOUND) => Ok(()),
+            (_, status) => Err(format!("{} responded {}", url, status)),
+        }
+    }
+}
+
+impl Write {
+    pub fn key(&self) -> &str {
+        match self {
+            Write::Set { key, .. } | Write::Delete { key } => key,
+        }
+    }
+}
+
+async fn write_behind(mut queue: mpsc::Receiver<Write>, config: OriginConfig) {
+    while let Some(write) = queue.next().await {
+        let mut backoff = BACKOFF;
+        let mut attempt = 0;
+        while let Err(err) = config.send(&write).await {
+            if attempt == config.write_retries {
+                // dead letter: the write is lost, the log is the only trace of it
+                error!("write-behind of {} dropped after {} attempts: {}", write.key(), attempt + 1, err);
+                break
+            }
+            warn!("write-behind of {} failed: {}", write.key(), err);
+            rt::time::delay_for(backoff).await;
+            backoff *= 2;
+            attempt += 1;
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use actix_web::{
+    HttpResponse,
+    dev::BodyEncoding,
+    http::ContentEncoding,
+    web::Bytes,
+};
+use futures::{StreamExt, channel::mpsc::{self, Sender}};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, RwLock},
+};
+
+/// messages waiting to be sent to a single subscriber, beyond that messages are dropped
+const QUEUE: usize = 1024;
+
+type Subscriber = Mutex<Sender<Arc<String>>>;
+
+/// Named fan-out channels unrelated to the key space, messages are not stored:
+/// only subscribers connected at the moment of publishing receive them.
+#[derive(Default)]
+pub struct PubSub {
+    channels: RwLock<HashMap<String, Vec<Subscriber>>>,
+}
+
+impl PubSub {
+    /// Returns count of subscribers message was delivered to.
+    pub fn publish(&self, channel: &str, message: String) -> usize {
+        let message = Arc::new(message);
+        let mut delivered = 0;
+        let mut disconnected = false;
+        if let Some(subscribers) = self.channels.read().unwrap().get(channel) {
+            for subscriber in subscribers {
+                match subscriber.lock().unwrap().try_send(message.clone()) {
+                    Ok(_) => delivered += 1,
+                    Err(err) => disconnected |= err.is_disconnected(),
+                }
+            }
+        }
+
+        if disconnected {
+            let mut channels = self.channels.write().unwrap();
+            if let Some(subscribers) = channels.get_mut(channel) {
+                subscribers.retain(|subscriber| !subscriber.lock().unwrap().is_closed());
+                if subscribers.is_empty() {
+                    channels.remove(channel);
+                }
+            }
+        }
+        delivered
+    }
+
+    /// Streams messages of the channel as `text/event-stream` until the client disconnects.
+    pub fn subscribe(&self, channel: String) -> HttpResponse {
+        let (sender, messages) = mpsc::channel(QUEUE);
+        self.channels.write().unwrap()
+            .entry(channel)
+            .or_default()
+            .push(Mutex::new(sender));
+
+        let body = messages.map(|message| {
+            // every line of multiline message needs its own data field
+            let data: String = message.split('\n').map(|line| format!("data: {}\n", line)).collect();
+            Ok::<_, actix_web::Error>(Bytes::from(format!("{}\n", data)))
+        });
+
+        HttpResponse::Ok()
+            .content_type("text/event-stream")
+            // compression would hold messages back until its buffer is full
+            .encoding(ContentEncoding::Identity)
+            .streaming(body)
+    }
+}
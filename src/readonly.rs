@@ -0,0 +1,131 @@
+use serde::{Serialize, Deserialize};
+use actix_web::{
+    get, put, Error, HttpResponse,
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    http::{Method, StatusCode},
+    web::{Data, Json},
+};
+use futures::future::{ok, Either, Ready};
+use tracing::warn;
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    task::{Context, Poll},
+};
+
+use crate::{auth, errors::error_response};
+
+/// path switching the mode, it takes writes while the mode is on so the mode can be switched off
+const TOGGLE_PATH: &str = "/admin/readonly";
+
+/// admin paths which are posted to but leave stores as they are
+const READ_POSTS: &[&str] = &["/admin/digest"];
+
+/// Maintenance mode refusing writes with 503 while reads are still served,
+/// switched at runtime by `PUT /admin/readonly`.
+#[derive(Default)]
+pub struct ReadOnly {
+    enabled: AtomicBool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ReadOnlyStatus {
+    pub enabled: bool,
+}
+
+impl ReadOnly {
+    pub fn new(enabled: bool) -> ReadOnly {
+        ReadOnly { enabled: AtomicBool::new(enabled) }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Returns the previous mode.
+    pub fn set(&self, enabled: bool) -> bool {
+        let previous = self.enabled.swap(enabled, Ordering::Relaxed);
+        if previous != enabled {
+            warn!("read only mode {}", if enabled { "on" } else { "off" });
+        }
+        previous
+    }
+
+    /// Refuses writes while the mode is on, every GET and HEAD is a read.
+    pub fn check(&self, req: &ServiceRequest) -> Result<(), HttpResponse> {
+        let (method, path) = (req.method(), auth::routed_path(req));
+        let read = matches!(*method, Method::GET | Method::HEAD)
+            || auth::is_read(method, path)
+            || READ_POSTS.contains(&path);
+        match self.is_enabled() && path != TOGGLE_PATH && !read {
+            false => Ok(()),
+            true => Err(error_response(StatusCode::SERVICE_UNAVAILABLE, "READONLY store is in read only mode")),
+        }
+    }
+}
+
+/// Middleware answering writes with 503 while the mode is on.
+pub struct ReadOnlyCheck(pub Arc<ReadOnly>);
+
+impl<S> Transform<S> for ReadOnlyCheck
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = ReadOnlyCheckMiddleware<S>;
+    type Future = Ready<Result<ReadOnlyCheckMiddleware<S>, ()>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(ReadOnlyCheckMiddleware { service, read_only: self.0.clone() })
+    }
+}
+
+pub struct ReadOnlyCheckMiddleware<S> {
+    service: S,
+    read_only: Arc<ReadOnly>,
+}
+
+impl<S> Service for ReadOnlyCheckMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Future = Either<S::Future, Ready<Result<ServiceResponse, Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        match self.read_only.check(&req) {
+            Ok(()) => Either::Left(self.service.call(req)),
+            Err(refused) => Either::Right(ok(req.into_response(refused))),
+        }
+    }
+}
+
+#[get("/admin/readonly")]
+pub async fn status(read_only: Data<ReadOnly>) -> HttpResponse {
+    HttpResponse::Ok().json(ReadOnlyStatus { enabled: read_only.is_enabled() })
+}
+
+#[derive(Serialize)]
+struct ToggleResp {
+    enabled: bool,
+    previous: bool,
+}
+
+#[put("/admin/readonly")]
+pub async fn toggle(read_only: Data<ReadOnly>, req: Json<ReadOnlyStatus>) -> HttpResponse {
+    let previous = read_only.set(req.enabled);
+    HttpResponse::Ok().json(ToggleResp { enabled: req.enabled, previous })
+}
@@ -0,0 +1,339 @@
+use actix_web::rt;
+use futures::{
+    StreamExt,
+    channel::mpsc::{channel, Sender},
+    future::{abortable, join_all, AbortHandle},
+};
+use tracing::{info, warn};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+use std::{
+    collections::HashSet,
+    io::{self, ErrorKind},
+    net,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::memcached::{Clock, Event, EventKind, Listener, Priority, Store};
+
+/// delay before reconnecting to a replica, doubled up to `MAX_BACKOFF`
+const BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+const SET: u8 = 1;
+const DELETE: u8 = 2;
+const SYNCED: u8 = 3;
+const SET_NEGATIVE: u8 = 4;
+const PRIORITY: u8 = 5;
+/// ttl of values which never expire
+const NO_TTL: u64 = u64::MAX;
+/// longest key a primary may send, longer frames are taken for garbage and drop the connection
+const MAX_KEY: usize = 64 << 10;
+/// longest secret a primary may introduce itself with
+const MAX_SECRET: usize = 1 << 10;
+
+/// Operation streamed from primary to replicas.
+#[derive(Debug)]
+enum Op {
+    /// ttl is relative, clocks of primary and replica don't share an epoch
+    Set { key: String, data: Vec<u8>, ttl: Option<Duration> },
+    /// known missing marker, it always has a ttl
+    SetNegative { key: String, ttl: Duration },
+    Delete { key: String },
+    /// eviction priority of a value set earlier
+    Priority { key: String, priority: Priority },
+    /// snapshot is over, replica drops keys the snapshot didn't have
+    Synced,
+}
+
+impl Op {
+    /// evictions are not replicated, replica makes room on its own
+    fn of(event: &Event) -> Option<Op> {
+        let key = event.key.to_owned();
+        let ttl = event.ttl.map(|ttl| ttl.checked_sub(event.at).unwrap_or_default());
+        match event.kind {
+            EventKind::Set if event.negative => Some(Op::SetNegative { key, ttl: ttl.unwrap_or_default() }),
+            // collections are not replicated, replica drops whatever it had under the key
+            EventKind::Set if event.collection => Some(Op::Delete { key }),
+            EventKind::Set => Some(Op::Set {
+                key,
+                data: event.data.to_vec(),
+                ttl,
+            }),
+            EventKind::Deleted | EventKind::Expired => Some(Op::Delete { key }),
+            EventKind::Prioritized if event.negative || event.collection => None,
+            EventKind::Prioritized => Some(Op::Priority { key, priority: event.priority }),
+            EventKind::Evicted => None,
+        }
+    }
+
+    /// Frame is the op byte followed by its fields, byte strings are prefixed with u32 length.
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        match self {
+            Op::Set { key, data, ttl } => {
+                buf.push(SET);
+                put_bytes(&mut buf, key.as_bytes());
+                let ttl = ttl.map_or(NO_TTL, |ttl| ttl.as_millis() as u64);
+                buf.extend_from_slice(&ttl.to_be_bytes());
+                put_bytes(&mut buf, data);
+            },
+            Op::SetNegative { key, ttl } => {
+                buf.push(SET_NEGATIVE);
+                put_bytes(&mut buf, key.as_bytes());
+                buf.extend_from_slice(&(ttl.as_millis() as u64).to_be_bytes());
+            },
+            Op::Delete { key } => {
+                buf.push(DELETE);
+                put_bytes(&mut buf, key.as_bytes());
+            },
+            Op::Priority { key, priority } => {
+                buf.push(PRIORITY);
+                put_bytes(&mut buf, key.as_bytes());
+                buf.push(match priority {
+                    Priority::Low => 0,
+                    Priority::Normal => 1,
+                    Priority::High => 2,
+                });
+            },
+            Op::Synced => buf.push(SYNCED),
+        }
+        buf
+    }
+
+    /// `max_value` caps lengths of values, a value larger than the store doesn't fit it anyway
+    async fn read<R: AsyncRead + Unpin>(from: &mut R, max_value: usize) -> io::Result<Op> {
+        match from.read_u8().await? {
+            SET => {
+                let key = read_key(from).await?;
+                let ttl = match from.read_u64().await? {
+                    NO_TTL => None,
+                    millis => Some(Duration::from_millis(millis)),
+                };
+                let data = read_bytes(from, max_value).await?;
+                Ok(Op::Set { key, data, ttl })
+            },
+            SET_NEGATIVE => {
+                let key = read_key(from).await?;
+                let ttl = Duration::from_millis(from.read_u64().await?);
+                Ok(Op::SetNegative { key, ttl })
+            },
+            DELETE => Ok(Op::Delete { key: read_key(from).await? }),
+            PRIORITY => {
+                let key = read_key(from).await?;
+                let priority = match from.read_u8().await? {
+                    0 => Priority::Low,
+                    1 => Priority::Normal,
+                    2 => Priority::High,
+                    priority => return Err(io::Error::new(ErrorKind::InvalidData, format!("unknown priority {}", priority))),
+                };
+                Ok(Op::Priority { key, priority })
+            },
+            SYNCED => Ok(Op::Synced),
+            op => Err(io::Error::new(ErrorKind::InvalidData, format!("unknown op {}", op))),
+        }
+    }
+}
+
+fn put_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    buf.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+    buf.extend_from_slice(bytes);
+}
+
+async fn read_bytes<R: AsyncRead + Unpin>(from: &mut R, max: usize) -> io::Result<Vec<u8>> {
+    let len = from.read_u32().await? as usize;
+    if len > max {
+        return Err(io::Error::new(ErrorKind::InvalidData, format!("frame of {}B exceeds {}B", len, max)))
+    }
+    let mut bytes = vec![0; len];
+    from.read_exact(&mut bytes).await?;
+    Ok(bytes)
+}
+
+async fn read_key<R: AsyncRead + Unpin>(from: &mut R) -> io::Result<String> {
+    String::from_utf8(read_bytes(from, MAX_KEY).await?)
+        .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))
+}
+
+/// Streams mutations of a store to its replicas. A replica which can't keep up
+/// is disconnected and gets a fresh snapshot once it is reconnected.
+/// Eviction priorities are streamed along with values, pins and sliding ttls are not.
+/// Lists, sets, maps and counters are not streamed: a replica deletes a key once
+/// the primary writes one under it, and snapshots leave them out.
+pub struct Primary {
+    /// operations which may wait to be sent to a single replica
+    buffer: usize,
+    replicas: Mutex<Vec<Sender<Arc<Op>>>>,
+}
+
+impl Primary {
+    pub fn new(buffer: usize) -> Primary {
+        Primary { buffer, replicas: Default::default() }
+    }
+
+    /// Listener forwarding mutations to replicas and then passing them to `next`.
+    pub fn listener(self: &Arc<Primary>, next: Listener) -> Listener {
+        let primary = self.clone();
+        Box::new(move |event: &Event| {
+            primary.publish(event);
+            next(event);
+        })
+    }
+
+    fn publish(&self, event: &Event) {
+        let mut replicas = self.replicas.lock().unwrap();
+        if replicas.is_empty() {
+            return
+        }
+
+        let op = match Op::of(event) {
+            Some(op) => Arc::new(op),
+            None => return,
+        };
+        // dropped sender ends the stream, so the replica is reconnected
+        replicas.retain_mut(|replica| replica.try_send(op.clone()).is_ok());
+    }
+}
+
+/// Keeps every replica from `replicas` in sync with `mc` until the task is aborted.
+/// `mc` must report its mutations to `primary.listener`, replicas must share the `secret`.
+pub fn spawn_primary(primary: &Arc<Primary>, mc: &Arc<Store>, replicas: Vec<String>, secret: String) -> AbortHandle {
+    let secret: Arc<str> = secret.into();
+    let streams = replicas.into_iter()
+        .map(|addr| stream_to(primary.clone(), mc.clone(), addr, secret.clone()));
+    let (task, handle) = abortable(join_all(streams));
+    rt::spawn(async move {
+        let _ = task.await;
+    });
+    handle
+}
+
+async fn stream_to(primary: Arc<Primary>, mc: Arc<Store>, addr: String, secret: Arc<str>) {
+    let mut backoff = BACKOFF;
+    loop {
+        match TcpStream::connect(&addr).await {
+            Ok(conn) => {
+                info!("replicating to {}", addr);
+                backoff = BACKOFF;
+                let err = sync(&primary, &mc, conn, &secret).await;
+                warn!("replica {} disconnected: {}", addr, err);
+            },
+            Err(err) => warn!("replica {} is unreachable: {}", addr, err),
+        }
+
+        rt::time::delay_for(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// Introduces itself with the secret, then sends a snapshot of the store followed by
+/// every later mutation, returns why it stopped.
+async fn sync(primary: &Primary, mc: &Store, mut conn: TcpStream, secret: &str) -> io::Error {
+    let mut hello = Vec::new();
+    put_bytes(&mut hello, secret.as_bytes());
+    if let Err(err) = conn.write_all(&hello).await {
+        return err
+    }
+
+    let (sender, mut ops) = channel(primary.buffer);
+    let snapshot: Vec<Op> = {
+        // nothing is mutated under the read lock, so snapshot and stream neither overlap nor leave a gap
+        let mc = mc.read().await;
+        primary.replicas.lock().unwrap().push(sender);
+        let now = mc.clock().now();
+        mc.iter()
+            .flat_map(|(key, data)| {
+                let set = Op::Set {
+                    key: key.to_owned(),
+                    data: data.to_vec(),
+                    ttl: mc.expires_at(key).map(|ttl| ttl.checked_sub(now).unwrap_or_default()),
+                };
+                let priority = mc.priority(key)
+                    .filter(|priority| *priority != Priority::Normal)
+                    .map(|priority| Op::Priority { key: key.to_owned(), priority });
+                Some(set).into_iter().chain(priority)
+            })
+            .collect()
+    };
+
+    for op in snapshot.iter().chain(Some(&Op::Synced)) {
+        if let Err(err) = conn.write_all(&op.encode()).await {
+            return err
+        }
+    }
+    while let Some(op) = ops.next().await {
+        if let Err(err) = conn.write_all(&op.encode()).await {
+            return err
+        }
+    }
+    io::Error::other("replica can't keep up")
+}
+
+/// Applies operations streamed by a primary to `mc` until the task is aborted.
+/// A single primary is served at a time, the next one waits for it to disconnect.
+/// Primaries which don't introduce themselves with the `secret` are disconnected.
+pub fn spawn_replica(mc: &Arc<Store>, listener: net::TcpListener, secret: String) -> io::Result<AbortHandle> {
+    let listener = TcpListener::from_std(listener)?;
+    let (task, handle) = abortable(replicate(mc.clone(), listener, secret));
+    rt::spawn(async move {
+        let _ = task.await;
+    });
+    Ok(handle)
+}
+
+async fn replicate(mc: Arc<Store>, mut listener: TcpListener, secret: String) {
+    loop {
+        match listener.accept().await {
+            Ok((conn, peer)) => {
+                info!("replicating from {}", peer);
+                if let Err(err) = apply(&mc, conn, &secret).await {
+                    warn!("primary {} disconnected: {}", peer, err);
+                }
+            },
+            Err(err) => warn!("can't accept primary: {}", err),
+        }
+    }
+}
+
+async fn apply(mc: &Store, conn: TcpStream, secret: &str) -> io::Result<()> {
+    let mut conn = BufReader::new(conn);
+    if !same(&read_bytes(&mut conn, MAX_SECRET).await?, secret.as_bytes()) {
+        return Err(io::Error::new(ErrorKind::PermissionDenied, "wrong replication secret"))
+    }
+    let max_value = mc.read().await.limit();
+    // keys left from an earlier primary connection, dropped unless the snapshot has them
+    let mut stale: HashSet<String> = mc.read().await.keys().map(ToOwned::to_owned).collect();
+    loop {
+        let op = Op::read(&mut conn, max_value).await?;
+        let mut mc = mc.write().await;
+        match op {
+            Op::Set { key, data, ttl } => {
+                stale.remove(&key);
+                // value which doesn't fit is just missing on the replica
+                let _ = mc.set(key, data, ttl);
+            },
+            Op::SetNegative { key, ttl } => {
+                stale.remove(&key);
+                let _ = mc.set_negative(key, ttl);
+            },
+            Op::Delete { key } => {
+                stale.remove(&key);
+                mc.delete(&key);
+            },
+            Op::Priority { key, priority } => {
+                mc.set_priority(&key, priority);
+            },
+            Op::Synced => stale.drain().for_each(|key| {
+                mc.delete(&key);
+            }),
+        }
+    }
+}
+
+/// Compares in time independent of where the bytes differ.
+fn same(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
@@ -1,27 +1,519 @@
-use serde::Deserialize;
+use serde::{Serialize, Deserialize};
+use actix_http::KeepAlive;
 use config::{Environment, Config, ConfigError};
 use duration_string::DurationString;
 
+use std::{
+    convert::TryFrom,
+    net::{IpAddr, ToSocketAddrs},
+    time::Duration,
+};
+
 use config;
 
-#[derive(Deserialize)]
+use crate::{
+    hotkeys::MAX_HOT_KEYS,
+    memcached::{KeyHasher, TtlJitter, Watermarks},
+    origin::WriteMode,
+    logging::{LogFormat, KeyLogging},
+};
+
+#[derive(Deserialize, Serialize)]
 pub struct Settings {
     pub memory_limit: u64,
+    /// items held at most, oldest ones are displaced for new keys above it, no limit if not set
+    pub max_items: Option<u64>,
     pub gc_interval: DurationString,
+    pub gc_max_keys: Option<u64>,
+    pub gc_max_duration: Option<DurationString>,
+    /// either a duration or a percentage of ttl, e.g. `10%`
+    pub ttl_jitter: Option<String>,
+    /// default for items set without explicit `sliding` flag
+    pub sliding_ttl: bool,
+    /// how long expired items can still be read with `allow_stale`
+    pub stale_grace: Option<DurationString>,
+    /// ttl of items set without one, they never expire if not set
+    pub default_ttl: Option<DurationString>,
+    /// caps ttls sent by clients, items set without one get it unless default_ttl is set
+    pub max_ttl: Option<DurationString>,
+    /// beta of probabilistic early expiration: gets of items near their deadline are occasionally
+    /// answered as misses so one caller refreshes them early, 1 is the usual choice, off if not set
+    pub early_expiration_beta: Option<f64>,
+    /// hash function of store keys: ahash, or siphash for the more conservative choice
+    pub key_hasher: String,
+    /// longest key in bytes stored, no limit if not set
+    pub key_max_length: Option<u64>,
+    /// characters keys may have besides ascii letters and digits, e.g. `:_-.`, any if not set
+    pub key_allowed_chars: Option<String>,
+    /// rejects keys with whitespace or control characters, which break memcached text protocol framing
+    pub key_reject_whitespace: bool,
+    /// values are kept in slabs of pages this large, each value has an allocation of its own if not set,
+    /// memory_limit counts whole pages
+    pub slab_page_size: Option<u64>,
+    /// chunk size of the smallest slab class
+    pub slab_min_chunk: u64,
+    /// ratio of chunk sizes of neighbouring slab classes
+    pub slab_growth_factor: f64,
+    /// expected number of keys of a Bloom filter answering definite misses without the store lock,
+    /// takes 10 bytes per key, there is no filter if not set
+    pub key_filter_capacity: Option<u64>,
+    /// fraction of memory_limit background eviction brings usage down to
+    pub evict_low_watermark: Option<f64>,
+    /// fraction of memory_limit above which background eviction starts
+    pub evict_high_watermark: Option<f64>,
+    /// entries of per worker read cache, 0 disables it
+    pub l1_capacity: u64,
+    /// staleness allowed for per worker read cache
+    pub l1_ttl: DurationString,
+    /// file of the default store cold tier, displaced items are dropped if not set
+    pub disk_tier_path: Option<String>,
+    /// bytes kept in the cold tier at most
+    pub disk_tier_limit: u64,
+    /// encrypts cold tier values, with the key of `disk_tier_key_file` if it is set
+    /// or else with a random key kept in memory of the process only
+    pub disk_tier_encrypt: bool,
+    /// file with the base64 encoded 32 byte AES-256 key of the cold tier
+    pub disk_tier_key_file: Option<String>,
+    /// read-through backend of the default store, `{key}` is replaced with the key
+    pub origin_url: Option<String>,
+    /// ttl of values fetched from origin, they don't expire if not set
+    pub origin_ttl: Option<DurationString>,
+    /// off, through or behind: how sets and deletes are forwarded to origin
+    pub origin_write: String,
+    /// write-behind attempts after the first failed one before the write is dropped
+    pub origin_write_retries: u64,
+    /// comma separated replication addresses the default store is streamed to
+    pub replicas: String,
+    /// address a primary streams to, makes this node a read only replica
+    pub replication_addr: Option<String>,
+    /// operations queued for a replica before it is disconnected and synced anew
+    pub replication_buffer: u64,
+    /// secret a primary introduces itself to replicas with, required if either replicas or replication_addr is set
+    pub replication_secret: Option<String>,
+    /// comma separated base urls of every cluster node, e.g. `http://10.0.0.1:8080`, cluster mode is off if empty
+    pub cluster_nodes: String,
+    /// base url of this node as listed in cluster_nodes
+    pub cluster_self: Option<String>,
+    /// latency critical gets a peer doesn't answer within this long are sent to it again, off if not set
+    pub cluster_hedge_delay: Option<DurationString>,
+    /// comma separated addresses the api is served on, e.g. `0.0.0.0:8080,[::]:8080`,
+    /// ignored if systemd passes listening sockets
     pub addr: String,
+    /// comma separated addresses `/admin` endpoints are served on, e.g. `127.0.0.1:9090`,
+    /// they are not found on other listeners while it is set
+    pub admin_addr: Option<String>,
+    /// address of memcached UDP protocol serving get and set of the default store, off if not set,
+    /// it must be a loopback or private one, as spoofed udp requests reflect responses at others,
+    /// commands are refused while api keys are configured and it can't be set in cluster mode
+    pub udp_addr: Option<String>,
+    /// unix socket a restarted server takes the default store over through,
+    /// api listeners are bound with SO_REUSEPORT if set
+    pub handover_socket: Option<String>,
+    /// address of cleartext HTTP/2 listener with prior knowledge, off if not set
+    pub h2c_addr: Option<String>,
     pub workers: Option<u64>,
+    /// connections each worker serves at once, further ones wait in the backlog
+    pub max_connections: u64,
+    /// time a new connection gets to send request headers before it is closed
+    pub client_request_timeout: DurationString,
+    /// time a closing connection gets to take the rest of its response before it is dropped
+    pub client_shutdown_timeout: DurationString,
+    /// how long an idle connection is kept open, whole seconds, 0 closes it after every response
+    pub keep_alive: DurationString,
+    /// how long in-flight requests are waited for on shutdown, whole seconds
+    pub shutdown_timeout: DurationString,
+    /// starts in read only mode, refusing writes until it is switched off by `PUT /admin/readonly`
+    pub read_only: bool,
+    pub bootstrap_file: Option<String>,
+    /// how often bytes stored by every tenant of the bootstrap file are measured
+    pub tenant_usage_interval: DurationString,
+    /// newline delimited json of entries loaded into the default store before serving
+    pub warmup_file: Option<String>,
+    /// csv file for sampled operation records, disabled if not set
+    pub audit_file: Option<String>,
+    /// fraction of requests recorded to audit file
+    pub audit_sample_rate: f64,
+    /// audit file is rotated once it grows beyond this size
+    pub audit_rotate_size: u64,
+    /// p99 latency above which optional features are disabled, load shedding is off if not set
+    pub degrade_p99_enter: Option<DurationString>,
+    /// p99 latency below which optional features are enabled again, half of enter threshold by default
+    pub degrade_p99_exit: Option<DurationString>,
+    /// window in which p99 latency is measured
+    pub degrade_interval: DurationString,
+    /// url receiving deleted, expired and evicted keys, webhooks are off if not set
+    pub webhook_url: Option<String>,
+    /// events sent in a single request at most
+    pub webhook_batch_size: u64,
+    /// how long events are collected before a partial batch is sent
+    pub webhook_flush_interval: DurationString,
+    /// attempts after the first failed one before a batch is dropped
+    pub webhook_retries: u64,
+    /// compresses responses with br, gzip, deflate or zstd, whichever `Accept-Encoding` ranks first
+    pub compress: bool,
+    pub compress_min_size: u64,
+    pub compress_content_types: String,
+    pub json_limit: u64,
+    pub decompress_limit: u64,
+    /// bytes of an import body, its lines are bounded by `json_limit`
+    pub import_limit: u64,
+    /// serves Swagger UI of `/openapi.json` at `/docs`
+    pub swagger_ui: bool,
+    pub cors_allowed_origins: String,
+    pub cors_allowed_methods: String,
+    pub cors_allowed_headers: String,
+    /// text or json, json lines have stable field names for log pipelines
+    pub log_format: String,
+    /// hash or truncate: how keys appear in the access log
+    pub access_log_keys: String,
+    /// chars of a key kept by truncate
+    pub access_log_key_length: u64,
+    /// grpc endpoint of an OpenTelemetry collector receiving traces and metrics, export is off if not set.
+    /// Spans are filtered by `RUST_LOG` just like logs.
+    pub otlp_endpoint: Option<String>,
+    pub otlp_service_name: String,
+    /// how often default store metrics are pushed to the collector
+    pub otlp_metrics_interval: DurationString,
+    /// store locks and gc sweeps taking longer than this are kept for `/slowlog`
+    pub slowlog_threshold: DurationString,
+    /// slow operations kept, 0 disables the slow log
+    pub slowlog_capacity: u64,
+    /// comma separated ascending upper bounds of latency histogram buckets,
+    /// microseconds (`100us`) are accepted besides the usual durations
+    pub latency_buckets: String,
+    /// hottest keys of http requests reported by `/admin/hotkeys` and `/metrics`, at most 100,
+    /// 0 disables tracking
+    pub hot_keys: u64,
 }
 
 impl Settings {
     pub fn new() -> Result<Settings, ConfigError> {
+        let limits = ConnectionLimits::default();
         let mut cfg = Config::new();
         cfg.merge(
             Environment::with_prefix("memcached")
         )?
         .set_default("memory_limit", 1 << 20)?
         .set_default("gc_interval", "100ms")?
-        .set_default("addr", "0.0.0.0:8080")?;
+        .set_default("sliding_ttl", false)?
+        .set_default("key_reject_whitespace", false)?
+        .set_default("key_hasher", "ahash")?
+        .set_default("slab_min_chunk", 64)?
+        .set_default("slab_growth_factor", 1.25)?
+        .set_default("l1_capacity", 0)?
+        .set_default("l1_ttl", "100ms")?
+        .set_default("disk_tier_limit", 1 << 30)?
+        .set_default("disk_tier_encrypt", false)?
+        .set_default("origin_write", "off")?
+        .set_default("origin_write_retries", 3)?
+        .set_default("replicas", "")?
+        .set_default("replication_buffer", 1 << 16)?
+        .set_default("cluster_nodes", "")?
+        .set_default("addr", "0.0.0.0:8080")?
+        .set_default("max_connections", limits.max_connections as i64)?
+        .set_default("client_request_timeout", millis(limits.client_request_timeout))?
+        .set_default("client_shutdown_timeout", millis(limits.client_shutdown_timeout))?
+        .set_default("keep_alive", millis(limits.keep_alive))?
+        .set_default("shutdown_timeout", millis(limits.shutdown_timeout))?
+        .set_default("read_only", false)?
+        .set_default("tenant_usage_interval", "10s")?
+        .set_default("audit_sample_rate", 0.01)?
+        .set_default("audit_rotate_size", 64 << 20)?
+        .set_default("degrade_interval", "1s")?
+        .set_default("webhook_batch_size", 100)?
+        .set_default("webhook_flush_interval", "1s")?
+        .set_default("webhook_retries", 3)?
+        .set_default("compress", false)?
+        .set_default("compress_min_size", 1024)?
+        .set_default("compress_content_types", "application/json,text/")?
+        .set_default("json_limit", 1 << 20)?
+        .set_default("decompress_limit", 8 << 20)?
+        .set_default("import_limit", 1i64 << 30)?
+        .set_default("swagger_ui", false)?
+        .set_default("cors_allowed_origins", "")?
+        .set_default("cors_allowed_methods", "GET,POST")?
+        .set_default("cors_allowed_headers", "content-type")?
+        .set_default("log_format", "text")?
+        .set_default("access_log_keys", "hash")?
+        .set_default("access_log_key_length", 32)?
+        .set_default("otlp_service_name", "rust_memcached")?
+        .set_default("otlp_metrics_interval", "10s")?
+        .set_default("slowlog_threshold", "10ms")?
+        .set_default("slowlog_capacity", 128)?
+        .set_default("latency_buckets", "100us,250us,500us,1ms,2ms,5ms,10ms,25ms,50ms,100ms,250ms,1s")?
+        .set_default("hot_keys", 0)?;
 
         cfg.try_into()
     }
+
+    /// Checks what can't be expressed by types, used by `check-config`.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.memory_limit == 0 {
+            return Err("memory_limit must be positive".to_owned())
+        }
+        if self.key_max_length == Some(0) {
+            return Err("key_max_length must be positive".to_owned())
+        }
+        if self.max_items == Some(0) {
+            return Err("max_items must be positive".to_owned())
+        }
+        if self.gc_max_keys == Some(0) || self.gc_max_duration.map(Into::<Duration>::into) == Some(Duration::from_secs(0)) {
+            return Err("gc_max_keys and gc_max_duration must be positive".to_owned())
+        }
+        if self.disk_tier_key_file.is_some() && !self.disk_tier_encrypt {
+            return Err("disk_tier_key_file requires disk_tier_encrypt".to_owned())
+        }
+        if Into::<Duration>::into(self.gc_interval) == Duration::from_secs(0) {
+            return Err("gc_interval must be positive".to_owned())
+        }
+        self.watermarks()?;
+        let (default_ttl, max_ttl) = (self.default_ttl.map(Into::<Duration>::into), self.max_ttl.map(Into::<Duration>::into));
+        if default_ttl == Some(Duration::from_secs(0)) || max_ttl == Some(Duration::from_secs(0)) {
+            return Err("default_ttl and max_ttl must be positive".to_owned())
+        }
+        if let (Some(default_ttl), Some(max_ttl)) = (default_ttl, max_ttl) {
+            if default_ttl > max_ttl {
+                return Err("default_ttl must not exceed max_ttl".to_owned())
+            }
+        }
+        if self.early_expiration_beta.is_some_and(|beta| beta.is_nan() || beta <= 0.0) {
+            return Err("early_expiration_beta must be positive".to_owned())
+        }
+        if let Some(jitter) = &self.ttl_jitter {
+            parse_ttl_jitter(jitter)?;
+        }
+        parse_key_hasher(&self.key_hasher)?;
+        if self.slab_page_size == Some(0) || self.slab_min_chunk == 0 {
+            return Err("slab_page_size and slab_min_chunk must be positive".to_owned())
+        }
+        if self.slab_page_size.is_some_and(|page_size| page_size > self.memory_limit) {
+            return Err("slab_page_size must not exceed memory_limit".to_owned())
+        }
+        if self.key_filter_capacity == Some(0) {
+            return Err("key_filter_capacity must be positive".to_owned())
+        }
+        if self.slab_growth_factor <= 1.0 {
+            return Err("slab_growth_factor must be greater than 1".to_owned())
+        }
+        self.origin_write.parse::<WriteMode>()?;
+        self.log_format.parse::<LogFormat>()?;
+        KeyLogging::new(&self.access_log_keys, self.access_log_key_length as usize)?;
+        parse_buckets(&self.latency_buckets)?;
+        if self.replication_buffer == 0 {
+            return Err("replication_buffer must be positive".to_owned())
+        }
+        let nodes = split_list(&self.cluster_nodes);
+        match &self.cluster_self {
+            Some(me) if !nodes.is_empty() && !nodes.contains(me) => {
+                return Err(format!("cluster_self {} is not one of cluster_nodes", me))
+            },
+            None if !nodes.is_empty() => return Err("cluster_self must be set in cluster mode".to_owned()),
+            _ => (),
+        }
+        if self.cluster_hedge_delay.map(Into::<Duration>::into) == Some(Duration::from_secs(0)) {
+            return Err("cluster_hedge_delay must be positive".to_owned())
+        }
+        let addrs = split_list(&self.addr);
+        if addrs.is_empty() {
+            return Err("addr must not be empty".to_owned())
+        }
+        for addr in addrs {
+            addr.to_socket_addrs()
+                .map_err(|err| format!("invalid addr {}: {}", addr, err))?;
+        }
+        for addr in split_list(self.admin_addr.as_deref().unwrap_or_default()) {
+            addr.to_socket_addrs()
+                .map_err(|err| format!("invalid admin_addr {}: {}", addr, err))?;
+        }
+        if let Some(addr) = &self.udp_addr {
+            let mut resolved = addr.to_socket_addrs()
+                .map_err(|err| format!("invalid udp_addr {}: {}", addr, err))?;
+            if !resolved.all(|resolved| is_internal(resolved.ip())) {
+                return Err(format!("udp_addr {} must be a loopback or private address", addr))
+            }
+            if !nodes.is_empty() {
+                return Err("udp_addr can't be served in cluster mode".to_owned())
+            }
+        }
+        if let Some(addr) = &self.h2c_addr {
+            addr.to_socket_addrs()
+                .map_err(|err| format!("invalid h2c_addr {}: {}", addr, err))?;
+        }
+        if let Some(addr) = &self.replication_addr {
+            addr.to_socket_addrs()
+                .map_err(|err| format!("invalid replication_addr {}: {}", addr, err))?;
+        }
+        let replicated = !split_list(&self.replicas).is_empty() || self.replication_addr.is_some();
+        match self.replication_secret.as_deref() {
+            None | Some("") if replicated => {
+                return Err("replication_secret must be set to replicate".to_owned())
+            },
+            Some(secret) if secret.len() > 1024 => {
+                return Err("replication_secret must be at most 1024 bytes".to_owned())
+            },
+            _ => (),
+        }
+        if Into::<Duration>::into(self.tenant_usage_interval) == Duration::from_secs(0) {
+            return Err("tenant_usage_interval must be positive".to_owned())
+        }
+        if !(0.0..=1.0).contains(&self.audit_sample_rate) {
+            return Err("audit_sample_rate must be between 0 and 1".to_owned())
+        }
+        if Into::<Duration>::into(self.degrade_interval) == Duration::from_secs(0) {
+            return Err("degrade_interval must be positive".to_owned())
+        }
+        if self.webhook_batch_size == 0 {
+            return Err("webhook_batch_size must be positive".to_owned())
+        }
+        if self.workers == Some(0) {
+            return Err("workers must be positive".to_owned())
+        }
+        if self.hot_keys > MAX_HOT_KEYS {
+            return Err(format!("hot_keys must not exceed {}", MAX_HOT_KEYS))
+        }
+        self.connection_limits()?;
+        if Into::<Duration>::into(self.otlp_metrics_interval) == Duration::from_secs(0) {
+            return Err("otlp_metrics_interval must be positive".to_owned())
+        }
+        if self.decompress_limit == 0 || self.json_limit == 0 || self.import_limit == 0 {
+            return Err("payload limits must be positive".to_owned())
+        }
+        self.addr.to_socket_addrs()
+            .map_err(|err| format!("invalid addr {}: {}", self.addr, err))?;
+
+        Ok(())
+    }
+}
+
+impl Settings {
+    /// Background eviction watermarks, both or none must be set.
+    pub fn watermarks(&self) -> Result<Option<Watermarks>, String> {
+        match (self.evict_low_watermark, self.evict_high_watermark) {
+            (None, None) => Ok(None),
+            (Some(low), Some(high)) if 0.0 < low && low < high && high <= 1.0 => {
+                Ok(Some(Watermarks { low, high }))
+            },
+            (Some(_), Some(_)) => Err("watermarks must satisfy 0 < low < high <= 1".to_owned()),
+            _ => Err("evict_low_watermark and evict_high_watermark must be set together".to_owned()),
+        }
+    }
+}
+
+impl Settings {
+    /// How api listeners treat connections.
+    pub fn connection_limits(&self) -> Result<ConnectionLimits, String> {
+        if self.max_connections == 0 {
+            return Err("max_connections must be positive".to_owned())
+        }
+        let keep_alive: Duration = self.keep_alive.into();
+        let shutdown_timeout: Duration = self.shutdown_timeout.into();
+        if keep_alive.subsec_nanos() != 0 || shutdown_timeout.subsec_nanos() != 0 {
+            return Err("keep_alive and shutdown_timeout must be whole seconds".to_owned())
+        }
+        Ok(ConnectionLimits {
+            max_connections: self.max_connections as usize,
+            client_request_timeout: self.client_request_timeout.into(),
+            client_shutdown_timeout: self.client_shutdown_timeout.into(),
+            keep_alive,
+            shutdown_timeout,
+        })
+    }
+}
+
+/// Connection handling of api listeners, defaults are the ones of actix.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConnectionLimits {
+    /// per worker
+    pub max_connections: usize,
+    pub client_request_timeout: Duration,
+    pub client_shutdown_timeout: Duration,
+    /// zero disables keep-alive
+    pub keep_alive: Duration,
+    pub shutdown_timeout: Duration,
+}
+
+impl ConnectionLimits {
+    pub fn keep_alive_policy(&self) -> KeepAlive {
+        match self.keep_alive.as_secs() {
+            0 => KeepAlive::Disabled,
+            secs => KeepAlive::Timeout(secs as usize),
+        }
+    }
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> ConnectionLimits {
+        ConnectionLimits {
+            max_connections: 25_000,
+            client_request_timeout: Duration::from_secs(5),
+            client_shutdown_timeout: Duration::from_secs(5),
+            keep_alive: Duration::from_secs(5),
+            shutdown_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Duration as a setting value.
+fn millis(duration: Duration) -> String {
+    format!("{}ms", duration.as_millis())
+}
+
+/// Loopback or private address, an IPv6 one is private if it's unique local (`fc00::/7`).
+fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_loopback() || ip.is_private(),
+        IpAddr::V6(ip) => ip.is_loopback() || ip.segments()[0] & 0xfe00 == 0xfc00,
+    }
+}
+
+/// Splits comma separated setting value, skipping empty entries.
+pub fn split_list(value: &str) -> Vec<String> {
+    value.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(ToOwned::to_owned)
+        .collect()
+}
+
+/// Parses comma separated durations, which must be ascending.
+/// `DurationString` stops at milliseconds, so microseconds are parsed here.
+pub fn parse_buckets(value: &str) -> Result<Vec<Duration>, String> {
+    let bounds = split_list(value).into_iter()
+        .map(|bound| match bound.strip_suffix("us").and_then(|micros| micros.parse().ok()) {
+            Some(micros) => Ok(Duration::from_micros(micros)),
+            None => DurationString::try_from(bound.clone())
+                .map(Into::<Duration>::into)
+                .map_err(|err| format!("invalid latency bucket {}: {}", bound, err)),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if bounds.is_empty() || bounds.windows(2).any(|pair| pair[0] >= pair[1]) {
+        return Err("latency_buckets must be ascending and not empty".to_owned())
+    }
+    Ok(bounds)
+}
+
+/// Parses either a percentage of ttl (`10%`) or an absolute duration (`5s`).
+pub fn parse_ttl_jitter(value: &str) -> Result<TtlJitter, String> {
+    let value = value.trim();
+    match value.strip_suffix('%') {
+        Some(percent) => {
+            let percent: f64 = percent.trim().parse()
+                .map_err(|_| format!("invalid ttl_jitter percentage {}", value))?;
+            if !(0.0..=100.0).contains(&percent) {
+                return Err(format!("ttl_jitter {} is not between 0% and 100%", value))
+            }
+            Ok(TtlJitter::Ratio(percent / 100.0))
+        },
+        None => DurationString::try_from(value.to_owned())
+            .map(|jitter| TtlJitter::Absolute(jitter.into()))
+            .map_err(|err| format!("invalid ttl_jitter {}: {}", value, err)),
+    }
+}
+
+pub fn parse_key_hasher(value: &str) -> Result<KeyHasher, String> {
+    match value.trim() {
+        "ahash" => Ok(KeyHasher::AHash),
+        "siphash" => Ok(KeyHasher::SipHash),
+        other => Err(format!("unknown key_hasher {}, expected ahash or siphash", other)),
+    }
 }
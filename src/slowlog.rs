@@ -0,0 +1,59 @@
+use serde::Serialize;
+use utoipa::ToSchema;
+use chrono::{DateTime, Utc};
+use std::{
+    collections::VecDeque,
+    panic::Location,
+    sync::Mutex,
+    time::Duration,
+};
+
+/// Store operation which took longer than the slow log threshold.
+#[derive(Serialize, Clone, ToSchema)]
+pub struct SlowOp {
+    pub at: DateTime<Utc>,
+    /// `read` or `write` for a store lock, `gc` or `evict` for a whole sweep
+    pub op: &'static str,
+    /// code which held the lock
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub site: Option<String>,
+    pub lock_wait_us: u64,
+    pub work_us: u64,
+}
+
+/// Last slow operations of every store, older ones are dropped once `capacity` is reached.
+pub struct SlowLog {
+    threshold: Duration,
+    capacity: usize,
+    ops: Mutex<VecDeque<SlowOp>>,
+}
+
+impl SlowLog {
+    pub fn new(threshold: Duration, capacity: usize) -> SlowLog {
+        SlowLog { threshold, capacity, ops: Default::default() }
+    }
+
+    /// Keeps the operation if waiting for the lock and working under it took longer than the threshold.
+    pub fn record(&self, op: &'static str, site: Option<&Location>, lock_wait: Duration, work: Duration) {
+        if self.capacity == 0 || lock_wait + work <= self.threshold {
+            return
+        }
+
+        let mut ops = self.ops.lock().unwrap();
+        if ops.len() == self.capacity {
+            ops.pop_front();
+        }
+        ops.push_back(SlowOp {
+            at: Utc::now(),
+            op,
+            site: site.map(ToString::to_string),
+            lock_wait_us: lock_wait.as_micros() as u64,
+            work_us: work.as_micros() as u64,
+        });
+    }
+
+    /// newest first
+    pub fn ops(&self) -> Vec<SlowOp> {
+        self.ops.lock().unwrap().iter().rev().cloned().collect()
+    }
+}
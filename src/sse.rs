@@ -0,0 +1,32 @@
+use actix_web::{
+    HttpResponse,
+    dev::BodyEncoding,
+    http::ContentEncoding,
+    web::Bytes,
+};
+use futures::StreamExt;
+
+use crate::events::{self, EventBus, KeyEvent};
+
+/// events waiting to be sent to a single client, beyond that events are dropped
+const QUEUE: usize = 1024;
+
+/// Streams events of the namespace with keys matching `pattern` (every key if not set)
+/// as `text/event-stream`, event name is the reason and data is the event json.
+pub fn stream(events: &EventBus, namespace: String, pattern: Option<String>) -> HttpResponse {
+    let events = events.subscribe(QUEUE, Box::new(move |event: &KeyEvent| {
+        event.namespace == namespace
+            && pattern.as_deref().is_none_or(|pattern| events::matches(pattern, &event.key))
+    }));
+
+    let body = events.map(|event| {
+        let data = serde_json::to_string(&*event).unwrap_or_default();
+        Ok::<_, actix_web::Error>(Bytes::from(format!("event: {}\ndata: {}\n\n", event.reason, data)))
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        // compression would hold events back until its buffer is full
+        .encoding(ContentEncoding::Identity)
+        .streaming(body)
+}
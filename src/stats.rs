@@ -0,0 +1,136 @@
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::memcached::{self, Buckets, ClassStats, Clock, Memcached};
+
+/// Capacity outlook of a store extrapolated from its average traffic since start.
+#[derive(Serialize, ToSchema)]
+pub struct Forecast {
+    memory_limit: usize,
+    used: usize,
+    /// bytes allocated for values, including free chunks of slab pages
+    reserved: usize,
+    /// bytes per second stored by sets
+    write_rate: f64,
+    /// bytes per second released by deletes, overwrites and expiry
+    free_rate: f64,
+    /// bytes per second displaced because the store was full
+    eviction_rate: f64,
+    /// seconds until the store is full at current rates, none if it doesn't grow
+    time_to_full: Option<f64>,
+    /// evictions have already started
+    evicting: bool,
+}
+
+impl Forecast {
+    pub fn of(mc: &Memcached) -> Forecast {
+        let stats = mc.stats();
+        let elapsed = (mc.clock().now() - stats.since).as_secs_f64();
+        let rate = |bytes: u64| match elapsed > 0.0 {
+            true => bytes as f64 / elapsed,
+            false => 0.0,
+        };
+
+        let write_rate = rate(stats.written_bytes);
+        let free_rate = rate(stats.freed_bytes);
+        let growth = write_rate - free_rate;
+        let free_space = mc.limit().saturating_sub(mc.size());
+
+        Forecast {
+            memory_limit: mc.limit(),
+            used: mc.size(),
+            reserved: mc.reserved(),
+            write_rate, free_rate,
+            eviction_rate: rate(stats.evicted_bytes),
+            time_to_full: match growth > 0.0 {
+                true => Some(free_space as f64 / growth),
+                false => None,
+            },
+            evicting: stats.evicted_bytes > 0,
+        }
+    }
+}
+
+/// Items of a store by ttl and value size, buckets are not cumulative.
+#[derive(Serialize, ToSchema)]
+pub struct Distribution {
+    /// ttls values were stored with since start, values which never expire are not counted
+    ttl: Vec<Bucket>,
+    /// time left until expiry of items stored now, e.g. the first three buckets expire within a minute
+    remaining_ttl: Vec<Bucket>,
+    never_expiring: u64,
+    /// bytes of values stored now
+    value_size: Vec<Bucket>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Bucket {
+    /// upper bound in seconds or bytes, none for the last bucket
+    le: Option<f64>,
+    count: u64,
+}
+
+impl Distribution {
+    /// Visits every item of the store.
+    pub fn of(mc: &Memcached) -> Distribution {
+        let distribution = mc.distribution();
+        let ttl_bounds: Vec<f64> = memcached::TTL_BOUNDS.iter().map(|bound| bound.as_secs_f64()).collect();
+        let size_bounds: Vec<f64> = memcached::SIZE_BOUNDS.iter().map(|bound| *bound as f64).collect();
+        Distribution {
+            ttl: buckets(&ttl_bounds, &mc.stats().ttls),
+            remaining_ttl: buckets(&ttl_bounds, &distribution.remaining_ttl),
+            never_expiring: distribution.never_expiring,
+            value_size: buckets(&size_bounds, &distribution.sizes),
+        }
+    }
+}
+
+fn buckets(bounds: &[f64], counts: &Buckets) -> Vec<Bucket> {
+    counts.iter().enumerate()
+        .map(|(i, count)| Bucket { le: bounds.get(i).copied(), count: *count })
+        .collect()
+}
+
+/// Items by slab class like memcached `stats items` and `stats slabs`,
+/// so a churning class of objects stands out from the totals.
+#[derive(Serialize, ToSchema)]
+pub struct ItemClasses {
+    classes: Vec<ItemClass>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ItemClass {
+    /// chunk size of the class, none for values allocated on their own
+    chunk_size: Option<usize>,
+    items: usize,
+    /// bytes of values
+    bytes: usize,
+    pages: usize,
+    /// chunks of the pages, taken and free ones
+    chunks: usize,
+    /// items displaced to make room since start
+    evicted: u64,
+    /// seconds since the least recently used item was touched
+    oldest_age: Option<f64>,
+}
+
+impl ItemClasses {
+    /// Visits every item of the store.
+    pub fn of(mc: &Memcached) -> ItemClasses {
+        ItemClasses { classes: mc.classes().into_iter().map(ItemClass::from).collect() }
+    }
+}
+
+impl From<ClassStats> for ItemClass {
+    fn from(class: ClassStats) -> ItemClass {
+        ItemClass {
+            chunk_size: class.chunk_size,
+            items: class.items,
+            bytes: class.bytes,
+            pages: class.pages,
+            chunks: class.chunks,
+            evicted: class.evicted,
+            oldest_age: class.oldest_age.map(|age| age.as_secs_f64()),
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! Socket activation, readiness and watchdog notifications of systemd.
+//! Everything here is a no-op unless the server is started by systemd.
+
+use actix_web::rt;
+use futures::future::{abortable, AbortHandle};
+use tracing::warn;
+use std::{
+    env, io, net, process,
+    ffi::OsStr,
+    os::{
+        linux::net::SocketAddrExt,
+        unix::{
+            ffi::OsStrExt,
+            io::{FromRawFd, RawFd},
+            net::{SocketAddr, UnixDatagram},
+        },
+    },
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::memcached::Store;
+
+/// first descriptor passed by socket activation
+const LISTEN_FDS_START: RawFd = 3;
+/// gc intervals without a finished sweep after which the watchdog is no longer pinged
+const GC_STALL_INTERVALS: u32 = 3;
+
+/// Takes listening sockets passed by socket activation, empty if there are none.
+/// Its variables are removed from the environment, so children don't take them too.
+pub fn listen_fds() -> Vec<net::TcpListener> {
+    let fds = match (env::var("LISTEN_PID"), env::var("LISTEN_FDS")) {
+        (Ok(pid), Ok(fds)) if pid.parse::<u32>() == Ok(process::id()) => fds.parse().unwrap_or(0),
+        _ => 0,
+    };
+    for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"].iter() {
+        env::remove_var(var);
+    }
+
+    // systemd passes them to this process only, nothing else owns them
+    (0..fds).map(|fd| unsafe { net::TcpListener::from_raw_fd(LISTEN_FDS_START + fd) }).collect()
+}
+
+/// Sends `state` such as `READY=1` to the service manager, failures are only logged.
+pub fn notify(state: &str) {
+    let path = match env::var_os("NOTIFY_SOCKET") {
+        Some(path) => path,
+        None => return,
+    };
+    if let Err(err) = send(&path, state) {
+        warn!("can't notify systemd of {}: {}", state, err);
+    }
+}
+
+fn send(path: &OsStr, state: &str) -> io::Result<()> {
+    let addr = match path.as_bytes().strip_prefix(b"@") {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(path)?,
+    };
+    UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(())
+}
+
+/// Pings the watchdog at half of its timeout while gc of `mc` keeps finishing sweeps,
+/// so a store stuck on its lock gets the service restarted.
+/// `None` if the watchdog is not enabled for this process.
+pub fn spawn_watchdog(mc: &Arc<Store>, gc_interval: Duration) -> Option<AbortHandle> {
+    let pid_matches = match env::var("WATCHDOG_PID") {
+        Ok(pid) => pid.parse::<u32>() == Ok(process::id()),
+        Err(_) => true,
+    };
+    let timeout = env::var("WATCHDOG_USEC").ok()
+        .and_then(|usec| usec.parse().ok())
+        .filter(|_| pid_matches)
+        .map(Duration::from_micros)?;
+
+    let (task, handle) = abortable(watchdog(mc.clone(), timeout / 2, gc_interval * GC_STALL_INTERVALS));
+    rt::spawn(async move {
+        let _ = task.await;
+    });
+    Some(handle)
+}
+
+async fn watchdog(mc: Arc<Store>, interval: Duration, stalled: Duration) {
+    loop {
+        match mc.since_gc() {
+            since_gc if since_gc <= stalled => notify("WATCHDOG=1"),
+            since_gc => warn!("gc hasn't finished a sweep for {:?}, watchdog is not pinged", since_gc),
+        }
+        rt::time::delay_for(interval).await;
+    }
+}
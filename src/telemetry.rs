@@ -0,0 +1,145 @@
+use actix_web::{dev::ServiceRequest, http::HeaderMap};
+use futures::Stream;
+use opentelemetry::{
+    global, KeyValue,
+    propagation::Extractor,
+    sdk::{
+        Resource, trace,
+        metrics::PushController,
+        propagation::TraceContextPropagator,
+    },
+    metrics::BatchObserverResult,
+};
+use opentelemetry_otlp::WithExportConfig;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tokio1::runtime::{self, Runtime};
+use std::{
+    io::{self, ErrorKind::Other},
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::memcached::Store;
+
+pub struct OtlpConfig {
+    /// grpc endpoint of the collector, e.g. `http://localhost:4317`
+    pub endpoint: String,
+    pub service_name: String,
+    /// how often store metrics are pushed
+    pub metrics_interval: Duration,
+}
+
+/// OTLP export of request traces and default store metrics.
+/// Exporters need tokio 1, so they get a runtime of their own next to the actix one.
+pub struct Telemetry {
+    config: OtlpConfig,
+    runtime: Runtime,
+    tracer: trace::Tracer,
+    metrics: Option<PushController>,
+}
+
+impl Telemetry {
+    /// Starts trace export and makes `traceparent` of incoming requests understood.
+    pub fn start(config: OtlpConfig) -> io::Result<Telemetry> {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+
+        let runtime = runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("otlp")
+            .enable_all()
+            .build()?;
+        let tracer = {
+            let _entered = runtime.enter();
+            opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(opentelemetry_otlp::new_exporter().tonic().with_endpoint(&config.endpoint))
+                .with_trace_config(trace::config().with_resource(Resource::new(attributes(&config))))
+                .install_batch(opentelemetry::runtime::Tokio)
+                .map_err(|err| io::Error::new(Other, err))?
+        };
+
+        Ok(Telemetry { config, runtime, tracer, metrics: None })
+    }
+
+    /// tracer for the `tracing` layer exporting spans
+    pub fn tracer(&self) -> trace::Tracer {
+        self.tracer.clone()
+    }
+
+    /// Pushes item count, memory usage and traffic counters of `mc` every `metrics_interval`.
+    pub fn export_metrics(&mut self, mc: &Arc<Store>) -> io::Result<()> {
+        let _entered = self.runtime.enter();
+        let controller = opentelemetry_otlp::new_pipeline()
+            .metrics(tokio1::spawn, delayed_interval)
+            .with_exporter(opentelemetry_otlp::new_exporter().tonic().with_endpoint(&self.config.endpoint))
+            .with_resource(attributes(&self.config))
+            .with_period(self.config.metrics_interval)
+            .build()
+            .map_err(|err| io::Error::new(Other, err))?;
+
+        let mc = mc.clone();
+        global::meter("rust_memcached").batch_observer(move |batch| {
+            let items = batch.u64_value_observer("cache.items").init();
+            let used = batch.u64_value_observer("cache.used_bytes").init();
+            let limit = batch.u64_value_observer("cache.limit_bytes").init();
+            let written = batch.u64_sum_observer("cache.written_bytes").init();
+            let freed = batch.u64_sum_observer("cache.freed_bytes").init();
+            let evicted = batch.u64_sum_observer("cache.evicted_bytes").init();
+
+            let mc = mc.clone();
+            Box::new(move |result: BatchObserverResult| {
+                // collection runs on the exporter runtime, never on an actix worker
+                let mc = mc.blocking_read();
+                let stats = mc.stats();
+                result.observe(&[], &[
+                    items.observation(mc.len() as u64),
+                    used.observation(mc.size() as u64),
+                    limit.observation(mc.limit() as u64),
+                    written.observation(stats.written_bytes),
+                    freed.observation(stats.freed_bytes),
+                    evicted.observation(stats.evicted_bytes),
+                ]);
+            })
+        });
+
+        self.metrics = Some(controller);
+        Ok(())
+    }
+
+    /// Flushes pending spans and metrics.
+    pub fn shutdown(self) {
+        let _entered = self.runtime.enter();
+        global::shutdown_tracer_provider();
+        drop(self.metrics);
+    }
+}
+
+/// resource attributes identifying this server among collected services
+fn attributes(config: &OtlpConfig) -> Vec<KeyValue> {
+    vec![KeyValue::new("service.name", config.service_name.clone())]
+}
+
+fn delayed_interval(period: Duration) -> impl Stream<Item = tokio1::time::Instant> {
+    let start = tokio1::time::Instant::now() + period;
+    tokio_stream::wrappers::IntervalStream::new(tokio1::time::interval_at(start, period))
+}
+
+/// Makes the current request span a child of the caller's span from `traceparent` header.
+/// Does nothing if the header is missing or export is off.
+pub fn continue_trace(req: &ServiceRequest) {
+    let parent = global::get_text_map_propagator(|propagator| propagator.extract(&Headers(req.headers())));
+    Span::current().set_parent(parent);
+}
+
+struct Headers<'a>(&'a HeaderMap);
+
+impl Extractor for Headers<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
@@ -0,0 +1,368 @@
+//! Tenants sharing a deployment: api keys of a tenant are held to its quotas by [`Quotas`],
+//! bytes it stores are charged as it writes, measured by [`spawn_usage`] and reported by [`tenants_usage`]
+//! and [`render`].
+
+use actix_web::{
+    get, rt, web, Error, HttpMessage, HttpResponse,
+    dev::{Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    error::PayloadError,
+    http::{Method, StatusCode},
+    web::Data,
+};
+use futures::{
+    executor, StreamExt,
+    future::{abortable, ok, AbortHandle, LocalBoxFuture, Ready},
+};
+use serde::Serialize;
+use std::{
+    cell::{Cell, RefCell},
+    fmt::Write,
+    rc::Rc,
+    sync::{
+        Arc, Mutex, RwLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    auth::{self, Acl},
+    errors::error_response,
+    keys,
+    memcached::Store,
+    namespaces::Namespaces,
+};
+
+/// ops reading or replaying keys which the request doesn't name
+const SPANNING_OPS: &[&str] = &["watch", "events", "import"];
+/// ops taking every key unless limited by `prefix`
+const PREFIXED_OPS: &[&str] = &["export", "dump"];
+/// ops only freeing bytes, allowed above max_bytes
+const FREEING_OPS: &[&str] = &["delete", "lpop", "rpop", "srem", "hdel"];
+
+#[derive(Clone, Debug, Default)]
+pub struct Quota {
+    /// bytes of values of keys with the tenant prefixes, writes are rejected once a body would exceed it
+    pub max_bytes: Option<u64>,
+    pub max_requests_per_sec: Option<u64>,
+    /// keys the tenant may touch, any if empty
+    pub key_prefixes: Vec<String>,
+}
+
+/// Tenant with its quota and usage so far.
+pub struct Tenant {
+    name: String,
+    quota: Quota,
+    started: Instant,
+    /// second since start of the current rate window and requests admitted in it
+    window: Mutex<(u64, u64)>,
+    requests: AtomicU64,
+    throttled: AtomicU64,
+    denied: AtomicU64,
+    rejected_writes: AtomicU64,
+    /// last measured, see [`spawn_usage`], and charged by writes since
+    bytes: AtomicU64,
+}
+
+impl Tenant {
+    /// `bytes` are the ones measured so far, e.g. by the tenant this one replaces on reload
+    pub fn new(name: String, quota: Quota, bytes: u64) -> Tenant {
+        Tenant {
+            name, quota,
+            started: Instant::now(),
+            window: Mutex::new((0, 0)),
+            requests: AtomicU64::new(0),
+            throttled: AtomicU64::new(0),
+            denied: AtomicU64::new(0),
+            rejected_writes: AtomicU64::new(0),
+            bytes: AtomicU64::new(bytes),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /// Counts the request against the rate limit, returns false if it is exceeded.
+    fn admit(&self) -> bool {
+        let max = match self.quota.max_requests_per_sec {
+            Some(max) => max,
+            None => return true,
+        };
+        let second = self.started.elapsed().as_secs();
+        let mut window = self.window.lock().unwrap();
+        if window.0 != second {
+            *window = (second, 0);
+        }
+        window.1 += 1;
+        window.1 <= max
+    }
+
+    fn allows(&self, key: &str) -> bool {
+        self.quota.key_prefixes.is_empty()
+            || self.quota.key_prefixes.iter().any(|prefix| key.starts_with(prefix.as_str()))
+    }
+
+    /// bytes a write may still add, `None` if there is no limit
+    fn room(&self) -> Option<u64> {
+        self.quota.max_bytes.map(|max| max.saturating_sub(self.bytes()))
+    }
+
+    fn usage(&self) -> TenantUsage {
+        TenantUsage {
+            name: self.name.clone(),
+            bytes: self.bytes(),
+            max_bytes: self.quota.max_bytes,
+            max_requests_per_sec: self.quota.max_requests_per_sec,
+            requests: self.requests.load(Ordering::Relaxed),
+            throttled: self.throttled.load(Ordering::Relaxed),
+            denied: self.denied.load(Ordering::Relaxed),
+            rejected_writes: self.rejected_writes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct TenantUsage {
+    pub name: String,
+    /// stored under the tenant prefixes as of the last measurement, with writes since charged
+    pub bytes: u64,
+    pub max_bytes: Option<u64>,
+    pub max_requests_per_sec: Option<u64>,
+    /// admitted by the rate limit
+    pub requests: u64,
+    /// rejected by the rate limit
+    pub throttled: u64,
+    /// touching keys outside the tenant prefixes
+    pub denied: u64,
+    /// writes rejected while the tenant was over max_bytes
+    pub rejected_writes: u64,
+}
+
+#[derive(Serialize)]
+struct TenantsResp {
+    tenants: Vec<TenantUsage>,
+}
+
+/// Usage of every tenant.
+#[get("/admin/tenants")]
+pub async fn tenants_usage(acl: Data<RwLock<Acl>>) -> HttpResponse {
+    let mut tenants: Vec<TenantUsage> = acl.read().unwrap().tenants().map(|tenant| tenant.usage()).collect();
+    tenants.sort_by(|a, b| a.name.cmp(&b.name));
+    HttpResponse::Ok().json(TenantsResp { tenants })
+}
+
+/// Name, type and help of a Prometheus metric, with the value it takes from a tenant.
+type Metric = (&'static str, &'static str, &'static str, fn(&TenantUsage) -> u64);
+
+/// Renders usage of every tenant in Prometheus text format.
+pub fn render(acl: &Acl, out: &mut String) {
+    let mut tenants: Vec<TenantUsage> = acl.tenants().map(|tenant| tenant.usage()).collect();
+    if tenants.is_empty() {
+        return
+    }
+    tenants.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let metrics: [Metric; 5] = [
+        ("memcached_tenant_bytes", "gauge", "bytes stored under the tenant prefixes", |usage| usage.bytes),
+        ("memcached_tenant_requests_total", "counter", "requests admitted by the tenant rate limit", |usage| usage.requests),
+        ("memcached_tenant_throttled_total", "counter", "requests rejected by the tenant rate limit", |usage| usage.throttled),
+        ("memcached_tenant_denied_total", "counter", "requests touching keys outside of the tenant prefixes", |usage| usage.denied),
+        ("memcached_tenant_rejected_writes_total", "counter", "writes rejected by the tenant max_bytes", |usage| usage.rejected_writes),
+    ];
+    for (name, kind, help, value) in metrics.iter() {
+        let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}", name, help, name, kind);
+        for usage in &tenants {
+            let _ = writeln!(out, "{}{{tenant=\"{}\"}} {}", name, usage.name, value(usage));
+        }
+    }
+}
+
+/// Measures bytes stored under prefixes of every tenant about every `interval`,
+/// across the default store and every namespace. Stores account bytes under the prefixes
+/// as they are written, so keys are walked only when the prefixes change, e.g. on reload.
+pub fn spawn_usage(
+    acl: Arc<RwLock<Acl>>, mc: Arc<Store>, namespaces: Arc<Namespaces>, interval: Duration,
+) -> AbortHandle {
+    let (task, handle) = abortable(async move {
+        loop {
+            let mut tenants: Vec<Arc<Tenant>> = acl.read().unwrap().tenants()
+                .filter(|tenant| !tenant.quota.key_prefixes.is_empty())
+                .cloned()
+                .collect();
+            // prefixes in a stable order, so stores don't walk keys again
+            tenants.sort_by(|a, b| a.name.cmp(&b.name));
+            if !tenants.is_empty() {
+                let mut stores = namespaces.stores();
+                stores.push(mc.clone());
+                // walks of changed prefixes hold the write lock, so they must not block the worker
+                let _ = web::block(move || {
+                    measure(&tenants, &stores);
+                    Ok::<_, ()>(())
+                }).await;
+            }
+            rt::time::delay_for(interval).await;
+        }
+    });
+    rt::spawn(async move {
+        let _ = task.await;
+    });
+    handle
+}
+
+/// Sums bytes under prefixes of each tenant, a key counts towards the first tenant allowing it.
+fn measure(tenants: &[Arc<Tenant>], stores: &[Arc<Store>]) {
+    let prefixes: Vec<String> = tenants.iter()
+        .flat_map(|tenant| tenant.quota.key_prefixes.iter().cloned())
+        .collect();
+    let mut bytes = vec![0; tenants.len()];
+    for mc in stores {
+        let mut mc = executor::block_on(mc.write());
+        mc.track_prefixes(&prefixes);
+        let owners = tenants.iter().enumerate()
+            .flat_map(|(owner, tenant)| tenant.quota.key_prefixes.iter().map(move |_| owner));
+        for (owner, (_, tracked)) in owners.zip(mc.prefix_bytes()) {
+            bytes[owner] += *tracked as u64;
+        }
+    }
+    for (tenant, bytes) in tenants.iter().zip(bytes) {
+        tenant.bytes.store(bytes, Ordering::Relaxed);
+    }
+}
+
+/// Middleware holding requests of tenants to their quotas, the tenant is put into request
+/// extensions by the acl check. Bodies are read up to `body_limit` to find the keys they name.
+pub struct Quotas {
+    pub body_limit: usize,
+}
+
+impl<S> Transform<S> for Quotas
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = Error> + 'static,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = QuotasMiddleware<S>;
+    type Future = Ready<Result<QuotasMiddleware<S>, ()>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(QuotasMiddleware { service: Rc::new(RefCell::new(service)), body_limit: self.body_limit })
+    }
+}
+
+pub struct QuotasMiddleware<S> {
+    service: Rc<RefCell<S>>,
+    body_limit: usize,
+}
+
+impl<S> Service for QuotasMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = Error> + 'static,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<ServiceResponse, Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let tenant = req.extensions().get::<Arc<Tenant>>().cloned();
+        let tenant = match tenant {
+            Some(tenant) => tenant,
+            None => return Box::pin(service.borrow_mut().call(req)),
+        };
+        let body_limit = self.body_limit;
+
+        Box::pin(async move {
+            if !tenant.admit() {
+                tenant.throttled.fetch_add(1, Ordering::Relaxed);
+                let throttled = error_response(StatusCode::TOO_MANY_REQUESTS, "tenant request rate exceeded");
+                return Ok(req.into_response(throttled))
+            }
+            tenant.requests.fetch_add(1, Ordering::Relaxed);
+
+            if !tenant.quota.key_prefixes.is_empty() {
+                if let Err(denied) = check_keys(&tenant, &mut req, body_limit).await {
+                    tenant.denied.fetch_add(1, Ordering::Relaxed);
+                    return Ok(req.into_response(denied))
+                }
+            }
+            let path = auth::routed_path(&req);
+            let freeing = req.method() == Method::DELETE || FREEING_OPS.contains(&keys::route(path).0);
+            let room = tenant.room().filter(|_| !freeing && !auth::is_read(req.method(), path));
+            let room = match room {
+                Some(room) => room,
+                None => {
+                    let res = service.borrow_mut().call(req);
+                    return res.await
+                },
+            };
+            if room == 0 {
+                tenant.rejected_writes.fetch_add(1, Ordering::Relaxed);
+                return Ok(req.into_response(storage_exceeded()))
+            }
+
+            // the body is charged as it is read, so a write can't take more than the room left
+            let written = Rc::new(Cell::new(0));
+            let counted = written.clone();
+            let payload = req.take_payload().map(move |chunk| {
+                let chunk = chunk?;
+                counted.set(counted.get() + chunk.len() as u64);
+                match counted.get() > room {
+                    true => Err(PayloadError::Overflow),
+                    false => Ok(chunk),
+                }
+            });
+            req.set_payload(Payload::Stream(Box::pin(payload)));
+
+            let res = service.borrow_mut().call(req);
+            let res = res.await?;
+            if written.get() > room {
+                tenant.rejected_writes.fetch_add(1, Ordering::Relaxed);
+                return Ok(res.into_response(storage_exceeded()))
+            }
+            if res.status().is_success() {
+                tenant.bytes.fetch_add(written.get(), Ordering::Relaxed);
+            }
+            Ok(res)
+        })
+    }
+}
+
+fn storage_exceeded() -> HttpResponse {
+    error_response(StatusCode::INSUFFICIENT_STORAGE, "tenant storage quota exceeded")
+}
+
+/// Checks every key the request names, in its path, query or json body, against the tenant prefixes.
+async fn check_keys(tenant: &Tenant, req: &mut ServiceRequest, body_limit: usize) -> Result<(), HttpResponse> {
+    let path = auth::routed_path(req).to_owned();
+    let (op, _) = keys::route(&path);
+    if SPANNING_OPS.contains(&op) {
+        return Err(error_response(StatusCode::FORBIDDEN, "operation spans keys outside of tenant prefixes"))
+    }
+
+    let mut keys = keys::request_keys(req, body_limit).await?
+        .ok_or_else(|| error_response(StatusCode::UNSUPPORTED_MEDIA_TYPE, "encoded bodies can't be checked against tenant prefixes"))?;
+    // exporting without a prefix takes every key
+    if PREFIXED_OPS.contains(&op) && keys.is_empty() {
+        keys.push(String::new());
+    }
+
+    match keys.iter().all(|key| tenant.allows(key)) {
+        true => Ok(()),
+        false => Err(error_response(StatusCode::FORBIDDEN, "key outside of tenant prefixes")),
+    }
+}
@@ -0,0 +1,406 @@
+//! Black box testing of the http api: real server on a random port with controllable time.
+//! Everything here must be used within actix runtime, e.g. from `#[actix_rt::test]`.
+
+use serde::{Serialize, Deserialize};
+use serde_json::json;
+use actix_web::{
+    App, rt, test,
+    client::ClientRequest,
+    dev::{Server, Service},
+    http::{Method, StatusCode},
+    middleware::{Compress, Condition},
+    web::Data,
+};
+use futures::future::AbortHandle;
+use std::{
+    net,
+    path::PathBuf,
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+
+use crate::{
+    api,
+    memcached::{self, Store, GcBudget, Options, StdClock},
+    namespaces::Namespaces,
+    l1::L1Config,
+    events::EventBus,
+    origin::OriginConfig,
+    disk::DiskTier,
+    auth::{Acl, AclCheck, DEFAULT_NAMESPACE},
+    tenants::{self, Quotas},
+    replication::{self, Primary},
+    udp,
+    logging::{LogFilter, Outcome},
+    readonly::{self, ReadOnly, ReadOnlyCheck},
+    h2c,
+    cluster::Cluster,
+    warmup,
+    slowlog::SlowLog,
+    metrics::Metrics,
+    settings::ConnectionLimits,
+    compression::CompressionFilter,
+    cors::CorsConfig,
+};
+
+#[derive(Deserialize)]
+struct DataResp {
+    data: String,
+}
+
+pub struct TestServerBuilder {
+    memory_limit: usize,
+    gc_interval: Duration,
+    gc_budget: GcBudget,
+    options: Options,
+    json_limit: usize,
+    decompress_limit: usize,
+    import_limit: usize,
+    l1: L1Config,
+    origin: Option<OriginConfig>,
+    disk_tier: Option<(PathBuf, u64)>,
+    replicas: Vec<String>,
+    replica: Option<net::TcpListener>,
+    replication_secret: String,
+    udp: Option<net::UdpSocket>,
+    h2c: Option<net::TcpListener>,
+    cluster: Option<Cluster>,
+    warmup_file: Option<PathBuf>,
+    slowlog_threshold: Duration,
+    read_only: Arc<ReadOnly>,
+    compression: CompressionFilter,
+    cors: CorsConfig,
+    acl: Arc<RwLock<Acl>>,
+}
+
+impl Default for TestServerBuilder {
+    fn default() -> TestServerBuilder {
+        TestServerBuilder {
+            memory_limit: 1 << 20,
+            gc_interval: Duration::from_millis(100),
+            gc_budget: GcBudget::default(),
+            options: Options::default(),
+            json_limit: 1 << 20,
+            decompress_limit: 8 << 20,
+            import_limit: 1 << 30,
+            l1: L1Config::default(),
+            origin: None,
+            disk_tier: None,
+            replicas: Vec::new(),
+            replica: None,
+            replication_secret: "secret".to_owned(),
+            udp: None,
+            h2c: None,
+            cluster: None,
+            warmup_file: None,
+            slowlog_threshold: Duration::from_millis(10),
+            read_only: Arc::new(ReadOnly::default()),
+            compression: CompressionFilter::new(false, 0, ""),
+            cors: CorsConfig::new("", "", ""),
+            acl: Arc::new(RwLock::new(Acl::default())),
+        }
+    }
+}
+
+impl TestServerBuilder {
+    pub fn memory_limit(mut self, memory_limit: usize) -> TestServerBuilder {
+        self.memory_limit = memory_limit;
+        self
+    }
+
+    pub fn gc_interval(mut self, gc_interval: Duration) -> TestServerBuilder {
+        self.gc_interval = gc_interval;
+        self
+    }
+
+    pub fn gc_budget(mut self, gc_budget: GcBudget) -> TestServerBuilder {
+        self.gc_budget = gc_budget;
+        self
+    }
+
+    pub fn options(mut self, options: Options) -> TestServerBuilder {
+        self.options = options;
+        self
+    }
+
+    pub fn json_limit(mut self, json_limit: usize) -> TestServerBuilder {
+        self.json_limit = json_limit;
+        self
+    }
+
+    pub fn decompress_limit(mut self, decompress_limit: usize) -> TestServerBuilder {
+        self.decompress_limit = decompress_limit;
+        self
+    }
+
+    pub fn import_limit(mut self, import_limit: usize) -> TestServerBuilder {
+        self.import_limit = import_limit;
+        self
+    }
+
+    pub fn l1(mut self, l1: L1Config) -> TestServerBuilder {
+        self.l1 = l1;
+        self
+    }
+
+    pub fn origin(mut self, origin: OriginConfig) -> TestServerBuilder {
+        self.origin = Some(origin);
+        self
+    }
+
+    /// cold tier of the default store
+    pub fn disk_tier(mut self, path: PathBuf, limit: u64) -> TestServerBuilder {
+        self.disk_tier = Some((path, limit));
+        self
+    }
+
+    /// replication addresses the default store is streamed to
+    pub fn replicas(mut self, replicas: Vec<String>) -> TestServerBuilder {
+        self.replicas = replicas;
+        self
+    }
+
+    /// secret shared by primary and replicas, `secret` by default
+    pub fn replication_secret(mut self, secret: &str) -> TestServerBuilder {
+        self.replication_secret = secret.to_owned();
+        self
+    }
+
+    /// makes the default store a replica of a primary connecting to `listener`
+    pub fn replica(mut self, listener: net::TcpListener) -> TestServerBuilder {
+        self.replica = Some(listener);
+        self
+    }
+
+    /// serves memcached UDP protocol on `socket`
+    pub fn udp(mut self, socket: net::UdpSocket) -> TestServerBuilder {
+        self.udp = Some(socket);
+        self
+    }
+
+    /// also serves the api over cleartext HTTP/2 on `listener`
+    pub fn h2c(mut self, listener: net::TcpListener) -> TestServerBuilder {
+        self.h2c = Some(listener);
+        self
+    }
+
+    pub fn cluster(mut self, cluster: Cluster) -> TestServerBuilder {
+        self.cluster = Some(cluster);
+        self
+    }
+
+    /// entries loaded into the default store before start
+    pub fn warmup_file(mut self, path: PathBuf) -> TestServerBuilder {
+        self.warmup_file = Some(path);
+        self
+    }
+
+    pub fn slowlog_threshold(mut self, threshold: Duration) -> TestServerBuilder {
+        self.slowlog_threshold = threshold;
+        self
+    }
+
+    /// mode checked the way the server checks it, switched by the test or by `PUT /admin/readonly`
+    pub fn read_only(mut self, read_only: Arc<ReadOnly>) -> TestServerBuilder {
+        self.read_only = read_only;
+        self
+    }
+
+    /// api keys and tenants checked the way the server checks them, tenant usage is measured
+    /// as often as gc runs
+    pub fn acl(mut self, acl: Arc<RwLock<Acl>>) -> TestServerBuilder {
+        self.acl = acl;
+        self
+    }
+
+    /// responses are compressed the way the server compresses them
+    pub fn compression(mut self, compression: CompressionFilter) -> TestServerBuilder {
+        self.compression = compression;
+        self
+    }
+
+    /// CORS policy the way the server applies it, disabled by default
+    pub fn cors(mut self, cors: CorsConfig) -> TestServerBuilder {
+        self.cors = cors;
+        self
+    }
+
+    pub fn start(self) -> TestServer {
+        let clock = StdClock::new();
+        let events = Arc::new(EventBus::new(clock.clone()));
+        let mut mc = memcached::new(self.memory_limit, clock.clone(), self.options.clone());
+        let primary = Arc::new(Primary::new(1024));
+        mc.set_listener(primary.listener(events.listener(DEFAULT_NAMESPACE.to_owned())));
+        if let Some((path, limit)) = self.disk_tier {
+            mc.set_cold_tier(Box::new(DiskTier::open(path, limit, clock.clone()).expect("can't open disk tier")));
+        }
+        if let Some(path) = self.warmup_file {
+            warmup::load(&mut mc, path).expect("can't warm up");
+        }
+        let slowlog = Arc::new(SlowLog::new(self.slowlog_threshold, 128));
+        let metrics = Arc::new(Metrics::new(vec![Duration::from_millis(1), Duration::from_millis(10)]));
+        let mc = Store::new(mc)
+            .with_slowlog(slowlog.clone())
+            .with_metrics(metrics.clone());
+        let mc = Arc::new(mc);
+        let gc = memcached::spawn_gc(&mc, self.gc_interval, self.gc_budget);
+        let mut tasks = vec![replication::spawn_primary(&primary, &mc, self.replicas, self.replication_secret.clone())];
+        if let Some(listener) = self.replica {
+            tasks.push(replication::spawn_replica(&mc, listener, self.replication_secret).expect("can't start replica"));
+        }
+        let log_filter = Arc::new(LogFilter::detached());
+        if let Some(socket) = self.udp {
+            tasks.push(udp::spawn(&mc, socket, false, self.read_only.clone(), self.acl.clone()).expect("can't serve udp"));
+        }
+        let namespaces = Arc::new(Namespaces::new(
+            clock.clone(), self.gc_interval, self.gc_budget, self.options, events.clone(),
+            slowlog.clone(), metrics,
+        ));
+        tasks.push(tenants::spawn_usage(self.acl.clone(), mc.clone(), namespaces.clone(), self.gc_interval));
+
+        let service_factory = api::service(
+            mc, namespaces.clone(), events.clone(),
+            self.json_limit, self.decompress_limit, self.import_limit, self.l1, self.origin, self.cluster, slowlog,
+            log_filter,
+        );
+        let (read_only, acl, json_limit, compression, cors) = (self.read_only, self.acl, self.json_limit, self.compression, self.cors);
+        let outcomes = Arc::new(Mutex::new(Vec::new()));
+        let logged = outcomes.clone();
+        let app = move || {
+            let compression = compression.clone();
+            let logged = logged.clone();
+            App::new()
+                .app_data(Data::from(read_only.clone()))
+                .app_data(Data::from(acl.clone()))
+                .service(readonly::status)
+                .service(readonly::toggle)
+                .service(tenants::tenants_usage)
+                .service(service_factory())
+                .wrap_fn(move |req, srv| {
+                    let logged = logged.clone();
+                    let res = srv.call(req);
+                    async move {
+                        let res = res.await?;
+                        if let Some(outcome) = res.response().extensions().get::<Outcome>() {
+                            logged.lock().unwrap().push(*outcome);
+                        }
+                        Ok(res)
+                    }
+                })
+                .wrap_fn(move |req, srv| {
+                    let compression = compression.clone();
+                    let res = srv.call(req);
+                    async move { Ok(compression.apply(res.await?)) }
+                })
+                .wrap(Quotas { body_limit: json_limit })
+                .wrap(AclCheck(acl.clone()))
+                .wrap(ReadOnlyCheck(read_only.clone()))
+                .wrap(Compress::default())
+                .wrap(Condition::new(cors.is_enabled(), cors.build()))
+        };
+        let h2c = self.h2c.map(|listener| h2c::start(listener, Some(1), ConnectionLimits::default(), app.clone()).expect("can't serve h2c"));
+        let server = test::start(app);
+
+        TestServer { server, h2c, clock, gc, tasks, namespaces, events, outcomes }
+    }
+}
+
+/// Server with the api scope and a typed client for it.
+/// Typed methods panic on transport errors and unexpected statuses.
+pub struct TestServer {
+    server: test::TestServer,
+    h2c: Option<Server>,
+    clock: StdClock,
+    gc: AbortHandle,
+    /// replication, udp serving and tenant usage
+    tasks: Vec<AbortHandle>,
+    namespaces: Arc<Namespaces>,
+    events: Arc<EventBus>,
+    /// of responses with one, in the order they were sent
+    outcomes: Arc<Mutex<Vec<Outcome>>>,
+}
+
+impl TestServer {
+    pub fn start() -> TestServer {
+        TestServerBuilder::default().start()
+    }
+
+    pub fn builder() -> TestServerBuilder {
+        TestServerBuilder::default()
+    }
+
+    /// Outcomes the access log shows so far, responses without one are left out.
+    pub fn outcomes(&self) -> Vec<Outcome> {
+        self.outcomes.lock().unwrap().clone()
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        self.server.url(path)
+    }
+
+    /// Mutations of every store of the server.
+    pub fn events(&self) -> &Arc<EventBus> {
+        &self.events
+    }
+
+    /// Moves time forward for every store of the server.
+    pub fn advance_time(&self, by: Duration) {
+        self.clock.advance(by);
+    }
+
+    /// raw request for anything typed methods don't cover
+    pub fn post(&self, path: &str) -> ClientRequest {
+        self.server.post(path)
+    }
+
+    /// raw GET request, e.g. for stats and job status
+    pub fn get_request(&self, path: &str) -> ClientRequest {
+        self.server.get(path)
+    }
+
+    /// raw request of any method, e.g. for key routes
+    pub fn request(&self, method: Method, path: &str) -> ClientRequest {
+        self.server.request(method, self.url(path))
+    }
+
+    pub async fn get(&self, key: &str) -> Option<String> {
+        self.data_or_none("/get", &json!({ "key": key })).await
+    }
+
+    /// returns response status, 200 means value is stored
+    pub async fn set(&self, key: &str, data: &str, ttl: Option<&str>) -> StatusCode {
+        self.post("/set")
+            .send_json(&json!({ "key": key, "data": data, "ttl": ttl }))
+            .await
+            .expect("request failed")
+            .status()
+    }
+
+    pub async fn delete(&self, key: &str) -> Option<String> {
+        self.data_or_none("/delete", &json!({ "key": key })).await
+    }
+
+    async fn data_or_none(&self, path: &str, req: &impl Serialize) -> Option<String> {
+        let mut resp = self.post(path)
+            .send_json(req)
+            .await
+            .expect("request failed");
+
+        match resp.status() {
+            StatusCode::OK => Some(resp.json::<DataResp>().await.expect("invalid response").data),
+            StatusCode::NOT_FOUND => None,
+            status => panic!("unexpected status {} from {}", status, path),
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.gc.abort();
+        self.tasks.iter().for_each(AbortHandle::abort);
+        self.namespaces.shutdown();
+        if let Some(h2c) = &self.h2c {
+            rt::spawn(h2c.stop(false));
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use actix_web::rt;
+use futures::future::{abortable, AbortHandle};
+use tokio::net::UdpSocket;
+use tracing::warn;
+use std::{
+    io, net, str,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    auth::Acl,
+    memcached::Store,
+    readonly::ReadOnly,
+};
+
+/// datagram size memcached clients expect at most, frame header included
+const MAX_DATAGRAM: usize = 1400;
+/// responses are cut off at this many times the request datagram, so that a spoofed request
+/// doesn't reflect much more traffic at its victim
+const AMPLIFICATION: usize = 10;
+/// request id, sequence number, datagram count and reserved, u16 each
+const HEADER: usize = 8;
+/// memcached exptime above 30 days is a unix timestamp rather than seconds from now
+const MAX_RELATIVE_EXPTIME: i64 = 60 * 60 * 24 * 30;
+const MAX_KEY: usize = 250;
+
+const END: &[u8] = b"END\r\n";
+const STORED: &[u8] = b"STORED\r\n";
+const ERROR: &[u8] = b"ERROR\r\n";
+const BAD_COMMAND: &[u8] = b"CLIENT_ERROR bad command line format\r\n";
+const BAD_DATA: &[u8] = b"CLIENT_ERROR bad data chunk\r\n";
+const NOT_STORED: &[u8] = b"SERVER_ERROR out of memory storing object\r\n";
+const READ_ONLY: &[u8] = b"SERVER_ERROR read only\r\n";
+const TOO_LARGE: &[u8] = b"SERVER_ERROR response is too large\r\n";
+const ACL_ENABLED: &[u8] = b"SERVER_ERROR api keys are required, use http\r\n";
+
+/// Serves `get` and `set` of the default store over memcached UDP protocol: a datagram is a frame
+/// header followed by a text protocol command. Requests must fit in a single datagram, lost ones
+/// are just not answered. Flags are not stored, values are returned with 0. Responses longer than
+/// [`AMPLIFICATION`] times the request datagram are replaced by an error.
+/// Sets are refused on a `replica` and while `read_only` mode is on.
+///
+/// Datagrams carry no api key, so every command is refused while `acl` has keys, and the socket
+/// should only be reachable by trusted clients: settings refuse a `udp_addr` that isn't loopback
+/// or private. Keys are not routed to their cluster owner either, settings refuse `udp_addr` in
+/// cluster mode.
+pub fn spawn(
+    mc: &Arc<Store>, socket: net::UdpSocket, replica: bool, read_only: Arc<ReadOnly>, acl: Arc<RwLock<Acl>>,
+) -> io::Result<AbortHandle> {
+    socket.set_nonblocking(true)?;
+    let socket = UdpSocket::from_std(socket)?;
+    let (task, handle) = abortable(serve(mc.clone(), socket, replica, read_only, acl));
+    rt::spawn(async move {
+        let _ = task.await;
+    });
+    Ok(handle)
+}
+
+async fn serve(mc: Arc<Store>, mut socket: UdpSocket, replica: bool, read_only: Arc<ReadOnly>, acl: Arc<RwLock<Acl>>) {
+    let mut buf = vec![0; 1 << 16];
+    loop {
+        let (len, peer) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(err) => {
+                warn!("can't receive udp request: {}", err);
+                continue
+            },
+        };
+        let (request_id, command) = match parse_header(&buf[..len]) {
+            Some(request) => request,
+            None => continue,
+        };
+
+        let acl_enabled = acl.read().unwrap().is_enabled();
+        let response = match acl_enabled {
+            true => ACL_ENABLED.to_vec(),
+            false => handle(&mc, command, replica || read_only.is_enabled()).await,
+        };
+        let response = match response.len() > len * AMPLIFICATION {
+            true => TOO_LARGE.to_vec(),
+            false => response,
+        };
+        for datagram in frames(request_id, &response) {
+            if let Err(err) = socket.send_to(&datagram, &peer).await {
+                warn!("can't send udp response to {}: {}", peer, err);
+                break
+            }
+        }
+    }
+}
+
+/// request id of a single datagram request and its command, `None` if the frame is malformed
+fn parse_header(datagram: &[u8]) -> Option<(u16, &[u8])> {
+    if datagram.len() < HEADER {
+        return None
+    }
+    let field = |at: usize| u16::from_be_bytes([datagram[at], datagram[at + 1]]);
+    match (field(2), field(4)) {
+        (0, 1) => Some((field(0), &datagram[HEADER..])),
+        _ => None,
+    }
+}
+
+/// Splits the response into datagrams, an empty response is not sent at all.
+fn frames(request_id: u16, response: &[u8]) -> Vec<Vec<u8>> {
+    let chunks: Vec<&[u8]> = response.chunks(MAX_DATAGRAM - HEADER).collect();
+    let total = chunks.len() as u16;
+    chunks.into_iter().enumerate()
+        .map(|(seq, chunk)| {
+            let mut datagram = Vec::with_capacity(HEADER + chunk.len());
+            for field in [request_id, seq as u16, total, 0].iter() {
+                datagram.extend_from_slice(&field.to_be_bytes());
+            }
+            datagram.extend_from_slice(chunk);
+            datagram
+        })
+        .collect()
+}
+
+async fn handle(mc: &Store, request: &[u8], read_only: bool) -> Vec<u8> {
+    let line_end = match request.windows(2).position(|end| end == b"\r\n") {
+        Some(at) => at,
+        None => return BAD_COMMAND.to_vec(),
+    };
+    let line = match str::from_utf8(&request[..line_end]) {
+        Ok(line) => line,
+        Err(_) => return BAD_COMMAND.to_vec(),
+    };
+    let mut words = line.split(' ').filter(|word| !word.is_empty());
+
+    match words.next() {
+        Some("get") => get(mc, words.collect()).await,
+        Some("set") => set(mc, words.collect(), &request[line_end + 2..], read_only).await,
+        _ => ERROR.to_vec(),
+    }
+}
+
+async fn get(mc: &Store, keys: Vec<&str>) -> Vec<u8> {
+    if keys.is_empty() {
+        return ERROR.to_vec()
+    }
+    let keys: Vec<&str> = keys.into_iter().filter(|key| mc.may_contain(key)).collect();
+
+    let mut response = Vec::new();
+    let mut sliding = Vec::new();
+    if !keys.is_empty() {
+        let store = mc.read().await;
+        for key in keys {
+            let data = match store.get(key) {
+                Some(data) => data,
+                None => continue,
+            };
+            if store.is_sliding(key) {
+                sliding.push(key);
+            }
+            response.extend_from_slice(format!("VALUE {} 0 {}\r\n", key, data.len()).as_bytes());
+            response.extend_from_slice(&data);
+            response.extend_from_slice(b"\r\n");
+        }
+    }
+    // sliding items must reach the store on every read to stay alive
+    if !sliding.is_empty() {
+        let mut store = mc.write().await;
+        sliding.into_iter().for_each(|key| {
+            store.refresh(key);
+        });
+    }
+
+    response.extend_from_slice(END);
+    response
+}
+
+/// `set <key> <flags> <exptime> <bytes> [noreply]` followed by the data block
+async fn set(mc: &Store, args: Vec<&str>, block: &[u8], read_only: bool) -> Vec<u8> {
+    let (key, exptime, len, noreply) = match args.as_slice() {
+        [key, flags, exptime, len, rest @ ..] if key.len() <= MAX_KEY && rest.len() <= 1 => {
+            match (flags.parse::<u32>(), exptime.parse::<i64>(), len.parse::<usize>()) {
+                (Ok(_), Ok(exptime), Ok(len)) => (*key, exptime, len, rest == ["noreply"]),
+                _ => return BAD_COMMAND.to_vec(),
+            }
+        },
+        _ => return BAD_COMMAND.to_vec(),
+    };
+    if block.len() != len + 2 || !block.ends_with(b"\r\n") {
+        return BAD_DATA.to_vec()
+    }
+    if read_only {
+        return READ_ONLY.to_vec()
+    }
+
+    let data = block[..len].to_vec();
+    let mut mc = mc.write().await;
+    let response = match ttl(exptime) {
+        // already expired, memcached just makes the key missing
+        Some(ttl) if ttl == Duration::from_secs(0) => {
+            mc.delete(key);
+            STORED
+        },
+        ttl => match mc.set(key.to_owned(), data, ttl) {
+            Ok(()) => STORED,
+            Err(_) => NOT_STORED,
+        },
+    };
+    match noreply {
+        true => Vec::new(),
+        false => response.to_vec(),
+    }
+}
+
+/// `None` for values which never expire
+fn ttl(exptime: i64) -> Option<Duration> {
+    match exptime {
+        0 => None,
+        exptime if exptime < 0 => Some(Duration::from_secs(0)),
+        exptime if exptime <= MAX_RELATIVE_EXPTIME => Some(Duration::from_secs(exptime as u64)),
+        deadline => {
+            let deadline = UNIX_EPOCH + Duration::from_secs(deadline as u64);
+            Some(deadline.duration_since(SystemTime::now()).unwrap_or_default())
+        },
+    }
+}
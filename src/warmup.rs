@@ -0,0 +1,59 @@
+use serde::Deserialize;
+use duration_string::DurationString;
+use tracing::warn;
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, ErrorKind::InvalidData},
+    path::Path,
+};
+
+use crate::memcached::Memcached;
+
+/// Line of a warm-up file: either a value with optional ttl
+/// or an item exported by `/dump/bulk`.
+#[derive(Deserialize)]
+struct Entry {
+    key: String,
+    data: Option<String>,
+    ttl: Option<DurationString>,
+    dump: Option<String>,
+}
+
+/// Loads newline delimited json entries into the store, returns how many of them are stored.
+/// Malformed line fails the whole load, entries the store can't fit are skipped.
+pub fn load(mc: &mut Memcached, path: impl AsRef<Path>) -> io::Result<usize> {
+    read(mc, BufReader::new(File::open(path)?))
+}
+
+/// [`load`] from any reader.
+pub fn read(mc: &mut Memcached, reader: impl BufRead) -> io::Result<usize> {
+    let mut stored = 0;
+    for (n, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue
+        }
+        let invalid = |err: String| io::Error::new(InvalidData, format!("line {}: {}", n + 1, err));
+
+        let entry: Entry = serde_json::from_str(&line).map_err(|err| invalid(err.to_string()))?;
+        let result = match entry {
+            Entry { key, dump: Some(dump), data: None, ttl: None } => {
+                let dump = crate::dump::decode(&dump).map_err(|err| invalid(err.to_string()))?;
+                mc.restore(key, dump)
+            },
+            Entry { key, data: Some(data), dump: None, ttl } => {
+                mc.set(key, data.into_bytes(), ttl.map(Into::into))
+            },
+            _ => return Err(invalid("either data or dump must be set".to_owned())),
+        };
+
+        match result {
+            Ok(()) => stored += 1,
+            Err(err) => {
+                let (key, _) = err.into_kv();
+                warn!("warm-up entry {} is not stored", key);
+            },
+        }
+    }
+    Ok(stored)
+}
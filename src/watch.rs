@@ -0,0 +1,86 @@
+use serde::Deserialize;
+use serde_json::json;
+use actix::{Actor, ActorContext, AsyncContext, StreamHandler};
+use actix_web::{HttpRequest, HttpResponse, web::Payload};
+use actix_web_actors::ws::{self, WebsocketContext, Message, ProtocolError};
+use futures::channel::mpsc::Receiver;
+use std::sync::Arc;
+
+use crate::events::{self, EventBus, KeyEvent};
+
+/// events waiting to be sent to a single client, beyond that events are dropped
+const QUEUE: usize = 1024;
+
+/// Client command, e.g. `{"subscribe": "user:*"}`.
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Command {
+    Subscribe(String),
+    Unsubscribe(String),
+}
+
+/// Streams events of keys matching client patterns as json text messages.
+struct Watcher {
+    patterns: Vec<String>,
+    events: Option<Receiver<Arc<KeyEvent>>>,
+}
+
+/// Upgrades the request to websocket streaming events of the given namespace,
+/// `pattern` is the initial subscription.
+pub fn start(
+    events: &EventBus, namespace: String, pattern: Option<String>,
+    req: &HttpRequest, stream: Payload,
+) -> Result<HttpResponse, actix_web::Error> {
+    let events = events.subscribe(QUEUE, Box::new(move |event: &KeyEvent| event.namespace == namespace));
+    let watcher = Watcher { patterns: pattern.into_iter().collect(), events: Some(events) };
+    ws::start(watcher, req, stream)
+}
+
+impl Actor for Watcher {
+    type Context = WebsocketContext<Watcher>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        if let Some(events) = self.events.take() {
+            ctx.add_stream(events);
+        }
+    }
+}
+
+impl StreamHandler<Arc<KeyEvent>> for Watcher {
+    fn handle(&mut self, event: Arc<KeyEvent>, ctx: &mut Self::Context) {
+        if self.patterns.iter().any(|pattern| events::matches(pattern, &event.key)) {
+            ctx.text(serde_json::to_string(&*event).unwrap_or_default());
+        }
+    }
+}
+
+impl StreamHandler<Result<Message, ProtocolError>> for Watcher {
+    fn handle(&mut self, msg: Result<Message, ProtocolError>, ctx: &mut Self::Context) {
+        match msg {
+            Ok(Message::Text(text)) => {
+                let reply = match serde_json::from_str(&text) {
+                    Ok(Command::Subscribe(pattern)) => {
+                        let reply = json!({ "subscribed": pattern });
+                        if !self.patterns.contains(&pattern) {
+                            self.patterns.push(pattern);
+                        }
+                        reply
+                    },
+                    Ok(Command::Unsubscribe(pattern)) => {
+                        self.patterns.retain(|subscribed| *subscribed != pattern);
+                        json!({ "unsubscribed": pattern })
+                    },
+                    Err(err) => json!({ "error": err.to_string() }),
+                };
+                ctx.text(reply.to_string());
+            },
+            Ok(Message::Ping(msg)) => ctx.pong(&msg),
+            Ok(Message::Close(reason)) => {
+                ctx.close(reason);
+                ctx.stop();
+            },
+            Ok(_) => (),
+            Err(_) => ctx.stop(),
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use actix_web::{rt, client::Client};
+use futures::{
+    StreamExt,
+    channel::mpsc::Receiver,
+    future::{abortable, AbortHandle},
+};
+use tracing::{warn, error};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::events::{EventBus, KeyEvent};
+
+/// events waiting to be batched, beyond that events are dropped
+const QUEUE: usize = 4096;
+
+/// delay before the first retry, doubled for each next one
+const BACKOFF: Duration = Duration::from_millis(100);
+
+pub struct WebhookConfig {
+    pub url: String,
+    pub batch_size: usize,
+    pub flush_interval: Duration,
+    pub retries: u32,
+}
+
+/// Posts deleted, expired and evicted keys to the configured url as json arrays
+/// until the task is aborted. Batches which can't be delivered are dropped.
+pub fn spawn(events: &EventBus, config: WebhookConfig) -> AbortHandle {
+    let events = events.subscribe(QUEUE, Box::new(|event: &KeyEvent| !matches!(event.reason, "set" | "prioritized")));
+    let (task, handle) = abortable(deliver(events, config));
+    rt::spawn(async move {
+        let _ = task.await;
+    });
+    handle
+}
+
+async fn deliver(mut events: Receiver<Arc<KeyEvent>>, config: WebhookConfig) {
+    let client = Client::default();
+    while let Some(first) = events.next().await {
+        let mut batch = vec![first];
+        let deadline = Instant::now() + config.flush_interval;
+        while batch.len() < config.batch_size {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match rt::time::timeout(left, events.next()).await {
+                Ok(Some(event)) => batch.push(event),
+                _ => break,
+            }
+        }
+
+        post(&client, &config, &batch).await;
+    }
+}
+
+async fn post(client: &Client, config: &WebhookConfig, batch: &[Arc<KeyEvent>]) {
+    let events: Vec<&KeyEvent> = batch.iter().map(AsRef::as_ref).collect();
+    let mut backoff = BACKOFF;
+    for attempt in 0..=config.retries {
+        if attempt > 0 {
+            rt::time::delay_for(backoff).await;
+            backoff *= 2;
+        }
+
+        match client.post(&config.url).send_json(&events).await {
+            Ok(res) if res.status().is_success() => return,
+            Ok(res) => warn!("webhook {} responded {}", config.url, res.status()),
+            Err(err) => warn!("webhook {} failed: {}", config.url, err),
+        }
+    }
+    error!("webhook {} unreachable, {} events dropped", config.url, batch.len());
+}
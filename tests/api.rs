@@ -0,0 +1,2522 @@
+use rust_memcached::{
+    testing::TestServer,
+    l1::L1Config,
+    memcached::{self, Clock, ColdTier, End, GcBudget, KeyRules, Options, SlabConfig, Store, StdClock},
+    namespaces::Namespaces,
+    events::EventBus,
+    slowlog::SlowLog,
+    webhook::{self, WebhookConfig},
+    origin::{OriginConfig, WriteMode},
+    cluster::Cluster,
+    metrics::Metrics,
+    handover,
+    bench::{self, Workload},
+    disk::{self, DiskTier},
+    tenants::{Quota, Quotas, Tenant},
+    readonly::ReadOnly,
+    keys::KeyCheck,
+    compression::CompressionFilter,
+    cors::CorsConfig,
+    audit::{AuditKey, key_hash},
+    logging::{Access, KeyLogging, Outcome},
+    telemetry::{self, OtlpConfig, Telemetry},
+    auth::{Acl, ApiKey},
+    bootstrap::Bootstrap,
+    hotkeys::{HotKeys, DECAY_EVERY},
+    settings::{self, ConnectionLimits, Settings},
+};
+use actix_web::{App, HttpMessage, HttpResponse, HttpServer, dev::Service, http::{Method, StatusCode}, test, web};
+use awc::ws::{Frame, Message};
+use futures::{Stream, StreamExt, SinkExt};
+use serde_json::{json, Value};
+use duration_string::DurationString;
+use tracing_subscriber::fmt::format::FmtSpan;
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    sync::{
+        Arc, Mutex, RwLock,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+#[actix_rt::test]
+async fn set_get_delete() {
+    let srv = TestServer::start();
+
+    assert_eq!(srv.set("a", "data", None).await, StatusCode::OK);
+    assert_eq!(srv.get("a").await, Some("data".to_owned()));
+    assert_eq!(srv.delete("a").await, Some("data".to_owned()));
+    assert_eq!(srv.get("a").await, None);
+}
+
+#[actix_rt::test]
+async fn expire() {
+    let srv = TestServer::start();
+
+    srv.set("a", "data", Some("1s")).await;
+    assert_eq!(srv.get("a").await, Some("data".to_owned()));
+
+    srv.advance_time(Duration::from_secs(2));
+    assert_eq!(srv.get("a").await, None);
+}
+
+#[actix_rt::test]
+async fn gc_on_demand() {
+    let srv = TestServer::builder().gc_interval(Duration::from_secs(3600)).start();
+
+    for key in &["a", "b", "c"] {
+        srv.set(key, "data", Some("1s")).await;
+    }
+    srv.set("d", "data", None).await;
+    srv.advance_time(Duration::from_secs(2));
+
+    let mut resp = srv.post("/admin/gc").send_json(&json!({ "max_keys": 2 })).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(body["keys_removed"], 2);
+    assert_eq!(body["done"], false);
+    assert!(body["bytes_reclaimed"].as_u64().unwrap() > 0);
+    assert!(body["duration_ms"].is_number());
+
+    let mut resp = srv.post("/admin/gc").send_json(&json!({})).await.unwrap();
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(body["keys_removed"], 1);
+    assert_eq!(body["done"], true);
+    assert_eq!(srv.get("d").await, Some("data".to_owned()));
+
+    let resp = srv.post("/admin/gc").send_json(&json!({ "namespace": "missing" })).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_rt::test]
+async fn sliding_ttl() {
+    let srv = TestServer::start();
+
+    let resp = srv.post("/set")
+        .send_json(&json!({ "key": "a", "data": "data", "ttl": "1s", "sliding": true }))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    for _ in 0..3 {
+        srv.advance_time(Duration::from_millis(700));
+        assert_eq!(srv.get("a").await, Some("data".to_owned()));
+    }
+
+    srv.advance_time(Duration::from_millis(1100));
+    assert_eq!(srv.get("a").await, None);
+}
+
+#[actix_rt::test]
+async fn default_and_max_ttl() {
+    let srv = TestServer::builder()
+        .options(Options {
+            default_ttl: Some(Duration::from_secs(10)),
+            max_ttl: Some(Duration::from_secs(60)),
+            ..Options::default()
+        })
+        .start();
+
+    srv.set("default", "data", None).await;
+    srv.set("capped", "data", Some("1h")).await;
+    let resp = srv.post("/set")
+        .send_json(&json!({ "key": "sliding", "data": "data", "ttl": "30s", "sliding": true }))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    srv.advance_time(Duration::from_secs(11));
+    assert_eq!(srv.get("default").await, None);
+    assert_eq!(srv.get("capped").await, Some("data".to_owned()));
+    assert_eq!(srv.get("sliding").await, Some("data".to_owned()));
+
+    // reads keep sliding the ttl, but not past max_ttl since the write
+    for _ in 0..2 {
+        srv.advance_time(Duration::from_secs(20));
+        assert_eq!(srv.get("sliding").await, Some("data".to_owned()));
+    }
+    srv.advance_time(Duration::from_secs(10));
+    assert_eq!(srv.get("capped").await, None);
+    assert_eq!(srv.get("sliding").await, None);
+}
+
+#[actix_rt::test]
+async fn expire_at() {
+    let srv = TestServer::start();
+
+    let set = |key: &'static str, expire_at: &'static str| srv.post("/set")
+        .send_json(&json!({ "key": key, "data": "data", "expire_at": expire_at }));
+    assert_eq!(set("future", "2999-01-01T00:00:00Z").await.unwrap().status(), StatusCode::OK);
+    assert_eq!(set("past", "2000-01-01T00:00:00+03:00").await.unwrap().status(), StatusCode::OK);
+
+    assert_eq!(srv.get("future").await, Some("data".to_owned()));
+    assert_eq!(srv.get("past").await, None);
+
+    let resp = srv.post("/set")
+        .send_json(&json!({ "key": "a", "data": "data", "ttl": "1s", "expire_at": "2999-01-01T00:00:00Z" }))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_rt::test]
+async fn l1_is_invalidated_by_writes() {
+    let l1 = L1Config { capacity: 2, ttl: Duration::from_secs(3600) };
+    let srv = TestServer::builder().l1(l1).start();
+
+    for key in &["a", "b", "c"] {
+        srv.set(key, "old", None).await;
+        assert_eq!(srv.get(key).await, Some("old".to_owned()));
+    }
+
+    srv.set("a", "new", None).await;
+    assert_eq!(srv.get("a").await, Some("new".to_owned()));
+    srv.delete("c").await;
+    assert_eq!(srv.get("c").await, None);
+}
+
+#[actix_rt::test]
+async fn stale_while_revalidate() {
+    let options = Options { stale_grace: Duration::from_secs(10), ..Options::default() };
+    let srv = TestServer::builder().options(options).start();
+    srv.set("a", "data", Some("1s")).await;
+    srv.advance_time(Duration::from_secs(2));
+
+    assert_eq!(srv.get("a").await, None);
+
+    let get_stale = || async {
+        let mut resp = srv.post("/get")
+            .send_json(&json!({ "key": "a", "allow_stale": true }))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        resp.json::<serde_json::Value>().await.unwrap()
+    };
+    assert_eq!(get_stale().await, json!({ "data": "data", "stale": true, "revalidate": true }));
+    assert_eq!(get_stale().await, json!({ "data": "data", "stale": true }));
+
+    srv.advance_time(Duration::from_secs(10));
+    assert_eq!(srv.get("a").await, None);
+}
+
+#[actix_rt::test]
+async fn key_filter() {
+    let options = Options {
+        key_filter: Some(100),
+        stale_grace: Duration::from_secs(10),
+        ..Options::default()
+    };
+    let srv = TestServer::builder().options(options).start();
+    assert_eq!(srv.get("a").await, None);
+
+    srv.set("a", "data", Some("1s")).await;
+    assert_eq!(srv.get("a").await, Some("data".to_owned()));
+    srv.advance_time(Duration::from_secs(2));
+    let resp = srv.post("/get")
+        .send_json(&json!({ "key": "a", "allow_stale": true }))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    srv.delete("a").await;
+    let mut resp = srv.post("/get").send_json(&json!({ "key": "a", "lease": "1s" })).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    assert!(resp.json::<Value>().await.unwrap()["lease"].is_u64());
+}
+
+#[actix_rt::test]
+async fn getset() {
+    let srv = &TestServer::start();
+
+    let getset = |data: &'static str| async move {
+        let mut resp = srv.post("/getset")
+            .send_json(&json!({ "key": "a", "data": data }))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        resp.json::<serde_json::Value>().await.unwrap()
+    };
+    assert_eq!(getset("first").await, json!({ "data": null }));
+    assert_eq!(getset("second").await, json!({ "data": "first" }));
+    assert_eq!(srv.get("a").await, Some("second".to_owned()));
+}
+
+#[actix_rt::test]
+async fn gat() {
+    let srv = TestServer::start();
+    srv.set("a", "data", Some("1s")).await;
+
+    let mut resp = srv.post("/gat")
+        .send_json(&json!({ "key": "a", "ttl": "1h" }))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.json::<serde_json::Value>().await.unwrap(), json!({ "data": "data" }));
+
+    srv.advance_time(Duration::from_secs(2));
+    assert_eq!(srv.get("a").await, Some("data".to_owned()));
+
+    let resp = srv.post("/gat")
+        .send_json(&json!({ "key": "missing" }))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_rt::test]
+async fn lists() {
+    let srv = TestServer::start();
+    let call = |path: &'static str, body: Value| {
+        let req = srv.post(path);
+        async move {
+            let mut resp = req.send_json(&body).await.unwrap();
+            let status = resp.status();
+            (status, resp.json::<Value>().await.unwrap_or_default())
+        }
+    };
+
+    assert_eq!(call("/rpush", json!({ "key": "l", "values": ["b", "c"], "ttl": "10s" })).await, (StatusCode::OK, json!({ "len": 2 })));
+    assert_eq!(call("/lpush", json!({ "key": "l", "values": ["a"] })).await, (StatusCode::OK, json!({ "len": 3 })));
+    assert_eq!(call("/lrange", json!({ "key": "l" })).await, (StatusCode::OK, json!({ "values": ["a", "b", "c"] })));
+    assert_eq!(call("/lrange", json!({ "key": "l", "start": 1, "stop": 1 })).await.1, json!({ "values": ["b"] }));
+    assert_eq!(call("/lpop", json!({ "key": "l" })).await.1, json!({ "values": ["a"] }));
+    assert_eq!(call("/rpop", json!({ "key": "l", "count": 5 })).await.1, json!({ "values": ["c", "b"] }));
+    assert_eq!(call("/lrange", json!({ "key": "l" })).await.0, StatusCode::NOT_FOUND);
+
+    srv.set("plain", "data", None).await;
+    assert_eq!(call("/rpush", json!({ "key": "plain", "values": ["a"] })).await.0, StatusCode::CONFLICT);
+    assert_eq!(call("/rpush", json!({ "key": "empty", "values": [] })).await.0, StatusCode::BAD_REQUEST);
+    call("/rpush", json!({ "key": "l", "values": ["a"], "ttl": "1s" })).await;
+    assert_eq!(srv.get("l").await, None);
+    srv.advance_time(Duration::from_secs(2));
+    assert_eq!(call("/lpop", json!({ "key": "l" })).await.0, StatusCode::NOT_FOUND);
+}
+
+#[actix_rt::test]
+async fn sets() {
+    let srv = TestServer::start();
+    let call = |path: &'static str, body: Value| {
+        let req = srv.post(path);
+        async move {
+            let mut resp = req.send_json(&body).await.unwrap();
+            let status = resp.status();
+            (status, resp.json::<Value>().await.unwrap_or_default())
+        }
+    };
+
+    assert_eq!(call("/sadd", json!({ "key": "seen", "members": ["b", "a"], "ttl": "1h" })).await, (StatusCode::OK, json!({ "count": 2 })));
+    assert_eq!(call("/sadd", json!({ "key": "seen", "members": ["a", "c"] })).await.1, json!({ "count": 1 }));
+    assert_eq!(call("/sismember", json!({ "key": "seen", "member": "a" })).await.1, json!({ "member": true }));
+    assert_eq!(call("/sismember", json!({ "key": "seen", "member": "z" })).await.1, json!({ "member": false }));
+    assert_eq!(call("/smembers", json!({ "key": "seen" })).await, (StatusCode::OK, json!({ "members": ["a", "b", "c"] })));
+    assert_eq!(call("/srem", json!({ "key": "seen", "members": ["a", "z"] })).await.1, json!({ "count": 1 }));
+    assert_eq!(call("/smembers", json!({ "key": "seen" })).await.1, json!({ "members": ["b", "c"] }));
+
+    call("/rpush", json!({ "key": "l", "values": ["a"] })).await;
+    assert_eq!(call("/sadd", json!({ "key": "l", "members": ["a"] })).await.0, StatusCode::CONFLICT);
+
+    srv.advance_time(Duration::from_secs(3601));
+    assert_eq!(call("/smembers", json!({ "key": "seen" })).await.0, StatusCode::NOT_FOUND);
+    assert_eq!(call("/sismember", json!({ "key": "seen", "member": "b" })).await.1, json!({ "member": false }));
+}
+
+#[actix_rt::test]
+async fn maps() {
+    let srv = TestServer::start();
+    let call = |path: &'static str, body: Value| {
+        let req = srv.post(path);
+        async move {
+            let mut resp = req.send_json(&body).await.unwrap();
+            let status = resp.status();
+            (status, resp.json::<Value>().await.unwrap_or_default())
+        }
+    };
+
+    let session = json!({ "key": "session", "fields": { "user": "1", "hits": "0" }, "ttl": "30m" });
+    assert_eq!(call("/hset", session).await, (StatusCode::OK, json!({ "count": 2 })));
+    assert_eq!(call("/hset", json!({ "key": "session", "fields": { "hits": "1" } })).await.1, json!({ "count": 0 }));
+    assert_eq!(call("/hget", json!({ "key": "session", "field": "hits" })).await, (StatusCode::OK, json!({ "value": "1" })));
+    assert_eq!(call("/hget", json!({ "key": "session", "field": "missing" })).await.0, StatusCode::NOT_FOUND);
+    assert_eq!(call("/hgetall", json!({ "key": "session" })).await.1, json!({ "fields": { "hits": "1", "user": "1" } }));
+    assert_eq!(call("/hdel", json!({ "key": "session", "fields": ["hits", "missing"] })).await.1, json!({ "count": 1 }));
+    assert_eq!(call("/hgetall", json!({ "key": "session" })).await.1, json!({ "fields": { "user": "1" } }));
+
+    srv.set("plain", "data", None).await;
+    assert_eq!(call("/hget", json!({ "key": "plain", "field": "a" })).await.0, StatusCode::CONFLICT);
+
+    srv.advance_time(Duration::from_secs(1801));
+    assert_eq!(call("/hgetall", json!({ "key": "session" })).await.0, StatusCode::NOT_FOUND);
+}
+
+#[actix_rt::test]
+async fn counters() {
+    let srv = TestServer::start();
+    let call = |path: &'static str, body: Value| {
+        let req = srv.post(path);
+        async move {
+            let mut resp = req.send_json(&body).await.unwrap();
+            let status = resp.status();
+            (status, resp.json::<Value>().await.unwrap_or_default())
+        }
+    };
+
+    let limiter = json!({ "key": "rate", "ttl": "1m", "initial": 100, "delta": -1, "overflow": "saturate" });
+    assert_eq!(call("/count", limiter).await, (StatusCode::OK, json!({ "value": 99 })));
+    assert_eq!(call("/count", json!({ "key": "rate", "delta": -5 })).await.1, json!({ "value": 94 }));
+    assert_eq!(call("/counter", json!({ "key": "rate" })).await, (StatusCode::OK, json!({ "value": 94 })));
+    assert_eq!(call("/count", json!({ "key": "rate", "delta": i64::MIN })).await.1, json!({ "value": i64::MIN + 94 }));
+    assert_eq!(call("/count", json!({ "key": "rate", "delta": -95 })).await.1, json!({ "value": i64::MIN }));
+    assert_eq!(call("/count", json!({ "key": "rate", "delta": -1 })).await.1, json!({ "value": i64::MIN }));
+
+    assert_eq!(call("/count", json!({ "key": "hits", "delta": i64::MAX })).await.1, json!({ "value": i64::MAX }));
+    assert_eq!(call("/count", json!({ "key": "hits" })).await.0, StatusCode::CONFLICT);
+    assert_eq!(call("/count", json!({ "key": "wrap", "initial": i64::MAX, "overflow": "wrap" })).await.1, json!({ "value": i64::MIN }));
+
+    srv.set("plain", "1", None).await;
+    assert_eq!(call("/count", json!({ "key": "plain" })).await.0, StatusCode::CONFLICT);
+    assert_eq!(call("/counter", json!({ "key": "missing" })).await.0, StatusCode::NOT_FOUND);
+
+    // the fixed window ends a minute after the first change, a refreshed one a minute after the last
+    assert_eq!(call("/count", json!({ "key": "idle", "ttl": "1m", "refresh_ttl": true })).await.1, json!({ "value": 1 }));
+    srv.advance_time(Duration::from_secs(40));
+    assert_eq!(call("/count", json!({ "key": "idle" })).await.1, json!({ "value": 2 }));
+    srv.advance_time(Duration::from_secs(40));
+    assert_eq!(call("/counter", json!({ "key": "rate" })).await.0, StatusCode::NOT_FOUND);
+    assert_eq!(call("/counter", json!({ "key": "idle" })).await.1, json!({ "value": 2 }));
+}
+
+#[actix_rt::test]
+async fn forecast() {
+    let srv = TestServer::builder().memory_limit(1000).start();
+    srv.set("a", &"a".repeat(100), None).await;
+    srv.advance_time(Duration::from_secs(10));
+
+    let mut resp = srv.get_request("/stats/forecast").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let forecast: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(forecast["used"], 100);
+    assert_eq!(forecast["reserved"], 100);
+    assert_eq!(forecast["evicting"], false);
+    let time_to_full = forecast["time_to_full"].as_f64().unwrap();
+    assert!(time_to_full > 85.0 && time_to_full < 100.0, "{}", time_to_full);
+}
+
+#[actix_rt::test]
+async fn distribution() {
+    let srv = TestServer::start();
+    srv.set("a", "data", Some("30s")).await;
+    srv.set("b", &"b".repeat(100), Some("2m")).await;
+    srv.set("c", "data", None).await;
+
+    let mut resp = srv.get_request("/stats/distribution").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let distribution: Value = resp.json().await.unwrap();
+    let counts = |buckets: &Value| buckets.as_array().unwrap().iter().map(|bucket| bucket["count"].as_u64().unwrap()).collect::<Vec<_>>();
+    assert_eq!(counts(&distribution["ttl"])[..4], [0, 0, 1, 1]);
+    assert_eq!(counts(&distribution["remaining_ttl"])[..4], [0, 0, 1, 1]);
+    assert_eq!(distribution["remaining_ttl"][2]["le"], 60.0);
+    assert_eq!(distribution["never_expiring"], 1);
+    assert_eq!(counts(&distribution["value_size"])[..2], [2, 1]);
+    assert_eq!(distribution["value_size"].as_array().unwrap().last().unwrap()["le"], Value::Null);
+}
+
+#[actix_rt::test]
+async fn item_classes() {
+    let slabs = SlabConfig { page_size: 1024, min_chunk: 64, growth_factor: 2.0 };
+    let srv = TestServer::builder().options(Options { slabs: Some(slabs), ..Options::default() }).start();
+    srv.set("a", "small", None).await;
+    srv.set("b", "small", None).await;
+    srv.set("c", &"c".repeat(100), None).await;
+
+    let mut resp = srv.get_request("/stats/items").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: Value = resp.json().await.unwrap();
+    let classes = body["classes"].as_array().unwrap();
+    assert_eq!(classes.len(), 2, "{}", body);
+    assert_eq!(classes[0]["chunk_size"], 64);
+    assert_eq!(classes[0]["items"], 2);
+    assert_eq!(classes[0]["bytes"], 10);
+    assert_eq!(classes[0]["chunks"], 16);
+    assert_eq!(classes[0]["evicted"], 0);
+    assert!(classes[0]["oldest_age"].is_f64());
+    assert_eq!(classes[1]["chunk_size"], 128);
+}
+
+#[actix_rt::test]
+async fn memory_usage() {
+    let srv = TestServer::start();
+    srv.set("a", &"a".repeat(100), None).await;
+
+    let mut resp = srv.get_request("/memory/usage?key=a").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let usage: Value = resp.json().await.unwrap();
+    let (key, value, overhead) = (usage["key"].as_u64().unwrap(), usage["value"].as_u64().unwrap(), usage["overhead"].as_u64().unwrap());
+    assert!(value >= 100, "{}", usage);
+    assert!(key > 1 && overhead > 0, "{}", usage);
+    assert_eq!(usage["total"].as_u64(), Some(key + value + overhead));
+
+    let resp = srv.get_request("/memory/usage?key=missing").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_rt::test]
+async fn allocator_stats() {
+    let srv = TestServer::start();
+
+    let mut resp = srv.get_request("/stats/allocator").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let stats: Value = resp.json().await.unwrap();
+    let expected = match (cfg!(feature = "jemalloc"), cfg!(feature = "mimalloc")) {
+        (true, _) => "jemalloc",
+        (_, true) => "mimalloc",
+        _ => "system",
+    };
+    assert_eq!(stats["allocator"], expected);
+}
+
+#[actix_rt::test]
+async fn openapi() {
+    let srv = TestServer::start();
+
+    let mut resp = srv.get_request("/openapi.json").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    // above the 64KiB awc reads by default
+    let doc: Value = resp.json().limit(1 << 20).await.unwrap();
+    assert!(doc["openapi"].as_str().unwrap().starts_with("3."));
+    assert!(doc["paths"]["/get"]["post"].is_object());
+    assert!(doc["paths"]["/ns/{name}/get"]["post"].is_object());
+    assert!(doc["paths"]["/admin/jobs/{id}"]["get"].is_object());
+    assert!(doc["components"]["schemas"]["SetReq"]["properties"]["ttl"].is_object());
+}
+
+#[actix_rt::test]
+async fn key_routes() {
+    let srv = TestServer::start();
+
+    let resp = srv.request(Method::PUT, "/keys/user/1?ttl=1s").send_body("data").await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let version = resp.headers().get("etag").unwrap().clone();
+    assert_eq!(srv.get("user/1").await, Some("data".to_owned()));
+
+    let mut resp = srv.get_request("/keys/user/1").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers().get("etag"), Some(&version));
+    assert_eq!(resp.body().await.unwrap(), "data");
+
+    let resp = srv.get_request("/keys/user/1").header("if-none-match", version.clone()).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+
+    let resp = srv.request(Method::PUT, "/keys/user/1")
+        .header("if-match", "\"0\"")
+        .send_body("stale")
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+
+    srv.advance_time(Duration::from_secs(2));
+    let resp = srv.get_request("/keys/user/1").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    let resp = srv.request(Method::PUT, "/keys/b").header("x-ttl", "1m").send_body("header ttl").await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = srv.request(Method::PUT, "/keys/b").header("x-ttl", "soon").send_body("data").await.unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let resp = srv.request(Method::DELETE, "/keys/b").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(srv.get("b").await, None);
+    let resp = srv.request(Method::DELETE, "/keys/b").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    srv.post("/admin/ns/create").send_json(&json!({ "name": "ns", "memory_limit": 1024 })).await.unwrap();
+    srv.request(Method::PUT, "/ns/ns/keys/a").send_body("namespaced").await.unwrap();
+    let mut resp = srv.get_request("/ns/ns/keys/a").send().await.unwrap();
+    assert_eq!(resp.body().await.unwrap(), "namespaced");
+    assert_eq!(srv.get("a").await, None);
+}
+
+#[actix_rt::test]
+async fn if_none_match() {
+    let srv = TestServer::start();
+    srv.set("a", "data", None).await;
+    let resp = srv.get_request("/keys/a").send().await.unwrap();
+    let tag = resp.headers().get("etag").unwrap().to_str().unwrap().to_owned();
+
+    for expected in [tag.clone(), format!("W/{}", tag), format!("\"0\", {}", tag), "*".to_owned()].iter() {
+        let mut resp = srv.get_request("/keys/a").header("if-none-match", expected.as_str()).send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED, "{}", expected);
+        assert!(resp.body().await.unwrap().is_empty());
+    }
+    let resp = srv.get_request("/keys/a").header("if-none-match", "\"0\"").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let resp = srv.post("/get").header("if-none-match", tag.as_str()).send_json(&json!({ "key": "a" })).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+    srv.set("a", "changed", None).await;
+    let resp = srv.post("/get").header("if-none-match", tag.as_str()).send_json(&json!({ "key": "a" })).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[actix_rt::test]
+async fn response_compression() {
+    let srv = TestServer::builder()
+        .compression(CompressionFilter::new(true, 16, "text/plain"))
+        .start();
+    let value = "compressible ".repeat(100);
+    srv.set("a", &value, None).await;
+
+    let mut resp = srv.get_request("/keys/a").header("accept-encoding", "gzip").no_decompress().send().await.unwrap();
+    assert_eq!(resp.headers().get("content-encoding").unwrap(), "gzip");
+    let mut decoded = String::new();
+    std::io::Read::read_to_string(&mut flate2::read::GzDecoder::new(&resp.body().await.unwrap()[..]), &mut decoded).unwrap();
+    assert_eq!(decoded, value);
+    let resp = srv.get_request("/keys/a").header("accept-encoding", "br").no_decompress().send().await.unwrap();
+    assert_eq!(resp.headers().get("content-encoding").unwrap(), "br");
+
+    // json is not among the content types
+    let resp = srv.post("/get").header("accept-encoding", "gzip").no_decompress()
+        .send_json(&json!({ "key": "a" })).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(resp.headers().get("content-encoding").is_none());
+
+    let disabled = TestServer::start();
+    disabled.set("a", &value, None).await;
+    let resp = disabled.get_request("/keys/a").header("accept-encoding", "gzip").no_decompress().send().await.unwrap();
+    assert!(resp.headers().get("content-encoding").is_none());
+}
+
+#[actix_rt::test]
+async fn cors() {
+    let srv = TestServer::builder()
+        .cors(CorsConfig::new("https://app.example", "GET,POST", "content-type"))
+        .start();
+    let preflight = |origin: &'static str, method: &'static str| srv.request(Method::OPTIONS, "/set")
+        .header("origin", origin)
+        .header("access-control-request-method", method)
+        .header("access-control-request-headers", "content-type")
+        .send();
+
+    let resp = preflight("https://app.example", "POST").await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers().get("access-control-allow-origin").unwrap(), "https://app.example");
+    let methods = resp.headers().get("access-control-allow-methods").unwrap().to_str().unwrap();
+    assert!(methods.contains("POST"), "{}", methods);
+    let resp = preflight("https://other.example", "POST").await.unwrap();
+    assert!(resp.headers().get("access-control-allow-origin").is_none());
+    let resp = preflight("https://app.example", "DELETE").await.unwrap();
+    assert!(resp.headers().get("access-control-allow-origin").is_none());
+
+    srv.set("a", "data", None).await;
+    let resp = srv.get_request("/keys/a").header("origin", "https://app.example").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers().get("access-control-allow-origin").unwrap(), "https://app.example");
+
+    let disabled = TestServer::start();
+    disabled.set("a", "data", None).await;
+    let resp = disabled.get_request("/keys/a").header("origin", "https://app.example").send().await.unwrap();
+    assert!(resp.headers().get("access-control-allow-origin").is_none());
+}
+
+#[actix_rt::test]
+async fn accept_encoding() {
+    let srv = TestServer::builder()
+        .compression(CompressionFilter::new(true, 16, "text/plain"))
+        .start();
+    let value = "compressible ".repeat(100);
+    srv.set("a", &value, None).await;
+    srv.set("small", "data", None).await;
+
+    let negotiated = |encoding: &'static str, key: &'static str| {
+        srv.get_request(&format!("/keys/{}", key)).header("accept-encoding", encoding).no_decompress().send()
+    };
+    fn header<S>(resp: &awc::ClientResponse<S>, name: &str) -> Option<String> {
+        resp.headers().get(name).map(|value| value.to_str().unwrap().to_owned())
+    }
+
+    let mut resp = negotiated("gzip, zstd;q=0.5", "a").await.unwrap();
+    assert_eq!(header(&resp, "content-encoding").as_deref(), Some("gzip"));
+    assert!(resp.body().await.unwrap().len() < value.len());
+    let tag = header(&resp, "etag").unwrap();
+    assert!(tag.starts_with("W/\""), "{}", tag);
+
+    let mut resp = negotiated("zstd, gzip;q=0.5", "a").await.unwrap();
+    assert_eq!(header(&resp, "content-encoding").as_deref(), Some("zstd"));
+    assert_eq!(header(&resp, "vary").as_deref(), Some("accept-encoding"));
+    assert_eq!(header(&resp, "etag"), Some(tag.clone()));
+    let body = resp.body().await.unwrap();
+    assert_eq!(zstd::stream::decode_all(&body[..]).unwrap(), value.as_bytes());
+
+    // identity keeps the strong tag, both name the same version
+    let resp = negotiated("identity", "a").await.unwrap();
+    assert_eq!(header(&resp, "content-encoding"), None);
+    assert_eq!(header(&resp, "etag").as_deref(), Some(&tag[2..]));
+    let resp = negotiated("gzip", "small").await.unwrap();
+    assert_eq!(header(&resp, "content-encoding"), None);
+    assert!(!header(&resp, "etag").unwrap().starts_with("W/"));
+
+    let resp = negotiated("gzip", "a").await.unwrap();
+    let resp = srv.get_request("/keys/a").header("if-none-match", header(&resp, "etag").unwrap()).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+    let resp = srv.request(Method::PUT, "/keys/a").header("if-match", tag.as_str()).send_body("changed").await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[actix_rt::test]
+async fn json_documents() {
+    let srv = TestServer::start();
+    let doc = json!({ "user": { "name": "a", "tags": ["x", "y"] }, "a/b": 1 });
+
+    let resp = srv.request(Method::PUT, "/json/doc?ttl=1m").send_body(doc.to_string()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = srv.request(Method::PUT, "/json/doc").send_body("{ not json").await.unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let mut resp = srv.get_request("/json/doc?path=/user/name").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let version = resp.headers().get("etag").unwrap().clone();
+    assert_eq!(resp.json::<Value>().await.unwrap(), json!("a"));
+    let mut resp = srv.get_request("/json/doc?path=/a~1b").send().await.unwrap();
+    assert_eq!(resp.json::<Value>().await.unwrap(), json!(1));
+    let mut resp = srv.get_request("/json/doc").send().await.unwrap();
+    assert_eq!(resp.json::<Value>().await.unwrap(), doc);
+    let resp = srv.get_request("/json/doc?path=/user/age").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let resp = srv.get_request("/json/doc?path=user").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let resp = srv.get_request("/json/doc?path=/user").header("if-none-match", version.clone()).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+
+    let patch = json!([
+        { "op": "test", "path": "/user/name", "value": "a" },
+        { "op": "replace", "path": "/user/name", "value": "b" },
+        { "op": "add", "path": "/user/tags/-", "value": "z" },
+        { "op": "add", "path": "/user/tags/0", "value": "w" },
+        { "op": "remove", "path": "/a~1b" },
+    ]);
+    let resp = srv.request(Method::PATCH, "/json/doc")
+        .header("content-type", "application/json-patch+json")
+        .send_body(patch.to_string())
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_ne!(resp.headers().get("etag"), Some(&version));
+    let patched = json!({ "user": { "name": "b", "tags": ["w", "x", "y", "z"] } });
+    let mut resp = srv.get_request("/json/doc").send().await.unwrap();
+    assert_eq!(resp.json::<Value>().await.unwrap(), patched);
+
+    // a failed operation leaves the document as it was
+    let patch = json!([
+        { "op": "remove", "path": "/user/tags/0" },
+        { "op": "test", "path": "/user/name", "value": "a" },
+    ]);
+    let resp = srv.request(Method::PATCH, "/json/doc").send_body(patch.to_string()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+    let resp = srv.request(Method::PATCH, "/json/doc")
+        .header("if-match", version)
+        .send_body("[]")
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+    let mut resp = srv.get_request("/json/doc").send().await.unwrap();
+    assert_eq!(resp.json::<Value>().await.unwrap(), patched);
+
+    // patches keep the ttl
+    srv.advance_time(Duration::from_secs(61));
+    let resp = srv.get_request("/json/doc").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    srv.set("text", "plain", None).await;
+    let resp = srv.get_request("/json/text").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+    let resp = srv.request(Method::PATCH, "/json/missing").send_body("[]").await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_rt::test]
+async fn conditional_set() {
+    let srv = TestServer::start();
+
+    let resp = srv.post("/set")
+        .send_json(&json!({ "key": "a", "data": "first" }))
+        .await
+        .unwrap();
+    let version = resp.headers().get("etag").unwrap().to_str().unwrap().to_owned();
+
+    let resp = srv.post("/get").send_json(&json!({ "key": "a" })).await.unwrap();
+    assert_eq!(resp.headers().get("etag").unwrap().to_str().unwrap(), version);
+
+    let resp = srv.post("/set")
+        .header("if-match", version.as_str())
+        .send_json(&json!({ "key": "a", "data": "second" }))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let resp = srv.post("/set")
+        .header("if-match", version.as_str())
+        .send_json(&json!({ "key": "a", "data": "third" }))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+
+    let resp = srv.post("/set")
+        .send_json(&json!({ "key": "missing", "data": "data", "if_version": 1 }))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+    assert_eq!(srv.get("a").await, Some("second".to_owned()));
+}
+
+#[actix_rt::test]
+async fn oversized_value() {
+    let srv = TestServer::builder().memory_limit(4).start();
+
+    srv.set("a", "aa", None).await;
+    assert_eq!(srv.set("b", "bbbbb", None).await, StatusCode::NOT_MODIFIED);
+    assert_eq!(srv.get("a").await, Some("aa".to_owned()));
+}
+
+#[actix_rt::test]
+async fn pinned_value() {
+    let srv = TestServer::builder().memory_limit(4).start();
+
+    let resp = srv.post("/set")
+        .send_json(&json!({ "key": "config", "data": "cc", "pinned": true }))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    srv.set("a", "aa", None).await;
+    srv.set("b", "bb", None).await;
+    assert_eq!(srv.get("config").await, Some("cc".to_owned()));
+    assert_eq!(srv.get("a").await, None);
+
+    let resp = srv.post("/unpin").send_json(&json!({ "key": "config" })).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    srv.set("c", "cc", None).await;
+    assert_eq!(srv.get("config").await, None);
+
+    let resp = srv.post("/pin").send_json(&json!({ "key": "missing" })).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_rt::test]
+async fn eviction_priority() {
+    let srv = TestServer::builder().memory_limit(4).start();
+
+    let resp = srv.post("/set")
+        .send_json(&json!({ "key": "config", "data": "cc", "priority": "high" }))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = srv.post("/set")
+        .send_json(&json!({ "key": "scratch", "data": "ss", "priority": "low" }))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    srv.set("a", "aa", None).await;
+    assert_eq!(srv.get("scratch").await, None);
+    srv.set("b", "bb", None).await;
+    assert_eq!(srv.get("a").await, None);
+    assert_eq!(srv.get("config").await, Some("cc".to_owned()));
+
+    let mut resp = srv.get_request("/export").send().await.unwrap();
+    let export = resp.body().await.unwrap();
+    let config = std::str::from_utf8(&export).unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .find(|line| line["key"] == "config")
+        .unwrap();
+    assert_eq!(config["priority"], "high");
+
+    let resp = srv.post("/set")
+        .send_json(&json!({ "key": "c", "data": "cc", "priority": "urgent" }))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_rt::test]
+async fn webhook() {
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let sink = received.clone();
+    let receiver = test::start(move || {
+        let sink = sink.clone();
+        App::new().route("/hook", web::post().to(move |events: web::Json<Vec<Value>>| {
+            sink.lock().unwrap().extend(events.into_inner());
+            HttpResponse::Ok().finish()
+        }))
+    });
+
+    let srv = TestServer::builder().memory_limit(4).start();
+    let hook = webhook::spawn(srv.events(), WebhookConfig {
+        url: receiver.url("/hook"),
+        batch_size: 10,
+        flush_interval: Duration::from_millis(50),
+        retries: 0,
+    });
+
+    srv.set("a", "aa", None).await;
+    srv.set("b", "bbb", None).await;
+    srv.set("c", "c", None).await;
+    srv.delete("c").await;
+    eventually(|| received.lock().unwrap().len() >= 2).await;
+    hook.abort();
+
+    let received = received.lock().unwrap();
+    let reasons: Vec<(&str, &str)> = received.iter()
+        .map(|event| (event["key"].as_str().unwrap(), event["reason"].as_str().unwrap()))
+        .collect();
+    assert_eq!(reasons, vec![("a", "evicted"), ("c", "deleted")]);
+    assert_eq!(received[0]["namespace"], "default");
+    assert_eq!(received[0]["size"], 2);
+}
+
+#[actix_rt::test]
+async fn watch() {
+    let srv = TestServer::start();
+    let (_, mut conn) = awc::Client::new().ws(srv.url("/watch?pattern=a*")).connect().await.unwrap();
+
+    srv.set("b", "data", None).await;
+    srv.set("a1", "data", None).await;
+    let event = next_json(&mut conn).await;
+    assert_eq!(event["key"], "a1");
+    assert_eq!(event["reason"], "set");
+
+    conn.send(Message::Text(json!({ "subscribe": "b" }).to_string())).await.unwrap();
+    assert_eq!(next_json(&mut conn).await, json!({ "subscribed": "b" }));
+    srv.delete("b").await;
+    let event = next_json(&mut conn).await;
+    assert_eq!(event["key"], "b");
+    assert_eq!(event["reason"], "deleted");
+
+    let resp = srv.get_request("/ns/missing/watch").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_rt::test]
+async fn sse() {
+    let srv = TestServer::start();
+    let mut resp = srv.get_request("/events?pattern=a*").send().await.unwrap();
+    assert_eq!(resp.headers().get("content-type").unwrap(), "text/event-stream");
+
+    srv.set("b", "data", None).await;
+    srv.set("a", "data", None).await;
+    let chunk = resp.next().await.unwrap().unwrap();
+    let chunk = std::str::from_utf8(&chunk).unwrap();
+    assert!(chunk.starts_with("event: set\ndata: {"), "{}", chunk);
+    assert!(chunk.contains(r#""key":"a""#), "{}", chunk);
+}
+
+#[actix_rt::test]
+async fn pubsub() {
+    let srv = TestServer::start();
+    let mut sub = srv.get_request("/subscribe/news").send().await.unwrap();
+    assert_eq!(sub.status(), StatusCode::OK);
+
+    let mut resp = srv.post("/publish")
+        .send_json(&json!({ "channel": "news", "message": "line 1\nline 2" }))
+        .await
+        .unwrap();
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(body["receivers"], 1);
+
+    let chunk = sub.next().await.unwrap().unwrap();
+    assert_eq!(&chunk[..], b"data: line 1\ndata: line 2\n\n");
+
+    let mut resp = srv.post("/publish")
+        .send_json(&json!({ "channel": "other", "message": "lost" }))
+        .await
+        .unwrap();
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(body["receivers"], 0);
+}
+
+#[actix_rt::test]
+async fn lock() {
+    let srv = TestServer::start();
+    let acquire = json!({ "key": "job", "ttl": "1s", "owner": "worker-1" });
+
+    let mut resp = srv.post("/lock/acquire").send_json(&acquire).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let token = resp.json::<Value>().await.unwrap()["token"].as_u64().unwrap();
+    assert_eq!(srv.get("job").await, Some("worker-1".to_owned()));
+
+    let resp = srv.post("/lock/acquire").send_json(&acquire).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+    let resp = srv.post("/lock/release")
+        .send_json(&json!({ "key": "job", "token": token + 1 }))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+    srv.advance_time(Duration::from_secs(2));
+    let mut resp = srv.post("/lock/acquire").send_json(&acquire).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let next = resp.json::<Value>().await.unwrap()["token"].as_u64().unwrap();
+    assert!(next > token);
+
+    let resp = srv.post("/lock/release")
+        .send_json(&json!({ "key": "job", "token": next }))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(srv.get("job").await, None);
+}
+
+#[actix_rt::test]
+async fn miss_lease() {
+    let srv = TestServer::start();
+    let get = json!({ "key": "a", "lease": "1s" });
+
+    let mut resp = srv.post("/get").send_json(&get).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let lease = resp.json::<Value>().await.unwrap()["lease"].as_u64().unwrap();
+
+    let resp = srv.post("/get").send_json(&get).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+    let resp = srv.post("/set")
+        .send_json(&json!({ "key": "a", "data": "filled", "lease": lease }))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(srv.get("a").await, Some("filled".to_owned()));
+
+    // lease ended with the set, so a late filler is rejected
+    let resp = srv.post("/set")
+        .send_json(&json!({ "key": "a", "data": "late", "lease": lease }))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+}
+
+#[actix_rt::test]
+async fn early_expiration() {
+    let options = Options { early_expiration: Some(1.0), ..Options::default() };
+    let srv = TestServer::builder().options(options).start();
+    srv.set("a", "old", Some("10s")).await;
+    assert_eq!(srv.get("a").await, Some("old".to_owned()));
+
+    // a thousandth of the lifetime left makes an early miss all but certain
+    srv.advance_time(Duration::from_millis(9990));
+    let mut resp = srv.post("/get").send_json(&json!({ "key": "a", "lease": "1s" })).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let early: Value = resp.json().await.unwrap();
+    assert_eq!(early["refresh"], true);
+    let lease = early["lease"].as_u64().unwrap();
+
+    // others keep reading while the lease holder refreshes
+    assert_eq!(srv.get("a").await, Some("old".to_owned()));
+    let resp = srv.post("/set")
+        .send_json(&json!({ "key": "a", "data": "new", "ttl": "10s", "lease": lease }))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(srv.get("a").await, Some("new".to_owned()));
+}
+
+#[actix_rt::test]
+async fn negative_caching() {
+    let srv = &TestServer::start();
+    let status = |path: &'static str, req: Value| async move {
+        srv.post(path).send_json(&req).await.unwrap().status()
+    };
+
+    let mut resp = srv.post("/get").send_json(&json!({ "key": "a", "lease": "1s" })).await.unwrap();
+    let lease = resp.json::<Value>().await.unwrap()["lease"].as_u64().unwrap();
+    let negative = json!({ "key": "a", "ttl": "1s", "lease": lease });
+    assert_eq!(status("/set_negative", negative.clone()).await, StatusCode::OK);
+    assert_eq!(status("/get", json!({ "key": "a" })).await, StatusCode::NO_CONTENT);
+    // lease ended with the marker
+    assert_eq!(status("/set_negative", negative).await, StatusCode::PRECONDITION_FAILED);
+
+    srv.advance_time(Duration::from_secs(2));
+    assert_eq!(status("/get", json!({ "key": "a" })).await, StatusCode::NOT_FOUND);
+
+    assert_eq!(status("/set_negative", json!({ "key": "a", "ttl": "1s" })).await, StatusCode::OK);
+    srv.set("a", "found", None).await;
+    assert_eq!(srv.get("a").await, Some("found".to_owned()));
+}
+
+#[actix_rt::test]
+async fn read_through() {
+    let fetches = Arc::new(AtomicUsize::new(0));
+    let counter = fetches.clone();
+    let origin = test::start(move || {
+        let counter = counter.clone();
+        App::new().route("/items/{key}", web::get().to(move |key: web::Path<String>| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            async move {
+                actix_rt::time::delay_for(Duration::from_millis(50)).await;
+                Ok::<_, actix_web::Error>(match key.as_str() {
+                    "missing" => HttpResponse::NotFound().finish(),
+                    key => HttpResponse::Ok().body(format!("origin {}", key)),
+                })
+            }
+        }))
+    });
+
+    let srv = TestServer::builder()
+        .origin(OriginConfig {
+            url: origin.url("/items/{key}"),
+            ttl: None,
+            write: WriteMode::Off,
+            write_retries: 0,
+        })
+        .start();
+
+    let (first, second) = futures::join!(srv.get("a b"), srv.get("a b"));
+    assert_eq!(first, Some("origin a b".to_owned()));
+    assert_eq!(second, Some("origin a b".to_owned()));
+    assert_eq!(fetches.load(Ordering::SeqCst), 1);
+
+    assert_eq!(srv.get("a b").await, Some("origin a b".to_owned()));
+    assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    let outcomes = srv.outcomes();
+    assert!(outcomes.contains(&Outcome::Filled), "{:?}", outcomes);
+    assert_eq!(outcomes.last(), Some(&Outcome::Hit));
+
+    assert_eq!(srv.get("missing").await, None);
+    assert_eq!(fetches.load(Ordering::SeqCst), 2);
+
+    // a write made while the value is fetched is kept
+    let (fetched, _) = futures::join!(srv.get("c"), async {
+        actix_rt::time::delay_for(Duration::from_millis(20)).await;
+        srv.set("c", "written", None).await
+    });
+    assert_eq!(fetched, Some("written".to_owned()));
+    assert_eq!(srv.get("c").await, Some("written".to_owned()));
+}
+
+/// origin keeping written items in memory, writes of `bad` key or value fail
+fn writable_origin(items: Arc<Mutex<HashMap<String, String>>>) -> test::TestServer {
+    test::start(move || {
+        let (put, delete) = (items.clone(), items.clone());
+        App::new()
+            .route("/items/{key}", web::put().to(move |key: web::Path<String>, body: String| {
+                let stored = match (key.as_str(), body.as_str()) {
+                    ("bad", _) | (_, "bad") => false,
+                    _ => {
+                        put.lock().unwrap().insert(key.into_inner(), body);
+                        true
+                    },
+                };
+                match stored {
+                    true => HttpResponse::Ok().finish(),
+                    false => HttpResponse::InternalServerError().finish(),
+                }
+            }))
+            .route("/items/{key}", web::delete().to(move |key: web::Path<String>| {
+                delete.lock().unwrap().remove(key.as_str());
+                HttpResponse::Ok().finish()
+            }))
+    })
+}
+
+#[actix_rt::test]
+async fn write_through() {
+    let items = Arc::new(Mutex::new(HashMap::new()));
+    let origin = writable_origin(items.clone());
+    let srv = TestServer::builder()
+        .origin(OriginConfig {
+            url: origin.url("/items/{key}"),
+            ttl: None,
+            write: WriteMode::Through,
+            write_retries: 0,
+        })
+        .start();
+
+    assert_eq!(srv.set("a", "data", None).await, StatusCode::OK);
+    assert_eq!(items.lock().unwrap().get("a"), Some(&"data".to_owned()));
+
+    assert_eq!(srv.set("bad", "data", None).await, StatusCode::BAD_GATEWAY);
+    assert_eq!(srv.get("bad").await, None);
+    // previous value is put back
+    assert_eq!(srv.set("a", "bad", None).await, StatusCode::BAD_GATEWAY);
+    assert_eq!(srv.get("a").await, Some("data".to_owned()));
+
+    srv.delete("a").await;
+    assert!(items.lock().unwrap().is_empty());
+}
+
+#[actix_rt::test]
+async fn write_behind() {
+    let items = Arc::new(Mutex::new(HashMap::new()));
+    let origin = writable_origin(items.clone());
+    let srv = TestServer::builder()
+        .origin(OriginConfig {
+            url: origin.url("/items/{key}"),
+            ttl: None,
+            write: WriteMode::Behind,
+            write_retries: 1,
+        })
+        .start();
+
+    assert_eq!(srv.set("a", "data", None).await, StatusCode::OK);
+    assert_eq!(srv.set("bad", "data", None).await, StatusCode::OK);
+    assert_eq!(srv.set("b", "data", None).await, StatusCode::OK);
+    eventually(|| items.lock().unwrap().len() == 2).await;
+
+    // failed write-behind doesn't affect the cache
+    assert_eq!(srv.get("bad").await, Some("data".to_owned()));
+    let items = items.lock().unwrap();
+    assert!(items.contains_key("a") && items.contains_key("b"));
+}
+
+/// Waits for `done` to hold, panics after 5 seconds.
+async fn eventually(mut done: impl FnMut() -> bool) {
+    for _ in 0..500 {
+        if done() {
+            return
+        }
+        actix_rt::time::delay_for(Duration::from_millis(10)).await;
+    }
+    panic!("condition didn't hold in 5s")
+}
+
+#[actix_rt::test]
+async fn disk_tier() {
+    let path = std::env::temp_dir().join(format!("rust_memcached_disk_tier_{}", std::process::id()));
+    let srv = TestServer::builder()
+        .memory_limit(4)
+        .disk_tier(path.clone(), 1 << 10)
+        .start();
+
+    srv.set("a", "aa", None).await;
+    srv.set("b", "bb", None).await;
+    srv.set("c", "cc", None).await;
+    assert_eq!(srv.get("a").await, Some("aa".to_owned()));
+    // promoted "a" displaced "b" to disk
+    assert_eq!(srv.get("b").await, Some("bb".to_owned()));
+    assert_eq!(srv.delete("c").await, Some("cc".to_owned()));
+    assert_eq!(srv.get("c").await, None);
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn encrypted_disk_tier() {
+    let path = std::env::temp_dir().join(format!("rust_memcached_encrypted_tier_{}", std::process::id()));
+    let mut tier = DiskTier::open(path.clone(), 1 << 10, StdClock::new()).unwrap().encrypted(rand::random());
+
+    tier.spill("a", b"plaintext value", None);
+    tier.spill("b", b"plaintext value", None);
+    tier.flush();
+    let file = std::fs::read(&path).unwrap();
+    assert!(!file.windows(9).any(|window| window == b"plaintext"));
+    // same value under distinct nonces
+    assert_ne!(file[..file.len() / 2], file[file.len() / 2..]);
+
+    assert_eq!(tier.take("a"), Some((b"plaintext value".to_vec(), None)));
+    assert_eq!(tier.take("a"), None);
+    assert_eq!(tier.take("b"), Some((b"plaintext value".to_vec(), None)));
+
+    // values are bound to their keys, swapped ones don't open
+    tier.spill("a", b"value of a", None);
+    tier.spill("b", b"value of b", None);
+    tier.flush();
+    let mut file = std::fs::read(&path).unwrap();
+    // the two values just written, sealed into 38 bytes each
+    let len = file.len();
+    file[len - 76..].rotate_left(38);
+    std::fs::write(&path, file).unwrap();
+    assert_eq!(tier.take("a"), None);
+    assert_eq!(tier.take("b"), None);
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn disk_tier_key_file() {
+    let path = std::env::temp_dir().join(format!("rust_memcached_disk_key_{}", std::process::id()));
+    std::fs::write(&path, format!("{}\n", base64::encode([7; 32]))).unwrap();
+    assert_eq!(disk::read_key(&path).unwrap(), [7; 32]);
+
+    std::fs::write(&path, base64::encode([7; 16])).unwrap();
+    assert!(disk::read_key(&path).is_err());
+    std::fs::write(&path, "not base64").unwrap();
+    assert!(disk::read_key(&path).is_err());
+
+    let mut settings = Settings::new().unwrap();
+    settings.disk_tier_key_file = Some(path.to_str().unwrap().to_owned());
+    assert!(settings.validate().is_err(), "key file without encryption");
+    settings.disk_tier_encrypt = true;
+    assert_eq!(settings.validate(), Ok(()));
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn encrypted_disk_tier_limit() {
+    let path = std::env::temp_dir().join(format!("rust_memcached_encrypted_limit_{}", std::process::id()));
+    let mut tier = DiskTier::open(path.clone(), 100, StdClock::new()).unwrap().encrypted(rand::random());
+
+    // nonce and tag take 28 bytes of the limit
+    tier.spill("a", &[0; 73], None);
+    tier.flush();
+    assert_eq!(tier.take("a"), None);
+    tier.spill("a", &[0; 72], None);
+    tier.flush();
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), 100);
+    tier.spill("b", &[0; 1], None);
+    assert_eq!(tier.take("b"), None);
+    assert_eq!(tier.take("a"), Some((vec![0; 72], None)));
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn disk_tier_compaction() {
+    let path = std::env::temp_dir().join(format!("rust_memcached_compacted_tier_{}", std::process::id()));
+    let clock = StdClock::new();
+    let mut tier = DiskTier::open(path.clone(), 100, clock.clone()).unwrap();
+
+    tier.spill("expiring", &[0; 50], Some(clock.now() + Duration::from_secs(1)));
+    tier.spill("a", &[1; 50], None);
+    tier.flush();
+    assert!(tier.take("a").is_some());
+    clock.advance(Duration::from_secs(2));
+    for key in ["b", "c"].iter() {
+        tier.spill(key, &[2; 50], None);
+        tier.flush();
+        assert!(tier.take(key).is_some());
+    }
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), 200);
+
+    // the file would exceed twice the limit, expired value is dropped rewriting it
+    tier.spill("d", &[3; 50], None);
+    tier.flush();
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), 50);
+    assert_eq!(tier.take("expiring"), None);
+    assert_eq!(tier.take("d"), Some((vec![3; 50], None)));
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[actix_rt::test]
+async fn replication() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let replica = TestServer::builder().replica(listener).start();
+    let primary = TestServer::builder().replicas(vec![addr]).start();
+
+    primary.set("a", "data", None).await;
+    assert!(replicated(&replica, "a", Some("data")).await);
+
+    primary.set("b", "data", Some("10s")).await;
+    primary.delete("a").await;
+    assert!(replicated(&replica, "b", Some("data")).await);
+    assert!(replicated(&replica, "a", None).await);
+
+    let resp = primary.post("/set")
+        .send_json(&json!({ "key": "c", "data": "data", "priority": "high" }))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(replicated(&replica, "c", Some("data")).await);
+    for _ in 0..100 {
+        if exported(&replica, "c").await["priority"] == "high" {
+            return
+        }
+        actix_rt::time::delay_for(Duration::from_millis(20)).await;
+    }
+    panic!("priority is not replicated");
+}
+
+/// export line of the key
+async fn exported(srv: &TestServer, key: &str) -> Value {
+    let mut resp = srv.get_request("/export").send().await.unwrap();
+    let export = resp.body().await.unwrap();
+    std::str::from_utf8(&export).unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .find(|line| line["key"] == key)
+        .unwrap_or_default()
+}
+
+#[actix_rt::test]
+async fn replication_requires_secret() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let replica = TestServer::builder().replica(listener).start();
+    let primary = TestServer::builder().replicas(vec![addr.clone()]).replication_secret("wrong").start();
+
+    primary.set("a", "data", None).await;
+    assert!(!replicated(&replica, "a", Some("data")).await);
+
+    // a value longer than the replica's memory drops the connection before anything is allocated
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    let mut conn = tokio::net::TcpStream::connect(&addr).await.unwrap();
+    let mut frames = 6u32.to_be_bytes().to_vec();
+    frames.extend_from_slice(b"secret");
+    frames.push(1);
+    frames.extend_from_slice(&1u32.to_be_bytes());
+    frames.push(b'b');
+    frames.extend_from_slice(&u64::MAX.to_be_bytes());
+    frames.extend_from_slice(&u32::MAX.to_be_bytes());
+    conn.write_all(&frames).await.unwrap();
+    let mut buf = [0; 1];
+    assert_eq!(conn.read(&mut buf).await.unwrap(), 0);
+}
+
+/// waits for the replica to catch up
+async fn replicated(replica: &TestServer, key: &str, data: Option<&str>) -> bool {
+    for _ in 0..100 {
+        if replica.get(key).await.as_deref() == data {
+            return true
+        }
+        actix_rt::time::delay_for(Duration::from_millis(20)).await;
+    }
+    false
+}
+
+#[actix_rt::test]
+async fn udp() {
+    let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap();
+    let srv = TestServer::builder().udp(socket).start();
+    let mut client = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client.connect(addr).await.unwrap();
+
+    assert_eq!(udp_request(&mut client, 1, b"set a 0 0 4\r\ndata\r\n").await, b"STORED\r\n");
+    assert_eq!(srv.get("a").await, Some("data".to_owned()));
+    srv.set("b", "http", None).await;
+    assert_eq!(
+        udp_request(&mut client, 2, b"get a missing b\r\n").await,
+        b"VALUE a 0 4\r\ndata\r\nVALUE b 0 4\r\nhttp\r\nEND\r\n".to_vec(),
+    );
+
+    // response spans several datagrams, the request is padded with missing keys to allow it
+    let big = "x".repeat(3000);
+    let set = format!("set big 0 60 3000 noreply\r\n{}\r\n", big);
+    client.send(&[&[0u8, 3, 0, 0, 0, 1, 0, 0][..], set.as_bytes()].concat()).await.unwrap();
+    let expected = format!("VALUE big 0 3000\r\n{}\r\nEND\r\n", big);
+    let padding: String = (0..80).map(|i| format!(" m{:03}", i)).collect();
+    let get = format!("get big{}\r\n", padding);
+    assert_eq!(udp_request(&mut client, 4, get.as_bytes()).await, expected.into_bytes());
+
+    assert_eq!(udp_request(&mut client, 5, b"set c 0 0 10\r\nshort\r\n").await, b"CLIENT_ERROR bad data chunk\r\n");
+    assert_eq!(udp_request(&mut client, 6, b"incr a 1\r\n").await, b"ERROR\r\n");
+    // log filter is changed only through the authenticated http api
+    assert_eq!(udp_request(&mut client, 7, b"verbosity 2\r\n").await, b"ERROR\r\n");
+
+    // response is more than 10 times the request
+    assert_eq!(udp_request(&mut client, 8, b"get big\r\n").await, b"SERVER_ERROR response is too large\r\n");
+    srv.set("huge", &"x".repeat(100_000), None).await;
+    assert_eq!(udp_request(&mut client, 9, get.replace("big", "huge").as_bytes()).await, b"SERVER_ERROR response is too large\r\n");
+}
+
+#[test]
+fn udp_addr_is_internal() {
+    let mut settings = Settings::new().unwrap();
+    for addr in ["127.0.0.1:11211", "10.1.2.3:11211", "192.168.0.1:11211", "[::1]:11211", "[fd00::1]:11211"].iter() {
+        settings.udp_addr = Some(addr.to_string());
+        assert_eq!(settings.validate(), Ok(()), "{}", addr);
+    }
+    for addr in ["0.0.0.0:11211", "8.8.8.8:11211", "[::]:11211", "[2001:db8::1]:11211"].iter() {
+        settings.udp_addr = Some(addr.to_string());
+        assert!(settings.validate().is_err(), "{}", addr);
+    }
+}
+
+#[actix_rt::test]
+async fn acl() {
+    let key = |namespaces: &[&str], admin: bool, read_only: bool| ApiKey {
+        namespaces: namespaces.iter().map(|ns| ns.to_string()).collect(),
+        admin, read_only,
+        tenant: None,
+    };
+    let acl = Acl::new(vec![
+        ("root".to_owned(), key(&["*"], true, false)),
+        ("writer".to_owned(), key(&["default"], false, false)),
+        ("reader".to_owned(), key(&["*"], false, true)),
+        ("scoped".to_owned(), key(&["ns"], false, false)),
+    ].into_iter().collect());
+    let srv = TestServer::builder().acl(Arc::new(RwLock::new(acl))).start();
+    let status = |path: &'static str, key: Option<&'static str>, body: Value| {
+        let mut req = srv.post(path);
+        if let Some(key) = key {
+            req = req.header("x-api-key", key);
+        }
+        async move { req.send_json(&body).await.unwrap().status() }
+    };
+    let set = json!({ "key": "a", "data": "data" });
+    let get = json!({ "key": "a" });
+
+    assert_eq!(status("/set", None, set.clone()).await, StatusCode::UNAUTHORIZED);
+    assert_eq!(status("/set", Some("unknown"), set.clone()).await, StatusCode::UNAUTHORIZED);
+    assert_eq!(status("/set", Some("writer"), set.clone()).await, StatusCode::OK);
+    let resp = srv.post("/get").header("authorization", "Bearer writer").send_json(&get).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // read only keys read anywhere and write nowhere
+    assert_eq!(status("/get", Some("reader"), get.clone()).await, StatusCode::OK);
+    assert_eq!(status("/set", Some("reader"), set.clone()).await, StatusCode::FORBIDDEN);
+    assert_eq!(status("/delete", Some("reader"), get.clone()).await, StatusCode::FORBIDDEN);
+
+    // namespace scoping
+    let create = json!({ "name": "ns", "memory_limit": 1024 });
+    assert_eq!(status("/admin/ns/create", Some("writer"), create.clone()).await, StatusCode::FORBIDDEN);
+    assert_eq!(status("/admin/ns/create", Some("root"), create).await, StatusCode::OK);
+    assert_eq!(status("/ns/ns/set", Some("scoped"), set.clone()).await, StatusCode::OK);
+    assert_eq!(status("/ns/ns/set", Some("writer"), set.clone()).await, StatusCode::FORBIDDEN);
+    assert_eq!(status("/get", Some("scoped"), get.clone()).await, StatusCode::FORBIDDEN);
+    assert_eq!(status("/ns/ns/get", Some("reader"), get).await, StatusCode::OK);
+}
+
+#[actix_rt::test]
+async fn acl_checks_routed_path() {
+    let key = ApiKey { namespaces: vec!["*".to_owned()], admin: false, read_only: false, tenant: None };
+    let acl = Acl::new(vec![("user".to_owned(), key)].into_iter().collect());
+    let srv = TestServer::builder().acl(Arc::new(RwLock::new(acl))).start();
+
+    for path in ["/admin/readonly", "/%61dmin/readonly", "/%61%64%6d%69%6e/tenants"].iter() {
+        let resp = srv.get_request(path).header("x-api-key", "user").send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN, "{}", path);
+    }
+}
+
+#[actix_rt::test]
+async fn udp_with_api_keys() {
+    let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap();
+    let key = ApiKey { namespaces: vec!["*".to_owned()], admin: true, read_only: false, tenant: None };
+    let acl = Acl::new(vec![("secret".to_owned(), key)].into_iter().collect());
+    let _srv = TestServer::builder().udp(socket).acl(Arc::new(RwLock::new(acl))).start();
+    let mut client = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client.connect(addr).await.unwrap();
+
+    assert_eq!(
+        udp_request(&mut client, 1, b"set a 0 0 4\r\ndata\r\n").await,
+        b"SERVER_ERROR api keys are required, use http\r\n".to_vec(),
+    );
+}
+
+/// Sends a single datagram memcached udp request, returns the response put together.
+async fn udp_request(client: &mut tokio::net::UdpSocket, id: u16, command: &[u8]) -> Vec<u8> {
+    let mut datagram = [id.to_be_bytes(), [0, 0], [0, 1], [0, 0]].concat();
+    datagram.extend_from_slice(command);
+    client.send(&datagram).await.unwrap();
+
+    let mut response = Vec::new();
+    let mut buf = [0; 1500];
+    loop {
+        let len = client.recv(&mut buf).await.unwrap();
+        assert_eq!(buf[..2], id.to_be_bytes());
+        response.extend_from_slice(&buf[8..len]);
+        let (seq, total) = (u16::from_be_bytes([buf[2], buf[3]]), u16::from_be_bytes([buf[4], buf[5]]));
+        if seq + 1 == total {
+            return response
+        }
+    }
+}
+
+#[actix_rt::test]
+async fn h2c() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let srv = TestServer::builder().h2c(listener).start();
+    srv.set("a", "data", None).await;
+
+    let conn = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let (client, conn) = h2::client::handshake(conn).await.unwrap();
+    actix_rt::spawn(async move {
+        let _ = conn.await;
+    });
+
+    // concurrent streams of a single connection
+    let responses = futures::future::join_all(
+        (0..100).map(|_| h2_post(client.clone(), "/get", json!({ "key": "a" })))
+    ).await;
+    for (status, resp) in responses {
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(resp["data"], "data");
+    }
+
+    let (status, _) = h2_post(client.clone(), "/set", json!({ "key": "b", "data": "over h2" })).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(srv.get("b").await, Some("over h2".to_owned()));
+}
+
+async fn h2_post(client: h2::client::SendRequest<web::Bytes>, path: &str, body: Value) -> (StatusCode, Value) {
+    let mut client = client.ready().await.unwrap();
+    let req = http::Request::post(format!("http://localhost{}", path))
+        .header("content-type", "application/json")
+        .body(())
+        .unwrap();
+    let (resp, mut stream) = client.send_request(req, false).unwrap();
+    stream.send_data(serde_json::to_vec(&body).unwrap().into(), true).unwrap();
+
+    let resp = resp.await.unwrap();
+    let status = resp.status();
+    let mut body = resp.into_body();
+    let mut data = Vec::new();
+    while let Some(chunk) = body.data().await {
+        data.extend_from_slice(&chunk.unwrap());
+    }
+    (status, serde_json::from_slice(&data).unwrap_or(Value::Null))
+}
+
+#[actix_rt::test]
+async fn cluster() {
+    let peer = TestServer::start();
+    let nodes = vec!["http://self".to_owned(), peer.url("/").trim_end_matches('/').to_owned()];
+    let srv = TestServer::builder()
+        .cluster(Cluster::new(nodes, "http://self", 1 << 20).unwrap())
+        .start();
+
+    let keys: Vec<String> = (0..50).map(|i| format!("key{}", i)).collect();
+    for key in &keys {
+        assert_eq!(srv.set(key, key, None).await, StatusCode::OK);
+    }
+
+    let mut on_peer = Vec::new();
+    for key in &keys {
+        assert_eq!(srv.get(key).await.as_deref(), Some(key.as_str()));
+        if peer.get(key).await.is_some() {
+            on_peer.push(key);
+        }
+    }
+    assert!(!on_peer.is_empty() && on_peer.len() < keys.len());
+    // gets the peer answered are not hits of this node
+    let outcomes = srv.outcomes();
+    assert!(outcomes.contains(&Outcome::Proxied), "{:?}", outcomes);
+    let hits = outcomes.iter().filter(|&&outcome| outcome == Outcome::Hit).count();
+    assert_eq!(hits, keys.len() - on_peer.len());
+
+    let mut resp = srv.post("/dump/bulk").send_json(&json!({})).await.unwrap();
+    let dumped: Value = resp.json().await.unwrap();
+    let items = dumped["items"].as_array().unwrap();
+    assert_eq!(items.len(), keys.len() - on_peer.len());
+    // restoring a key of the peer here would shadow its value
+    let misplaced = json!({ "items": [{ "key": on_peer[0], "dump": items[0]["dump"] }] });
+    let resp = srv.post("/restore/bulk").send_json(&misplaced).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let resp = srv.post("/restore/bulk").send_json(&json!({ "items": items, "replace": true })).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    assert_eq!(srv.delete(on_peer[0]).await.as_deref(), Some(on_peer[0].as_str()));
+    assert_eq!(peer.get(on_peer[0]).await, None);
+}
+
+#[actix_rt::test]
+async fn hedged_get() {
+    // the first request of every key is stuck, the one sent again is answered
+    let requests = Arc::new(Mutex::new(HashMap::<String, usize>::new()));
+    let seen = requests.clone();
+    let peer = test::start(move || {
+        let seen = seen.clone();
+        App::new().route("/get", web::post().to(move |req: web::Json<Value>| {
+            let key = req["key"].as_str().unwrap().to_owned();
+            let attempt = {
+                let mut seen = seen.lock().unwrap();
+                let attempt = seen.entry(key.clone()).or_default();
+                *attempt += 1;
+                *attempt
+            };
+            async move {
+                if attempt == 1 {
+                    actix_rt::time::delay_for(Duration::from_secs(2)).await;
+                }
+                web::Json(json!({ "data": key }))
+            }
+        }))
+    });
+    let nodes = vec!["http://self".to_owned(), peer.url("/").trim_end_matches('/').to_owned()];
+    let ring = Cluster::new(nodes.clone(), "http://self", 1 << 20).unwrap();
+    let on_peer: Vec<String> = (0..50).map(|i| format!("key{}", i)).filter(|key| ring.owner(key).is_some()).collect();
+    let srv = TestServer::builder()
+        .cluster(Cluster::new(nodes, "http://self", 1 << 20).unwrap().with_hedge_delay(Duration::from_millis(50)))
+        .start();
+
+    let started = std::time::Instant::now();
+    let mut resp = srv.post("/get").send_json(&json!({ "key": on_peer[0], "latency_critical": true })).await.unwrap();
+    assert_eq!(resp.json::<Value>().await.unwrap()["data"], on_peer[0].as_str());
+    assert!(started.elapsed() < Duration::from_secs(1), "{:?}", started.elapsed());
+    assert_eq!(requests.lock().unwrap()[&on_peer[0]], 2);
+
+    // other gets wait for the peer
+    let started = std::time::Instant::now();
+    let mut resp = srv.post("/get").send_json(&json!({ "key": on_peer[1] })).await.unwrap();
+    assert_eq!(resp.json::<Value>().await.unwrap()["data"], on_peer[1].as_str());
+    assert!(started.elapsed() >= Duration::from_secs(2));
+    assert_eq!(requests.lock().unwrap()[&on_peer[1]], 1);
+}
+
+#[actix_rt::test]
+async fn hedging_follows_peer_latency() {
+    // a peer which is always slow, hedging every get to it would only double its load
+    let requests = Arc::new(AtomicUsize::new(0));
+    let seen = requests.clone();
+    let peer = test::start(move || {
+        let seen = seen.clone();
+        App::new().route("/get", web::post().to(move |req: web::Json<Value>| {
+            seen.fetch_add(1, Ordering::SeqCst);
+            async move {
+                actix_rt::time::delay_for(Duration::from_millis(200)).await;
+                web::Json(json!({ "data": req["key"] }))
+            }
+        }))
+    });
+    let nodes = vec!["http://self".to_owned(), peer.url("/").trim_end_matches('/').to_owned()];
+    let ring = Cluster::new(nodes.clone(), "http://self", 1 << 20).unwrap();
+    let key = (0..50).map(|i| format!("key{}", i)).find(|key| ring.owner(key).is_some()).unwrap();
+    let srv = TestServer::builder()
+        .cluster(Cluster::new(nodes, "http://self", 1 << 20).unwrap().with_hedge_delay(Duration::from_millis(20)))
+        .start();
+
+    for _ in 0..3 {
+        srv.post("/get").send_json(&json!({ "key": key })).await.unwrap();
+    }
+    assert_eq!(requests.load(Ordering::SeqCst), 3);
+    let mut resp = srv.post("/get").send_json(&json!({ "key": key, "latency_critical": true })).await.unwrap();
+    assert_eq!(resp.json::<Value>().await.unwrap()["data"], key.as_str());
+    assert_eq!(requests.load(Ordering::SeqCst), 4);
+}
+
+#[actix_rt::test]
+async fn dump_restore() {
+    let src = TestServer::start();
+    let dst = TestServer::start();
+    src.set("user:1", "one", Some("10s")).await;
+    src.set("user:2", "two", None).await;
+    src.set("other", "three", None).await;
+
+    let mut resp = src.post("/dump").send_json(&json!({ "key": "user:1" })).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let dumped: Value = resp.json().await.unwrap();
+    let restore = json!({ "key": "user:1", "dump": dumped["dump"] });
+
+    let resp = dst.post("/restore").send_json(&restore).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(dst.get("user:1").await, Some("one".to_owned()));
+    let resp = dst.post("/restore").send_json(&restore).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+    let mut resp = src.post("/dump/bulk").send_json(&json!({ "prefix": "user:" })).await.unwrap();
+    let dumped: Value = resp.json().await.unwrap();
+    assert_eq!(dumped["items"].as_array().unwrap().len(), 2);
+
+    let mut resp = dst.post("/restore/bulk").send_json(&dumped).await.unwrap();
+    let restored: Value = resp.json().await.unwrap();
+    assert_eq!(restored, json!({ "restored": 1, "skipped": 1 }));
+    assert_eq!(dst.get("user:2").await, Some("two".to_owned()));
+    assert_eq!(dst.get("other").await, None);
+
+    dst.advance_time(Duration::from_secs(11));
+    assert_eq!(dst.get("user:1").await, None);
+}
+
+#[actix_rt::test]
+async fn export_import() {
+    let src = TestServer::start();
+    let dst = TestServer::start();
+    src.set("user:1", "one", Some("10s")).await;
+    src.set("user:2", "two", None).await;
+    src.set("other", "three", None).await;
+
+    let mut resp = src.get_request("/export?prefix=user:").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let export = resp.body().await.unwrap();
+    let lines: Vec<Value> = std::str::from_utf8(&export).unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines[0], json!({ "format": "rust_memcached", "version": 1 }));
+    assert_eq!(lines.len(), 3);
+    let one = lines.iter().find(|line| line["key"] == "user:1").unwrap();
+    assert_eq!(one["data"], base64::encode("one"));
+    // the test clock keeps running
+    assert!((9_000..=10_000).contains(&one["ttl_ms"].as_u64().unwrap()), "{}", one);
+
+    dst.set("user:2", "kept", None).await;
+    let mut resp = dst.post("/import").send_body(export.clone()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let imported: Value = resp.json().await.unwrap();
+    assert_eq!(imported, json!({ "restored": 1, "skipped": 1 }));
+    assert_eq!(dst.get("user:1").await, Some("one".to_owned()));
+    assert_eq!(dst.get("user:2").await, Some("kept".to_owned()));
+
+    let mut resp = dst.post("/import?replace=true").send_body(export).await.unwrap();
+    let imported: Value = resp.json().await.unwrap();
+    assert_eq!(imported, json!({ "restored": 2, "skipped": 0 }));
+    assert_eq!(dst.get("user:2").await, Some("two".to_owned()));
+
+    // fixtures may be written by hand, optional fields left out
+    let fixture = "{\"format\":\"rust_memcached\",\"version\":1}\n{\"key\":\"fixture\",\"data\":\"ZGF0YQ==\"}";
+    let resp = dst.post("/import").send_body(fixture).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(dst.get("fixture").await, Some("data".to_owned()));
+
+    let newer = "{\"format\":\"rust_memcached\",\"version\":2}\n";
+    let resp = dst.post("/import").send_body(newer).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let resp = dst.post("/import").send_body("{\"key\":\"a\",\"data\":\"\"}\n").await.unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    dst.advance_time(Duration::from_secs(11));
+    assert_eq!(dst.get("user:1").await, None);
+
+    let limited = TestServer::builder().json_limit(64).import_limit(256).start();
+    let long = format!("{}\n{{\"key\":\"long\",\"data\":\"{}\"}}", lines[0], base64::encode([0; 64]));
+    let resp = limited.post("/import").send_body(long).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let many: String = (0..20).map(|i| format!("{{\"key\":\"{}\",\"data\":\"\"}}\n", i)).collect();
+    let resp = limited.post("/import").send_body(format!("{}\n{}", lines[0], many)).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[actix_rt::test]
+async fn batch() {
+    let srv = TestServer::start();
+    srv.set("counter", "41", Some("10s")).await;
+    srv.set("text", "data", None).await;
+
+    let ops = json!([
+        { "op": "set", "key": "a", "data": "one", "ttl": "1s" },
+        { "op": "get", "key": "a" },
+        { "op": "incr", "key": "counter" },
+        { "op": "incr", "key": "counter", "delta": -2 },
+        { "op": "incr", "key": "text" },
+        { "op": "touch", "key": "a", "ttl": "1m" },
+        { "op": "delete", "key": "text" },
+        { "op": "get", "key": "missing" },
+    ]);
+    let mut resp = srv.post("/batch").send_json(&ops).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let results: Value = resp.json().await.unwrap();
+    assert_eq!(results, json!([
+        { "status": 200 },
+        { "status": 200, "data": "one" },
+        { "status": 200, "value": 42 },
+        { "status": 200, "value": 40 },
+        { "status": 400, "error": "value is not a decimal integer" },
+        { "status": 200 },
+        { "status": 200, "data": "data" },
+        { "status": 404, "error": "key not found" },
+    ]));
+
+    srv.advance_time(Duration::from_secs(2));
+    assert_eq!(srv.get("a").await, Some("one".to_owned()));
+    assert_eq!(srv.get("counter").await, Some("40".to_owned()));
+    srv.advance_time(Duration::from_secs(9));
+    assert_eq!(srv.get("counter").await, None);
+
+    let resp = srv.post("/batch").send_json(&json!([{ "op": "flush" }])).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_rt::test]
+async fn transaction() {
+    let srv = TestServer::start();
+    srv.set("from", "10", None).await;
+    srv.set("to", "0", None).await;
+    let resp = srv.get_request("/keys/from").send().await.unwrap();
+    let version = resp.headers().get("etag").unwrap().to_str().unwrap().trim_matches('"').parse::<u64>().unwrap();
+
+    let ops = json!([
+        { "op": "incr", "key": "from", "delta": -3, "if_version": version },
+        { "op": "incr", "key": "to", "delta": 3 },
+        { "op": "set", "key": "log", "data": "moved 3", "if_version": 0 },
+    ]);
+    let mut resp = srv.post("/transaction").send_json(&ops).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let results: Value = resp.json().await.unwrap();
+    assert_eq!(results, json!([{ "status": 200, "value": 7 }, { "status": 200, "value": 3 }, { "status": 200 }]));
+    let resp = srv.get_request("/keys/from").send().await.unwrap();
+    let committed = resp.headers().get("etag").unwrap().clone();
+
+    // stale precondition fails before anything is applied
+    let mut resp = srv.post("/transaction").send_json(&ops).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+    let failed: Value = resp.json().await.unwrap();
+    assert_eq!(failed["failed"], 0);
+    assert_eq!(failed["result"]["status"], 412);
+
+    // failed operation rolls back the ones before it
+    let ops = json!([
+        { "op": "set", "key": "from", "data": "0" },
+        { "op": "delete", "key": "log" },
+        { "op": "set", "key": "new", "data": "new" },
+        { "op": "incr", "key": "missing" },
+    ]);
+    let mut resp = srv.post("/transaction").send_json(&ops).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+    let failed: Value = resp.json().await.unwrap();
+    assert_eq!(failed, json!({ "failed": 3, "result": { "status": 404, "error": "key not found" } }));
+    assert_eq!(srv.get("from").await, Some("7".to_owned()));
+    assert_eq!(srv.get("log").await, Some("moved 3".to_owned()));
+    assert_eq!(srv.get("new").await, None);
+    // rolled back values keep their versions
+    let resp = srv.get_request("/keys/from").send().await.unwrap();
+    assert_eq!(resp.headers().get("etag"), Some(&committed));
+
+    // collections can't be dumped, but are rolled back all the same
+    srv.post("/rpush").send_json(&json!({ "key": "l", "values": ["a", "b"] })).await.unwrap();
+    let ops = json!([
+        { "op": "set", "key": "l", "data": "plain" },
+        { "op": "incr", "key": "missing" },
+    ]);
+    let resp = srv.post("/transaction").send_json(&ops).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+    let mut resp = srv.post("/lrange").send_json(&json!({ "key": "l" })).await.unwrap();
+    assert_eq!(resp.json::<Value>().await.unwrap(), json!({ "values": ["a", "b"] }));
+}
+
+#[actix_rt::test]
+async fn warmup() {
+    let src = TestServer::start();
+    src.set("dumped", "from dump", None).await;
+    let mut resp = src.post("/dump").send_json(&json!({ "key": "dumped" })).await.unwrap();
+    let dumped: Value = resp.json().await.unwrap();
+
+    let path = std::env::temp_dir().join(format!("rust_memcached_warmup_{}", std::process::id()));
+    let lines = [
+        json!({ "key": "a", "data": "data" }),
+        json!({ "key": "b", "data": "expiring", "ttl": "1s" }),
+        json!({ "key": "dumped", "dump": dumped["dump"] }),
+    ];
+    let file: Vec<String> = lines.iter().map(Value::to_string).collect();
+    std::fs::write(&path, file.join("\n")).unwrap();
+
+    let srv = TestServer::builder().warmup_file(path.clone()).start();
+    assert_eq!(srv.get("a").await, Some("data".to_owned()));
+    assert_eq!(srv.get("b").await, Some("expiring".to_owned()));
+    assert_eq!(srv.get("dumped").await, Some("from dump".to_owned()));
+
+    srv.advance_time(Duration::from_secs(2));
+    assert_eq!(srv.get("b").await, None);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[actix_rt::test]
+async fn handover() {
+    let path = std::env::temp_dir().join(format!("rust_memcached_handover_{}", std::process::id()));
+    let namespaces = || Namespaces::new(
+        StdClock::new(), Duration::from_secs(60), GcBudget::default(), Options::default(),
+        Arc::new(EventBus::new(StdClock::new())), Arc::new(SlowLog::new(Duration::from_secs(1), 1)),
+        Arc::new(Metrics::new(Vec::new())),
+    );
+    let mut old = memcached::new(1 << 20, StdClock::new(), Options::default());
+    assert!(old.set("a".to_owned(), b"data".to_vec(), None).is_ok());
+    assert!(old.set("b".to_owned(), b"expiring".to_vec(), Some(Duration::from_secs(60))).is_ok());
+    assert_eq!(old.push("l", End::Back, vec![b"x".to_vec(), b"y".to_vec()], None), Ok(2));
+    assert!(old.set_negative("n".to_owned(), Duration::from_secs(60)).is_ok());
+    let old = Store::new(old);
+    let old_namespaces = namespaces();
+    old_namespaces.create("ns".to_owned(), 1 << 10, None, None);
+    assert!(old_namespaces.get("ns").unwrap().write().await.set("c".to_owned(), b"in ns".to_vec(), None).is_ok());
+    let server = HttpServer::new(App::new).bind("127.0.0.1:0").unwrap().run();
+    let serving = handover::listen(&path, server.clone()).unwrap();
+    let mode = std::os::unix::fs::PermissionsExt::mode(&std::fs::metadata(&path).unwrap().permissions());
+    assert_eq!(mode & 0o777, 0o600);
+
+    let (mut new, new_namespaces) = (memcached::new(1 << 20, StdClock::new(), Options::default()), namespaces());
+    let taking_over = handover::take_over(&mut new, &new_namespaces, &path);
+    let handing_over = async {
+        // stopped by the new process connecting
+        server.await.unwrap();
+        serving.finish(&old, &old_namespaces).await.unwrap()
+    };
+    let (loaded, sent) = futures::join!(taking_over, handing_over);
+    assert_eq!(sent, 5);
+    assert_eq!(loaded.unwrap(), Some(5));
+    assert_eq!(new.get("a"), Some(b"data".to_vec()));
+    assert_eq!(new.get("b"), Some(b"expiring".to_vec()));
+    assert_eq!(new.range("l", 0, -1), Ok(Some(vec![b"x".to_vec(), b"y".to_vec()])));
+    assert!(new.is_negative("n"));
+    let ns = new_namespaces.get("ns").unwrap();
+    assert_eq!(ns.get("c").await, Some(b"in ns".to_vec()));
+    assert_eq!(ns.read().await.limit(), 1 << 10);
+
+    std::fs::remove_file(&path).unwrap();
+    let mut alone = memcached::new(1 << 20, StdClock::new(), Options::default());
+    assert_eq!(handover::take_over(&mut alone, &namespaces(), &path).await.unwrap(), None);
+}
+
+#[actix_rt::test]
+async fn slowlog() {
+    let srv = TestServer::builder().slowlog_threshold(Duration::from_secs(0)).start();
+    assert_eq!(srv.set("a", "data", None).await, StatusCode::OK);
+
+    let mut resp = srv.get_request("/slowlog").send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let slowlog: Value = resp.json().await.unwrap();
+    let write = slowlog["ops"].as_array().unwrap().iter()
+        .find(|op| op["op"] == "write" && op["site"].as_str().unwrap_or_default().contains("api.rs"))
+        .expect("set is not in the slow log");
+    assert!(write["lock_wait_us"].is_u64());
+    assert!(write["work_us"].is_u64());
+}
+
+#[actix_rt::test]
+async fn latency_histograms() {
+    let metrics = Arc::new(Metrics::new(vec![Duration::from_millis(1), Duration::from_millis(10)]));
+    let mc = Store::new(memcached::new(100, StdClock::new(), Options::default()))
+        .with_metrics(metrics.clone());
+    assert!(mc.set("a".to_owned(), b"data".to_vec(), None).await.is_ok());
+    assert_eq!(mc.get("a").await, Some(b"data".to_vec()));
+    metrics.observe_request("get", Duration::from_millis(5));
+    metrics.observe_request("watch", Duration::from_millis(5));
+
+    let out = metrics.render(&*mc.read().await);
+    assert!(out.contains("memcached_items 1\n"), "{}", out);
+    assert!(out.contains("memcached_request_duration_seconds_bucket{op=\"get\",le=\"0.001\"} 0\n"), "{}", out);
+    assert!(out.contains("memcached_request_duration_seconds_bucket{op=\"get\",le=\"0.01\"} 1\n"), "{}", out);
+    assert!(out.contains("memcached_request_duration_seconds_bucket{op=\"get\",le=\"+Inf\"} 1\n"), "{}", out);
+    assert!(out.contains("memcached_request_duration_seconds_count{op=\"set\"} 0\n"), "{}", out);
+    assert!(!out.contains("watch"), "{}", out);
+    assert!(out.contains("memcached_lock_wait_seconds_count{lock=\"write\"} 1\n"), "{}", out);
+    assert!(out.contains("memcached_lock_wait_seconds_count{lock=\"read\"} 2\n"), "{}", out);
+    assert!(out.contains("# TYPE memcached_stored_value_bytes histogram\n"), "{}", out);
+    assert!(out.contains("memcached_stored_value_bytes_bucket{le=\"64\"} 1\n"), "{}", out);
+    assert!(out.contains("memcached_stored_value_bytes_sum 4\nmemcached_stored_value_bytes_count 1\n"), "{}", out);
+    assert!(out.contains("memcached_stored_ttl_seconds_count 0\n"), "{}", out);
+}
+
+#[actix_rt::test]
+async fn embedded_store() {
+    let mc = Store::new(memcached::new(100, StdClock::new(), Options::default()));
+
+    assert!(mc.set("a".to_owned(), b"data".to_vec(), None).await.is_ok());
+    assert_eq!(mc.get("a").await, Some(b"data".to_vec()));
+    assert_eq!(mc.delete("a").await, Some(b"data".to_vec()));
+    assert_eq!(mc.get("a").await, None);
+}
+
+async fn next_json<S, E>(conn: &mut S) -> Value
+where S: Stream<Item = Result<Frame, E>> + Unpin, E: Debug {
+    match conn.next().await {
+        Some(Ok(Frame::Text(text))) => serde_json::from_slice(&text).unwrap(),
+        frame => panic!("unexpected frame {:?}", frame),
+    }
+}
+
+#[actix_rt::test]
+async fn keyspace_digest() {
+    let (left, right) = (TestServer::start(), TestServer::start());
+    // insertion order doesn't matter
+    for (key, data) in &[("a:1", "x"), ("a:2", "y"), ("b:1", "z")] {
+        left.set(key, data, None).await;
+    }
+    for (key, data) in &[("b:1", "z"), ("a:2", "y"), ("a:1", "x")] {
+        right.set(key, data, Some("1h")).await;
+    }
+    let digest = |srv: &TestServer| {
+        let req = srv.post("/admin/digest").send_json(&json!({ "prefix_len": 2 }));
+        async move {
+            let mut resp = req.await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            resp.json::<Value>().await.unwrap()
+        }
+    };
+
+    let (same, other) = (digest(&left).await, digest(&right).await);
+    assert_eq!(same, other);
+    assert_eq!(same["keys"], 3);
+    assert_eq!(same["buckets"]["a:"]["keys"], 2);
+
+    right.set("b:1", "changed", None).await;
+    let other = digest(&right).await;
+    assert_ne!(same["digest"], other["digest"]);
+    assert_eq!(same["buckets"]["a:"], other["buckets"]["a:"]);
+    assert_ne!(same["buckets"]["b:"], other["buckets"]["b:"]);
+
+    for prefix_len in [0, 17].iter() {
+        let resp = left.post("/admin/digest").send_json(&json!({ "prefix_len": prefix_len })).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+}
+
+/// Lines a tracing subscriber writes, parsed as json.
+#[derive(Clone, Default)]
+struct Captured(Arc<Mutex<Vec<u8>>>);
+
+impl Captured {
+    /// json subscriber of the current thread writing here, as `log_format=json` configures it
+    fn subscribe(&self, spans: FmtSpan) -> tracing::subscriber::DefaultGuard {
+        let captured = self.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .json()
+            .flatten_event(true)
+            .with_span_events(spans)
+            .with_max_level(tracing::Level::DEBUG)
+            .with_writer(move || captured.clone())
+            .finish();
+        tracing::subscriber::set_default(subscriber)
+    }
+
+    fn lines(&self) -> Vec<Value> {
+        std::str::from_utf8(&self.0.lock().unwrap()).unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+}
+
+impl std::io::Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[actix_rt::test]
+async fn json_access_log() {
+    let captured = Captured::default();
+    let _subscribed = captured.subscribe(FmtSpan::NONE);
+    let mut app = test::init_service(
+        App::new()
+            .route("/set", web::post().to(|| {
+                let mut resp = HttpResponse::Ok().body("stored");
+                resp.extensions_mut().insert(AuditKey::new("a"));
+                resp.extensions_mut().insert(Outcome::Stored);
+                resp
+            }))
+            .wrap_fn(|req, srv| {
+                let access = Access::begin(&req, KeyLogging::Hash);
+                let res = srv.call(req);
+                async move {
+                    let mut res = res.await?;
+                    access.finish(&mut res);
+                    Ok(res)
+                }
+            })
+    ).await;
+
+    let req = test::TestRequest::post().uri("/set").header("x-request-id", "req-1").to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.headers().get("x-request-id").unwrap(), "req-1");
+
+    let lines = captured.lines();
+    let access = lines.iter().find(|line| line["target"] == "access").expect("access is not logged");
+    assert_eq!(access["level"], "INFO");
+    assert_eq!(access["request_id"], "req-1");
+    assert_eq!(access["method"], "POST");
+    assert_eq!(access["path"], "/set");
+    assert_eq!(access["key"], format!("{:016x}", AuditKey::new("a").hash()));
+    assert_eq!(access["outcome"], "stored");
+    assert_eq!(access["status"], 200);
+    assert_eq!(access["resp_size"], 6);
+    assert!(access["latency_us"].is_u64());
+
+    // ids a client sends are not trusted blindly
+    let req = test::TestRequest::post().uri("/set").header("x-request-id", "with space").to_request();
+    let resp = test::call_service(&mut app, req).await;
+    let id = resp.headers().get("x-request-id").unwrap().to_str().unwrap();
+    assert_eq!(id.len(), 32);
+}
+
+#[actix_rt::test]
+async fn store_operation_spans() {
+    let captured = Captured::default();
+    let _subscribed = captured.subscribe(FmtSpan::CLOSE);
+    let mc = Store::new(memcached::new(1 << 20, StdClock::new(), Options::default()));
+
+    assert!(mc.set("a".to_owned(), b"data".to_vec(), None).await.is_ok());
+    assert_eq!(mc.get("a").await, Some(b"data".to_vec()));
+    assert_eq!(mc.delete("missing").await, None);
+
+    let lines = captured.lines();
+    let closed = |name: &str| lines.iter()
+        .find(|line| line["message"] == "close" && line["span"]["name"] == name)
+        .unwrap_or_else(|| panic!("no {} span", name))["span"]
+        .clone();
+    for (name, value_size) in [("set", 4), ("get", 4), ("delete", 0)].iter() {
+        let span = closed(name);
+        assert_eq!(span["key_hash"], key_hash(if *name == "delete" { "missing" } else { "a" }), "{}", name);
+        assert_eq!(span["value_size"], *value_size, "{}", name);
+        assert!(span["lock_wait_us"].is_u64(), "{}", name);
+    }
+}
+
+#[test]
+fn otlp_trace_context() {
+    use opentelemetry::trace::TraceContextExt;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    // nothing listens there, spans are dropped once export fails
+    let telemetry = Telemetry::start(OtlpConfig {
+        endpoint: "http://127.0.0.1:1".to_owned(),
+        service_name: "rust_memcached_test".to_owned(),
+        metrics_interval: Duration::from_secs(60),
+    }).unwrap();
+    let subscriber = || tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(telemetry.tracer()));
+    let trace_id = |traceparent: Option<&str>| tracing::subscriber::with_default(subscriber(), || {
+        let span = tracing::info_span!("request");
+        let _entered = span.enter();
+        let mut req = test::TestRequest::default();
+        if let Some(traceparent) = traceparent {
+            req = req.header("traceparent", traceparent);
+        }
+        telemetry::continue_trace(&req.to_srv_request());
+        span.context().span().span_context().trace_id().to_u128()
+    });
+
+    let continued = trace_id(Some("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01"));
+    assert_eq!(continued, 0x0af7651916cd43dd8448eb211c80319c);
+    let started = trace_id(None);
+    assert_ne!(started, 0);
+    assert_ne!(started, continued);
+
+    telemetry.shutdown();
+}
+
+#[actix_rt::test]
+async fn log_level() {
+    let srv = TestServer::start();
+
+    let set = |filter: &'static str| srv.request(Method::PUT, "/admin/loglevel").send_json(&json!({ "filter": filter }));
+    let mut resp = set("info,memcached_core=debug").await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(body, json!({ "filter": "info,memcached_core=debug", "previous": "error" }));
+
+    let mut resp = set("warn").await.unwrap();
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(body["previous"], "info,memcached_core=debug");
+
+    let resp = set("memcached_core=loud").await.unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_rt::test]
+async fn bench() {
+    let key = ApiKey { namespaces: vec!["*".to_owned()], admin: false, read_only: false, tenant: None };
+    let acl = Acl::new(vec![("bench-key".to_owned(), key)].into_iter().collect());
+    let srv = TestServer::builder().acl(Arc::new(RwLock::new(acl))).start();
+    let workload = Workload {
+        keys: 20,
+        value_size: 10..=20,
+        read_ratio: 0.5,
+        concurrency: 3,
+        operations: 100,
+        prefill: true,
+    };
+
+    let report = bench::run_remote(&srv.url("/"), Some("bench-key"), &workload).await;
+    assert_eq!(report.operations(), 100);
+    assert_eq!(report.errors, 0);
+    assert_eq!(report.hits, report.reads);
+    assert!(report.percentile(0.5) <= report.percentile(1.0));
+    let resp = srv.post("/get").header("x-api-key", "bench-key").send_json(&json!({ "key": "bench:19" })).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let report = bench::run_remote(&srv.url("/"), None, &workload).await;
+    assert_eq!(report.errors, report.operations());
+
+    let mc = memcached::new(1 << 20, StdClock::new(), Options::default());
+    let report = bench::run_local(Arc::new(Store::new(mc)), workload);
+    assert_eq!(report.operations(), 100);
+    assert_eq!(report.hits, report.reads);
+}
+
+#[actix_rt::test]
+async fn tenant_quotas() {
+    let quota = Quota {
+        max_bytes: None,
+        // every request below is admitted but the last one
+        max_requests_per_sec: Some(6),
+        key_prefixes: vec!["t1:".to_owned()],
+    };
+    let tenant = Arc::new(Tenant::new("t1".to_owned(), quota, 0));
+    let mut app = test::init_service(
+        App::new()
+            // handlers still get the body after its keys are checked
+            .route("/set", web::post().to(|body: web::Json<Value>| HttpResponse::Ok().json(body.into_inner())))
+            .route("/keys/{key}", web::get().to(HttpResponse::Ok))
+            .wrap(Quotas { body_limit: 1 << 10 })
+            .wrap_fn(move |req, srv| {
+                req.extensions_mut().insert(tenant.clone());
+                srv.call(req)
+            })
+    ).await;
+
+    let req = test::TestRequest::post().uri("/set").set_json(&json!({ "key": "t1:a", "data": "data" })).to_request();
+    let body: Value = test::read_response_json(&mut app, req).await;
+    assert_eq!(body["key"], "t1:a");
+
+    let req = test::TestRequest::post().uri("/set").set_json(&json!({ "key": "t2:a", "data": "data" })).to_request();
+    assert_eq!(test::call_service(&mut app, req).await.status(), StatusCode::FORBIDDEN);
+
+    // json is decoded whatever the declared type, so it doesn't let keys through unchecked
+    for content_type in &["Application/JSON", "text/plain"] {
+        let req = test::TestRequest::post().uri("/set")
+            .header("content-type", *content_type)
+            .set_payload(json!({ "key": "t2:a", "data": "data" }).to_string())
+            .to_request();
+        assert_eq!(test::call_service(&mut app, req).await.status(), StatusCode::FORBIDDEN, "{}", content_type);
+    }
+
+    let req = test::TestRequest::get().uri("/keys/t2:a").to_request();
+    assert_eq!(test::call_service(&mut app, req).await.status(), StatusCode::FORBIDDEN);
+
+    let req = test::TestRequest::get().uri("/keys/t1:a").to_request();
+    assert_eq!(test::call_service(&mut app, req).await.status(), StatusCode::OK);
+
+    let req = test::TestRequest::get().uri("/keys/t1:a").to_request();
+    assert_eq!(test::call_service(&mut app, req).await.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[actix_rt::test]
+async fn tenant_storage() {
+    let path = std::env::temp_dir().join(format!("rust_memcached_tenants_{}.json", std::process::id()));
+    let write_spec = |tenant: Value| std::fs::write(&path, json!({
+        "api_keys": [
+            { "key": "admin", "namespaces": ["*"], "admin": true },
+            { "key": "t1", "namespaces": ["*"], "tenant": "t1" },
+        ],
+        "tenants": [tenant],
+    }).to_string()).unwrap();
+    let namespaces = Namespaces::new(
+        StdClock::new(), Duration::from_secs(60), GcBudget::default(), Options::default(),
+        Arc::new(EventBus::new(StdClock::new())), Arc::new(SlowLog::new(Duration::from_secs(1), 1)),
+        Arc::new(Metrics::new(Vec::new())),
+    );
+    let acl = Arc::new(RwLock::new(Acl::default()));
+    let bootstrap = Bootstrap::new(path.to_str().unwrap().to_owned());
+
+    write_spec(json!({ "name": "t1", "max_bytes": 8 }));
+    assert!(bootstrap.apply(&namespaces, &acl).await.is_err(), "max_bytes without key_prefixes");
+    write_spec(json!({ "name": "t1", "max_bytes": 8, "key_prefixes": ["t1:"] }));
+    bootstrap.apply(&namespaces, &acl).await.unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let srv = TestServer::builder().acl(acl).gc_interval(Duration::from_millis(20)).start();
+    let put = |key: &str, data: &'static str| srv.request(Method::PUT, &format!("/keys/{}", key))
+        .header("x-api-key", "t1")
+        .send_body(data);
+
+    assert_eq!(srv.request(Method::PUT, "/keys/t1:a").send_body("data").await.unwrap().status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(put("t2:a", "data").await.unwrap().status(), StatusCode::FORBIDDEN);
+    assert_eq!(srv.get_request("/admin/tenants").header("x-api-key", "t1").send().await.unwrap().status(), StatusCode::FORBIDDEN);
+
+    // written bytes are charged before they are measured
+    assert_eq!(put("t1:a", "abcdef").await.unwrap().status(), StatusCode::OK);
+    assert_eq!(put("t1:b", "abc").await.unwrap().status(), StatusCode::INSUFFICIENT_STORAGE);
+    let missing = srv.get_request("/keys/t1:b").header("x-api-key", "t1").send().await.unwrap();
+    assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+
+    // deletes are let through, measurement takes the freed bytes off
+    let deleted = srv.request(Method::DELETE, "/keys/t1:a").header("x-api-key", "t1").send().await.unwrap();
+    assert_eq!(deleted.status(), StatusCode::OK);
+    for _ in 0..100 {
+        if tenant_bytes(&srv).await == 0 {
+            break
+        }
+        actix_rt::time::delay_for(Duration::from_millis(10)).await;
+    }
+    assert_eq!(tenant_bytes(&srv).await, 0);
+    assert_eq!(put("t1:b", "abc").await.unwrap().status(), StatusCode::OK);
+    assert_eq!(tenant_bytes(&srv).await, 3);
+}
+
+/// bytes of the only tenant reported to the admin
+async fn tenant_bytes(srv: &TestServer) -> u64 {
+    let resp: Value = srv.get_request("/admin/tenants").header("x-api-key", "admin")
+        .send().await.unwrap().json().await.unwrap();
+    resp["tenants"][0]["bytes"].as_u64().unwrap()
+}
+
+#[actix_rt::test]
+async fn read_only_mode() {
+    let read_only = Arc::new(ReadOnly::default());
+    let srv = TestServer::builder().read_only(read_only.clone()).start();
+    let toggle = |enabled: bool| srv.request(Method::PUT, "/admin/readonly").send_json(&json!({ "enabled": enabled }));
+
+    assert_eq!(srv.set("a", "data", None).await, StatusCode::OK);
+
+    let resp: Value = toggle(true).await.unwrap().json().await.unwrap();
+    assert_eq!(resp, json!({ "enabled": true, "previous": false }));
+    assert!(read_only.is_enabled());
+    assert_eq!(srv.set("a", "changed", None).await, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(srv.request(Method::DELETE, "/keys/a").send().await.unwrap().status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(srv.post("/admin/flush").send().await.unwrap().status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    // reads of every kind are still served
+    assert_eq!(srv.get("a").await, Some("data".to_owned()));
+    assert_eq!(srv.get_request("/keys/a").send().await.unwrap().status(), StatusCode::OK);
+    assert_eq!(srv.get_request("/slowlog").send().await.unwrap().status(), StatusCode::OK);
+    assert_eq!(srv.get_request("/admin/jobs/1").send().await.unwrap().status(), StatusCode::NOT_FOUND);
+    assert_eq!(srv.post("/admin/digest").send_json(&json!({})).await.unwrap().status(), StatusCode::OK);
+    let resp: Value = srv.get_request("/admin/readonly").send().await.unwrap().json().await.unwrap();
+    assert_eq!(resp["enabled"], true);
+
+    // the mode can still be switched off
+    let resp: Value = toggle(false).await.unwrap().json().await.unwrap();
+    assert_eq!(resp["previous"], true);
+    assert_eq!(srv.set("a", "changed", None).await, StatusCode::OK);
+}
+
+#[actix_rt::test]
+async fn key_rules() {
+    let rules = KeyRules { max_len: Some(8), allowed_chars: Some(":".to_owned()), reject_whitespace: true };
+    let mut app = test::init_service(
+        App::new()
+            .route("/set", web::post().to(HttpResponse::Ok))
+            .route("/get", web::post().to(HttpResponse::Ok))
+            .route("/keys/{key}", web::put().to(HttpResponse::Ok))
+            .wrap(KeyCheck { rules, body_limit: 1 << 10 })
+    ).await;
+    let set = |key: &str| test::TestRequest::post().uri("/set").set_json(&json!({ "key": key, "data": "data" })).to_request();
+
+    assert_eq!(test::call_service(&mut app, set("user:1")).await.status(), StatusCode::OK);
+    assert_eq!(test::call_service(&mut app, set("user:123456")).await.status(), StatusCode::BAD_REQUEST);
+    let resp: Value = test::read_response_json(&mut app, set("a b")).await;
+    assert_eq!(resp["error"], "key contains whitespace or control characters");
+
+    let req = test::TestRequest::put().uri("/keys/a%2Fb").set_payload("data").to_request();
+    assert_eq!(test::call_service(&mut app, req).await.status(), StatusCode::BAD_REQUEST);
+
+    // reads of such keys just miss
+    let req = test::TestRequest::post().uri("/get").set_json(&json!({ "key": "a b" })).to_request();
+    assert_eq!(test::call_service(&mut app, req).await.status(), StatusCode::OK);
+}
+
+#[test]
+fn hot_keys() {
+    let hot_keys = HotKeys::new(2);
+    for i in 0..1000 {
+        hot_keys.observe("hot");
+        if i % 2 == 0 {
+            hot_keys.observe("warm");
+        }
+        hot_keys.observe(&format!("cold:{}", i));
+    }
+    let top: Vec<(String, u64)> = hot_keys.top().into_iter().map(|hot| (hot.key, hot.requests)).collect();
+    assert_eq!(top.len(), 2);
+    assert_eq!(top[0].0, "hot");
+    assert!(top[0].1 >= 1000);
+    assert_eq!(top[1].0, "warm");
+
+    // counts decay, so the top follows current traffic
+    for i in 0..DECAY_EVERY {
+        hot_keys.observe(&format!("other:{}", i % 3));
+    }
+    assert!(hot_keys.top().iter().all(|hot| hot.key.starts_with("other:")));
+
+    let disabled = HotKeys::new(0);
+    disabled.observe("hot");
+    assert!(disabled.top().is_empty());
+}
+
+#[actix_rt::test]
+async fn encoded_set_body() {
+    let srv = TestServer::builder().decompress_limit(1 << 10).start();
+    let body = json!({ "key": "a", "data": "gzipped" }).to_string();
+    let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    std::io::Write::write_all(&mut gzip, body.as_bytes()).unwrap();
+    let send = |encoding: &'static str, body: Vec<u8>| srv.post("/set")
+        .content_type("application/json")
+        .header("content-encoding", encoding)
+        .send_body(body);
+
+    assert_eq!(send("gzip", gzip.finish().unwrap()).await.unwrap().status(), StatusCode::OK);
+    assert_eq!(srv.get("a").await, Some("gzipped".to_owned()));
+    let body = json!({ "key": "b", "data": "zstd" }).to_string();
+    let zstd = zstd::stream::encode_all(body.as_bytes(), 3).unwrap();
+    assert_eq!(send("zstd", zstd).await.unwrap().status(), StatusCode::OK);
+    assert_eq!(srv.get("b").await, Some("zstd".to_owned()));
+
+    assert_eq!(send("br", body.into_bytes()).await.unwrap().status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert_eq!(send("gzip", b"not gzip".to_vec()).await.unwrap().status(), StatusCode::BAD_REQUEST);
+    // small on the wire, over the limit once decoded
+    let bomb = json!({ "key": "c", "data": "x".repeat(4 << 10) }).to_string();
+    let bomb = zstd::stream::encode_all(bomb.as_bytes(), 3).unwrap();
+    assert_eq!(send("zstd", bomb).await.unwrap().status(), StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[actix_rt::test]
+async fn malformed_json() {
+    let srv = TestServer::start();
+
+    let mut resp = srv.post("/get")
+        .content_type("application/json")
+        .send_body("{")
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["code"], 400);
+}
+
+#[actix_rt::test]
+async fn payload_limits() {
+    let srv = TestServer::builder().json_limit(64).start();
+    let long = "x".repeat(64);
+
+    for (path, body) in [
+        ("/set", json!({ "key": "a", "data": long })),
+        ("/get", json!({ "key": long })),
+    ].iter() {
+        let mut resp = srv.post(path).send_json(body).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE, "{}", path);
+        let error: Value = resp.json().await.unwrap();
+        assert_eq!(error["code"], 413);
+        assert!(error["error"].is_string());
+    }
+    assert_eq!(srv.set("a", "short", None).await, StatusCode::OK);
+
+    let mut resp = srv.post("/get").content_type("text/plain").send_body("{\"key\":\"a\"}").await.unwrap();
+    assert_eq!(resp.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert_eq!(resp.json::<Value>().await.unwrap()["code"], 415);
+}
+
+#[actix_rt::test]
+async fn namespaces() {
+    let srv = TestServer::start();
+
+    let resp = srv.post("/admin/ns/create")
+        .send_json(&json!({ "name": "ns", "memory_limit": 100 }))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let resp = srv.post("/ns/ns/set")
+        .send_json(&json!({ "key": "a", "data": "ns data" }))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(srv.get("a").await, None);
+
+    let resp = srv.post("/ns/missing/get")
+        .send_json(&json!({ "key": "a" }))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn connection_limits() {
+    let settings = Settings::new().unwrap();
+    assert_eq!(settings.connection_limits(), Ok(ConnectionLimits::default()));
+    assert_eq!(settings.validate(), Ok(()));
+
+    let mut invalid = Settings::new().unwrap();
+    invalid.max_connections = 0;
+    assert!(invalid.validate().is_err());
+
+    let mut invalid = Settings::new().unwrap();
+    invalid.keep_alive = DurationString::from(Duration::from_millis(1500));
+    assert!(invalid.validate().is_err());
+}
+
+#[test]
+fn latency_buckets() {
+    assert_eq!(
+        settings::parse_buckets("100us, 1ms,2s"),
+        Ok(vec![Duration::from_micros(100), Duration::from_millis(1), Duration::from_secs(2)]),
+    );
+    assert!(settings::parse_buckets("1ms,100us").is_err());
+    assert!(settings::parse_buckets("1us0").is_err());
+    assert!(settings::parse_buckets("").is_err());
+}